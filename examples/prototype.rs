@@ -44,7 +44,7 @@ pub struct Args {
     #[structopt(long = "mach", help = "Output mach file")]
     mach: bool,
 
-    #[structopt(long = "library", help = "Output a static library (Unimplemented)")]
+    #[structopt(long = "library", help = "Output a static library")]
     library: bool,
 
     #[structopt(long = "dwarf", help = "Emit some DWARF sections")]
@@ -364,7 +364,7 @@ fn link(name: &str, output: &str, linkline: &[String]) -> anyhow::Result<()> {
     //ld -e _start -I/usr/lib/ld-linux-x86-64.so.2 -L/usr/lib/ /usr/lib/crti.o /usr/lib/Scrt1.o /usr/lib/crtn.o test.o -lc -o test
     let child = Command::new("cc")
         .args(linkline)
-        .args(&[name, "-o", output])
+        .args([name, "-o", output])
         .spawn()?;
     let child = child.wait_with_output()?;
     println!(
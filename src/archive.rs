@@ -0,0 +1,257 @@
+//! The static library backend, bundling emitted object files into a single `ar` archive.
+//!
+//! ELF and COFF objects are archived in the System V/GNU variant, with a `/` symbol index and a
+//! `//` long name table. Mach-O objects are archived in the BSD variant, with a
+//! `__.SYMDEF SORTED` symbol index, as expected by `ld64`.
+
+use crate::artifact::{Artifact, ArtifactError, DefinedDecl};
+use crate::{target::make_ctx, Scope};
+
+use scroll::{IOwrite, BE};
+use std::io::{Cursor, Write};
+use std::path::Path;
+use target_lexicon::BinaryFormat;
+
+/// The magic every archive starts with
+const MAGIC: &[u8] = b"!<arch>\n";
+/// The size of a member header, see `Header`
+const SIZEOF_HEADER: usize = 60;
+/// The longest name which fits into a System V member header, after the terminating `/`
+const MAX_SHORT_NAME_LEN: usize = 15;
+/// The name of the BSD symbol index member; "SORTED" allows the linker to binary search it
+const BSD_SYMDEF_NAME: &str = "__.SYMDEF SORTED";
+/// ld64 requires the object files inside an archive to be 8 byte aligned
+const BSD_ALIGN: usize = 8;
+
+/// A member file header, with deterministic timestamps, owners and permissions
+struct Header<'a> {
+    name: &'a str,
+    size: usize,
+}
+
+impl<'a> Header<'a> {
+    fn new(name: &'a str, size: usize) -> Self {
+        Header { name, size }
+    }
+    fn write<W: Write>(&self, w: &mut W) -> Result<(), ArtifactError> {
+        // the name and size are padded to 16 and 10 characters, and mustn't overflow them
+        if self.name.len() > 16 || self.size.to_string().len() > 10 {
+            return Err(ArtifactError::ArchiveMemberTooLarge {
+                name: self.name.to_string(),
+                size: self.size,
+            });
+        }
+        writeln!(
+            w,
+            "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`",
+            self.name, 0, 0, 0, 644, self.size
+        )?;
+        Ok(())
+    }
+}
+
+/// An emitted object file, and the global symbols it defines
+struct Member<'a> {
+    name: &'a str,
+    symbols: Vec<String>,
+    data: Vec<u8>,
+}
+
+impl<'a> Member<'a> {
+    fn new(artifact: &'a Artifact, format: BinaryFormat) -> Result<Self, ArtifactError> {
+        let data = artifact.emit_object(format)?;
+        let name = Path::new(&artifact.name)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(&artifact.name);
+        let symbols = artifact
+            .definitions()
+            .filter_map(|def| {
+                let scope = match def.decl {
+                    DefinedDecl::Function(d) => d.get_scope(),
+                    DefinedDecl::Data(d) => d.get_scope(),
+                    // custom section symbols are always local
+                    DefinedDecl::Section(_) => return None,
+                };
+                if scope == Scope::Local {
+                    return None;
+                }
+                Some(match format {
+                    // mach-o prefixes every symbol with an underscore
+                    BinaryFormat::Macho => format!("_{}", def.name),
+                    _ => def.name.to_string(),
+                })
+            })
+            .collect();
+        Ok(Member {
+            name,
+            symbols,
+            data,
+        })
+    }
+}
+
+/// Pad `size` up to the next multiple of `align`
+fn pad(size: usize, align: usize) -> usize {
+    size.div_ceil(align) * align
+}
+
+/// Write a System V/GNU archive
+fn write_gnu<W: Write>(w: &mut W, members: &[Member]) -> Result<(), ArtifactError> {
+    let nsymbols = members.iter().map(|m| m.symbols.len()).sum::<usize>();
+    let sizeof_symbol_names = members
+        .iter()
+        .flat_map(|m| m.symbols.iter())
+        .map(|s| s.len() + 1)
+        .sum::<usize>();
+    let sizeof_index = 4 + 4 * nsymbols + sizeof_symbol_names;
+
+    // names which are too long, or could be confused with the `/` terminator, go in the `//` member
+    let mut long_names = Vec::new();
+    let names = members
+        .iter()
+        .map(|m| {
+            if m.name.len() <= MAX_SHORT_NAME_LEN && !m.name.contains('/') {
+                format!("{}/", m.name)
+            } else {
+                let offset = long_names.len();
+                long_names.extend_from_slice(m.name.as_bytes());
+                long_names.extend_from_slice(b"/\n");
+                format!("/{}", offset)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut offset = MAGIC.len() + SIZEOF_HEADER + pad(sizeof_index, 2);
+    if !long_names.is_empty() {
+        offset += SIZEOF_HEADER + pad(long_names.len(), 2);
+    }
+    let mut offsets = Vec::with_capacity(members.len());
+    for member in members {
+        offsets.push(offset);
+        offset += SIZEOF_HEADER + pad(member.data.len(), 2);
+    }
+
+    w.write_all(MAGIC)?;
+    // the symbol index is always big endian, regardless of the archived objects
+    Header::new("/", sizeof_index).write(w)?;
+    w.iowrite_with(nsymbols as u32, BE)?;
+    for (member, offset) in members.iter().zip(&offsets) {
+        for _ in &member.symbols {
+            w.iowrite_with(*offset as u32, BE)?;
+        }
+    }
+    for symbol in members.iter().flat_map(|m| m.symbols.iter()) {
+        w.write_all(symbol.as_bytes())?;
+        w.write_all(&[0])?;
+    }
+    if sizeof_index % 2 != 0 {
+        w.write_all(b"\n")?;
+    }
+
+    if !long_names.is_empty() {
+        Header::new("//", long_names.len()).write(w)?;
+        w.write_all(&long_names)?;
+        if long_names.len() % 2 != 0 {
+            w.write_all(b"\n")?;
+        }
+    }
+
+    for (member, name) in members.iter().zip(&names) {
+        Header::new(name, member.data.len()).write(w)?;
+        w.write_all(&member.data)?;
+        if member.data.len() % 2 != 0 {
+            w.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// Write a BSD archive; the symbol index uses the byte order of the archived objects
+fn write_bsd<W: Write>(
+    w: &mut W,
+    members: &[Member],
+    endian: scroll::Endian,
+) -> Result<(), ArtifactError> {
+    // BSD archives store the member name right after the header, as `#1/<length>`; we pad the
+    // name with zeros so the contents which follow a header at `offset` are aligned
+    fn sizeof_name(offset: usize, name: &str) -> usize {
+        pad(offset + SIZEOF_HEADER + name.len(), BSD_ALIGN) - offset - SIZEOF_HEADER
+    }
+
+    let mut strtab = Vec::new();
+    let mut ranlibs = Vec::new();
+    let sizeof_index_name = sizeof_name(MAGIC.len(), BSD_SYMDEF_NAME);
+    let mut symbols = members
+        .iter()
+        .enumerate()
+        .flat_map(|(i, m)| m.symbols.iter().map(move |s| (s, i)))
+        .collect::<Vec<_>>();
+    symbols.sort();
+    for (symbol, member) in symbols {
+        ranlibs.push((strtab.len() as u32, member));
+        strtab.extend_from_slice(symbol.as_bytes());
+        strtab.push(0);
+    }
+    // the ranlib array and the two sizes are all 4 byte fields
+    let sizeof_ranlibs = ranlibs.len() * 8;
+    let sizeof_index = pad(
+        sizeof_index_name + 4 + sizeof_ranlibs + 4 + strtab.len(),
+        BSD_ALIGN,
+    );
+    strtab.resize(
+        sizeof_index - (sizeof_index_name + 4 + sizeof_ranlibs + 4),
+        0,
+    );
+
+    let mut offset = MAGIC.len() + SIZEOF_HEADER + sizeof_index;
+    // (header offset, name size, member size)
+    let mut layout = Vec::with_capacity(members.len());
+    for member in members {
+        let sizeof_name = sizeof_name(offset, member.name);
+        let size = pad(sizeof_name + member.data.len(), BSD_ALIGN);
+        layout.push((offset, sizeof_name, size));
+        offset += SIZEOF_HEADER + size;
+    }
+
+    w.write_all(MAGIC)?;
+    Header::new(&format!("#1/{}", sizeof_index_name), sizeof_index).write(w)?;
+    w.write_all(BSD_SYMDEF_NAME.as_bytes())?;
+    w.write_all(&vec![0; sizeof_index_name - BSD_SYMDEF_NAME.len()])?;
+    w.iowrite_with(sizeof_ranlibs as u32, endian)?;
+    for (strx, member) in ranlibs {
+        w.iowrite_with(strx, endian)?;
+        w.iowrite_with(layout[member].0 as u32, endian)?;
+    }
+    w.iowrite_with(strtab.len() as u32, endian)?;
+    w.write_all(&strtab)?;
+
+    for (member, &(_, sizeof_name, size)) in members.iter().zip(&layout) {
+        Header::new(&format!("#1/{}", sizeof_name), size).write(w)?;
+        w.write_all(member.name.as_bytes())?;
+        w.write_all(&vec![0; sizeof_name - member.name.len()])?;
+        w.write_all(&member.data)?;
+        w.write_all(&vec![b'\n'; size - sizeof_name - member.data.len()])?;
+    }
+    Ok(())
+}
+
+/// Emit each of `artifacts` as an object file in `format`, and bundle them into a static library
+pub fn to_bytes(artifacts: &[&Artifact], format: BinaryFormat) -> Result<Vec<u8>, ArtifactError> {
+    let members = artifacts
+        .iter()
+        .map(|artifact| Member::new(artifact, format))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut buffer = Cursor::new(Vec::new());
+    match format {
+        BinaryFormat::Macho => {
+            let endian = artifacts
+                .first()
+                .map(|artifact| make_ctx(&artifact.target).le)
+                .unwrap_or(scroll::LE);
+            write_bsd(&mut buffer, &members, endian)?
+        }
+        _ => write_gnu(&mut buffer, &members)?,
+    }
+    Ok(buffer.into_inner())
+}
@@ -2,34 +2,49 @@
 
 use indexmap::IndexMap;
 use string_interner::StringInterner;
-use target_lexicon::{BinaryFormat, Triple};
+use target_lexicon::{Architecture, BinaryFormat, Triple};
 use thiserror::Error;
 
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::io::Write;
+use std::io::{Cursor, Seek, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
-use crate::{elf, mach};
+use crate::{archive, coff, elf, linker, mach, read, wasm};
 
 pub(crate) mod decl;
 pub use crate::artifact::decl::{
-    DataType, Decl, DefinedDecl, ImportKind, Scope, SectionKind, Visibility,
+    DataType, Decl, DefinedDecl, ImportKind, Scope, SectionKind, Signature, UnwindTable, Visibility,
 };
 
-// we need Ord so that `InternalDefinition` can go in a BTreeSet
 /// The data to be stored in an artifact, representing a function body or data object.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub enum Data {
     /// A blob of binary bytes, representing a function body, or data object
     Blob(Vec<u8>),
+    /// A blob of binary bytes shared with its owner, e.g. a code cache, which is neither copied
+    /// when it's defined nor when the artifact is cloned
+    Shared(Arc<[u8]>),
     /// Zero-initialized data with a given size. This is implemented as a .bss section.
     ZeroInit(usize),
 }
 
 /// The kind of relocation for a link.
+///
+/// Besides `Auto`, the portable kinds (`Absolute`, `Call`, `PcRelative`, `GotPcRelative`,
+/// `SectionOffset` and `ImageOffset`) describe the value a link computes independently of the binary
+/// format, and each backend translates them to its native relocation for the target architecture.
+/// Emitting a kind the format or architecture can't express is an `UnsupportedRelocation` error.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 pub enum Reloc {
-    /// Automatic relocation determined by the `from` and `to` of the link.
+    /// Automatic relocation determined by the `from` and `to` of the link, and the target architecture.
+    ///
+    /// On aarch64, code must lay out its instructions the way the relocations expect them: a call
+    /// is a `bl` at the link's offset, and the address of data is materialized by an `adrp` at the
+    /// link's offset followed, at `at + 4`, by an `add` for data defined in the artifact, or an
+    /// `ldr` from the global offset table (the thread-local variable descriptor on Mach-o) for
+    /// imported and thread-local data. Both instructions are relocated.
     Auto,
     /// A raw relocation and its addend, to optionally override the "auto" relocation behavior of faerie.
     /// **NB**: This is implementation defined, and can break code invariants if used improperly, you have been warned.
@@ -37,19 +52,201 @@ pub enum Reloc {
         /// Raw relocation, as an integer value to be encoded by the backend
         reloc: u32,
         /// Raw addend, significance depends on the raw relocation used
-        addend: i32,
+        addend: i64,
     },
     /// A relocation in a debug section.
     Debug {
         /// Size (in bytes) of the pointer to be relocated
         size: u8,
         /// Addend for the relocation
-        addend: i32,
+        addend: i64,
+    },
+    /// The absolute address of the target plus `addend`.
+    Absolute {
+        /// Size (in bytes) of the address to be relocated
+        size: u8,
+        /// Addend for the relocation
+        addend: i64,
+    },
+    /// A call or jump to the target, in the form the call instruction at the link's offset
+    /// encodes it: the 32 bit displacement of a `call` or `jmp` on x86, or a `bl` instruction on
+    /// aarch64.
+    Call,
+    /// The 32 bit offset of the target plus `addend` from the end of the relocated field, as x86
+    /// RIP-relative addressing uses.
+    PcRelative {
+        /// Addend for the relocation
+        addend: i64,
+    },
+    /// The 32 bit offset of the global offset table entry holding the address of the target, plus
+    /// `addend`, from the end of the relocated field.
+    GotPcRelative {
+        /// Addend for the relocation
+        addend: i64,
+    },
+    /// The offset of the target plus `addend` from the start of its section, as debug info refers
+    /// to other debug sections.
+    SectionOffset {
+        /// Size (in bytes) of the offset to be relocated
+        size: u8,
+        /// Addend for the relocation
+        addend: i64,
     },
+    /// The 32 bit offset of the target plus `addend` from the base of the image it's linked into.
+    ImageOffset {
+        /// Addend for the relocation
+        addend: i64,
+    },
+}
+
+impl Reloc {
+    /// The fewest bytes at the offset of a link this relocation can patch; the backends may patch
+    /// more, e.g. a pointer sized `Auto` relocation
+    fn min_size(self) -> u64 {
+        match self {
+            Reloc::Raw { .. } => 1,
+            Reloc::Debug { size, .. }
+            | Reloc::Absolute { size, .. }
+            | Reloc::SectionOffset { size, .. } => u64::from(size),
+            Reloc::Auto
+            | Reloc::Call
+            | Reloc::PcRelative { .. }
+            | Reloc::GotPcRelative { .. }
+            | Reloc::ImageOffset { .. } => 4,
+        }
+    }
 }
 
 type StringID = usize;
-type Relocation = (StringID, StringID, u64, Reloc);
+type Relocation = (StringID, StringID, u64, Reloc, i64);
+
+/// The identity of an artifact, which the handles to its symbols carry.
+///
+/// A clone gets an identity of its own, as the symbols it declares from then on differ from those
+/// of the original.
+#[derive(Debug, PartialEq, Eq)]
+struct ArtifactId(usize);
+
+impl ArtifactId {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        ArtifactId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Clone for ArtifactId {
+    fn clone(&self) -> Self {
+        ArtifactId::new()
+    }
+}
+
+/// A handle to a symbol of an artifact, returned by `declare`, which `define`, `link` and the like
+/// accept in place of its name to skip looking the name up again.
+///
+/// A handle is only meaningful to the artifact which returned it: any other artifact, including
+/// its clones and an artifact it's merged into, rejects it as `Undeclared`.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone)]
+pub struct SymbolId(usize, StringID);
+
+mod private {
+    use super::{ArtifactError, Link, StringID, SymbolId, SymbolLink};
+    use string_interner::StringInterner;
+
+    pub trait Sealed {
+        /// The id of this symbol in `strings`, the symbols of the artifact `owner`, interning its
+        /// name if it's new
+        fn intern(
+            &self,
+            owner: usize,
+            strings: &mut StringInterner<StringID>,
+        ) -> Result<StringID, ArtifactError>;
+    }
+
+    impl<T: AsRef<str>> Sealed for T {
+        fn intern(
+            &self,
+            _owner: usize,
+            strings: &mut StringInterner<StringID>,
+        ) -> Result<StringID, ArtifactError> {
+            Ok(strings.get_or_intern(self.as_ref()))
+        }
+    }
+
+    impl Sealed for SymbolId {
+        fn intern(
+            &self,
+            owner: usize,
+            strings: &mut StringInterner<StringID>,
+        ) -> Result<StringID, ArtifactError> {
+            match strings.resolve(self.1) {
+                Some(_) if self.0 == owner => Ok(self.1),
+                _ => Err(ArtifactError::Undeclared(format!("{:?}", self))),
+            }
+        }
+    }
+
+    pub trait SealedLink {
+        /// The ids of the symbols this link binds, and its offset, see `Sealed::intern`
+        fn intern(
+            &self,
+            owner: usize,
+            strings: &mut StringInterner<StringID>,
+        ) -> Result<(StringID, StringID, u64), ArtifactError>;
+    }
+
+    impl<'a> SealedLink for Link<'a> {
+        fn intern(
+            &self,
+            owner: usize,
+            strings: &mut StringInterner<StringID>,
+        ) -> Result<(StringID, StringID, u64), ArtifactError> {
+            Ok((
+                self.from.intern(owner, strings)?,
+                self.to.intern(owner, strings)?,
+                self.at,
+            ))
+        }
+    }
+
+    impl SealedLink for SymbolLink {
+        fn intern(
+            &self,
+            owner: usize,
+            strings: &mut StringInterner<StringID>,
+        ) -> Result<(StringID, StringID, u64), ArtifactError> {
+            Ok((
+                self.from.intern(owner, strings)?,
+                self.to.intern(owner, strings)?,
+                self.at,
+            ))
+        }
+    }
+}
+
+/// A symbol of an artifact, by its name or by the [`SymbolId`](struct.SymbolId.html) handle
+/// `declare` returned for it
+pub trait SymbolRef: private::Sealed {}
+
+impl<T: AsRef<str>> SymbolRef for T {}
+
+impl SymbolRef for SymbolId {}
+
+/// A relocation between two symbols of an artifact, a [`Link`](struct.Link.html) by their names
+/// or a [`SymbolLink`](struct.SymbolLink.html) by their handles
+pub trait LinkRef: private::SealedLink {}
+
+impl<'a> LinkRef for Link<'a> {}
+
+impl LinkRef for SymbolLink {}
+
+/// Whether a function registered with an artifact runs when it is loaded, or when it exits
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
+pub(crate) enum InitKind {
+    /// A static constructor, run before `main`
+    Constructor,
+    /// A static destructor, run after `main` returns or `exit` is called
+    Destructor,
+}
 
 /// The kinds of errors that can befall someone creating an Artifact
 #[derive(Error, Debug)]
@@ -83,14 +280,145 @@ pub enum ArtifactError {
     #[error("Attempt to add custom symbols {1:?} to non section declaration {0:?}")]
     NonSectionCustomSymbols(DefinedDecl, BTreeMap<String, u64>),
 
+    /// Only functions declared in the artifact can be constructors or destructors
+    #[error("Only defined functions can be constructors or destructors, got: {0}")]
+    NonFunctionInitializer(String),
+
+    /// Only functions and function imports can have a signature
+    #[error("Only functions can have a signature, got: {0}")]
+    NonFunctionSignature(String),
+
+    /// Only symbols defined in the artifact can be in a COMDAT group
+    #[error("Only definitions can be in a COMDAT group, got import: {0}")]
+    ImportComdat(String),
+
+    /// The alignment of a declaration isn't a power of two
+    #[error("Alignment {align} of {name} is not a power of two")]
+    InvalidAlignment {
+        /// The declared symbol
+        name: String,
+        /// The alignment it was declared with
+        align: u64,
+    },
+
+    /// A member of a static library archive doesn't fit in the header the format gives it
+    #[error("Archive member {name} of {size} bytes doesn't fit in its header")]
+    ArchiveMemberTooLarge {
+        /// The name of the member in its header
+        name: String,
+        /// The size of the member
+        size: usize,
+    },
+
+    /// Only defined functions can have line info
+    #[error("Only defined functions can have line info, got: {0}")]
+    NonFunctionLineInfo(String),
+
+    /// Only defined functions can have unwind info
+    #[error("Only defined functions can have unwind info, got: {0}")]
+    NonFunctionUnwindInfo(String),
+
+    /// The unwind info of a function has an offset that isn't a multiple of the data alignment
+    /// factor of the architecture, the size of a stack slot
+    #[error("Unwind info of {function} has offset {offset}, which isn't a multiple of the stack slot size")]
+    MisalignedFrameOffset {
+        /// The function the unwind info is for
+        function: String,
+        /// The offset from the CFA
+        offset: i64,
+    },
+
+    /// A function was emitted in a binary format which needs its signature, without one
+    #[error("Missing signature of function: {0}")]
+    MissingSignature(String),
+
     /// Artifact created with a binary format not supported by Faerie
     #[error("Unsupported binary format `{0}`")]
     UnsupportedBinaryFormat(BinaryFormat),
 
+    /// The architecture of the artifact can't be emitted in the binary format
+    #[error("Unsupported architecture `{architecture}` for binary format `{format}`")]
+    UnsupportedArchitecture {
+        /// Architecture the artifact was created for
+        architecture: Architecture,
+        /// Binary format being emitted
+        format: BinaryFormat,
+    },
+
+    /// A link can't be expressed as a relocation for the architecture and binary format
+    #[error(
+        "Unsupported relocation from {from} to {to} with {reloc:?} for architecture \
+         `{architecture}` in binary format `{format}`"
+    )]
+    UnsupportedRelocation {
+        /// Symbol the relocation is in
+        from: String,
+        /// Symbol the relocation targets
+        to: String,
+        /// Kind of relocation requested
+        reloc: Reloc,
+        /// Architecture the artifact was created for
+        architecture: Architecture,
+        /// Binary format being emitted
+        format: BinaryFormat,
+    },
+
+    /// A link targets a symbol which is missing from the symbol table of the object file
+    #[error("Relocation from {from} to {to} targets a symbol missing from the symbol table")]
+    MissingSymbol {
+        /// Symbol the relocation is in
+        from: String,
+        /// Symbol the relocation targets
+        to: String,
+    },
+
+    /// A link is at an offset outside the contents of the definition it's in
+    #[error("Relocation from {from} to {to} at offset {at} is outside the contents of {from}")]
+    LinkOutOfBounds {
+        /// Symbol the relocation is in
+        from: String,
+        /// Symbol the relocation targets
+        to: String,
+        /// Offset of the relocation into `from`
+        at: u64,
+    },
+
+    /// The addend of a link doesn't fit the relocation the backend emits for it
+    #[error("Addend {addend} of relocation from {from} to {to} doesn't fit its relocation")]
+    AddendOverflow {
+        /// Symbol the relocation is in
+        from: String,
+        /// Symbol the relocation targets
+        to: String,
+        /// Addend which doesn't fit
+        addend: i64,
+    },
+
+    /// An artifact was merged into one created for a different target
+    #[error("Can't merge an artifact for `{found}` into one for `{expected}`")]
+    MismatchedTargets {
+        /// Target of the artifact being merged into
+        expected: Triple,
+        /// Target of the artifact being merged
+        found: Triple,
+    },
+
+    /// Artifact was created for a different architecture than the process loading it
+    #[error("Can't load an artifact for `{0}` into this process")]
+    ForeignTarget(Triple),
+
+    /// The resolver of a loaded artifact had no address for an import
+    #[error("Unresolved import `{0}`")]
+    UnresolvedImport(String),
+
     /// Artifact contained symbols which were declared but not defined
     #[error("Undefined symbols: {0:?}")]
     UndefinedSymbols(Vec<String>),
 
+    /// An object file could not be read into an Artifact
+    #[error("Unsupported object file: {0}")]
+    UnsupportedObject(String),
+
     /// Output of ELF format encountered error from `goblin` crate
     #[error("Goblin error: {0}")]
     Goblin(#[from] goblin::error::Error),
@@ -100,7 +428,7 @@ pub enum ArtifactError {
     Io(#[from] std::io::Error),
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 struct InternalDefinition {
     decl: DefinedDecl,
     name: StringID,
@@ -115,9 +443,15 @@ struct InternalDecl {
     defined: bool,
 }
 
-impl Into<Data> for Vec<u8> {
-    fn into(self) -> Data {
-        Data::Blob(self)
+impl From<Vec<u8>> for Data {
+    fn from(val: Vec<u8>) -> Self {
+        Data::Blob(val)
+    }
+}
+
+impl From<Arc<[u8]>> for Data {
+    fn from(val: Arc<[u8]>) -> Self {
+        Data::Shared(val)
     }
 }
 
@@ -127,23 +461,32 @@ impl Data {
     /// This is different from the bytes of _memory_ for the `ZeroInit` variant,
     /// since .bss sections are only allocated at load time.
     pub fn file_size(&self) -> usize {
+        self.bytes().map_or(0, <[u8]>::len)
+    }
+    /// Return the number of bytes of _memory_ this data will use.
+    pub fn size(&self) -> usize {
         match self {
-            Data::Blob(blob) => blob.len(),
-            Data::ZeroInit(_) => 0,
+            Data::ZeroInit(size) => *size,
+            _ => self.file_size(),
         }
     }
     /// Return whether the data has at least one byte defined
     pub fn is_empty(&self) -> bool {
-        match self {
-            Data::Blob(blob) => blob.is_empty(),
-            Data::ZeroInit(size) => *size == 0,
-        }
+        self.size() == 0
     }
     /// Return whether this data is a ZeroInit variant
     pub fn is_zero_init(&self) -> bool {
         match self {
             Data::ZeroInit(_) => true,
-            Data::Blob(_) => false,
+            Data::Blob(_) | Data::Shared(_) => false,
+        }
+    }
+    /// Return the bytes of this data, or `None` if it's zero-initialized
+    pub fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Data::Blob(blob) => Some(blob),
+            Data::Shared(blob) => Some(blob),
+            Data::ZeroInit(_) => None,
         }
     }
 }
@@ -169,6 +512,34 @@ pub struct Binding<'a> {
     pub name: &'a str,
     /// Declaration of symbol
     pub decl: &'a Decl,
+    /// Id of the name of the symbol in the artifact
+    pub(crate) id: StringID,
+    /// The COMDAT group of the symbol, if it's in one
+    pub(crate) comdat: Option<&'a str>,
+}
+
+/// A value a backend computes once for each symbol of an artifact it binds, e.g. the index of its
+/// own symbol for it, kept by the id of the symbol's name rather than looked up by the name again
+#[derive(Debug)]
+pub(crate) struct SymbolMap<T> {
+    values: Vec<Option<T>>,
+}
+
+impl<T: Copy> SymbolMap<T> {
+    pub(crate) fn new() -> Self {
+        SymbolMap { values: Vec::new() }
+    }
+    /// The value for the symbol of `binding`, computed from its name by `f` if it's new
+    pub(crate) fn get_or_insert_with<F: FnOnce(&str) -> T>(
+        &mut self,
+        binding: &Binding,
+        f: F,
+    ) -> T {
+        if self.values.len() <= binding.id {
+            self.values.resize_with(binding.id + 1, || None);
+        }
+        *self.values[binding.id].get_or_insert_with(|| f(binding.name))
+    }
 }
 
 /// A relocation binding one declaration to another
@@ -182,6 +553,49 @@ pub struct LinkAndDecl<'a> {
     pub at: u64,
     /// Type of relocation to use
     pub reloc: Reloc,
+    /// Adjustment to the addend the backend selects for an `Auto` relocation, zero for other kinds
+    pub addend: i64,
+}
+
+impl<'a> LinkAndDecl<'a> {
+    /// The error for a backend which can't express this link in `format` for `architecture`
+    pub(crate) fn unsupported(
+        &self,
+        architecture: Architecture,
+        format: BinaryFormat,
+    ) -> ArtifactError {
+        ArtifactError::UnsupportedRelocation {
+            from: self.from.name.to_string(),
+            to: self.to.name.to_string(),
+            reloc: self.reloc,
+            architecture,
+            format,
+        }
+    }
+    /// The error for a backend which can't find the target of this link in its symbol table
+    pub(crate) fn missing_symbol(&self) -> ArtifactError {
+        ArtifactError::MissingSymbol {
+            from: self.from.name.to_string(),
+            to: self.to.name.to_string(),
+        }
+    }
+    /// The error for a backend which can't fit the relocation it emits for this link into the
+    /// contents of `from`
+    pub(crate) fn out_of_bounds(&self) -> ArtifactError {
+        ArtifactError::LinkOutOfBounds {
+            from: self.from.name.to_string(),
+            to: self.to.name.to_string(),
+            at: self.at,
+        }
+    }
+    /// The error for a backend which can't fit `addend` into the relocation it emits for this link
+    pub(crate) fn addend_overflow(&self, addend: i64) -> ArtifactError {
+        ArtifactError::AddendOverflow {
+            from: self.from.name.to_string(),
+            to: self.to.name.to_string(),
+            addend,
+        }
+    }
 }
 
 /// A definition of a symbol with its properties the various backends receive
@@ -195,19 +609,19 @@ pub(crate) struct Definition<'a> {
     pub symbols: &'a BTreeMap<String, u64>,
     /// Declaration of symbol
     pub decl: &'a DefinedDecl,
+    /// The COMDAT group of the symbol, if it's in one
+    pub comdat: Option<&'a str>,
 }
 
-impl<'a> From<(&'a InternalDefinition, &'a StringInterner<StringID>)> for Definition<'a> {
-    fn from((def, strings): (&'a InternalDefinition, &'a StringInterner<StringID>)) -> Self {
-        Definition {
-            name: strings
-                .resolve(def.name)
-                .expect("internal definition to have name"),
-            data: &def.data,
-            symbols: &def.symbols,
-            decl: &def.decl,
-        }
-    }
+/// A function the backends run when the artifact is loaded or exits
+#[derive(Debug, Clone)]
+pub(crate) struct Initializer<'a> {
+    /// Name of the function
+    pub name: &'a str,
+    /// Whether the function is a constructor or destructor
+    pub kind: InitKind,
+    /// The order to run the function in, relative to others of the same kind
+    pub priority: Option<u16>,
 }
 
 /// An abstract relocation linking one symbol to another, at an offset
@@ -220,6 +634,18 @@ pub struct Link<'a> {
     pub at: u64,
 }
 
+/// A [`Link`](struct.Link.html) between two symbols given by the handles `declare` returned for
+/// them
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolLink {
+    /// The relocation is relative `from` this symbol
+    pub from: SymbolId,
+    /// The relocation is `to` this symbol
+    pub to: SymbolId,
+    /// The byte offset _relative_ to `from` where the relocation should be performed
+    pub at: u64,
+}
+
 /// Builder for creating an artifact
 pub struct ArtifactBuilder {
     target: Triple,
@@ -267,10 +693,22 @@ pub struct Artifact {
     // will keep this for now; may be useful to pre-partition code and data vectors, not sure
     imports: Vec<(StringID, ImportKind)>,
     links: Vec<Relocation>,
+    initializers: Vec<(StringID, InitKind, Option<u16>)>,
+    signatures: BTreeMap<StringID, Signature>,
+    comdats: BTreeMap<StringID, StringID>,
     declarations: IndexMap<StringID, InternalDecl>,
-    local_definitions: BTreeSet<InternalDefinition>,
-    nonlocal_definitions: BTreeSet<InternalDefinition>,
+    /// The definitions, in the order they were defined, which is the order they're emitted in
+    definitions: IndexMap<StringID, InternalDefinition>,
     strings: StringInterner<StringID>,
+    id: ArtifactId,
+}
+
+/// The name of the interned symbol `id`
+fn symbol_name(strings: &StringInterner<StringID>, id: StringID) -> String {
+    strings
+        .resolve(id)
+        .expect("interned symbol to have name")
+        .to_string()
 }
 
 // api less subject to change
@@ -280,15 +718,27 @@ impl Artifact {
         Artifact {
             imports: Vec::new(),
             links: Vec::new(),
+            initializers: Vec::new(),
+            signatures: BTreeMap::new(),
+            comdats: BTreeMap::new(),
             name,
             target,
             is_library: false,
             declarations: IndexMap::new(),
-            local_definitions: BTreeSet::new(),
-            nonlocal_definitions: BTreeSet::new(),
+            definitions: IndexMap::new(),
             strings: StringInterner::new(),
+            id: ArtifactId::new(),
         }
     }
+    /// Read an ELF relocatable or Mach-o object file back into a new Artifact.
+    ///
+    /// Each function and data object must be in a section of its own on ELF (as with
+    /// `-ffunction-sections -fdata-sections`), or start at a symbol in a Mach-o file using
+    /// subsections via symbols. All other sections become custom sections. Relocations are read
+    /// as `Reloc::Raw`, so that emitting the artifact again reproduces them exactly.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ArtifactError> {
+        read::from_bytes(bytes)
+    }
     /// Get an iterator over this artifact's imports
     pub fn imports<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a str, &'a ImportKind)> + 'a> {
         Box::new(
@@ -299,41 +749,91 @@ impl Artifact {
     }
     pub(crate) fn definitions<'a>(&'a self) -> Box<dyn Iterator<Item = Definition<'a>> + 'a> {
         Box::new(
-            self.local_definitions
-                .iter()
-                .chain(self.nonlocal_definitions.iter())
-                .map(move |int_def| Definition::from((int_def, &self.strings))),
+            self.definitions
+                .values()
+                .map(move |int_def| self.to_definition(int_def)),
         )
     }
+    fn to_definition<'a>(&'a self, def: &'a InternalDefinition) -> Definition<'a> {
+        Definition {
+            name: self
+                .strings
+                .resolve(def.name)
+                .expect("internal definition to have name"),
+            data: &def.data,
+            symbols: &def.symbols,
+            decl: &def.decl,
+            comdat: self.comdat_of(def.name),
+        }
+    }
+    /// The COMDAT group of the symbol `id`, if it's in one
+    fn comdat_of(&self, id: StringID) -> Option<&str> {
+        self.comdats.get(&id).map(|&group| {
+            self.strings
+                .resolve(group)
+                .expect("COMDAT group has a name")
+        })
+    }
+    /// The declaration of `name`, if it's declared
+    pub(crate) fn declaration(&self, name: &str) -> Option<&Decl> {
+        let id = self.strings.get(name)?;
+        self.declarations.get(&id).map(|int| &int.decl)
+    }
+    /// The definition of `name`, if it's defined
+    pub(crate) fn definition(&self, name: &str) -> Option<Definition<'_>> {
+        let id = self.strings.get(name)?;
+        self.definitions
+            .get(&id)
+            .map(|int_def| self.to_definition(int_def))
+    }
     /// Get this artifacts relocations
     pub(crate) fn links<'a>(&'a self) -> Box<dyn Iterator<Item = LinkAndDecl<'a>> + 'a> {
+        Box::new(self.links.iter().map(move |(from, to, at, reloc, addend)| {
+            // FIXME: I think its safe to unwrap since the links are only ever constructed by us and we
+            // ensure it has a declaration
+            let (from_decl, to_decl) = (
+                self.declarations.get(from).expect("declaration present"),
+                self.declarations.get(to).unwrap(),
+            );
+            let from = Binding {
+                name: self.strings.resolve(*from).expect("from link"),
+                decl: &from_decl.decl,
+                id: *from,
+                comdat: self.comdat_of(*from),
+            };
+            let to = Binding {
+                name: self.strings.resolve(*to).expect("to link"),
+                decl: &to_decl.decl,
+                id: *to,
+                comdat: self.comdat_of(*to),
+            };
+            LinkAndDecl {
+                from,
+                to,
+                at: *at,
+                reloc: *reloc,
+                addend: *addend,
+            }
+        }))
+    }
+    /// Get this artifacts constructors and destructors, in the order they were registered
+    pub(crate) fn initializers<'a>(&'a self) -> Box<dyn Iterator<Item = Initializer<'a>> + 'a> {
         Box::new(
-            self.links
+            self.initializers
                 .iter()
-                .map(move |&(ref from, ref to, ref at, ref reloc)| {
-                    // FIXME: I think its safe to unwrap since the links are only ever constructed by us and we
-                    // ensure it has a declaration
-                    let (ref from_decl, ref to_decl) = (
-                        self.declarations.get(from).expect("declaration present"),
-                        self.declarations.get(to).unwrap(),
-                    );
-                    let from = Binding {
-                        name: self.strings.resolve(*from).expect("from link"),
-                        decl: &from_decl.decl,
-                    };
-                    let to = Binding {
-                        name: self.strings.resolve(*to).expect("to link"),
-                        decl: &to_decl.decl,
-                    };
-                    LinkAndDecl {
-                        from,
-                        to,
-                        at: *at,
-                        reloc: *reloc,
-                    }
+                .map(move |&(name, kind, priority)| Initializer {
+                    name: self.strings.resolve(name).expect("initializer has a name"),
+                    kind,
+                    priority,
                 }),
         )
     }
+    /// Get the signature of the function `name`, if it has one
+    pub(crate) fn get_signature(&self, name: &str) -> Option<&Signature> {
+        self.strings
+            .get(name)
+            .and_then(|id| self.signatures.get(&id))
+    }
     /// Declare and define a new symbolic reference with the given `decl` and given `definition`.
     /// This is sugar for `declare` and then `define`
     pub fn declare_with<T: AsRef<str>, D: Into<Decl>>(
@@ -341,26 +841,38 @@ impl Artifact {
         name: T,
         decl: D,
         definition: Vec<u8>,
-    ) -> Result<(), ArtifactError> {
-        self.declare(name.as_ref(), decl)?;
-        self.define(name, definition)?;
-        Ok(())
+    ) -> Result<SymbolId, ArtifactError> {
+        let id = self.declare(name, decl)?;
+        self.define(id, definition)?;
+        Ok(id)
     }
-    /// Declare a new symbolic reference, with the given `decl`.
+    /// Declare a new symbolic reference, with the given `decl`, returning the handle to refer to
+    /// it by from now on.
     /// **Note**: All declarations _must_ precede their definitions.
     pub fn declare<T: AsRef<str>, D: Into<Decl>>(
         &mut self,
         name: T,
         decl: D,
-    ) -> Result<(), ArtifactError> {
+    ) -> Result<SymbolId, ArtifactError> {
         let decl = decl.into();
+        if let Decl::Defined(defined) = &decl {
+            match defined.get_align() {
+                Some(align) if !align.is_power_of_two() => {
+                    return Err(ArtifactError::InvalidAlignment {
+                        name: name.as_ref().to_string(),
+                        align,
+                    });
+                }
+                _ => {}
+            }
+        }
         let decl_name = self.strings.get_or_intern(name.as_ref());
         let previous_was_import;
         let new_idecl = {
             let previous = self
                 .declarations
                 .entry(decl_name)
-                .or_insert(InternalDecl::new(decl.clone()));
+                .or_insert(InternalDecl::new(decl));
             previous_was_import = previous.decl.is_import();
             previous.decl.absorb(decl)?;
             previous
@@ -370,7 +882,7 @@ impl Artifact {
                 // we have to check because otherwise duplicate imports cause an error
                 // FIXME: ditto fixme, below, use orderset
                 let mut present = false;
-                for &(ref name, _) in self.imports.iter() {
+                for (name, _) in self.imports.iter() {
                     if *name == decl_name {
                         present = true;
                     }
@@ -380,13 +892,12 @@ impl Artifact {
                         .expect("can convert from explicitly matched decls to importkind");
                     self.imports.push((decl_name, kind));
                 }
-                Ok(())
             }
             // we have to delete it, because it was upgraded from an import :/
             _ if previous_was_import => {
                 let mut index = None;
                 // FIXME: do binary search or make imports an indexmap
-                for (i, &(ref name, _)) in self.imports.iter().enumerate() {
+                for (i, (name, _)) in self.imports.iter().enumerate() {
                     if *name == decl_name {
                         index = Some(i);
                     }
@@ -394,10 +905,15 @@ impl Artifact {
                 let _ = self
                     .imports
                     .swap_remove(index.expect("previous import was not in the imports array"));
-                Ok(())
             }
-            _ => Ok(()),
+            _ => (),
         }
+        Ok(SymbolId(self.id.0, decl_name))
+    }
+    /// The handle of the symbol `name`, if it's declared
+    pub fn symbol<T: AsRef<str>>(&self, name: T) -> Option<SymbolId> {
+        let id = self.strings.get(name.as_ref())?;
+        self.declarations.get(&id).map(|_| SymbolId(self.id.0, id))
     }
     /// [Declare](struct.Artifact.html#method.declare) a sequence of name, [Decl](enum.Decl.html) pairs
     pub fn declarations<T: AsRef<str>, D: Iterator<Item = (T, Decl)>>(
@@ -413,23 +929,39 @@ impl Artifact {
     /// **NB**: If you attempt to define an import, this will return an error.
     /// If you attempt to define something which has not been declared, this will return an error.
     ///
+    /// Definitions are laid out in the emitted file in the order they're defined, whatever their
+    /// declarations, e.g. functions in `.text` or `__text`.
+    ///
     /// See the documentation for [`Data`](type.Data.html) for the difference
     /// from `define_zero_init`.
     #[inline]
-    pub fn define<T: AsRef<str>>(&mut self, name: T, data: Vec<u8>) -> Result<(), ArtifactError> {
-        self.define_with_symbols(name, Data::Blob(data), BTreeMap::new())
+    pub fn define<S: SymbolRef>(&mut self, symbol: S, data: Vec<u8>) -> Result<(), ArtifactError> {
+        self.define_with_symbols(symbol, Data::Blob(data), BTreeMap::new())
     }
 
     /// Defines a _previously declared_ program object with all zeros.
     /// **NB**: If you attempt to define an import, this will return an error.
     /// If you attempt to define something which has not been declared, this will return an error.
     #[inline]
-    pub fn define_zero_init<T: AsRef<str>>(
+    pub fn define_zero_init<S: SymbolRef>(
         &mut self,
-        name: T,
+        symbol: S,
         size: usize,
     ) -> Result<(), ArtifactError> {
-        self.define_with_symbols(name, Data::ZeroInit(size), BTreeMap::new())
+        self.define_with_symbols(symbol, Data::ZeroInit(size), BTreeMap::new())
+    }
+
+    /// Defines a _previously declared_ program object with bytes shared with their owner, which
+    /// are neither copied into the artifact nor when it's cloned, e.g. code in a code cache.
+    /// **NB**: If you attempt to define an import, this will return an error.
+    /// If you attempt to define something which has not been declared, this will return an error.
+    #[inline]
+    pub fn define_shared<S: SymbolRef>(
+        &mut self,
+        symbol: S,
+        data: Arc<[u8]>,
+    ) -> Result<(), ArtifactError> {
+        self.define_with_symbols(symbol, Data::Shared(data), BTreeMap::new())
     }
 
     /// Same as `define` but also allows to add custom symbols referencing a section decl.
@@ -457,25 +989,25 @@ impl Artifact {
     ///
     /// let _blob = artifact.emit().unwrap();
     /// ```
-    pub fn define_with_symbols<T: AsRef<str>, D: Into<Data>>(
+    pub fn define_with_symbols<S: SymbolRef, D: Into<Data>>(
         &mut self,
-        name: T,
+        symbol: S,
         data: D,
         symbols: BTreeMap<String, u64>,
     ) -> Result<(), ArtifactError> {
-        let decl_name = self.strings.get_or_intern(name.as_ref());
+        let decl_name = symbol.intern(self.id.0, &mut self.strings)?;
+        let strings = &self.strings;
+        let name = || symbol_name(strings, decl_name);
         let data = data.into();
         match self.declarations.get_mut(&decl_name) {
             Some(ref mut stype) => {
                 if stype.defined {
-                    Err(ArtifactError::DuplicateDefinition(
-                        name.as_ref().to_string(),
-                    ))?;
+                    Err(ArtifactError::DuplicateDefinition(name()))?;
                 }
                 let decl = match stype.decl {
                     Decl::Defined(decl) => decl,
                     Decl::Import(_) => {
-                        return Err(ArtifactError::ImportDefined(name.as_ref().to_string()));
+                        return Err(ArtifactError::ImportDefined(name()));
                     }
                 };
 
@@ -496,24 +1028,18 @@ impl Artifact {
                     }
                 }
 
-                if decl.is_global() {
-                    self.nonlocal_definitions.insert(InternalDefinition {
-                        name: decl_name,
-                        data,
-                        symbols,
-                        decl,
-                    });
-                } else {
-                    self.local_definitions.insert(InternalDefinition {
+                self.definitions.insert(
+                    decl_name,
+                    InternalDefinition {
                         name: decl_name,
                         data,
                         symbols,
                         decl,
-                    });
-                }
+                    },
+                );
                 stype.define();
             }
-            None => Err(ArtifactError::Undeclared(name.as_ref().to_string()))?,
+            None => Err(ArtifactError::Undeclared(name()))?,
         }
         Ok(())
     }
@@ -523,40 +1049,230 @@ impl Artifact {
         &mut self,
         import: T,
         kind: ImportKind,
-    ) -> Result<(), ArtifactError> {
-        self.declare(import.as_ref(), Decl::Import(kind))?;
-        Ok(())
+    ) -> Result<SymbolId, ArtifactError> {
+        self.declare(import, Decl::Import(kind))
     }
     /// Link a relocation at `link.at` from `link.from` to `link.to`
     /// **NB**: If either `link.from` or `link.to` is undeclared, then this will return an error.
     /// If `link.from` is an import you previously declared, this will also return an error.
-    pub fn link<'a>(&mut self, link: Link<'a>) -> Result<(), ArtifactError> {
+    pub fn link<L: LinkRef>(&mut self, link: L) -> Result<(), ArtifactError> {
         self.link_with(link, Reloc::Auto)
     }
     /// A variant of `link` with a `Reloc` provided. Has all of the same invariants as
     /// `link`.
-    pub fn link_with<'a>(&mut self, link: Link<'a>, reloc: Reloc) -> Result<(), ArtifactError> {
-        let (link_from, link_to) = (
-            self.strings.get_or_intern(link.from),
-            self.strings.get_or_intern(link.to),
-        );
+    pub fn link_with<L: LinkRef>(&mut self, link: L, reloc: Reloc) -> Result<(), ArtifactError> {
+        self.add_link(link, reloc, 0)
+    }
+    /// A variant of `link` which adds `addend` to the addend of the automatically selected
+    /// relocation, e.g. to point 24 bytes into `link.to`, or to account for an instruction whose
+    /// operand isn't its last 4 bytes. Has all of the same invariants as `link`.
+    ///
+    /// The automatic addend of a PC-relative relocation makes it relative to the end of the 4 byte
+    /// field at `link.at`, so an `addend` of `-1` suits an operand followed by a 1 byte immediate.
+    pub fn link_with_addend<L: LinkRef>(
+        &mut self,
+        link: L,
+        addend: i64,
+    ) -> Result<(), ArtifactError> {
+        self.add_link(link, Reloc::Auto, addend)
+    }
+    fn add_link<L: LinkRef>(
+        &mut self,
+        link: L,
+        reloc: Reloc,
+        addend: i64,
+    ) -> Result<(), ArtifactError> {
+        let (link_from, link_to, at) = link.intern(self.id.0, &mut self.strings)?;
         match (
             self.declarations.get(&link_from),
             self.declarations.get(&link_to),
         ) {
-            (Some(ref from_type), Some(_)) => {
+            (Some(from_type), Some(_)) => {
                 if from_type.decl.is_import() {
-                    return Err(ArtifactError::RelocateImport(link.from.to_string()));
+                    return Err(ArtifactError::RelocateImport(symbol_name(
+                        &self.strings,
+                        link_from,
+                    )));
                 }
-                let link = (link_from, link_to, link.at, reloc);
+                let link = (link_from, link_to, at, reloc, addend);
+                self.check_link(&link)?;
                 self.links.push(link);
             }
             (None, _) => {
-                return Err(ArtifactError::Undeclared(link.from.to_string()));
+                return Err(ArtifactError::Undeclared(symbol_name(
+                    &self.strings,
+                    link_from,
+                )));
             }
             (_, None) => {
-                return Err(ArtifactError::Undeclared(link.to.to_string()));
+                return Err(ArtifactError::Undeclared(symbol_name(
+                    &self.strings,
+                    link_to,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Register the function `symbol`, which must be declared (and eventually defined) in this
+    /// artifact, as a static constructor that runs when the object is loaded, before `main`.
+    ///
+    /// On ELF and COFF, constructors with a `priority` run in increasing order of priority, before those
+    /// without one; priorities up to 100 are reserved for the implementation. Mach-o has no
+    /// priorities, so constructors run in the order they were registered.
+    pub fn constructor<S: SymbolRef>(
+        &mut self,
+        symbol: S,
+        priority: Option<u16>,
+    ) -> Result<(), ArtifactError> {
+        let id = symbol.intern(self.id.0, &mut self.strings)?;
+        self.add_initializer(id, InitKind::Constructor, priority)
+    }
+    /// Register the function `symbol`, which must be declared (and eventually defined) in this
+    /// artifact, as a static destructor that runs when `main` returns or `exit` is called.
+    ///
+    /// On ELF, destructors without a `priority` run first, followed by the rest in decreasing
+    /// order of priority. Mach-o has no priorities, so they are ignored.
+    pub fn destructor<S: SymbolRef>(
+        &mut self,
+        symbol: S,
+        priority: Option<u16>,
+    ) -> Result<(), ArtifactError> {
+        let id = symbol.intern(self.id.0, &mut self.strings)?;
+        self.add_initializer(id, InitKind::Destructor, priority)
+    }
+    fn add_initializer(
+        &mut self,
+        id: StringID,
+        kind: InitKind,
+        priority: Option<u16>,
+    ) -> Result<(), ArtifactError> {
+        match self.declarations.get(&id) {
+            Some(InternalDecl {
+                decl: Decl::Defined(DefinedDecl::Function(_)),
+                ..
+            }) => {
+                self.initializers.push((id, kind, priority));
+                Ok(())
+            }
+            Some(_) => Err(ArtifactError::NonFunctionInitializer(symbol_name(
+                &self.strings,
+                id,
+            ))),
+            None => Err(ArtifactError::Undeclared(symbol_name(&self.strings, id))),
+        }
+    }
+
+    /// Give the function or function import `symbol`, which must be declared in this artifact, a
+    /// `signature`, replacing any previous one.
+    ///
+    /// Only WebAssembly needs signatures, where every function and function import must have one.
+    /// The other binary formats ignore them.
+    pub fn signature<S: SymbolRef>(
+        &mut self,
+        symbol: S,
+        signature: Signature,
+    ) -> Result<(), ArtifactError> {
+        let id = symbol.intern(self.id.0, &mut self.strings)?;
+        match self.declarations.get(&id).map(|int| &int.decl) {
+            Some(Decl::Defined(DefinedDecl::Function(_)))
+            | Some(Decl::Import(ImportKind::Function)) => {
+                self.signatures.insert(id, signature);
+                Ok(())
             }
+            Some(_) => Err(ArtifactError::NonFunctionSignature(symbol_name(
+                &self.strings,
+                id,
+            ))),
+            None => Err(ArtifactError::Undeclared(symbol_name(&self.strings, id))),
+        }
+    }
+
+    /// Place the definition of `symbol`, which must be declared in this artifact, in the COMDAT
+    /// group `group`, replacing any previous one. The linker keeps a single copy of every group with
+    /// the same name, discarding the others along with all of their members.
+    pub fn comdat<S: SymbolRef, G: AsRef<str>>(
+        &mut self,
+        symbol: S,
+        group: G,
+    ) -> Result<(), ArtifactError> {
+        let id = symbol.intern(self.id.0, &mut self.strings)?;
+        match self.declarations.get(&id).map(|int| &int.decl) {
+            Some(Decl::Defined(_)) => {
+                let group = self.strings.get_or_intern(group.as_ref());
+                self.comdats.insert(id, group);
+                Ok(())
+            }
+            Some(Decl::Import(_)) => {
+                Err(ArtifactError::ImportComdat(symbol_name(&self.strings, id)))
+            }
+            None => Err(ArtifactError::Undeclared(symbol_name(&self.strings, id))),
+        }
+    }
+
+    /// Merge `other`, e.g. filled on another thread, into this artifact.
+    ///
+    /// Each declaration of `other` is declared in this artifact, with the same rules as
+    /// [absorb](enum.Decl.html#method.absorb), and its definitions, links, constructors,
+    /// destructors, signatures and COMDAT groups are moved over, so nothing is copied or defined twice. Its
+    /// definitions are laid out after this artifact's own.
+    ///
+    /// **NB**: If a declaration is incompatible, or a symbol is defined in both, this will return
+    /// an error and leave this artifact as it was.
+    pub fn merge(&mut self, other: Artifact) -> Result<(), ArtifactError> {
+        if other.target != self.target {
+            return Err(ArtifactError::MismatchedTargets {
+                expected: self.target.clone(),
+                found: other.target,
+            });
+        }
+        for (id, other_decl) in &other.declarations {
+            let name = symbol_name(&other.strings, *id);
+            let existing = self
+                .strings
+                .get(&name)
+                .and_then(|id| self.declarations.get(&id));
+            if let Some(existing) = existing {
+                if existing.defined && other_decl.defined {
+                    return Err(ArtifactError::DuplicateDefinition(name));
+                }
+                let mut decl = existing.decl;
+                decl.absorb(other_decl.decl)?;
+            }
+        }
+
+        // the id in this artifact of each symbol of `other`
+        let mut ids = HashMap::new();
+        for (id, other_decl) in other.declarations {
+            let name = symbol_name(&other.strings, id);
+            ids.insert(id, self.declare(name, other_decl.decl)?.1);
+        }
+        for (id, def) in other.definitions {
+            self.define_with_symbols(SymbolId(self.id.0, ids[&id]), def.data, def.symbols)?;
+        }
+        self.links.extend(
+            other
+                .links
+                .into_iter()
+                .map(|(from, to, at, reloc, addend)| (ids[&from], ids[&to], at, reloc, addend)),
+        );
+        self.initializers.extend(
+            other
+                .initializers
+                .into_iter()
+                .map(|(id, kind, priority)| (ids[&id], kind, priority)),
+        );
+        self.signatures.extend(
+            other
+                .signatures
+                .into_iter()
+                .map(|(id, signature)| (ids[&id], signature)),
+        );
+        for (id, group) in other.comdats {
+            let group = self
+                .strings
+                .get_or_intern(symbol_name(&other.strings, group));
+            self.comdats.insert(ids[&id], group);
         }
         Ok(())
     }
@@ -568,7 +1284,7 @@ impl Artifact {
         for (&name, _) in self
             .declarations
             .iter()
-            .filter(|&(_, &int)| !int.defined && !int.decl.is_import())
+            .filter(|&(_, int)| !int.defined && !int.decl.is_import())
         {
             syms.push(String::from(
                 self.strings.resolve(name).expect("declaration has a name"),
@@ -577,28 +1293,107 @@ impl Artifact {
         syms
     }
 
+    /// Check that each link whose `from` is defined relocates bytes inside its contents
+    fn check_link(&self, link: &Relocation) -> Result<(), ArtifactError> {
+        let &(from, to, at, reloc, _) = link;
+        let def = match self.definitions.get(&from) {
+            Some(def) => def,
+            None => return Ok(()),
+        };
+        // zero-init data has no contents in the file to relocate
+        match at.checked_add(reloc.min_size()) {
+            Some(end) if end <= def.data.file_size() as u64 => Ok(()),
+            _ => Err(ArtifactError::LinkOutOfBounds {
+                from: symbol_name(&self.strings, from),
+                to: symbol_name(&self.strings, to),
+                at,
+            }),
+        }
+    }
+
+    /// Check that every link relocates bytes inside the contents of the definition it's in, for the
+    /// links added before their `from` was defined
+    pub(crate) fn check_links(&self) -> Result<(), ArtifactError> {
+        self.links.iter().try_for_each(|link| self.check_link(link))
+    }
+
     /// Emit a blob of bytes representing the object file in the format specified in the target the
     /// `Artifact` was constructed with.
+    ///
+    /// If this artifact is a library, the object file is wrapped in a static library archive.
     pub fn emit(&self) -> Result<Vec<u8>, ArtifactError> {
         self.emit_as(self.target.binary_format)
     }
 
     /// Emit a blob of bytes representing an object file in the given format.
+    ///
+    /// If this artifact is a library, the object file is wrapped in a static library archive.
     pub fn emit_as(&self, format: BinaryFormat) -> Result<Vec<u8>, ArtifactError> {
+        if self.is_library {
+            archive::to_bytes(&[self], format)
+        } else {
+            self.emit_object(format)
+        }
+    }
+
+    /// Emit a static library archive in the given format, containing each of `artifacts` as an
+    /// object file, and a symbol index of their global definitions.
+    ///
+    /// ELF and COFF objects are bundled into a System V/GNU archive, and Mach-O objects into a
+    /// BSD archive. Whether the artifacts are libraries themselves is ignored.
+    pub fn emit_archive(
+        artifacts: &[&Artifact],
+        format: BinaryFormat,
+    ) -> Result<Vec<u8>, ArtifactError> {
+        archive::to_bytes(artifacts, format)
+    }
+
+    /// Link `artifacts` into a static x86-64 ELF executable, starting at the global function
+    /// `entry`, which runs without a dynamic loader or a C runtime.
+    ///
+    /// Every import of an artifact must be defined by another one. Debug sections, thread-local
+    /// data, constructors and destructors are not supported.
+    pub fn emit_executable(artifacts: &[&Artifact], entry: &str) -> Result<Vec<u8>, ArtifactError> {
+        linker::executable(artifacts, entry)
+    }
+
+    /// Link this artifact into a position-independent x86-64 ELF shared library, which depends on
+    /// the `needed` libraries.
+    ///
+    /// Global and weak definitions which aren't hidden are exported, and imports are resolved by
+    /// the dynamic loader when the library is loaded: functions through a procedure linkage table,
+    /// and data through the global offset table. Debug sections, thread-local data, constructors
+    /// and destructors are not supported.
+    pub fn emit_shared_library(&self, needed: &[&str]) -> Result<Vec<u8>, ArtifactError> {
+        linker::shared_library(&[self], needed)
+    }
+
+    /// Emit the object file itself, regardless of whether this artifact is a library.
+    pub(crate) fn emit_object(&self, format: BinaryFormat) -> Result<Vec<u8>, ArtifactError> {
+        let mut buffer = Cursor::new(Vec::new());
+        self.write_object(&mut buffer, format)?;
+        Ok(buffer.into_inner())
+    }
+
+    /// Write the object file itself to `sink`, regardless of whether this artifact is a library.
+    fn write_object<W: Write + Seek>(
+        &self,
+        mut sink: W,
+        format: BinaryFormat,
+    ) -> Result<(), ArtifactError> {
         let undef = self.undefined_symbols();
         if undef.is_empty() {
-            let bytes = match format {
-                BinaryFormat::Elf => elf::to_bytes(self)?,
-                BinaryFormat::Macho => mach::to_bytes(self)?,
-                _ => {
-                    return Err(ArtifactError::UnsupportedBinaryFormat(
-                        self.target.binary_format.to_owned(),
-                    ))
-                }
-            };
-            Ok(bytes)
+            self.check_links()?;
+            match format {
+                BinaryFormat::Elf => elf::write_to(self, sink),
+                BinaryFormat::Macho => mach::write_to(self, sink),
+                BinaryFormat::Coff => coff::write_to(self, sink),
+                // every section is prefixed with its size, so the module is built in memory
+                BinaryFormat::Wasm => Ok(sink.write_all(&wasm::to_bytes(self)?)?),
+                _ => Err(ArtifactError::UnsupportedBinaryFormat(format)),
+            }
         } else {
-            return Err(ArtifactError::UndefinedSymbols(undef));
+            Err(ArtifactError::UndefinedSymbols(undef))
         }
     }
 
@@ -608,9 +1403,62 @@ impl Artifact {
         self.write_as(sink, self.target.binary_format)
     }
 
-    /// Emit and write to disk a blob of bytes representing an object file in the given format.
-    pub fn write_as(&self, mut sink: File, format: BinaryFormat) -> Result<(), ArtifactError> {
-        let bytes = self.emit_as(format)?;
+    /// Emit the object file in the format specified in the target the `Artifact` was constructed
+    /// with straight into `sink`, starting at its current position, e.g. an already open file or
+    /// an in-memory buffer, without building a copy of the whole file in memory first.
+    ///
+    /// If this artifact is a library, the archive is built in memory and then written to `sink`, as
+    /// the header of its member comes before the object file and holds its size.
+    pub fn write_to<W: Write + Seek>(&self, sink: W) -> Result<(), ArtifactError> {
+        self.write_as(sink, self.target.binary_format)
+    }
+
+    /// Emit an object file in the given format straight into `sink`, see `write_to`.
+    pub fn write_as<W: Write + Seek>(
+        &self,
+        mut sink: W,
+        format: BinaryFormat,
+    ) -> Result<(), ArtifactError> {
+        if self.is_library {
+            sink.write_all(&archive::to_bytes(&[self], format)?)?;
+            Ok(())
+        } else {
+            self.write_object(sink, format)
+        }
+    }
+
+    /// Emit and write to disk a static library archive containing each of `artifacts`, see
+    /// `emit_archive`.
+    pub fn write_archive(
+        artifacts: &[&Artifact],
+        mut sink: File,
+        format: BinaryFormat,
+    ) -> Result<(), ArtifactError> {
+        let bytes = Artifact::emit_archive(artifacts, format)?;
+        sink.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Link `artifacts` into a static x86-64 ELF executable, starting at the global function
+    /// `entry`, and write it to disk.
+    pub fn write_executable(
+        artifacts: &[&Artifact],
+        mut sink: File,
+        entry: &str,
+    ) -> Result<(), ArtifactError> {
+        let bytes = Artifact::emit_executable(artifacts, entry)?;
+        sink.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Link this artifact into a shared library depending on the `needed` libraries, see
+    /// `emit_shared_library`, and write it to disk.
+    pub fn write_shared_library(
+        &self,
+        mut sink: File,
+        needed: &[&str],
+    ) -> Result<(), ArtifactError> {
+        let bytes = self.emit_shared_library(needed)?;
         sink.write_all(&bytes)?;
         Ok(())
     }
@@ -16,6 +16,8 @@ pub enum ImportKind {
     Function,
     /// An imported piece of data
     Data,
+    /// An imported thread-local variable
+    Tls,
 }
 
 impl ImportKind {
@@ -147,17 +149,14 @@ macro_rules! datatype_methods {
 
 macro_rules! align_methods {
     () => {
-        /// Build alignment. Size is in bytes. If None, a default is chosen
-        /// in the backend.
+        /// Build alignment. Size is in bytes, and must be a power of two, or declaring it is an
+        /// error. If None, a default is chosen in the backend.
         pub fn with_align(mut self, align: Option<u64>) -> Self {
             self.set_align(align);
             self
         }
         /// Set alignment
         pub fn set_align(&mut self, align: Option<u64>) {
-            if let Some(align) = align {
-                debug_assert_eq!(align.checked_next_power_of_two(), Some(align));
-            }
             self.align = align;
         }
         /// Get alignment
@@ -181,26 +180,17 @@ pub enum DefinedDecl {
 impl DefinedDecl {
     /// Accessor to determine whether variant is Function
     pub fn is_function(&self) -> bool {
-        match self {
-            DefinedDecl::Function { .. } => true,
-            _ => false,
-        }
+        matches!(self, DefinedDecl::Function { .. })
     }
 
     /// Accessor to determine whether variant is Data
     pub fn is_data(&self) -> bool {
-        match self {
-            DefinedDecl::Data { .. } => true,
-            _ => false,
-        }
+        matches!(self, DefinedDecl::Data { .. })
     }
 
     /// Accessor to determine whether variant is Section
     pub fn is_section(&self) -> bool {
-        match self {
-            DefinedDecl::Section(_) => true,
-            _ => false,
-        }
+        matches!(self, DefinedDecl::Section(_))
     }
 
     /// Accessor to determine whether scope is global
@@ -221,6 +211,14 @@ impl DefinedDecl {
         }
     }
 
+    /// Accessor to determine whether this is thread-local data
+    pub fn is_tls(&self) -> bool {
+        match self {
+            DefinedDecl::Data(a) => a.is_tls(),
+            DefinedDecl::Function(_) | DefinedDecl::Section(_) => false,
+        }
+    }
+
     /// Accessor to determine the minimal alignment
     pub fn get_align(&self) -> Option<u64> {
         match self {
@@ -240,6 +238,10 @@ impl Decl {
     pub fn data_import() -> DataImportDecl {
         DataImportDecl::default()
     }
+    /// An import of a thread-local variable defined in another object
+    pub fn tls_import() -> TlsImportDecl {
+        TlsImportDecl::default()
+    }
     /// A function defined in this artifact
     pub fn function() -> FunctionDecl {
         FunctionDecl::default()
@@ -271,12 +273,11 @@ impl Decl {
     // ref https://github.com/m4b/faerie/issues/24
     // ref https://github.com/m4b/faerie/issues/18
     pub fn absorb(&mut self, other: Self) -> Result<(), ArtifactError> {
-        // FIXME: i can't think of a way offhand to not clone here, without unusual contortions
-        match self.clone() {
+        match *self {
             Decl::Import(ImportKind::Data) => {
                 match other {
-                    // data imports can be upgraded to any kind of data declaration
-                    Decl::Defined(DefinedDecl::Data { .. }) => {
+                    // data imports can be upgraded to any kind of non thread-local data declaration
+                    Decl::Defined(DefinedDecl::Data(d)) if !d.is_tls() => {
                         *self = other;
                         Ok(())
                     }
@@ -284,8 +285,21 @@ impl Decl {
                     _ => Err(ArtifactError::IncompatibleDeclaration {
                         old: *self,
                         new: other,
+                    }),
+                }
+            }
+            Decl::Import(ImportKind::Tls) => {
+                match other {
+                    // thread-local imports can be upgraded to any kind of thread-local data declaration
+                    Decl::Defined(DefinedDecl::Data(d)) if d.is_tls() => {
+                        *self = other;
+                        Ok(())
                     }
-                    .into()),
+                    Decl::Import(ImportKind::Tls) => Ok(()),
+                    _ => Err(ArtifactError::IncompatibleDeclaration {
+                        old: *self,
+                        new: other,
+                    }),
                 }
             }
             Decl::Import(ImportKind::Function) => {
@@ -299,14 +313,14 @@ impl Decl {
                     _ => Err(ArtifactError::IncompatibleDeclaration {
                         old: *self,
                         new: other,
-                    }
-                    .into()),
+                    }),
                 }
             }
-            // a previous data declaration can only be re-declared a data import, or it must match exactly the
-            // next declaration
-            decl @ Decl::Defined(DefinedDecl::Data { .. }) => match other {
-                Decl::Import(ImportKind::Data) => Ok(()),
+            // a previous data declaration can only be re-declared a data import (or a thread-local import,
+            // if it is thread-local), or it must match exactly the next declaration
+            decl @ Decl::Defined(DefinedDecl::Data(d)) => match other {
+                Decl::Import(ImportKind::Data) if !d.is_tls() => Ok(()),
+                Decl::Import(ImportKind::Tls) if d.is_tls() => Ok(()),
                 other => {
                     if decl == other {
                         Ok(())
@@ -314,8 +328,7 @@ impl Decl {
                         Err(ArtifactError::IncompatibleDeclaration {
                             old: *self,
                             new: other,
-                        }
-                        .into())
+                        })
                     }
                 }
             },
@@ -330,8 +343,7 @@ impl Decl {
                         Err(ArtifactError::IncompatibleDeclaration {
                             old: *self,
                             new: other,
-                        }
-                        .into())
+                        })
                     }
                 }
             },
@@ -342,57 +354,55 @@ impl Decl {
                     Err(ArtifactError::IncompatibleDeclaration {
                         old: *self,
                         new: other,
-                    }
-                    .into())
+                    })
                 }
             }
         }
     }
     /// Is this an import (function or data) from a shared library?
     pub fn is_import(&self) -> bool {
-        match *self {
-            Decl::Import(_) => true,
-            _ => false,
-        }
+        matches!(*self, Decl::Import(_))
     }
     /// Is this a section?
     pub fn is_section(&self) -> bool {
-        match *self {
-            Decl::Defined(DefinedDecl::Section { .. }) => true,
-            _ => false,
+        matches!(*self, Decl::Defined(DefinedDecl::Section { .. }))
+    }
+    /// Is this thread-local data, either defined or imported?
+    pub fn is_tls(&self) -> bool {
+        match self {
+            Decl::Import(kind) => *kind == ImportKind::Tls,
+            Decl::Defined(decl) => decl.is_tls(),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 /// Builder for function import declarations
 pub struct FunctionImportDecl {}
 
-impl Default for FunctionImportDecl {
-    fn default() -> Self {
-        FunctionImportDecl {}
-    }
-}
-
-impl Into<Decl> for FunctionImportDecl {
-    fn into(self) -> Decl {
+impl From<FunctionImportDecl> for Decl {
+    fn from(_: FunctionImportDecl) -> Self {
         Decl::Import(ImportKind::Function)
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 /// Builder for data import declarations
 pub struct DataImportDecl {}
 
-impl Default for DataImportDecl {
-    fn default() -> Self {
-        DataImportDecl {}
+impl From<DataImportDecl> for Decl {
+    fn from(_: DataImportDecl) -> Self {
+        Decl::Import(ImportKind::Data)
     }
 }
 
-impl Into<Decl> for DataImportDecl {
-    fn into(self) -> Decl {
-        Decl::Import(ImportKind::Data)
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// Builder for thread-local import declarations
+pub struct TlsImportDecl {}
+
+impl From<TlsImportDecl> for Decl {
+    fn from(_: TlsImportDecl) -> Self {
+        Decl::Import(ImportKind::Tls)
     }
 }
 
@@ -420,9 +430,9 @@ impl FunctionDecl {
     align_methods!();
 }
 
-impl Into<Decl> for FunctionDecl {
-    fn into(self) -> Decl {
-        Decl::Defined(DefinedDecl::Function(self))
+impl From<FunctionDecl> for Decl {
+    fn from(val: FunctionDecl) -> Self {
+        Decl::Defined(DefinedDecl::Function(val))
     }
 }
 
@@ -432,6 +442,7 @@ pub struct DataDecl {
     scope: Scope,
     visibility: Visibility,
     writable: bool,
+    tls: bool,
     datatype: DataType,
     align: Option<u64>,
 }
@@ -442,6 +453,7 @@ impl Default for DataDecl {
             scope: Scope::Local,
             visibility: Visibility::Default,
             writable: false,
+            tls: false,
             datatype: DataType::Bytes,
             align: None,
         }
@@ -475,11 +487,28 @@ impl DataDecl {
     pub fn is_writable(&self) -> bool {
         self.writable
     }
+    /// Builder for thread locality
+    pub fn with_tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+    /// Make every thread have its own copy of this data
+    pub fn tls(self) -> Self {
+        self.with_tls(true)
+    }
+    /// Setter for thread locality
+    pub fn set_tls(&mut self, tls: bool) {
+        self.tls = tls;
+    }
+    /// Accessor for thread locality
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
 }
 
-impl Into<Decl> for DataDecl {
-    fn into(self) -> Decl {
-        Decl::Defined(DefinedDecl::Data(self))
+impl From<DataDecl> for Decl {
+    fn from(val: DataDecl) -> Self {
+        Decl::Defined(DefinedDecl::Data(val))
     }
 }
 
@@ -496,12 +525,24 @@ pub enum SectionKind {
     Text,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+/// The unwind tables a section holds, which the binary formats give sections of their own
+pub enum UnwindTable {
+    /// Call frame information, which is loaded with the code for the unwinder to read, in
+    /// `.eh_frame` or `__TEXT,__eh_frame`
+    EhFrame,
+    /// Mach-o compact unwind entries, which the linker turns into `__unwind_info`, in
+    /// `__LD,__compact_unwind`
+    CompactUnwind,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 /// Builder for a section declaration
 pub struct SectionDecl {
     kind: SectionKind,
     datatype: DataType,
     align: Option<u64>,
+    unwind: Option<UnwindTable>,
 }
 
 impl SectionDecl {
@@ -514,9 +555,25 @@ impl SectionDecl {
             kind,
             datatype: DataType::Bytes,
             align: None,
+            unwind: None,
         }
     }
 
+    /// Build the unwind tables this section holds, as
+    /// [`UnwindBuilder`](../unwind/struct.UnwindBuilder.html) declares them
+    pub fn with_unwind(mut self, unwind: Option<UnwindTable>) -> Self {
+        self.unwind = unwind;
+        self
+    }
+    /// Set the unwind tables this section holds
+    pub fn set_unwind(&mut self, unwind: Option<UnwindTable>) {
+        self.unwind = unwind;
+    }
+    /// Get the unwind tables this section holds, if any
+    pub fn get_unwind(&self) -> Option<UnwindTable> {
+        self.unwind
+    }
+
     /// Sections are never global, but we have an accessor
     /// for symmetry with other section declarations
     pub fn is_global(&self) -> bool {
@@ -537,8 +594,37 @@ impl SectionDecl {
     }
 }
 
-impl Into<Decl> for SectionDecl {
-    fn into(self) -> Decl {
-        Decl::Defined(DefinedDecl::Section(self))
+impl From<SectionDecl> for Decl {
+    fn from(val: SectionDecl) -> Self {
+        Decl::Defined(DefinedDecl::Section(val))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Type of a value passed to or returned from a function
+pub enum ValueType {
+    /// 32 bit integer
+    I32,
+    /// 64 bit integer
+    I64,
+    /// 32 bit float
+    F32,
+    /// 64 bit float
+    F64,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// The types of the parameters and results of a function, which WebAssembly needs to declare it
+pub struct Signature {
+    /// Types of the parameters, in order
+    pub params: Vec<ValueType>,
+    /// Types of the results, in order
+    pub results: Vec<ValueType>,
+}
+
+impl Signature {
+    /// Create a `Signature` taking `params` and returning `results`
+    pub fn new(params: Vec<ValueType>, results: Vec<ValueType>) -> Self {
+        Signature { params, results }
     }
 }
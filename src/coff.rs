@@ -0,0 +1,784 @@
+//! The COFF backend for transforming an artifact to a valid, Windows COFF object file.
+
+use crate::{
+    artifact::{
+        self, Artifact, ArtifactError, Data, Decl, DefinedDecl, ImportKind, InitKind, Initializer,
+        LinkAndDecl, Reloc,
+    },
+    Scope, SectionKind,
+};
+
+use indexmap::IndexMap;
+use scroll::{IOwrite, Pwrite, LE};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{BufWriter, Cursor, Seek, Write};
+use target_lexicon::{Architecture, BinaryFormat};
+
+use goblin::pe::header::{CoffHeader, SIZEOF_COFF_HEADER};
+use goblin::pe::relocation;
+use goblin::pe::section_table::{SectionTable, SIZEOF_SECTION_TABLE};
+use goblin::pe::symbol::{self, AuxSectionDefinition, COFF_SYMBOL_SIZE};
+
+// an index into the symbol table, counting auxiliary records
+type SymbolIndex = usize;
+// an index into the section table, zero based
+type SectionIndex = usize;
+type Relocation = goblin::pe::relocation::Relocation;
+type Symbol = goblin::pe::symbol::Symbol;
+
+/// The prefix the Windows toolchain uses for the import address table slot of an import
+const IMPORT_PREFIX: &str = "__imp_";
+/// Short names are stored inline in symbols and section headers, longer ones go into the strtab
+const SHORT_NAME_LEN: usize = 8;
+/// The first section index which can not be encoded in a regular COFF object
+const MAX_SECTIONS: usize = 0xff00;
+/// The maximum number of relocations encodable in a section header before overflowing
+const MAX_RELOCATIONS: usize = 0xffff;
+
+struct MachineTag(u16);
+
+impl TryFrom<Architecture> for MachineTag {
+    type Error = ArtifactError;
+    fn try_from(architecture: Architecture) -> Result<MachineTag, ArtifactError> {
+        use goblin::pe::header::*;
+        use target_lexicon::Architecture::*;
+        Ok(MachineTag(match architecture {
+            X86_64 => COFF_MACHINE_X86_64,
+            X86_32(_) => COFF_MACHINE_X86,
+            Aarch64(_) => COFF_MACHINE_ARM64,
+            Arm(_) => COFF_MACHINE_ARMNT,
+            Unknown => COFF_MACHINE_UNKNOWN,
+            architecture => {
+                return Err(ArtifactError::UnsupportedArchitecture {
+                    architecture,
+                    format: BinaryFormat::Coff,
+                })
+            }
+        }))
+    }
+}
+
+/// Convert a byte alignment, a power of two as `declare` checks, into the `IMAGE_SCN_ALIGN_*`
+/// characteristic
+fn align_characteristics(align: u64) -> u32 {
+    // IMAGE_SCN_ALIGN_8192BYTES is the largest alignment COFF can express
+    let align_exp = std::cmp::min(align.trailing_zeros(), 13);
+    (align_exp + 1) << 20
+}
+
+/// The number of bytes patched in place by a relocation of type `typ`
+fn addend_width(typ: u16) -> usize {
+    match typ {
+        relocation::IMAGE_REL_AMD64_ABSOLUTE => 0,
+        relocation::IMAGE_REL_AMD64_ADDR64 => 8,
+        relocation::IMAGE_REL_AMD64_SECTION => 2,
+        _ => 4,
+    }
+}
+
+/// The section the CRT runs the pointers to the constructors or destructors of `kind` in, ordered by
+/// the linker by the suffix after the `$`; these follow the names clang uses for priorities
+fn initializer_section_name(kind: InitKind, priority: Option<u16>) -> String {
+    let (prefix, unprioritized) = match kind {
+        InitKind::Constructor => (".CRT$XC", 'U'),
+        InitKind::Destructor => (".CRT$XT", 'X'),
+    };
+    match priority {
+        None => format!("{}{}", prefix, unprioritized),
+        Some(priority) => {
+            let group = match priority {
+                0..=199 => 'A',
+                200..=399 => 'C',
+                400 => 'L',
+                _ => 'T',
+            };
+            format!("{}{}{:05}", prefix, group, priority)
+        }
+    }
+}
+
+/// A COFF string table; offsets include the leading 4 byte size field
+#[derive(Debug)]
+struct StrTable {
+    bytes: Vec<u8>,
+    offsets: HashMap<String, u32>,
+}
+
+impl StrTable {
+    pub fn new() -> Self {
+        StrTable {
+            bytes: vec![0; 4],
+            offsets: HashMap::new(),
+        }
+    }
+    /// Insert `name`, returning its offset in the string table
+    pub fn insert(&mut self, name: &str) -> u32 {
+        if let Some(offset) = self.offsets.get(name) {
+            return *offset;
+        }
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(name.as_bytes());
+        self.bytes.push(0);
+        self.offsets.insert(name.to_owned(), offset);
+        offset
+    }
+    /// Finalize the string table by writing its size into the header
+    pub fn finish(mut self) -> Vec<u8> {
+        let size = self.bytes.len() as u32;
+        self.bytes.pwrite_with(size, 0, LE).unwrap();
+        self.bytes
+    }
+}
+
+/// The kind of symbol this is; used in [SymbolBuilder](struct.SymbolBuilder.html)
+enum SymbolType<'a> {
+    /// From a definition
+    Decl(&'a DefinedDecl),
+    /// An import
+    Import,
+    /// A section reference
+    Section,
+    /// A custom symbol pointing into a section
+    Label,
+}
+
+/// A builder for creating a COFF symbol
+struct SymbolBuilder<'a> {
+    name: &'a str,
+    typ: SymbolType<'a>,
+    section: Option<SectionIndex>,
+    value: u32,
+}
+
+impl<'a> SymbolBuilder<'a> {
+    /// Create a new symbol named `name` with `typ`
+    pub fn new(name: &'a str, typ: SymbolType<'a>) -> Self {
+        SymbolBuilder {
+            name,
+            typ,
+            section: None,
+            value: 0,
+        }
+    }
+    /// Set the section this symbol is defined in
+    pub fn section(mut self, section: SectionIndex) -> Self {
+        self.section = Some(section);
+        self
+    }
+    /// Set the value field, usually an offset from the beginning of the section
+    pub fn value(mut self, value: u64) -> Self {
+        self.value = value as u32;
+        self
+    }
+    /// Finalize and create the symbol, storing a long name in `strtab`
+    pub fn create(self, strtab: &mut StrTable) -> Symbol {
+        use goblin::pe::symbol::{
+            IMAGE_SYM_CLASS_EXTERNAL, IMAGE_SYM_CLASS_STATIC, IMAGE_SYM_DTYPE_FUNCTION,
+            IMAGE_SYM_DTYPE_SHIFT, IMAGE_SYM_UNDEFINED,
+        };
+        let mut symbol = Symbol {
+            value: self.value,
+            // section numbers are one based
+            section_number: self
+                .section
+                .map(|idx| idx as i16 + 1)
+                .unwrap_or(IMAGE_SYM_UNDEFINED),
+            ..Default::default()
+        };
+        if self.name.len() > SHORT_NAME_LEN {
+            symbol.set_name_offset(strtab.insert(self.name));
+        } else {
+            symbol.name[..self.name.len()].copy_from_slice(self.name.as_bytes());
+        }
+        symbol.storage_class = match self.typ {
+            SymbolType::Decl(decl) => {
+                if let DefinedDecl::Function(_) = decl {
+                    symbol.typ = IMAGE_SYM_DTYPE_FUNCTION << IMAGE_SYM_DTYPE_SHIFT;
+                }
+                match decl {
+                    DefinedDecl::Function(d) if d.get_scope() != Scope::Local => {
+                        IMAGE_SYM_CLASS_EXTERNAL
+                    }
+                    DefinedDecl::Data(d) if d.get_scope() != Scope::Local => {
+                        IMAGE_SYM_CLASS_EXTERNAL
+                    }
+                    _ => IMAGE_SYM_CLASS_STATIC,
+                }
+            }
+            SymbolType::Import => IMAGE_SYM_CLASS_EXTERNAL,
+            SymbolType::Section | SymbolType::Label => IMAGE_SYM_CLASS_STATIC,
+        };
+        symbol
+    }
+}
+
+/// The kind of section this can be; used in [SectionBuilder](struct.SectionBuilder.html)
+enum SectionType {
+    Code,
+    Data,
+    ReadOnlyData,
+    ZeroInit,
+    Debug,
+}
+
+/// A builder for creating a COFF section header
+struct SectionBuilder {
+    typ: SectionType,
+    size: u64,
+    align: Option<u64>,
+    comdat: bool,
+}
+
+impl SectionBuilder {
+    /// Create a new section with `size`
+    pub fn new(typ: SectionType, size: u64) -> Self {
+        SectionBuilder {
+            typ,
+            size,
+            align: None,
+            comdat: false,
+        }
+    }
+    /// Specify section alignment
+    pub fn align(mut self, align: Option<u64>) -> Self {
+        self.align = align;
+        self
+    }
+    /// Make this section a COMDAT section, so the linker keeps only one copy of it
+    pub fn comdat(mut self, comdat: bool) -> Self {
+        self.comdat = comdat;
+        self
+    }
+    /// Finalize and create the actual section header, storing a long name in `strtab`
+    pub fn create(self, name: &str, strtab: &mut StrTable) -> SectionTable {
+        use goblin::pe::section_table::*;
+        let mut section = SectionTable {
+            size_of_raw_data: self.size as u32,
+            ..Default::default()
+        };
+        if name.len() > SHORT_NAME_LEN {
+            section
+                .set_name_offset(strtab.insert(name) as usize)
+                .expect("string table offset fits in a section name");
+        } else {
+            section.name[..name.len()].copy_from_slice(name.as_bytes());
+        }
+        let (characteristics, default_align) = match self.typ {
+            SectionType::Code => (
+                IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE | IMAGE_SCN_MEM_READ,
+                0x10,
+            ),
+            SectionType::Data => (
+                IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+                0x8,
+            ),
+            SectionType::ReadOnlyData => (IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ, 1),
+            SectionType::ZeroInit => (
+                IMAGE_SCN_CNT_UNINITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_WRITE,
+                0x8,
+            ),
+            SectionType::Debug => (
+                IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ | IMAGE_SCN_MEM_DISCARDABLE,
+                1,
+            ),
+        };
+        section.characteristics =
+            characteristics | align_characteristics(self.align.unwrap_or(default_align));
+        if self.comdat {
+            section.characteristics |= IMAGE_SCN_LNK_COMDAT;
+        }
+        section
+    }
+}
+
+/// A builder for constructing a COFF relocation
+struct RelocationBuilder {
+    symbol: SymbolIndex,
+    offset: u64,
+    typ: u16,
+}
+
+impl RelocationBuilder {
+    /// Create a new relocation with `typ`
+    pub fn new(typ: u16) -> Self {
+        RelocationBuilder {
+            symbol: 0,
+            offset: 0,
+            typ,
+        }
+    }
+    /// Set the section relative offset this relocation refers to
+    pub fn offset(mut self, offset: u64) -> Self {
+        self.offset = offset;
+        self
+    }
+    /// Set the symbol index this relocation affects
+    pub fn sym(mut self, symbol: SymbolIndex) -> Self {
+        self.symbol = symbol;
+        self
+    }
+    /// Finalize and actually create this relocation
+    pub fn create(self) -> Relocation {
+        Relocation {
+            virtual_address: self.offset as u32,
+            symbol_table_index: self.symbol as u32,
+            typ: self.typ,
+        }
+    }
+}
+
+/// A symbol table entry and its auxiliary records
+struct SymbolEntry {
+    symbol: Symbol,
+    aux: Vec<[u8; COFF_SYMBOL_SIZE]>,
+}
+
+/// A section, its contents and its relocations
+struct SectionInfo<'a> {
+    header: SectionTable,
+    data: Option<Cow<'a, [u8]>>,
+    relocations: Vec<Relocation>,
+    /// The symbol table index of the section symbol
+    symbol: SymbolIndex,
+    /// The position of the section symbol in `Coff::symbols`, whose aux record mirrors the header
+    entry: usize,
+    comdat: bool,
+}
+
+/// An intermediate COFF object file container
+struct Coff<'a> {
+    machine: MachineTag,
+    architecture: Architecture,
+    sections: Vec<SectionInfo<'a>>,
+    symbols: Vec<SymbolEntry>,
+    nsymbols: usize,
+    /// Symbol table indexes of definitions, imports and section symbols by name
+    indexes: HashMap<&'a str, SymbolIndex>,
+    /// Section indexes of definitions by name
+    section_indexes: HashMap<&'a str, SectionIndex>,
+    /// The COMDAT groups, and the section indexes of their members
+    groups: IndexMap<&'a str, Vec<SectionIndex>>,
+    strtab: StrTable,
+}
+
+impl<'a> Coff<'a> {
+    pub fn new(artifact: &'a Artifact) -> Result<Self, ArtifactError> {
+        let mut coff = Coff {
+            machine: MachineTag::try_from(artifact.target.architecture)?,
+            architecture: artifact.target.architecture,
+            sections: Vec::new(),
+            symbols: Vec::new(),
+            nsymbols: 0,
+            indexes: HashMap::new(),
+            section_indexes: HashMap::new(),
+            groups: IndexMap::new(),
+            strtab: StrTable::new(),
+        };
+        // the .file symbol carries the artifact name in as many aux records as needed
+        let mut file = Symbol {
+            section_number: symbol::IMAGE_SYM_DEBUG,
+            storage_class: symbol::IMAGE_SYM_CLASS_FILE,
+            ..Default::default()
+        };
+        file.name[..5].copy_from_slice(b".file");
+        let aux = artifact
+            .name
+            .as_bytes()
+            .chunks(COFF_SYMBOL_SIZE)
+            .map(|chunk| {
+                let mut record = [0; COFF_SYMBOL_SIZE];
+                record[..chunk.len()].copy_from_slice(chunk);
+                record
+            })
+            .collect();
+        coff.add_symbol(file, aux);
+        Ok(coff)
+    }
+    /// Append a symbol and its auxiliary records, returning its symbol table index
+    fn add_symbol(&mut self, mut symbol: Symbol, aux: Vec<[u8; COFF_SYMBOL_SIZE]>) -> SymbolIndex {
+        let index = self.nsymbols;
+        symbol.number_of_aux_symbols = aux.len() as u8;
+        self.nsymbols += 1 + aux.len();
+        self.symbols.push(SymbolEntry { symbol, aux });
+        index
+    }
+    pub fn add_definition(&mut self, def: artifact::Definition<'a>) {
+        let name = def.name;
+        let decl = def.decl;
+
+        let section_name = match (def.data, decl) {
+            (Data::Blob(_) | Data::Shared(_), DefinedDecl::Function(_)) => {
+                format!(".text${}", name)
+            }
+            (Data::ZeroInit(_), DefinedDecl::Function(_)) => {
+                unreachable!("cannot define function as zero-init")
+            }
+            // the linker gathers thread-local data between the CRT's `.tls` and `.tls$ZZZ` markers
+            (_, DefinedDecl::Data(decl)) if decl.is_tls() => ".tls$".to_owned(),
+            (Data::Blob(_) | Data::Shared(_), DefinedDecl::Data(decl)) => format!(
+                "{}${}",
+                if decl.is_writable() {
+                    ".data"
+                } else {
+                    ".rdata"
+                },
+                name
+            ),
+            (Data::ZeroInit(_), DefinedDecl::Data(_)) => format!(".bss${}", name),
+            (_, DefinedDecl::Section(_)) => name.to_owned(),
+        };
+
+        let size = def.data.size() as u64;
+        let section = match decl {
+            DefinedDecl::Function(d) => SectionBuilder::new(SectionType::Code, size)
+                .align(d.get_align())
+                .comdat(d.get_scope() == Scope::Weak || def.comdat.is_some()),
+            DefinedDecl::Data(d) => SectionBuilder::new(
+                if d.is_tls() {
+                    SectionType::Data
+                } else if def.data.is_zero_init() {
+                    SectionType::ZeroInit
+                } else if d.is_writable() {
+                    SectionType::Data
+                } else {
+                    SectionType::ReadOnlyData
+                },
+                size,
+            )
+            .align(d.get_align())
+            .comdat(d.get_scope() == Scope::Weak || def.comdat.is_some()),
+            DefinedDecl::Section(d) => SectionBuilder::new(
+                match d.kind() {
+                    SectionKind::Data => SectionType::Data,
+                    SectionKind::Debug => SectionType::Debug,
+                    SectionKind::Text => SectionType::Code,
+                },
+                size,
+            )
+            .align(d.get_align())
+            .comdat(def.comdat.is_some()),
+        };
+        let data = match def.data {
+            Data::Blob(_) | Data::Shared(_) => def.data.bytes().map(Cow::Borrowed),
+            // the thread-local template is copied for each thread, so it must be initialized
+            Data::ZeroInit(size) if decl.is_tls() => Some(Cow::Owned(vec![0; *size])),
+            Data::ZeroInit(_) => None,
+        };
+        let shndx = self.add_section(&section_name, section, data);
+        self.section_indexes.insert(name, shndx);
+        if let Some(group) = def.comdat {
+            self.groups.entry(group).or_default().push(shndx);
+        }
+
+        match decl {
+            DefinedDecl::Function(_) | DefinedDecl::Data(_) => {
+                // a COMDAT section's symbol must immediately follow its section symbol
+                let symbol = SymbolBuilder::new(name, SymbolType::Decl(decl))
+                    .section(shndx)
+                    .create(&mut self.strtab);
+                let index = self.add_symbol(symbol, Vec::new());
+                self.indexes.insert(name, index);
+            }
+            DefinedDecl::Section(_) => {
+                self.indexes.insert(name, self.sections[shndx].symbol);
+                for (symbol, symbol_dst_offset) in def.symbols {
+                    let symbol = SymbolBuilder::new(symbol, SymbolType::Label)
+                        .section(shndx)
+                        .value(*symbol_dst_offset)
+                        .create(&mut self.strtab);
+                    self.add_symbol(symbol, Vec::new());
+                }
+            }
+        }
+    }
+    /// Create a section (and its section symbol), and return the section index.
+    fn add_section(
+        &mut self,
+        name: &str,
+        section: SectionBuilder,
+        data: Option<Cow<'a, [u8]>>,
+    ) -> SectionIndex {
+        let shndx = self.sections.len();
+        let comdat = section.comdat;
+        let header = section.create(name, &mut self.strtab);
+        let symbol = SymbolBuilder::new(name, SymbolType::Section)
+            .section(shndx)
+            .create(&mut self.strtab);
+        let entry = self.symbols.len();
+        // the aux record is filled in once the relocations are known
+        let symbol = self.add_symbol(symbol, vec![[0; COFF_SYMBOL_SIZE]]);
+        self.sections.push(SectionInfo {
+            header,
+            data,
+            relocations: Vec::new(),
+            symbol,
+            entry,
+            comdat,
+        });
+        shndx
+    }
+    /// Add a `.CRT$XC` or `.CRT$XT` section for the constructors or destructors of each priority,
+    /// holding a pointer to each of their functions
+    pub fn add_initializers<I: Iterator<Item = Initializer<'a>>>(
+        &mut self,
+        initializers: I,
+    ) -> Result<(), ArtifactError> {
+        use goblin::pe::relocation::IMAGE_REL_AMD64_ADDR64;
+        let mut arrays: IndexMap<String, Vec<&'a str>> = IndexMap::new();
+        for init in initializers {
+            // the pointers are only relocated for x86_64 so far
+            if self.architecture != Architecture::X86_64 {
+                return Err(ArtifactError::UnsupportedArchitecture {
+                    architecture: self.architecture,
+                    format: BinaryFormat::Coff,
+                });
+            }
+            let name = initializer_section_name(init.kind, init.priority);
+            arrays.entry(name).or_default().push(init.name);
+        }
+        for (name, functions) in arrays {
+            let size = 8 * functions.len();
+            let section =
+                SectionBuilder::new(SectionType::ReadOnlyData, size as u64).align(Some(8));
+            let shndx = self.add_section(&name, section, Some(Cow::Owned(vec![0; size])));
+            for (i, function) in functions.into_iter().enumerate() {
+                let symbol = *self
+                    .indexes
+                    .get(function)
+                    .expect("initializer present in symbols");
+                let reloc = RelocationBuilder::new(IMAGE_REL_AMD64_ADDR64)
+                    .sym(symbol)
+                    .offset(8 * i as u64)
+                    .create();
+                self.sections[shndx].relocations.push(reloc);
+            }
+        }
+        Ok(())
+    }
+    pub fn import(&mut self, import: &'a str, kind: &ImportKind) {
+        // data imports are accessed indirectly through the import address table
+        let name = match kind {
+            // thread-local data can't be imported from a DLL, so it must be linked statically
+            ImportKind::Function | ImportKind::Tls => Cow::Borrowed(import),
+            ImportKind::Data => Cow::Owned(format!("{}{}", IMPORT_PREFIX, import)),
+        };
+        let symbol = SymbolBuilder::new(&name, SymbolType::Import).create(&mut self.strtab);
+        let index = self.add_symbol(symbol, Vec::new());
+        self.indexes.insert(import, index);
+    }
+    pub fn link(&mut self, l: &LinkAndDecl) -> Result<(), ArtifactError> {
+        use goblin::pe::relocation::*;
+        debug!("Link: {:?}", l);
+        if self.architecture != Architecture::X86_64 {
+            return Err(self.unsupported(l));
+        }
+        let (typ, addend) = match l.reloc {
+            Reloc::Auto => match *l.from.decl {
+                // the addend is implicit in COFF: REL32 is relative to the end of the 4 byte field
+                Decl::Defined(DefinedDecl::Function { .. }) => match *l.to.decl {
+                    // thread-local data is found at its offset into the `.tls` section in the
+                    // thread's block, indexed by `_tls_index`
+                    _ if l.to.decl.is_tls() => (IMAGE_REL_AMD64_SECREL, l.addend),
+                    Decl::Defined(DefinedDecl::Function { .. })
+                    | Decl::Defined(DefinedDecl::Data { .. })
+                    | Decl::Import(_) => (IMAGE_REL_AMD64_REL32, l.addend),
+                    _ => return Err(self.unsupported(l)),
+                },
+                Decl::Defined(DefinedDecl::Data { .. }) if !l.to.decl.is_tls() => {
+                    (IMAGE_REL_AMD64_ADDR64, l.addend)
+                }
+                _ => return Err(self.unsupported(l)),
+            },
+            Reloc::Raw { reloc, addend } => (reloc as u16, addend),
+            Reloc::Absolute { size: 4, addend } => (IMAGE_REL_AMD64_ADDR32, addend),
+            Reloc::Absolute { size: 8, addend } => (IMAGE_REL_AMD64_ADDR64, addend),
+            Reloc::Call => (IMAGE_REL_AMD64_REL32, 0),
+            Reloc::PcRelative { addend } => (IMAGE_REL_AMD64_REL32, addend),
+            Reloc::SectionOffset { size: 4, addend } => (IMAGE_REL_AMD64_SECREL, addend),
+            Reloc::ImageOffset { addend } => (IMAGE_REL_AMD64_ADDR32NB, addend),
+            Reloc::Absolute { .. } | Reloc::GotPcRelative { .. } | Reloc::SectionOffset { .. } => {
+                return Err(self.unsupported(l))
+            }
+            Reloc::Debug { size, addend } => match size {
+                // offsets into other debug sections are section relative
+                4 if l.to.decl.is_section() => (IMAGE_REL_AMD64_SECREL, addend),
+                4 => (IMAGE_REL_AMD64_ADDR32, addend),
+                8 => (IMAGE_REL_AMD64_ADDR64, addend),
+                _ => return Err(self.unsupported(l)),
+            },
+        };
+        let symbol = *self
+            .indexes
+            .get(l.to.name)
+            .ok_or_else(|| l.missing_symbol())?;
+        let shndx = *self
+            .section_indexes
+            .get(l.from.name)
+            .expect("from symbol present in sections");
+        let section = &mut self.sections[shndx];
+        if addend != 0 {
+            // COFF relocations have no explicit addend, so it is written into the section, which
+            // zero-init data doesn't have
+            let data = match section.data.as_mut() {
+                Some(data) => data.to_mut(),
+                None => return Err(l.out_of_bounds()),
+            };
+            let at = l.at as usize;
+            let overflow = |_| l.addend_overflow(addend);
+            match addend_width(typ) {
+                8 => data.pwrite_with(addend, at, LE),
+                4 => data.pwrite_with(i32::try_from(addend).map_err(overflow)?, at, LE),
+                2 => data.pwrite_with(i16::try_from(addend).map_err(overflow)?, at, LE),
+                // there is no field to hold the addend of an absolute relocation
+                _ => return Err(l.addend_overflow(addend)),
+            }
+            .map_err(|_| l.out_of_bounds())?;
+        }
+        let reloc = RelocationBuilder::new(typ)
+            .sym(symbol)
+            .offset(l.at)
+            .create();
+        section.relocations.push(reloc);
+        Ok(())
+    }
+    fn unsupported(&self, l: &LinkAndDecl) -> ArtifactError {
+        l.unsupported(self.architecture, BinaryFormat::Coff)
+    }
+    pub fn write<T: Write + Seek>(mut self, file: T) -> goblin::error::Result<()> {
+        use goblin::pe::section_table::IMAGE_SCN_LNK_NRELOC_OVFL;
+        let mut file = BufWriter::new(file);
+
+        if self.sections.len() >= MAX_SECTIONS {
+            return Err(goblin::error::Error::Malformed(format!(
+                "COFF objects support at most {} sections, got {}",
+                MAX_SECTIONS,
+                self.sections.len()
+            )));
+        }
+
+        /////////////////////////////////////
+        // Compute Offsets
+        /////////////////////////////////////
+        // COFF has no groups, so one section of every group is kept or discarded by the linker,
+        // and the others are associated with it: the section of the definition named after the
+        // group, or otherwise the first
+        let mut associations = HashMap::new();
+        for (group, members) in &self.groups {
+            let leader = match self.section_indexes.get(group) {
+                Some(shndx) if members.contains(shndx) => *shndx,
+                _ => members[0],
+            };
+            for &member in members.iter().filter(|&&member| member != leader) {
+                associations.insert(member, leader);
+            }
+        }
+        let mut offset = (SIZEOF_COFF_HEADER + self.sections.len() * SIZEOF_SECTION_TABLE) as u32;
+        for (shndx, section) in self.sections.iter_mut().enumerate() {
+            if let Some(data) = section.data.as_ref() {
+                section.header.pointer_to_raw_data = offset;
+                offset += data.len() as u32;
+            }
+            let mut nrelocs = section.relocations.len();
+            if nrelocs >= MAX_RELOCATIONS {
+                // the real count is stored in the first relocation
+                let count = RelocationBuilder::new(0).offset(nrelocs as u64 + 1);
+                section.relocations.insert(0, count.create());
+                section.header.characteristics |= IMAGE_SCN_LNK_NRELOC_OVFL;
+                nrelocs = MAX_RELOCATIONS;
+            }
+            if !section.relocations.is_empty() {
+                section.header.pointer_to_relocations = offset;
+                section.header.number_of_relocations = nrelocs as u16;
+                offset += (section.relocations.len() * relocation::COFF_RELOCATION_SIZE) as u32;
+            }
+            let (number, selection) = match associations.get(&shndx) {
+                // section numbers are one-based
+                Some(leader) => (*leader as u16 + 1, symbol::IMAGE_COMDAT_SELECT_ASSOCIATIVE),
+                None if section.comdat => (0, symbol::IMAGE_COMDAT_SELECT_ANY),
+                None => (0, 0),
+            };
+            let aux = AuxSectionDefinition {
+                length: section.header.size_of_raw_data,
+                number_of_relocations: nrelocs as u16,
+                number,
+                selection,
+                ..Default::default()
+            };
+            self.symbols[section.entry].aux[0].pwrite_with(aux, 0, LE)?;
+        }
+        let symtab_offset = offset;
+
+        /////////////////////////////////////
+        // Header
+        /////////////////////////////////////
+        let header = CoffHeader {
+            machine: self.machine.0,
+            number_of_sections: self.sections.len() as u16,
+            pointer_to_symbol_table: symtab_offset,
+            number_of_symbol_table: self.nsymbols as u32,
+            ..Default::default()
+        };
+        file.iowrite_with(header, LE)?;
+
+        /////////////////////////////////////
+        // Sections
+        /////////////////////////////////////
+        let mut section_headers = Cursor::new(Vec::new());
+        for section in self.sections.iter() {
+            section_headers.iowrite_with(section.header.clone(), LE)?;
+        }
+        file.write_all(&section_headers.into_inner())?;
+
+        /////////////////////////////////////
+        // Data and relocations
+        /////////////////////////////////////
+        for section in self.sections.iter() {
+            if let Some(data) = section.data.as_ref() {
+                file.write_all(data)?;
+            }
+            for reloc in section.relocations.iter() {
+                debug!("Relocation: {:?}", reloc);
+                file.iowrite_with(*reloc, LE)?;
+            }
+        }
+
+        /////////////////////////////////////
+        // Symtab
+        /////////////////////////////////////
+        for entry in self.symbols.iter() {
+            debug!("Symbol: {:?}", entry.symbol);
+            file.iowrite_with(entry.symbol, LE)?;
+            for aux in entry.aux.iter() {
+                file.write_all(aux)?;
+            }
+        }
+
+        /////////////////////////////////////
+        // Strtab
+        /////////////////////////////////////
+        file.write_all(&self.strtab.finish())?;
+
+        debug!("done");
+        Ok(())
+    }
+}
+
+pub fn write_to<W: Write + Seek>(artifact: &Artifact, sink: W) -> Result<(), ArtifactError> {
+    let mut coff = Coff::new(artifact)?;
+    for def in artifact.definitions() {
+        debug!("Def: {:?}", def);
+        coff.add_definition(def);
+    }
+    coff.add_initializers(artifact.initializers())?;
+    for (import, kind) in artifact.imports() {
+        debug!("Import: {:?} -> {:?}", import, kind);
+        coff.import(import, kind);
+    }
+    for link in artifact.links() {
+        coff.link(&link)?;
+    }
+    coff.write(sink)?;
+    Ok(())
+}
@@ -0,0 +1,446 @@
+//! Generation of DWARF debug info mapping the code of functions back to source lines, so that
+//! debuggers like `gdb` and `lldb` can show where in the source an address comes from.
+//!
+//! The debug info is added to an artifact as the `.debug_abbrev`, `.debug_info`, `.debug_line` and
+//! `.debug_ranges` debug sections, which the backends name and relocate as their binary format
+//! expects, e.g. `__debug_line` in the `__DWARF` segment of a Mach-o file.
+//!
+//! ```
+//! # use faerie::{triple, Artifact, Decl};
+//! use faerie::dwarf::{CompilationUnit, DwarfBuilder, LineRow};
+//!
+//! let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "main.o".into());
+//! obj.declare("main", Decl::function().global()).unwrap();
+//! // xor %eax, %eax; ret
+//! obj.define("main", vec![0x31, 0xc0, 0xc3]).unwrap();
+//! let row = |offset, line, column| LineRow {
+//!     offset,
+//!     file: "main.c".to_string(),
+//!     line,
+//!     column,
+//! };
+//! DwarfBuilder::new(CompilationUnit::new("main.c").comp_dir("/src"))
+//!     .function("main", vec![row(0, 1, 12), row(2, 2, 5)])
+//!     .finish(&mut obj)
+//!     .unwrap();
+//! ```
+
+use crate::artifact::{ArtifactError, Decl, DefinedDecl, Reloc, SectionKind};
+use crate::leb::{write_sleb, write_uleb};
+use crate::{Artifact, Link, SectionDecl};
+
+use indexmap::IndexSet;
+use target_lexicon::{BinaryFormat, Endianness};
+
+/// The version of DWARF generated, which every debugger in use understands
+const VERSION: u16 = 4;
+
+// the subset of the constants of the DWARF 4 standard which is used
+const DW_TAG_COMPILE_UNIT: u64 = 0x11;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_CHILDREN_NO: u8 = 0;
+const DW_CHILDREN_YES: u8 = 1;
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_STMT_LIST: u64 = 0x10;
+const DW_AT_LOW_PC: u64 = 0x11;
+const DW_AT_HIGH_PC: u64 = 0x12;
+const DW_AT_LANGUAGE: u64 = 0x13;
+const DW_AT_COMP_DIR: u64 = 0x1b;
+const DW_AT_PRODUCER: u64 = 0x25;
+const DW_AT_EXTERNAL: u64 = 0x3f;
+const DW_AT_RANGES: u64 = 0x55;
+const DW_FORM_ADDR: u64 = 0x01;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_SEC_OFFSET: u64 = 0x17;
+const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+const DW_LNS_COPY: u8 = 0x01;
+const DW_LNS_ADVANCE_PC: u8 = 0x02;
+const DW_LNS_ADVANCE_LINE: u8 = 0x03;
+const DW_LNS_SET_FILE: u8 = 0x04;
+const DW_LNS_SET_COLUMN: u8 = 0x05;
+const DW_LNE_END_SEQUENCE: u8 = 0x01;
+const DW_LNE_SET_ADDRESS: u8 = 0x02;
+
+/// The abbreviation codes of the two kinds of entries in `.debug_info`
+const COMPILE_UNIT_CODE: u64 = 1;
+const SUBPROGRAM_CODE: u64 = 2;
+
+/// The number of operands of each standard line number opcode, which is all of them up to
+/// `DW_LNS_set_isa`; the opcodes from `OPCODE_BASE` on are special opcodes, which aren't emitted
+const STANDARD_OPCODE_LENGTHS: [u8; 12] = [0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1];
+const OPCODE_BASE: u8 = STANDARD_OPCODE_LENGTHS.len() as u8 + 1;
+
+/// The compilation unit the debug info describes, which is the source file the artifact was
+/// compiled from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilationUnit {
+    name: String,
+    comp_dir: Option<String>,
+    producer: Option<String>,
+    language: Option<u16>,
+}
+
+impl CompilationUnit {
+    /// A compilation unit for the primary source file `name`
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        CompilationUnit {
+            name: name.into(),
+            comp_dir: None,
+            producer: None,
+            language: None,
+        }
+    }
+    /// Set the directory the compilation ran in, which relative file names are relative to
+    pub fn comp_dir<T: Into<String>>(mut self, comp_dir: T) -> Self {
+        self.comp_dir = Some(comp_dir.into());
+        self
+    }
+    /// Set the name and version of the compiler
+    pub fn producer<T: Into<String>>(mut self, producer: T) -> Self {
+        self.producer = Some(producer.into());
+        self
+    }
+    /// Set the source language, as one of the `DW_LANG_*` codes of the DWARF standard
+    pub fn language(mut self, language: u16) -> Self {
+        self.language = Some(language);
+        self
+    }
+}
+
+/// The source location the code of a function at `offset` onwards comes from, up to the next row
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LineRow {
+    /// Offset of the code into the function
+    pub offset: u64,
+    /// Source file, relative to the `comp_dir` of the compilation unit or absolute
+    pub file: String,
+    /// Line in `file`, starting at 1, or 0 for code with no line of its own
+    pub line: u64,
+    /// Column in `line`, starting at 1, or 0 for the whole line
+    pub column: u64,
+}
+
+/// Builder for the debug info mapping the functions of an artifact to their source lines
+#[derive(Debug, Clone)]
+pub struct DwarfBuilder {
+    unit: CompilationUnit,
+    functions: Vec<(String, Vec<LineRow>)>,
+}
+
+/// The contents of a section being generated, with the links from it to add once it's defined, as
+/// the offset, target and relocation of each
+pub(crate) struct Section {
+    pub(crate) bytes: Vec<u8>,
+    links: Vec<(u64, String, Reloc)>,
+    big_endian: bool,
+}
+
+impl Section {
+    pub(crate) fn new(big_endian: bool) -> Self {
+        Section {
+            bytes: Vec::new(),
+            links: Vec::new(),
+            big_endian,
+        }
+    }
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub(crate) fn uleb(&mut self, value: u64) {
+        write_uleb(&mut self.bytes, value);
+    }
+    pub(crate) fn sleb(&mut self, value: i64) {
+        write_sleb(&mut self.bytes, value);
+    }
+    pub(crate) fn string(&mut self, value: &str) {
+        self.bytes.extend_from_slice(value.as_bytes());
+        self.bytes.push(0);
+    }
+    /// Write the low `size` bytes of `value`
+    pub(crate) fn word(&mut self, size: u8, value: u64) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()[8 - size as usize..].to_vec()
+        } else {
+            value.to_le_bytes()[..size as usize].to_vec()
+        };
+        self.bytes.extend_from_slice(&bytes);
+    }
+    /// Overwrite the 4 byte word at `at` with `value`
+    pub(crate) fn patch(&mut self, at: usize, value: u32) {
+        let bytes = if self.big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        };
+        self.bytes[at..at + 4].copy_from_slice(&bytes);
+    }
+    /// Write a `size` byte offset into the debug section `to` plus `addend`, left to a relocation
+    pub(crate) fn link(&mut self, size: u8, to: &str, addend: i64) {
+        self.relocate(size, to, Reloc::Debug { size, addend });
+    }
+    /// Write the `size` byte address of `to` plus `addend`, left to a relocation
+    pub(crate) fn address(&mut self, size: u8, to: &str, addend: i64) {
+        self.relocate(size, to, Reloc::Absolute { size, addend });
+    }
+    /// Write a `size` byte field filled in by `reloc` to `to`
+    pub(crate) fn relocate(&mut self, size: u8, to: &str, reloc: Reloc) {
+        self.links
+            .push((self.bytes.len() as u64, to.to_string(), reloc));
+        self.word(size, 0);
+    }
+    /// Reserve the 4 byte length of what follows, to patch with `end_length`
+    pub(crate) fn begin_length(&mut self) -> usize {
+        let at = self.bytes.len();
+        self.word(4, 0);
+        at
+    }
+    pub(crate) fn end_length(&mut self, at: usize) {
+        let length = self.bytes.len() - at - 4;
+        self.patch(at, length as u32);
+    }
+}
+
+impl DwarfBuilder {
+    /// Start the debug info of `unit`
+    pub fn new(unit: CompilationUnit) -> Self {
+        DwarfBuilder {
+            unit,
+            functions: Vec::new(),
+        }
+    }
+    /// Describe the source locations of the code of the function `name`, which must be defined in
+    /// the artifact when the debug info is added to it. The rows can be in any order.
+    pub fn function<T: Into<String>>(mut self, name: T, rows: Vec<LineRow>) -> Self {
+        self.functions.push((name.into(), rows));
+        self
+    }
+    /// Generate the debug sections and add them, with their links, to `artifact`, which must be
+    /// emitted as ELF or Mach-o
+    pub fn finish(self, artifact: &mut Artifact) -> Result<(), ArtifactError> {
+        let format = artifact.target.binary_format;
+        if format != BinaryFormat::Elf && format != BinaryFormat::Macho {
+            return Err(ArtifactError::UnsupportedBinaryFormat(format));
+        }
+        let address_size = match artifact.target.pointer_width() {
+            Ok(width) => width.bytes(),
+            Err(()) => {
+                return Err(ArtifactError::UnsupportedArchitecture {
+                    architecture: artifact.target.architecture,
+                    format,
+                })
+            }
+        };
+        let big_endian = artifact.target.endianness() == Ok(Endianness::Big);
+        let mut functions = Vec::with_capacity(self.functions.len());
+        for (name, mut rows) in self.functions {
+            let size = function_size(artifact, &name, ArtifactError::NonFunctionLineInfo)?;
+            rows.sort_by_key(|row| row.offset);
+            functions.push((name, size, rows));
+        }
+
+        let mut abbrev = Section::new(big_endian);
+        let mut info = Section::new(big_endian);
+        let mut line = Section::new(big_endian);
+        let mut ranges = Section::new(big_endian);
+
+        // the compilation unit covers the ranges of all of its functions, which can be anywhere
+        let mut attributes = Vec::new();
+        if self.unit.producer.is_some() {
+            attributes.push((DW_AT_PRODUCER, DW_FORM_STRING));
+        }
+        if self.unit.language.is_some() {
+            attributes.push((DW_AT_LANGUAGE, DW_FORM_DATA2));
+        }
+        attributes.push((DW_AT_NAME, DW_FORM_STRING));
+        if self.unit.comp_dir.is_some() {
+            attributes.push((DW_AT_COMP_DIR, DW_FORM_STRING));
+        }
+        attributes.extend_from_slice(&[
+            (DW_AT_STMT_LIST, DW_FORM_SEC_OFFSET),
+            (DW_AT_LOW_PC, DW_FORM_ADDR),
+            (DW_AT_RANGES, DW_FORM_SEC_OFFSET),
+        ]);
+        let subprogram = [
+            (DW_AT_NAME, DW_FORM_STRING),
+            (DW_AT_EXTERNAL, DW_FORM_FLAG_PRESENT),
+            (DW_AT_LOW_PC, DW_FORM_ADDR),
+            (DW_AT_HIGH_PC, DW_FORM_DATA4),
+        ];
+        for (code, tag, children, attributes) in [
+            (
+                COMPILE_UNIT_CODE,
+                DW_TAG_COMPILE_UNIT,
+                DW_CHILDREN_YES,
+                &attributes[..],
+            ),
+            (
+                SUBPROGRAM_CODE,
+                DW_TAG_SUBPROGRAM,
+                DW_CHILDREN_NO,
+                &subprogram[..],
+            ),
+        ] {
+            abbrev.uleb(code);
+            abbrev.uleb(tag);
+            abbrev.u8(children);
+            for &(name, form) in attributes {
+                abbrev.uleb(name);
+                abbrev.uleb(form);
+            }
+            abbrev.uleb(0);
+            abbrev.uleb(0);
+        }
+        abbrev.uleb(0);
+
+        let length = info.begin_length();
+        info.word(2, u64::from(VERSION));
+        info.link(4, ".debug_abbrev", 0);
+        info.u8(address_size);
+        info.uleb(COMPILE_UNIT_CODE);
+        if let Some(producer) = &self.unit.producer {
+            info.string(producer);
+        }
+        if let Some(language) = self.unit.language {
+            info.word(2, u64::from(language));
+        }
+        info.string(&self.unit.name);
+        if let Some(comp_dir) = &self.unit.comp_dir {
+            info.string(comp_dir);
+        }
+        info.link(4, ".debug_line", 0);
+        // the base the ranges of the compilation unit are relative to
+        info.word(address_size, 0);
+        info.link(4, ".debug_ranges", 0);
+        for (name, size, _) in &functions {
+            info.uleb(SUBPROGRAM_CODE);
+            info.string(name);
+            info.address(address_size, name, 0);
+            info.word(4, *size);
+            ranges.address(address_size, name, 0);
+            ranges.address(address_size, name, *size as i64);
+        }
+        info.uleb(0);
+        info.end_length(length);
+        ranges.word(address_size, 0);
+        ranges.word(address_size, 0);
+
+        // the files are numbered from 1, in the order they first appear
+        let files = functions
+            .iter()
+            .flat_map(|(_, _, rows)| rows.iter().map(|row| row.file.as_str()))
+            .collect::<IndexSet<_>>();
+        let length = line.begin_length();
+        line.word(2, u64::from(VERSION));
+        let header_length = line.begin_length();
+        // minimum_instruction_length, maximum_operations_per_instruction, default_is_stmt,
+        // line_base, line_range and opcode_base, for which no special opcodes are emitted
+        line.bytes
+            .extend_from_slice(&[1, 1, 1, -5i8 as u8, 14, OPCODE_BASE]);
+        line.bytes.extend_from_slice(&STANDARD_OPCODE_LENGTHS);
+        // no include directories, as each file is in the compilation directory or absolute
+        line.u8(0);
+        for file in &files {
+            line.string(file);
+            // directory, modification time and length
+            line.uleb(0);
+            line.uleb(0);
+            line.uleb(0);
+        }
+        line.u8(0);
+        line.end_length(header_length);
+        // each function is a sequence of its own, as it can be anywhere
+        for (name, size, rows) in &functions {
+            line.u8(0);
+            line.uleb(1 + u64::from(address_size));
+            line.u8(DW_LNE_SET_ADDRESS);
+            line.address(address_size, name, 0);
+            let (mut address, mut file, mut row_line, mut column) = (0, 1, 1, 0);
+            for row in rows {
+                let row_file = files.get_index_of(row.file.as_str()).unwrap() as u64 + 1;
+                if row_file != file {
+                    line.u8(DW_LNS_SET_FILE);
+                    line.uleb(row_file);
+                    file = row_file;
+                }
+                if row.column != column {
+                    line.u8(DW_LNS_SET_COLUMN);
+                    line.uleb(row.column);
+                    column = row.column;
+                }
+                if row.line != row_line {
+                    line.u8(DW_LNS_ADVANCE_LINE);
+                    line.sleb(row.line.wrapping_sub(row_line) as i64);
+                    row_line = row.line;
+                }
+                if row.offset != address {
+                    line.u8(DW_LNS_ADVANCE_PC);
+                    line.uleb(row.offset - address);
+                    address = row.offset;
+                }
+                line.u8(DW_LNS_COPY);
+            }
+            // the sequence ends after the function
+            if *size > address {
+                line.u8(DW_LNS_ADVANCE_PC);
+                line.uleb(size - address);
+            }
+            line.u8(0);
+            line.uleb(1);
+            line.u8(DW_LNE_END_SEQUENCE);
+        }
+        line.end_length(length);
+
+        let debug = Decl::section(SectionKind::Debug);
+        add_sections(
+            artifact,
+            vec![
+                (".debug_abbrev", debug, abbrev),
+                (".debug_info", debug, info),
+                (".debug_line", debug, line),
+                (".debug_ranges", debug, ranges),
+            ],
+        )
+    }
+}
+
+/// Declare and define each of `sections` in `artifact`, and add their links; they can link to
+/// each other, so they're all declared before any is linked
+pub(crate) fn add_sections(
+    artifact: &mut Artifact,
+    sections: Vec<(&str, SectionDecl, Section)>,
+) -> Result<(), ArtifactError> {
+    for (name, decl, _) in &sections {
+        artifact.declare(name, *decl)?;
+    }
+    for (name, _, section) in sections {
+        artifact.define(name, section.bytes)?;
+        for (at, to, reloc) in section.links {
+            let link = Link {
+                from: name,
+                to: &to,
+                at,
+            };
+            artifact.link_with(link, reloc)?;
+        }
+    }
+    Ok(())
+}
+
+/// The size of the code of the function `name`, which must be defined in `artifact`, or
+/// `non_function(name)` if it's something else
+pub(crate) fn function_size(
+    artifact: &Artifact,
+    name: &str,
+    non_function: fn(String) -> ArtifactError,
+) -> Result<u64, ArtifactError> {
+    match artifact.declaration(name) {
+        Some(Decl::Defined(DefinedDecl::Function(_))) => match artifact.definition(name) {
+            Some(def) => Ok(def.data.size() as u64),
+            None => Err(ArtifactError::UndefinedSymbols(vec![name.to_string()])),
+        },
+        Some(_) => Err(non_function(name.to_string())),
+        None => Err(ArtifactError::Undeclared(name.to_string())),
+    }
+}
@@ -7,22 +7,23 @@
 
 use crate::{
     artifact::{
-        self, Artifact, Data, DataType, Decl, DefinedDecl, ImportKind, LinkAndDecl, Reloc, Scope,
-        Visibility,
+        self, Artifact, ArtifactError, Data, DataType, Decl, DefinedDecl, ImportKind, InitKind,
+        Initializer, LinkAndDecl, Reloc, Scope, SectionKind, SymbolMap, UnwindTable, Visibility,
     },
     target::make_ctx,
     Ctx,
 };
-use goblin;
 
 use indexmap::IndexMap;
 use scroll::{IOwrite, Pwrite};
+use std::borrow::Cow;
 use std::collections::{hash_map, HashMap};
+use std::convert::TryFrom;
 use std::fmt;
 use std::io::SeekFrom::*;
-use std::io::{BufWriter, Cursor, Seek, Write};
+use std::io::{BufWriter, Seek, Write};
 use string_interner::StringInterner;
-use target_lexicon::Architecture;
+use target_lexicon::{Architecture, BinaryFormat};
 
 use goblin::elf::header::{self, Header};
 use goblin::elf::reloc;
@@ -35,14 +36,17 @@ type Offset = usize;
 type Relocation = goblin::elf::reloc::Reloc;
 type Symbol = goblin::elf::sym::Sym;
 type Section = SectionHeader;
+// a relocation type and addend, with the offset it applies at
+type RelocAt = ((u32, i64), u64);
 
 struct MachineTag(u16);
 
-impl From<Architecture> for MachineTag {
-    fn from(architecture: Architecture) -> MachineTag {
+impl TryFrom<Architecture> for MachineTag {
+    type Error = ArtifactError;
+    fn try_from(architecture: Architecture) -> Result<MachineTag, ArtifactError> {
         use goblin::elf::header::*;
         use target_lexicon::Architecture::*;
-        MachineTag(match architecture {
+        Ok(MachineTag(match architecture {
             X86_64 => EM_X86_64,
             X86_32(_) => EM_386,
             Aarch64(_) => EM_AARCH64,
@@ -56,13 +60,15 @@ impl From<Architecture> for MachineTag {
             Sparc64 | Sparcv9 => EM_SPARCV9,
             Msp430 => EM_MSP430,
             Unknown => EM_NONE,
-            Hexagon => panic!("goblin does not have EM_HEXAGON yet"),
-            Nvptx64 => panic!("nvptx64 does not exist in ELF"),
-            AmdGcn => panic!("amdgcn does not exist in ELF"),
-            Asmjs => panic!("asm.js does not exist in ELF"),
-            Wasm32 => panic!("wasm32 does not exist in ELF"),
-            arch => panic!("Unknown architecture: {}", arch),
-        })
+            // goblin does not have EM_HEXAGON yet, and nvptx64, amdgcn, asm.js and wasm32 don't
+            // exist in ELF
+            _ => {
+                return Err(ArtifactError::UnsupportedArchitecture {
+                    architecture,
+                    format: BinaryFormat::Elf,
+                })
+            }
+        }))
     }
 }
 
@@ -71,7 +77,7 @@ enum SymbolType<'a> {
     /// From a definition
     Decl(&'a DefinedDecl),
     /// An import
-    Import,
+    Import(ImportKind),
     /// A section reference
     Section,
     /// A file reference
@@ -116,7 +122,7 @@ impl<'a> SymbolBuilder<'a> {
     /// Set the section index
     pub fn section_index(mut self, shndx: usize) -> Self {
         // Underlying representation is only 32 bits. Catch this early!
-        debug_assert!(shndx < u32::max_value() as usize);
+        debug_assert!(shndx < u32::MAX as usize);
         self.shndx = shndx;
         self
     }
@@ -130,7 +136,7 @@ impl<'a> SymbolBuilder<'a> {
         use goblin::elf::section_header::SHN_ABS;
         use goblin::elf::sym::{
             STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FILE, STT_FUNC, STT_NOTYPE, STT_OBJECT,
-            STT_SECTION, STV_DEFAULT, STV_HIDDEN, STV_PROTECTED,
+            STT_SECTION, STT_TLS, STV_DEFAULT, STV_HIDDEN, STV_PROTECTED,
         };
         let mut st_shndx = self.shndx;
         let mut st_info = 0;
@@ -161,12 +167,17 @@ impl<'a> SymbolBuilder<'a> {
                 st_other |= vis_stother_flags(d.get_visibility());
             }
             SymbolType::Decl(DefinedDecl::Data(d)) => {
-                st_info |= STT_OBJECT;
+                st_info |= if d.is_tls() { STT_TLS } else { STT_OBJECT };
                 st_info |= scope_stb_flags(d.get_scope());
                 st_other |= vis_stother_flags(d.get_visibility());
             }
-            SymbolType::Import => {
-                st_info = STT_NOTYPE;
+            SymbolType::Import(kind) => {
+                // the linker requires thread-local references to be to thread-local symbols
+                st_info = if kind == ImportKind::Tls {
+                    STT_TLS
+                } else {
+                    STT_NOTYPE
+                };
                 st_info |= STB_GLOBAL << 4;
             }
             SymbolType::Decl(DefinedDecl::Section(_)) | SymbolType::Section => {
@@ -201,6 +212,9 @@ enum SectionType {
     SymTab,
     Relocation,
     SymTabShndx,
+    Group,
+    InitArray,
+    FiniArray,
     None,
 }
 
@@ -210,6 +224,8 @@ struct SectionBuilder {
     exec: bool,
     write: bool,
     alloc: bool,
+    tls: bool,
+    group: bool,
     size: u64,
     name_offset: usize,
     align: Option<u64>,
@@ -223,6 +239,8 @@ impl SectionBuilder {
             exec: false,
             write: false,
             alloc: false,
+            tls: false,
+            group: false,
             name_offset: 0,
             size,
             align: None,
@@ -243,6 +261,16 @@ impl SectionBuilder {
         self.write = writable;
         self
     }
+    /// Make this section a template for thread-local data
+    pub fn tls(mut self, tls: bool) -> Self {
+        self.tls = tls;
+        self
+    }
+    /// Make this section a member of a section group
+    pub fn group(mut self, group: bool) -> Self {
+        self.group = group;
+        self
+    }
     /// Specify section alignment
     pub fn align(mut self, align: Option<u64>) -> Self {
         self.align = align;
@@ -262,10 +290,12 @@ impl SectionBuilder {
     /// Finalize and create the actual section
     pub fn create(self, ctx: &Ctx) -> Section {
         use goblin::elf::section_header::*;
-        let mut shdr = Section::default();
-        shdr.sh_flags = 0u64;
-        shdr.sh_size = self.size;
-        shdr.sh_name = self.name_offset;
+        let mut shdr = Section {
+            sh_flags: 0u64,
+            sh_size: self.size,
+            sh_name: self.name_offset,
+            ..Default::default()
+        };
         if self.exec {
             shdr.sh_flags |= SHF_EXECINSTR as u64
         }
@@ -275,9 +305,15 @@ impl SectionBuilder {
         if self.alloc {
             shdr.sh_flags |= SHF_ALLOC as u64
         }
+        if self.tls {
+            shdr.sh_flags |= SHF_TLS as u64
+        }
+        if self.group {
+            shdr.sh_flags |= SHF_GROUP as u64
+        }
 
         let align = if let Some(align) = self.align {
-            align as u64
+            align
         } else if self.exec {
             0x10
         } else if self.write {
@@ -321,7 +357,22 @@ impl SectionBuilder {
                 shdr.sh_addralign = 4;
                 shdr.sh_type = SHT_SYMTAB_SHNDX;
             }
+            SectionType::Group => {
+                shdr.sh_entsize = 4;
+                shdr.sh_addralign = 4;
+                shdr.sh_type = SHT_GROUP;
+            }
+            SectionType::InitArray | SectionType::FiniArray => {
+                shdr.sh_entsize = ctx.size() as u64;
+                shdr.sh_addralign = align;
+                shdr.sh_type = if let SectionType::InitArray = self.typ {
+                    SHT_INIT_ARRAY
+                } else {
+                    SHT_FINI_ARRAY
+                };
+            }
             SectionType::NoBits => {
+                shdr.sh_addralign = align;
                 shdr.sh_type = SHT_NOBITS;
                 // .bss is always SHF_WRITE and SHF_ALLOC
                 // TODO: warn users if self.alloc is not set
@@ -393,19 +444,26 @@ impl RelocationBuilder {
 /// An intermediate ELF object file container
 struct Elf<'a> {
     name: &'a str,
-    code: IndexMap<StringIndex, &'a [u8]>,
+    code: IndexMap<StringIndex, Cow<'a, [u8]>>,
     relocations: IndexMap<StringIndex, (Section, Vec<Relocation>)>,
     symbols: IndexMap<StringIndex, Symbol>,
+    /// The symbols of global and weak definitions, which follow every local symbol
+    globals: Vec<(StringIndex, Symbol)>,
     special_symbols: Vec<Symbol>,
     imports: HashMap<StringIndex, ImportKind>,
     sections: IndexMap<StringIndex, SectionInfo>,
+    /// The COMDAT groups, and the indexes of their member sections
+    groups: IndexMap<&'a str, Vec<usize>>,
     offsets: HashMap<StringIndex, Offset>,
     sizeof_strtab: Offset,
     strings: StringInterner<StringIndex>,
+    /// The strings of the symbols links bind, by their ids in the artifact
+    link_strings: SymbolMap<StringIndex>,
     sizeof_bits: Offset,
     nsections: u32,
     ctx: Ctx,
     architecture: Architecture,
+    machine: MachineTag,
     nlocals: usize,
 }
 
@@ -427,9 +485,12 @@ impl<'a> fmt::Debug for Elf<'a> {
 
 const STRTAB_LINK: u16 = 1;
 const SYMTAB_LINK: u16 = 2;
+/// The flag for a section group whose duplicates the linker discards; goblin doesn't define it
+pub(crate) const GRP_COMDAT: u32 = 0x1;
 
 impl<'a> Elf<'a> {
-    pub fn new(artifact: &'a Artifact) -> Self {
+    pub fn new(artifact: &'a Artifact) -> Result<Self, ArtifactError> {
+        let machine = MachineTag::try_from(artifact.target.architecture)?;
         let ctx = make_ctx(&artifact.target);
         let mut offsets = HashMap::new();
         let mut strings: StringInterner<usize> = StringInterner::new();
@@ -459,24 +520,59 @@ impl<'a> Elf<'a> {
             );
         }
 
+        // group sections come before the sections in them
+        let mut groups = IndexMap::new();
+        for def in artifact.definitions() {
+            if let Some(group) = def.comdat {
+                groups.entry(group).or_insert_with(Vec::new);
+            }
+        }
+
         let sizeof_bits = Header::size(ctx);
-        Elf {
+        let mut elf = Elf {
             name: &artifact.name,
             code: IndexMap::new(),
             relocations: IndexMap::new(),
             imports: HashMap::new(),
             symbols: IndexMap::new(),
+            globals: Vec::new(),
             special_symbols,
             sections: IndexMap::new(),
-            nsections: 4,
+            nsections: 4 + groups.len() as u32,
+            groups,
             offsets,
             strings,
+            link_strings: SymbolMap::new(),
             sizeof_strtab,
             sizeof_bits,
             ctx,
             architecture: artifact.target.architecture,
+            machine,
             nlocals: 0,
+        };
+
+        // every group is identified by a signature symbol of the same name; if none of its
+        // definitions has that name, give it a local symbol of its own, as the assembler does
+        for (i, group) in elf.groups.keys().cloned().enumerate().collect::<Vec<_>>() {
+            let has_symbol = artifact
+                .definitions()
+                .any(|def| def.name == group && (def.decl.is_function() || def.decl.is_data()));
+            if !has_symbol {
+                let (idx, offset) = elf.new_string(group.to_string());
+                let symbol = SymbolBuilder::new(SymbolType::None)
+                    .name_offset(offset)
+                    .section_index(3 + i)
+                    .create();
+                elf.symbols.insert(idx, symbol);
+                elf.nlocals += 1;
+            }
         }
+        Ok(elf)
+    }
+    /// The index of the first section holding a definition, after the null section, strtab,
+    /// symtab and group sections
+    fn first_section_index(&self) -> usize {
+        3 + self.groups.len()
     }
     fn new_string(&mut self, name: String) -> (StringIndex, usize) {
         let size = name.len() + 1;
@@ -502,18 +598,27 @@ impl<'a> Elf<'a> {
     pub fn add_definition(&mut self, def: artifact::Definition<'a>) {
         let name = def.name;
         let decl = def.decl;
-        let def_size = def.data.file_size();
+        // zero initialized sections take up no space in the file, but still need their size
+        let def_size = def.data.size();
 
         let section_name = match (def.data, decl) {
-            (Data::Blob(_), DefinedDecl::Function(_)) => format!(".text.{}", name),
+            (Data::Blob(_) | Data::Shared(_), DefinedDecl::Function(_)) => {
+                format!(".text.{}", name)
+            }
             (Data::ZeroInit(_), DefinedDecl::Function(_)) => {
                 unreachable!("cannot define function as zero-init")
             }
-            (Data::Blob(_), DefinedDecl::Data(decl)) => format!(
+            (Data::Blob(_) | Data::Shared(_), DefinedDecl::Data(decl)) if decl.is_tls() => {
+                format!(".tdata.{}", name)
+            }
+            (Data::Blob(_) | Data::Shared(_), DefinedDecl::Data(decl)) => format!(
                 ".{}.{}",
                 if decl.is_writable() { "data" } else { "rodata" },
                 name
             ),
+            (Data::ZeroInit(_), DefinedDecl::Data(decl)) if decl.is_tls() => {
+                format!(".tbss.{}", name)
+            }
             (Data::ZeroInit(_), DefinedDecl::Data(_)) => format!(".bss.{}", name),
             (_, DefinedDecl::Section(_)) => name.to_owned(),
         };
@@ -524,6 +629,7 @@ impl<'a> Elf<'a> {
                 .alloc()
                 .writable(false)
                 .exec(true)
+                .group(def.comdat.is_some())
                 .align(d.get_align()),
             DefinedDecl::Data(d) => SectionBuilder::new(def_size as u64)
                 .section_type(Self::section_type_for_data(
@@ -531,7 +637,10 @@ impl<'a> Elf<'a> {
                     def.data.is_zero_init(),
                 ))
                 .alloc()
-                .writable(d.is_writable())
+                // every thread gets a copy of the template, which it is free to modify
+                .writable(d.is_writable() || d.is_tls())
+                .tls(d.is_tls())
+                .group(def.comdat.is_some())
                 .exec(false)
                 .align(d.get_align()),
             DefinedDecl::Section(d) => SectionBuilder::new(def_size as u64)
@@ -543,13 +652,24 @@ impl<'a> Elf<'a> {
                         Self::section_type_for_data(d.get_datatype(), def.data.is_zero_init())
                     },
                 )
+                .group(def.comdat.is_some())
                 .align(d.get_align()),
         };
+        // the unwinder reads the frame descriptions out of memory, so they're loaded with the code
+        let section = match decl {
+            DefinedDecl::Section(d) if d.get_unwind() == Some(UnwindTable::EhFrame) => {
+                section.alloc()
+            }
+            _ => section,
+        };
 
-        let shndx = match def.data {
-            Data::Blob(bytes) => self.add_progbits(section_name, section, bytes),
-            Data::ZeroInit(_) => self.add_section(section_name, section).1,
+        let shndx = match def.data.bytes() {
+            Some(bytes) => self.add_progbits(section_name, section, bytes),
+            None => self.add_section(section_name, section).1,
         };
+        if let Some(group) = def.comdat {
+            self.groups[group].push(shndx);
+        }
 
         match decl {
             DefinedDecl::Function(_) | DefinedDecl::Data(_) => {
@@ -564,13 +684,19 @@ impl<'a> Elf<'a> {
                     .name_offset(offset)
                     .section_index(shndx)
                     .create();
-                // insert it into our symbol table
-                self.symbols.insert(idx, symbol);
                 // sh_info requires nsections + nlocals to add as delimiter; see the associated FunFact
-                // nonglobals go into the symbol table first (per iteration through definitions in
-                // caller)
-                if !decl.is_global() {
+                // locals go into the symbol table first, and global and weak symbols once every
+                // definition is added, so that the sections stay in the order of the definitions
+                let scope = match decl {
+                    DefinedDecl::Function(d) => d.get_scope(),
+                    DefinedDecl::Data(d) => d.get_scope(),
+                    DefinedDecl::Section(_) => unreachable!("sections have no symbol of their own"),
+                };
+                if scope == Scope::Local {
+                    self.symbols.insert(idx, symbol);
                     self.nlocals += 1;
+                } else {
+                    self.globals.push((idx, symbol));
                 }
             }
             DefinedDecl::Section(_) => {
@@ -595,8 +721,14 @@ impl<'a> Elf<'a> {
         }
     }
     /// Create a progbits section (and its section symbol), and return the section index.
-    fn add_progbits(&mut self, name: String, section: SectionBuilder, data: &'a [u8]) -> usize {
+    fn add_progbits<D: Into<Cow<'a, [u8]>>>(
+        &mut self,
+        name: String,
+        section: SectionBuilder,
+        data: D,
+    ) -> usize {
         let (idx, shndx) = self.add_section(name, section);
+        let data = data.into();
         // increment the size
         self.sizeof_bits += data.len();
 
@@ -612,7 +744,7 @@ impl<'a> Elf<'a> {
         );
 
         // the symbols section reference/index will be the current number of sections
-        let shndx = self.sections.len() + 3; // null + strtab + symtab
+        let shndx = self.sections.len() + self.first_section_index();
         let section_symbol = SymbolBuilder::new(SymbolType::Section)
             .section_index(shndx)
             .create();
@@ -631,31 +763,116 @@ impl<'a> Elf<'a> {
         self.nsections += 1;
         (idx, shndx)
     }
+    /// Add an `.init_array` or `.fini_array` section for the constructors or destructors of each
+    /// priority, holding a pointer to each of their functions
+    pub fn add_initializers<I: Iterator<Item = Initializer<'a>>>(
+        &mut self,
+        initializers: I,
+    ) -> Result<(), ArtifactError> {
+        let mut arrays: IndexMap<String, Vec<&'a str>> = IndexMap::new();
+        for init in initializers {
+            let prefix = match init.kind {
+                InitKind::Constructor => ".init_array",
+                InitKind::Destructor => ".fini_array",
+            };
+            // the linker sorts these by the priority in their name
+            let name = match init.priority {
+                Some(priority) => format!("{}.{:05}", prefix, priority),
+                None => prefix.to_string(),
+            };
+            arrays.entry(name).or_default().push(init.name);
+        }
+        if arrays.is_empty() {
+            return Ok(());
+        }
+        // each entry holds the address of its function
+        let reloc = match (self.architecture, self.ctx.is_big()) {
+            (Architecture::Aarch64(_), true) => reloc::R_AARCH64_ABS64,
+            (Architecture::Aarch64(_), false) => reloc::R_AARCH64_ABS32,
+            (Architecture::X86_64, true) => reloc::R_X86_64_64,
+            (Architecture::X86_64, false) => reloc::R_X86_64_32,
+            (architecture, _) => {
+                return Err(ArtifactError::UnsupportedArchitecture {
+                    architecture,
+                    format: BinaryFormat::Elf,
+                })
+            }
+        };
+
+        let pointer_size = self.ctx.size();
+        let mut shndxs = Vec::new();
+        for (name, functions) in &arrays {
+            let typ = if name.starts_with(".init_array") {
+                SectionType::InitArray
+            } else {
+                SectionType::FiniArray
+            };
+            let size = pointer_size * functions.len();
+            let section = SectionBuilder::new(size as u64)
+                .section_type(typ)
+                .alloc()
+                .writable(true)
+                .align(Some(pointer_size as u64));
+            shndxs.push(self.add_progbits(name.clone(), section, vec![0; size]));
+        }
+
+        // symbol indexes are only known once every section has been added
+        for ((name, functions), shndx) in arrays.iter().zip(shndxs) {
+            for (i, function) in functions.iter().enumerate() {
+                let idx = self.strings.get_or_intern(*function);
+                let (sym_idx, _, _) = self
+                    .symbols
+                    .get_full(&idx)
+                    .expect("initializer present in symbols");
+                let sym_idx = sym_idx + self.special_symbols.len() + self.sections.len();
+                let relocation = RelocationBuilder::new(reloc)
+                    .sym(sym_idx)
+                    .offset((i * pointer_size) as u64)
+                    .addend(0)
+                    .create();
+                self.add_reloc(name, relocation, sym_idx, shndx);
+            }
+        }
+        Ok(())
+    }
+    /// Add the symbols of the global and weak definitions, after the local ones
+    pub fn add_globals(&mut self) {
+        self.symbols.extend(self.globals.drain(..));
+    }
     pub fn import(&mut self, import: String, kind: &ImportKind) {
         let (idx, offset) = self.new_string(import);
-        let symbol = SymbolBuilder::new(SymbolType::Import)
+        let symbol = SymbolBuilder::new(SymbolType::Import(*kind))
             .name_offset(offset)
             .create();
-        self.imports.insert(idx, kind.clone());
+        self.imports.insert(idx, *kind);
         self.symbols.insert(idx, symbol);
     }
-    pub fn link(&mut self, l: &LinkAndDecl) {
+    pub fn link(&mut self, l: &LinkAndDecl) -> Result<(), ArtifactError> {
         debug!("Link: {:?}", l);
+        let strings = &mut self.strings;
+        let to_idx = self
+            .link_strings
+            .get_or_insert_with(&l.to, |name| strings.get_or_intern(name));
+        let from_idx = self
+            .link_strings
+            .get_or_insert_with(&l.from, |name| strings.get_or_intern(name));
         let (to_idx, to_shndx) = {
-            let to_idx = self.strings.get_or_intern(l.to.name);
             if l.to.decl.is_section() {
                 let (to_idx, _, _) = self
                     .sections
                     .get_full(&to_idx)
-                    .expect("to_idx present in sections");
+                    .ok_or_else(|| l.missing_symbol())?;
                 // Section symbols come after special symbols.
-                // The section index is after null + strtab + symtab.
-                (to_idx + self.special_symbols.len(), to_idx + 3)
+                // The section index is after null + strtab + symtab + groups.
+                (
+                    to_idx + self.special_symbols.len(),
+                    to_idx + self.first_section_index(),
+                )
             } else {
                 let (to_idx, _, symbol) = self
                     .symbols
                     .get_full(&to_idx)
-                    .expect("to_idx present in symbols");
+                    .ok_or_else(|| l.missing_symbol())?;
                 // Normal symbols come after special symbols and section symbols.
                 (
                     to_idx + self.special_symbols.len() + self.sections.len(),
@@ -664,20 +881,22 @@ impl<'a> Elf<'a> {
             }
         };
         let (from_idx, from_shndx) = {
-            let from_idx = self.strings.get_or_intern(l.from.name);
             if l.from.decl.is_section() {
                 let (from_idx, _, _) = self
                     .sections
                     .get_full(&from_idx)
-                    .expect("from_idx present in sections");
+                    .ok_or_else(|| l.missing_symbol())?;
                 // Section symbols come after special symbols.
-                // The section index is after null + strtab + symtab.
-                (from_idx + self.special_symbols.len(), from_idx + 3)
+                // The section index is after null + strtab + symtab + groups.
+                (
+                    from_idx + self.special_symbols.len(),
+                    from_idx + self.first_section_index(),
+                )
             } else {
                 let (from_idx, _, symbol) = self
                     .symbols
                     .get_full(&from_idx)
-                    .expect("from_idx present in symbols");
+                    .ok_or_else(|| l.missing_symbol())?;
                 // Normal symbols come after special symbols and section symbols.
                 (
                     from_idx + self.special_symbols.len() + self.sections.len(),
@@ -685,55 +904,125 @@ impl<'a> Elf<'a> {
                 )
             }
         };
-        let (reloc, addend) = match l.reloc {
-            Reloc::Auto => {
-                match *l.from.decl {
-                    Decl::Defined(DefinedDecl::Function { .. }) => {
-                        match *l.to.decl {
-                            // NB: this now forces _all_ function references, whether local or not, through the PLT
-                            // although we're not in the worst company here: https://github.com/ocaml/ocaml/pull/1330
-                            Decl::Defined(DefinedDecl::Function { .. })
-                            | Decl::Import(ImportKind::Function) => (reloc::R_X86_64_PLT32, -4),
-                            Decl::Defined(DefinedDecl::Data { .. }) => (reloc::R_X86_64_PC32, -4),
-                            Decl::Import(ImportKind::Data) => (reloc::R_X86_64_GOTPCREL, -4),
-                            _ => panic!("unsupported relocation {:?}", l),
-                        }
-                    }
-                    Decl::Defined(DefinedDecl::Data { .. }) => {
-                        if self.ctx.is_big() {
-                            // Select an absolute relocation that is the size of a pointer.
-                            (reloc::R_X86_64_64, 0)
-                        } else {
-                            (reloc::R_X86_64_32, 0)
-                        }
-                    }
-                    _ => panic!("unsupported relocation {:?}", l),
+        // most relocations patch a single location, but aarch64 materializes addresses with a pair
+        // of instructions, each needing a relocation of its own
+        let relocs = match l.reloc {
+            Reloc::Auto => match self.architecture {
+                Architecture::Aarch64(_) => self.aarch64_auto_relocs(l)?,
+                Architecture::X86_64 => {
+                    vec![(x86_64_auto_reloc(l, self.ctx, self.architecture)?, l.at)]
                 }
-            }
-            Reloc::Raw { reloc, addend } => (reloc, addend),
-            Reloc::Debug { size, addend } => match size {
-                4 => (reloc::R_X86_64_32, addend),
-                8 => (reloc::R_X86_64_64, addend),
-                _ => panic!("unsupported relocation {:?}", l),
+                _ => return Err(self.unsupported(l)),
             },
+            Reloc::Raw { reloc, addend } => vec![((reloc, addend), l.at)],
+            Reloc::Absolute { .. }
+            | Reloc::Call
+            | Reloc::PcRelative { .. }
+            | Reloc::GotPcRelative { .. }
+            | Reloc::SectionOffset { .. }
+            | Reloc::ImageOffset { .. } => vec![(portable_reloc(l, self.architecture)?, l.at)],
+            Reloc::Debug { size, addend } => {
+                // the location of thread-local data is an offset into the thread's block
+                let reloc = match (self.architecture, size, l.to.decl.is_tls()) {
+                    (Architecture::Aarch64(_), 8, true) => reloc::R_AARCH64_TLS_DTPREL,
+                    (Architecture::Aarch64(_), 4, false) => reloc::R_AARCH64_ABS32,
+                    (Architecture::Aarch64(_), 8, false) => reloc::R_AARCH64_ABS64,
+                    (Architecture::Aarch64(_), _, _) => return Err(self.unsupported(l)),
+                    (Architecture::X86_64, 4, true) => reloc::R_X86_64_DTPOFF32,
+                    (Architecture::X86_64, 8, true) => reloc::R_X86_64_DTPOFF64,
+                    (Architecture::X86_64, 4, false) => reloc::R_X86_64_32,
+                    (Architecture::X86_64, 8, false) => reloc::R_X86_64_64,
+                    _ => return Err(self.unsupported(l)),
+                };
+                vec![((reloc, addend), l.at)]
+            }
         };
-        let addend = i64::from(addend);
 
         let sym_idx = match *l.to.decl {
-            Decl::Defined(_) => {
-                // We don't emit symbols for null + strtab + symtab, and
+            // thread-local relocations must refer to the symbol, as the assembler does, and so
+            // must relocations to a group, whose section symbol is discarded with the group
+            Decl::Defined(ref d) if !d.is_tls() && l.to.comdat.is_none() => {
+                // We don't emit symbols for null + strtab + symtab + groups, and
                 // section symbols come after special symbols.
-                (to_shndx - 3) + self.special_symbols.len()
+                (to_shndx - self.first_section_index()) + self.special_symbols.len()
             }
-            Decl::Import(_) => to_idx,
+            Decl::Defined(_) | Decl::Import(_) => to_idx,
         };
 
-        let reloc = RelocationBuilder::new(reloc)
-            .sym(sym_idx)
-            .offset(l.at)
-            .addend(addend)
-            .create();
-        self.add_reloc(l.from.name, reloc, from_idx, from_shndx)
+        for ((reloc, addend), at) in relocs {
+            // the addend of an ELF32 relocation is 32 bits wide
+            if !self.ctx.is_big() && i32::try_from(addend).is_err() {
+                return Err(l.addend_overflow(addend));
+            }
+            let reloc = RelocationBuilder::new(reloc)
+                .sym(sym_idx)
+                .offset(at)
+                .addend(addend)
+                .create();
+            self.add_reloc(l.from.name, reloc, from_idx, from_shndx)
+        }
+        Ok(())
+    }
+    /// The error for a link which has no ELF relocation on this architecture
+    fn unsupported(&self, l: &LinkAndDecl) -> ArtifactError {
+        l.unsupported(self.architecture, BinaryFormat::Elf)
+    }
+    /// The aarch64 relocation types and addends for an `Auto` link, with the offset each applies to.
+    ///
+    /// Code is expected to call functions with a `bl` at `l.at`, and to materialize the address of
+    /// data with an `adrp` at `l.at` followed by an `add` (or an `ldr` from the GOT, for imported
+    /// data) in the next instruction.
+    fn aarch64_auto_relocs(&self, l: &LinkAndDecl) -> Result<Vec<RelocAt>, ArtifactError> {
+        let relocs = match *l.from.decl {
+            Decl::Defined(DefinedDecl::Function { .. }) => match l.to.decl {
+                Decl::Defined(DefinedDecl::Function { .. })
+                | Decl::Import(ImportKind::Function) => {
+                    vec![((reloc::R_AARCH64_CALL26, 0), l.at)]
+                }
+                Decl::Defined(DefinedDecl::Data(d)) if d.is_tls() => Self::aarch64_tlsie_relocs(l),
+                Decl::Import(ImportKind::Tls) => Self::aarch64_tlsie_relocs(l),
+                Decl::Defined(DefinedDecl::Data { .. }) => vec![
+                    ((reloc::R_AARCH64_ADR_PREL_PG_HI21, 0), l.at),
+                    ((reloc::R_AARCH64_ADD_ABS_LO12_NC, 0), l.at + 4),
+                ],
+                Decl::Import(ImportKind::Data) => vec![
+                    ((reloc::R_AARCH64_ADR_GOT_PAGE, 0), l.at),
+                    ((reloc::R_AARCH64_LD64_GOT_LO12_NC, 0), l.at + 4),
+                ],
+                _ => return Err(self.unsupported(l)),
+            },
+            Decl::Defined(DefinedDecl::Data { .. }) if l.to.decl.is_tls() => {
+                return Err(self.unsupported(l))
+            }
+            Decl::Defined(DefinedDecl::Data { .. }) => {
+                if self.ctx.is_big() {
+                    vec![((reloc::R_AARCH64_ABS64, 0), l.at)]
+                } else {
+                    vec![((reloc::R_AARCH64_ABS32, 0), l.at)]
+                }
+            }
+            _ => return Err(self.unsupported(l)),
+        };
+        // an addend would offset the GOT entry, rather than the import or thread-local data in it
+        let indirect = relocs.iter().any(|&((typ, _), _)| {
+            typ == reloc::R_AARCH64_ADR_GOT_PAGE
+                || typ == reloc::R_AARCH64_TLSIE_ADR_GOTTPREL_PAGE21
+        });
+        if indirect && l.addend != 0 {
+            return Err(self.unsupported(l));
+        }
+        Ok(relocs
+            .into_iter()
+            .map(|((typ, _), at)| ((typ, l.addend), at))
+            .collect())
+    }
+    /// The aarch64 initial exec relocations for thread-local data, loading its offset from the
+    /// thread pointer out of the GOT with an `adrp` at `l.at` and an `ldr` in the next instruction
+    fn aarch64_tlsie_relocs(l: &LinkAndDecl) -> Vec<RelocAt> {
+        vec![
+            ((reloc::R_AARCH64_TLSIE_ADR_GOTTPREL_PAGE21, 0), l.at),
+            ((reloc::R_AARCH64_TLSIE_LD64_GOTTPREL_LO12_NC, 0), l.at + 4),
+        ]
     }
     fn add_reloc(&mut self, relocee: &str, reloc: Relocation, idx: usize, shndx: usize) {
         debug!(
@@ -750,16 +1039,20 @@ impl<'a> Elf<'a> {
         } else {
             debug!("{} does NOT have relocs", relocee);
             // now create the relocation section
-            let reloc_name = {
+            let (reloc_name, group_flag) = {
                 let (_, section) = self
                     .sections
-                    .get_index(shndx - 3)
+                    .get_index(shndx - self.first_section_index())
                     .expect("shndx present in sections");
                 let section_name = self
                     .strings
                     .resolve(section.name)
                     .expect("section name in strings");
-                format!(".rela{}", section_name)
+                (
+                    format!(".rela{}", section_name),
+                    // relocations are in the same group as the section they apply to
+                    section.header.sh_flags & u64::from(section_header::SHF_GROUP),
+                )
             };
             let (_reloc_idx, reloc_section_offset) = self.new_string(reloc_name);
             let mut reloc_section = SectionBuilder::new(reloc_size)
@@ -770,7 +1063,7 @@ impl<'a> Elf<'a> {
             reloc_section.sh_link = SYMTAB_LINK as u32;
             // info tells us which section these relocations apply to
             reloc_section.sh_info = shndx as u32;
-            reloc_section.sh_flags |= section_header::SHF_INFO_LINK as u64;
+            reloc_section.sh_flags |= section_header::SHF_INFO_LINK as u64 | group_flag;
             self.relocations.insert(shndx, (reloc_section, vec![reloc]));
             self.nsections += 1;
         }
@@ -781,8 +1074,10 @@ impl<'a> Elf<'a> {
             *offset += sizeof_t - alignment;
         }
     }
-    pub fn write<T: Write + Seek>(mut self, file: T) -> goblin::error::Result<()> {
+    pub fn write<T: Write + Seek>(mut self, mut file: T) -> goblin::error::Result<()> {
         use goblin::elf::section_header::{SHN_LORESERVE, SHN_XINDEX};
+        // the file starts wherever the sink is, which every offset is relative to
+        let base = file.stream_position()?;
         let mut file = BufWriter::new(file);
 
         /////////////////////////////////////
@@ -796,7 +1091,7 @@ impl<'a> Elf<'a> {
         let mut sizeof_symtab_shndx = 0;
         let mut symtab_shndx_name_offset = 0;
         let mut need_symtab_shndx = false;
-        if self.nsections >= SHN_LORESERVE.into() {
+        if self.nsections >= SHN_LORESERVE {
             self.nsections += 1;
             sizeof_symtab_shndx = symbol_count as u64 * 4;
             symtab_shndx_name_offset = self.new_string(".symtab_shndx".into()).1;
@@ -805,9 +1100,14 @@ impl<'a> Elf<'a> {
         let sizeof_relocs = self
             .relocations
             .iter()
-            .fold(0, |acc, (_, &(ref _shdr, ref rels))| rels.len() + acc)
+            .fold(0, |acc, (_, (_shdr, rels))| rels.len() + acc)
             * Relocation::size(true, self.ctx);
         let nonexec_stack_note_name_offset = self.new_string(".note.GNU-stack".into()).1;
+        let group_name_offset = if self.groups.is_empty() {
+            0
+        } else {
+            self.new_string(".group".into()).1
+        };
         let strtab_offset = self.sizeof_bits as u64;
 
         // alignment required for below
@@ -822,7 +1122,27 @@ impl<'a> Elf<'a> {
         let mut reloc_offset = symtab_shndx_offset + sizeof_symtab_shndx;
         let reloc_align = self.ctx.size() as u64;
         Self::align(&mut reloc_offset, reloc_align);
-        let mut sh_offset = reloc_offset + sizeof_relocs as u64;
+        // each group is a flag word, followed by the indexes of its sections and their relocations
+        let group_sections = self
+            .groups
+            .values()
+            .map(|members| {
+                let mut contents = vec![GRP_COMDAT];
+                contents.extend(members.iter().map(|&shndx| shndx as u32));
+                contents.extend(members.iter().filter_map(|shndx| {
+                    self.relocations.get_index_of(shndx).map(|i| {
+                        (self.first_section_index()
+                            + self.sections.len()
+                            + need_symtab_shndx as usize
+                            + i) as u32
+                    })
+                }));
+                contents
+            })
+            .collect::<Vec<_>>();
+        let groups_offset = reloc_offset + sizeof_relocs as u64;
+        let sizeof_groups = group_sections.iter().map(Vec::len).sum::<usize>() as u64 * 4;
+        let mut sh_offset = groups_offset + sizeof_groups;
         let shdr_align = self.ctx.size() as u64;
         Self::align(&mut sh_offset, shdr_align);
 
@@ -835,11 +1155,10 @@ impl<'a> Elf<'a> {
         // Header
         /////////////////////////////////////
         let mut header = Header::new(self.ctx);
-        let machine: MachineTag = self.architecture.into();
-        header.e_machine = machine.0;
+        header.e_machine = self.machine.0;
         header.e_type = header::ET_REL;
         header.e_shoff = sh_offset;
-        header.e_shnum = if self.nsections >= SHN_LORESERVE.into() {
+        header.e_shnum = if self.nsections >= SHN_LORESERVE {
             0
         } else {
             self.nsections as u16
@@ -847,7 +1166,7 @@ impl<'a> Elf<'a> {
         header.e_shstrndx = STRTAB_LINK;
 
         file.iowrite_with(header, self.ctx)?;
-        let after_header = file.seek(Current(0))?;
+        let after_header = file.stream_position()? - base;
         debug!("after_header {:#x}", after_header);
         assert_eq!(after_header, Header::size(self.ctx) as u64);
 
@@ -856,9 +1175,9 @@ impl<'a> Elf<'a> {
         /////////////////////////////////////
 
         for (_idx, bytes) in self.code.drain(..) {
-            file.write_all(bytes)?;
+            file.write_all(&bytes)?;
         }
-        let after_code = file.seek(Current(0))?;
+        let after_code = file.stream_position()? - base;
         debug!("after_code {:#x}", after_code);
         assert_eq!(after_code, strtab_offset);
 
@@ -867,7 +1186,7 @@ impl<'a> Elf<'a> {
         /////////////////////////////////////
 
         let mut section_headers = vec![SectionHeader::default()];
-        if self.nsections >= SHN_LORESERVE.into() {
+        if self.nsections >= SHN_LORESERVE {
             section_headers[0].sh_size = self.nsections as u64;
         }
         let mut strtab = {
@@ -894,10 +1213,30 @@ impl<'a> Elf<'a> {
         symtab.sh_info = (self.special_symbols.len() + self.sections.len() + self.nlocals) as u32;
         section_headers.push(symtab);
 
+        let mut group_offset = groups_offset;
+        for (group, contents) in self.groups.keys().zip(&group_sections) {
+            let size = contents.len() as u64 * 4;
+            let mut section = SectionBuilder::new(size)
+                .name_offset(group_name_offset)
+                .section_type(SectionType::Group)
+                .create(&self.ctx);
+            section.sh_offset = group_offset;
+            section.sh_link = SYMTAB_LINK as u32;
+            // the group's signature symbol
+            let signature = self
+                .strings
+                .get(*group)
+                .and_then(|idx| self.symbols.get_index_of(&idx))
+                .expect("group has a signature symbol");
+            section.sh_info = (signature + self.special_symbols.len() + self.sections.len()) as u32;
+            section_headers.push(section);
+            group_offset += size;
+        }
+
         /////////////////////////////////////
         // Strtab
         /////////////////////////////////////
-        file.seek(Start(strtab_offset))?;
+        file.seek(Start(base + strtab_offset))?;
         file.iowrite(0u8)?; // for the null value in the strtab;
         for (_id, string) in &self.strings {
             debug!("String: {:?}", string);
@@ -905,7 +1244,7 @@ impl<'a> Elf<'a> {
             file.iowrite(0u8)?;
         }
         {
-            let mut after_strtab = file.seek(Current(0))?;
+            let mut after_strtab = file.stream_position()? - base;
             Self::align(&mut after_strtab, symtab_align);
             debug!("after_strtab {:#x}", after_strtab);
             assert_eq!(after_strtab, symtab_offset);
@@ -920,7 +1259,7 @@ impl<'a> Elf<'a> {
             Vec::new()
         };
         let mut offset = 0;
-        file.seek(Start(symtab_offset))?;
+        file.seek(Start(base + symtab_offset))?;
         for symbol in self.special_symbols.into_iter() {
             debug!("Special Symbol: {:?}", symbol);
             // the special symbols's section indexs have special meanings
@@ -934,7 +1273,7 @@ impl<'a> Elf<'a> {
         }
         for (_id, section) in self.sections.into_iter() {
             debug!("Section Symbol: {:?}", section.symbol);
-            let mut sym = section.symbol.clone();
+            let mut sym = section.symbol;
             if need_symtab_shndx {
                 symtab_shndx_data
                     .gwrite_with(sym.st_shndx as u32, &mut offset, self.ctx.le)
@@ -948,7 +1287,7 @@ impl<'a> Elf<'a> {
         }
         for (_id, symbol) in self.symbols.into_iter() {
             debug!("Symbol: {:?}", symbol);
-            let mut sym = symbol.clone();
+            let mut sym = symbol;
             if need_symtab_shndx {
                 symtab_shndx_data
                     .gwrite_with(sym.st_shndx as u32, &mut offset, self.ctx.le)
@@ -961,12 +1300,12 @@ impl<'a> Elf<'a> {
         }
         if need_symtab_shndx {
             {
-                let mut after_symtab = file.seek(Current(0))?;
+                let mut after_symtab = file.stream_position()? - base;
                 Self::align(&mut after_symtab, symtab_shndx_align);
                 debug!("after_symtab {:#x}", after_symtab);
                 assert_eq!(after_symtab, symtab_shndx_offset);
             }
-            file.seek(Start(symtab_shndx_offset))?;
+            file.seek(Start(base + symtab_shndx_offset))?;
             file.write_all(&symtab_shndx_data)?;
             let mut section = SectionBuilder::new(sizeof_symtab_shndx)
                 .name_offset(symtab_shndx_name_offset)
@@ -977,7 +1316,7 @@ impl<'a> Elf<'a> {
             section_headers.push(section);
         }
         {
-            let mut after_symtab_shndx = file.seek(Current(0))?;
+            let mut after_symtab_shndx = file.stream_position()? - base;
             Self::align(&mut after_symtab_shndx, reloc_align);
             debug!(
                 "after_symtab_shndx {:#x} - shdr_size {}",
@@ -990,7 +1329,7 @@ impl<'a> Elf<'a> {
         /////////////////////////////////////
         // Relocations
         /////////////////////////////////////
-        file.seek(Start(reloc_offset))?;
+        file.seek(Start(base + reloc_offset))?;
         let mut roffset = reloc_offset;
         for (_, (mut section, mut relocations)) in self.relocations.into_iter() {
             section.sh_offset = roffset;
@@ -1001,8 +1340,17 @@ impl<'a> Elf<'a> {
                 file.iowrite_with(relocation, (relocation.r_addend.is_some(), self.ctx))?;
             }
         }
+
+        /////////////////////////////////////
+        // Groups
+        /////////////////////////////////////
+        for contents in group_sections {
+            for word in contents {
+                file.iowrite_with(word, self.ctx.le)?;
+            }
+        }
         {
-            let mut after_relocs = file.seek(Current(0))?;
+            let mut after_relocs = file.stream_position()? - base;
             Self::align(&mut after_relocs, shdr_align);
             debug!("after_relocs {:#x}", after_relocs);
             assert_eq!(after_relocs, sh_offset);
@@ -1023,14 +1371,14 @@ impl<'a> Elf<'a> {
         let sizeof_shdr = Section::size(self.ctx) as u64;
         let shdr_size = section_headers.len() as u64 * sizeof_shdr;
 
-        file.seek(Start(sh_offset))?;
+        file.seek(Start(base + sh_offset))?;
         for shdr in section_headers {
             debug!("Section: {:?}", shdr);
             file.iowrite_with(shdr, self.ctx)?;
         }
 
         {
-            let after_shdrs = file.seek(Current(0))?;
+            let after_shdrs = file.stream_position()? - base;
             let expected = sh_offset + shdr_size;
             debug!("after_shdrs {:#x}", after_shdrs);
             assert_eq!(after_shdrs, expected);
@@ -1041,22 +1389,105 @@ impl<'a> Elf<'a> {
     }
 }
 
-pub fn to_bytes(artifact: &Artifact) -> goblin::error::Result<Vec<u8>> {
+/// The x86-64 relocation type and addend for an `Auto` link, including the link's own addend
+pub(crate) fn x86_64_auto_reloc(
+    l: &LinkAndDecl,
+    ctx: Ctx,
+    architecture: Architecture,
+) -> Result<(u32, i64), ArtifactError> {
+    let (typ, addend): (u32, i64) = match *l.from.decl {
+        Decl::Defined(DefinedDecl::Function { .. }) => {
+            match l.to.decl {
+                // NB: this now forces _all_ function references, whether local or not, through the PLT
+                // although we're not in the worst company here: https://github.com/ocaml/ocaml/pull/1330
+                Decl::Defined(DefinedDecl::Function { .. })
+                | Decl::Import(ImportKind::Function) => (reloc::R_X86_64_PLT32, -4),
+                // thread-local data uses the initial exec model, e.g. `movq x@gottpoff(%rip), %rax`
+                Decl::Defined(DefinedDecl::Data(d)) if d.is_tls() => (reloc::R_X86_64_GOTTPOFF, -4),
+                Decl::Import(ImportKind::Tls) => (reloc::R_X86_64_GOTTPOFF, -4),
+                Decl::Defined(DefinedDecl::Data { .. }) => (reloc::R_X86_64_PC32, -4),
+                Decl::Import(ImportKind::Data) => (reloc::R_X86_64_GOTPCREL, -4),
+                _ => return Err(l.unsupported(architecture, BinaryFormat::Elf)),
+            }
+        }
+        // thread-local data has no address until a thread is running
+        Decl::Defined(DefinedDecl::Data { .. }) if l.to.decl.is_tls() => {
+            return Err(l.unsupported(architecture, BinaryFormat::Elf))
+        }
+        Decl::Defined(DefinedDecl::Data { .. }) => {
+            if ctx.is_big() {
+                // Select an absolute relocation that is the size of a pointer.
+                (reloc::R_X86_64_64, 0)
+            } else {
+                (reloc::R_X86_64_32, 0)
+            }
+        }
+        _ => return Err(l.unsupported(architecture, BinaryFormat::Elf)),
+    };
+    let addend = addend
+        .checked_add(l.addend)
+        .ok_or_else(|| l.addend_overflow(l.addend))?;
+    Ok((typ, addend))
+}
+
+/// The relocation type and addend of a link with one of the portable relocation kinds
+pub(crate) fn portable_reloc(
+    l: &LinkAndDecl,
+    architecture: Architecture,
+) -> Result<(u32, i64), ArtifactError> {
+    let unsupported = || l.unsupported(architecture, BinaryFormat::Elf);
+    // the addends of PC-relative ELF relocations are relative to the start of the field
+    let from_end = |addend: i64| {
+        addend
+            .checked_sub(4)
+            .ok_or_else(|| l.addend_overflow(addend))
+    };
+    // debug sections aren't allocated, so the address of anything in them is its offset
+    let debug = matches!(
+        l.to.decl,
+        Decl::Defined(DefinedDecl::Section(s)) if s.kind() == SectionKind::Debug
+    );
+    Ok(match (architecture, l.reloc) {
+        (Architecture::X86_64, reloc) => match reloc {
+            Reloc::Absolute { size: 4, addend } => (reloc::R_X86_64_32, addend),
+            Reloc::Absolute { size: 8, addend } => (reloc::R_X86_64_64, addend),
+            Reloc::Call => (reloc::R_X86_64_PLT32, -4),
+            Reloc::PcRelative { addend } => (reloc::R_X86_64_PC32, from_end(addend)?),
+            Reloc::GotPcRelative { addend } => (reloc::R_X86_64_GOTPCREL, from_end(addend)?),
+            Reloc::SectionOffset { size: 4, addend } if debug => (reloc::R_X86_64_32, addend),
+            Reloc::SectionOffset { size: 8, addend } if debug => (reloc::R_X86_64_64, addend),
+            _ => return Err(unsupported()),
+        },
+        (Architecture::Aarch64(_), reloc) => match reloc {
+            Reloc::Absolute { size: 4, addend } => (reloc::R_AARCH64_ABS32, addend),
+            Reloc::Absolute { size: 8, addend } => (reloc::R_AARCH64_ABS64, addend),
+            Reloc::Call => (reloc::R_AARCH64_CALL26, 0),
+            Reloc::PcRelative { addend } => (reloc::R_AARCH64_PREL32, from_end(addend)?),
+            Reloc::SectionOffset { size: 4, addend } if debug => (reloc::R_AARCH64_ABS32, addend),
+            Reloc::SectionOffset { size: 8, addend } if debug => (reloc::R_AARCH64_ABS64, addend),
+            _ => return Err(unsupported()),
+        },
+        _ => return Err(unsupported()),
+    })
+}
+
+pub fn write_to<W: Write + Seek>(artifact: &Artifact, sink: W) -> Result<(), ArtifactError> {
     // TODO: make new fully construct the elf object, e.g., the definitions, imports, and links don't take self
     // this means that a call to new has a fully constructed object ready to marshal into bytes, similar to the mach backend
-    let mut elf = Elf::new(&artifact);
+    let mut elf = Elf::new(artifact)?;
     for def in artifact.definitions() {
         debug!("Def: {:?}", def);
         elf.add_definition(def);
     }
+    elf.add_globals();
+    elf.add_initializers(artifact.initializers())?;
     for (ref import, ref kind) in artifact.imports() {
         debug!("Import: {:?} -> {:?}", import, kind);
         elf.import(import.to_string(), kind);
     }
     for link in artifact.links() {
-        elf.link(&link);
+        elf.link(&link)?;
     }
-    let mut buffer = Cursor::new(Vec::new());
-    elf.write(&mut buffer)?;
-    Ok(buffer.into_inner())
+    elf.write(sink)?;
+    Ok(())
 }
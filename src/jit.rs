@@ -0,0 +1,484 @@
+//! An in-process loader which maps an artifact straight into executable memory, to run freshly
+//! compiled code without emitting an object file and going through the system linker.
+
+use crate::artifact::{
+    ArtifactError, Decl, DefinedDecl, ImportKind, LinkAndDecl, Reloc, SectionKind,
+};
+use crate::Artifact;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::ptr;
+use target_lexicon::{Architecture, BinaryFormat, HOST};
+
+/// The size of a function import stub, which jumps to the address stored at its end
+const STUB_SIZE: usize = 16;
+/// The size of an address in the global offset table of data imports
+const GOT_ENTRY_SIZE: usize = 8;
+
+/// The segments of the mapping, each with the memory protection of its contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Segment {
+    /// Functions, code sections and import stubs
+    Text,
+    /// Read-only data and the addresses of data imports
+    ReadOnly,
+    /// Writable data, data sections and zero-initialized data
+    Writable,
+}
+
+impl Segment {
+    fn of(decl: &DefinedDecl) -> Option<Segment> {
+        match decl {
+            DefinedDecl::Function(_) => Some(Segment::Text),
+            // each thread needs its own copy, which a plain mapping can't provide
+            DefinedDecl::Data(d) if d.is_tls() => None,
+            DefinedDecl::Data(d) if d.is_writable() => Some(Segment::Writable),
+            DefinedDecl::Data(_) => Some(Segment::ReadOnly),
+            DefinedDecl::Section(s) => match s.kind() {
+                SectionKind::Text => Some(Segment::Text),
+                SectionKind::Data => Some(Segment::Writable),
+                // nothing reads debug info out of the process itself
+                SectionKind::Debug => None,
+            },
+        }
+    }
+    fn protection(self) -> libc::c_int {
+        match self {
+            Segment::Text => libc::PROT_READ | libc::PROT_EXEC,
+            Segment::ReadOnly => libc::PROT_READ,
+            Segment::Writable => libc::PROT_READ | libc::PROT_WRITE,
+        }
+    }
+}
+
+/// A contiguous run of the mapping, laid out before it is allocated
+#[derive(Debug, Default)]
+struct SegmentLayout {
+    /// Offset of the segment in the mapping, page aligned
+    offset: usize,
+    /// Size of the contents of the segment
+    size: usize,
+}
+
+impl SegmentLayout {
+    /// Reserve `size` bytes aligned to `align`, returning their offset in the segment
+    fn reserve(&mut self, size: usize, align: usize) -> usize {
+        let offset = align_up(self.size, align);
+        self.size = offset + size;
+        offset
+    }
+}
+
+fn align_up(offset: usize, align: usize) -> usize {
+    (offset + align - 1) & !(align - 1)
+}
+
+/// An artifact loaded into memory of this process, with its links applied and its imports resolved.
+///
+/// Functions are mapped readable and executable, read-only data readable, and writable and
+/// zero-initialized data readable and writable. Links are applied the way the ELF backend would
+/// relocate them, so only `Reloc::Auto` links between definitions and imports which ELF supports
+/// can be loaded. Thread-local data and debug sections aren't loaded.
+///
+/// The memory is unmapped when the module is dropped, so the pointers it hands out must not be used
+/// after that.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use faerie::jit::JitModule;
+/// use faerie::{Artifact, Decl, Triple};
+///
+/// let mut obj = Artifact::new(Triple::host(), "jit".into());
+/// obj.declare("answer", Decl::function()).unwrap();
+/// // mov $42, %eax; ret
+/// obj.define("answer", vec![0xb8, 0x2a, 0, 0, 0, 0xc3]).unwrap();
+///
+/// let module = JitModule::load(&obj, |_| None).unwrap();
+/// let answer = module.function("answer").unwrap();
+/// let answer: extern "C" fn() -> u32 = unsafe { std::mem::transmute(answer) };
+/// assert_eq!(answer(), 42);
+/// ```
+#[derive(Debug)]
+pub struct JitModule {
+    memory: *mut u8,
+    len: usize,
+    functions: HashMap<String, *const u8>,
+    data: HashMap<String, *const u8>,
+}
+
+impl JitModule {
+    /// Map the definitions of `artifact` into memory, resolving the address of each of its imports
+    /// with `resolver`, and apply its links.
+    ///
+    /// The artifact must be created for the architecture of this process.
+    pub fn load<F>(artifact: &Artifact, mut resolver: F) -> Result<JitModule, ArtifactError>
+    where
+        F: FnMut(&str) -> Option<*const u8>,
+    {
+        let architecture = artifact.target.architecture;
+        if architecture != HOST.architecture {
+            return Err(ArtifactError::ForeignTarget(artifact.target.clone()));
+        }
+        if !matches!(
+            architecture,
+            Architecture::X86_64 | Architecture::Aarch64(_)
+        ) {
+            return Err(ArtifactError::UnsupportedArchitecture {
+                architecture,
+                format: BinaryFormat::Elf,
+            });
+        }
+        let undef = artifact.undefined_symbols();
+        if !undef.is_empty() {
+            return Err(ArtifactError::UndefinedSymbols(undef));
+        }
+        artifact.check_links()?;
+
+        // lay out every definition, followed by the stubs and addresses of the imports
+        let mut text = SegmentLayout::default();
+        let mut rodata = SegmentLayout::default();
+        let mut data = SegmentLayout::default();
+        let mut definitions = Vec::new();
+        for def in artifact.definitions() {
+            let segment = match Segment::of(def.decl) {
+                Some(segment) => segment,
+                None => continue,
+            };
+            let layout = match segment {
+                Segment::Text => &mut text,
+                Segment::ReadOnly => &mut rodata,
+                Segment::Writable => &mut data,
+            };
+            // pointer aligned data can be read through the pointers handed out without surprises
+            let align = def.decl.get_align().unwrap_or(match segment {
+                Segment::Text => 16,
+                Segment::ReadOnly | Segment::Writable => 8,
+            }) as usize;
+            let size = def.data.size();
+            let offset = layout.reserve(size, align);
+            definitions.push((def, segment, offset, size));
+        }
+        let mut stubs = Vec::new();
+        let mut got = Vec::new();
+        for (name, kind) in artifact.imports() {
+            match kind {
+                ImportKind::Function => stubs.push((name, text.reserve(STUB_SIZE, STUB_SIZE))),
+                ImportKind::Data => {
+                    got.push((name, rodata.reserve(GOT_ENTRY_SIZE, GOT_ENTRY_SIZE)))
+                }
+                // thread-local imports are only reachable through links, which are unsupported
+                ImportKind::Tls => (),
+            }
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        rodata.offset = align_up(text.offset + text.size, page_size);
+        data.offset = align_up(rodata.offset + rodata.size, page_size);
+        let len = align_up(data.offset + data.size, page_size);
+
+        let mut module = JitModule::map(len)?;
+        let base = module.memory as usize;
+        let segment_offset = |segment| match segment {
+            Segment::Text => text.offset,
+            Segment::ReadOnly => rodata.offset,
+            Segment::Writable => data.offset,
+        };
+
+        // the address and extent of every definition, and the address of every import
+        let mut addresses = HashMap::new();
+        for (def, segment, offset, size) in &definitions {
+            let offset = segment_offset(*segment) + offset;
+            if let Some(bytes) = def.data.bytes() {
+                module.bytes_mut()[offset..offset + size].copy_from_slice(bytes);
+            }
+            let address = (base + offset) as *const u8;
+            addresses.insert(def.name, (offset, *size));
+            if def.decl.is_function() {
+                module.functions.insert(def.name.to_string(), address);
+            } else {
+                module.data.insert(def.name.to_string(), address);
+                for (symbol, symbol_offset) in def.symbols {
+                    let address = (base + offset + *symbol_offset as usize) as *const u8;
+                    module.data.insert(symbol.clone(), address);
+                }
+            }
+        }
+        let mut imports = HashMap::new();
+        for (name, kind) in artifact.imports() {
+            if let ImportKind::Tls = kind {
+                continue;
+            }
+            let address =
+                resolver(name).ok_or_else(|| ArtifactError::UnresolvedImport(name.to_string()))?;
+            imports.insert(name, address as usize);
+        }
+        let mut indirections = HashMap::new();
+        for (name, offset) in stubs {
+            let offset = text.offset + offset;
+            module.write_stub(architecture, offset, imports[name]);
+            indirections.insert(name, base + offset);
+        }
+        for (name, offset) in got {
+            let offset = rodata.offset + offset;
+            write(
+                module.bytes_mut(),
+                offset,
+                &(imports[name] as u64).to_le_bytes(),
+            );
+            indirections.insert(name, base + offset);
+        }
+
+        let targets = Targets {
+            base,
+            addresses: &addresses,
+            imports: &imports,
+            indirections: &indirections,
+        };
+        for link in artifact.links() {
+            // debug info isn't loaded, so neither are its links
+            if let Decl::Defined(DefinedDecl::Section(s)) = link.from.decl {
+                if s.kind() == SectionKind::Debug {
+                    continue;
+                }
+            }
+            module.link(architecture, &link, &targets)?;
+        }
+
+        for (segment, layout) in &[
+            (Segment::Text, &text),
+            (Segment::ReadOnly, &rodata),
+            (Segment::Writable, &data),
+        ] {
+            let size = align_up(layout.size, page_size);
+            module.protect(layout.offset, size, segment.protection())?;
+        }
+        if let Architecture::Aarch64(_) = architecture {
+            clear_cache(module.memory, text.size);
+        }
+        Ok(module)
+    }
+
+    /// The address of the function `name`, which can be transmuted into a function pointer of its
+    /// signature
+    pub fn function(&self, name: &str) -> Option<*const u8> {
+        self.functions.get(name).copied()
+    }
+
+    /// The address of the data object or section `name`, or of a custom symbol in a section
+    pub fn data(&self, name: &str) -> Option<*const u8> {
+        self.data.get(name).copied()
+    }
+
+    fn map(len: usize) -> Result<JitModule, ArtifactError> {
+        let memory = if len == 0 {
+            ptr::null_mut()
+        } else {
+            let memory = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                )
+            };
+            if memory == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error().into());
+            }
+            memory as *mut u8
+        };
+        Ok(JitModule {
+            memory,
+            len,
+            functions: HashMap::new(),
+            data: HashMap::new(),
+        })
+    }
+
+    fn protect(&self, offset: usize, size: usize, protection: libc::c_int) -> io::Result<()> {
+        if size == 0 {
+            return Ok(());
+        }
+        let start = unsafe { self.memory.add(offset) } as *mut libc::c_void;
+        if unsafe { libc::mprotect(start, size, protection) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        // the mapping stays writable until it is protected, once everything is written
+        unsafe { std::slice::from_raw_parts_mut(self.memory, self.len) }
+    }
+
+    /// Write a stub at `offset` which jumps to `address`, so that calls from code reach imports
+    /// anywhere in the address space
+    fn write_stub(&mut self, architecture: Architecture, offset: usize, address: usize) {
+        let stub: &[u8] = match architecture {
+            // jmp *0(%rip)
+            Architecture::X86_64 => &[0xff, 0x25, 0, 0, 0, 0],
+            // ldr x16, #8; br x16
+            _ => &[0x50, 0, 0, 0x58, 0, 0x02, 0x1f, 0xd6],
+        };
+        let bytes = self.bytes_mut();
+        let address_offset = match architecture {
+            Architecture::X86_64 => 6,
+            _ => 8,
+        };
+        bytes[offset..offset + stub.len()].copy_from_slice(stub);
+        write(
+            bytes,
+            offset + address_offset,
+            &(address as u64).to_le_bytes(),
+        );
+    }
+
+    /// Apply `link` in place, the way the ELF backend's relocation of it would be applied by a
+    /// linker
+    fn link(
+        &mut self,
+        architecture: Architecture,
+        link: &LinkAndDecl,
+        targets: &Targets,
+    ) -> Result<(), ArtifactError> {
+        let unsupported = || link.unsupported(architecture, BinaryFormat::Elf);
+        if link.reloc != Reloc::Auto {
+            return Err(unsupported());
+        }
+        let &(from, from_size) = targets
+            .addresses
+            .get(link.from.name)
+            .ok_or_else(unsupported)?;
+        let at = from + link.at as usize;
+        let place = targets.base + at;
+        let from_function = matches!(link.from.decl, Decl::Defined(DefinedDecl::Function(_)));
+        let from_data = matches!(link.from.decl, Decl::Defined(DefinedDecl::Data(_)));
+        // the address of the target itself, or the stub or address of an import reached from code
+        let target = match link.to.decl {
+            _ if link.to.decl.is_tls() => return Err(unsupported()),
+            Decl::Import(_) if from_function => targets.indirections[link.to.name],
+            Decl::Import(_) => targets.imports[link.to.name],
+            Decl::Defined(_) => match targets.addresses.get(link.to.name) {
+                Some(&(offset, _)) => targets.base + offset,
+                None => return Err(link.missing_symbol()),
+            },
+        };
+        // which relocation the ELF backend would pick, by the number of bytes it patches
+        let size = match (from_function, link.to.decl, architecture) {
+            (true, Decl::Defined(DefinedDecl::Section(_)), _) => return Err(unsupported()),
+            (true, _, Architecture::X86_64) => 4,
+            (true, Decl::Defined(DefinedDecl::Function(_)), _)
+            | (true, Decl::Import(ImportKind::Function), _) => 4,
+            (true, _, _) => 8,
+            (false, _, _) if from_data => 8,
+            (false, _, _) => return Err(unsupported()),
+        };
+        if link.at as usize + size > from_size {
+            return Err(unsupported());
+        }
+        // an addend would offset the GOT entry arm64 loads the address of imported data from
+        let indirect = from_function && matches!(link.to.decl, Decl::Import(ImportKind::Data));
+        if link.addend != 0 && indirect && architecture != Architecture::X86_64 {
+            return Err(unsupported());
+        }
+        let target = (target as i64)
+            .checked_add(link.addend)
+            .ok_or_else(|| link.addend_overflow(link.addend))?;
+        let bytes = self.bytes_mut();
+        match (from_function, architecture) {
+            (true, Architecture::X86_64) => {
+                // PLT32, PC32 and GOTPCREL all land on the end of the 4 byte displacement
+                let value = target.wrapping_sub(place as i64 + 4);
+                let value = i32::try_from(value).map_err(|_| unsupported())?;
+                write(bytes, at, &value.to_le_bytes());
+            }
+            (true, _) if size == 4 => {
+                // bl: a signed 26 bit word offset
+                let offset = target.wrapping_sub(place as i64);
+                if offset % 4 != 0 || !(-(1 << 27)..1 << 27).contains(&offset) {
+                    return Err(unsupported());
+                }
+                let insn = read_u32(bytes, at);
+                let insn = (insn & 0xfc00_0000) | ((offset >> 2) as u32 & 0x03ff_ffff);
+                write(bytes, at, &insn.to_le_bytes());
+            }
+            (true, _) => {
+                // adrp: a signed 21 bit page offset
+                let pages = (target >> 12) - (place as i64 >> 12);
+                if !(-(1 << 20)..1 << 20).contains(&pages) {
+                    return Err(unsupported());
+                }
+                let insn = read_u32(bytes, at);
+                let (immlo, immhi) = (pages as u32 & 0x3, (pages >> 2) as u32 & 0x7_ffff);
+                let insn = (insn & 0x9f00_001f) | (immlo << 29) | (immhi << 5);
+                write(bytes, at, &insn.to_le_bytes());
+                // add, or ldr of the 8 byte aligned address of an import: the offset in the page
+                let imm = match link.to.decl {
+                    Decl::Import(_) => (target & 0xfff) >> 3,
+                    _ => target & 0xfff,
+                } as u32;
+                let insn = read_u32(bytes, at + 4);
+                let insn = (insn & !(0xfff << 10)) | (imm << 10);
+                write(bytes, at + 4, &insn.to_le_bytes());
+            }
+            (false, _) => {
+                write(bytes, at, &(target as u64).to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for JitModule {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe {
+                libc::munmap(self.memory as *mut libc::c_void, self.len);
+            }
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[at..at + 4]);
+    u32::from_le_bytes(word)
+}
+
+fn write(bytes: &mut [u8], at: usize, value: &[u8]) {
+    bytes[at..at + value.len()].copy_from_slice(value);
+}
+
+/// Where the links of an artifact being loaded point to
+struct Targets<'a> {
+    /// Address of the mapping
+    base: usize,
+    /// Offset in the mapping and size of every loaded definition
+    addresses: &'a HashMap<&'a str, (usize, usize)>,
+    /// Resolved address of every import
+    imports: &'a HashMap<&'a str, usize>,
+    /// Address of the stub of every function import, and of the address of every data import
+    indirections: &'a HashMap<&'a str, usize>,
+}
+
+#[cfg(target_arch = "aarch64")]
+fn clear_cache(start: *mut u8, len: usize) {
+    extern "C" {
+        fn __clear_cache(start: *mut libc::c_char, end: *mut libc::c_char);
+    }
+    unsafe {
+        __clear_cache(
+            start as *mut libc::c_char,
+            start.add(len) as *mut libc::c_char,
+        );
+    }
+}
+
+#[cfg(not(target_arch = "aarch64"))]
+fn clear_cache(_start: *mut u8, _len: usize) {}
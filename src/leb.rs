@@ -0,0 +1,27 @@
+//! LEB128 encoding of integers, as used by DWARF, unwind tables and WebAssembly.
+
+/// Append `value` to `buf` as an unsigned LEB128
+pub(crate) fn write_uleb(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Append `value` to `buf` as a signed LEB128
+pub(crate) fn write_sleb(buf: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
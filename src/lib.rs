@@ -3,6 +3,8 @@
 
 extern crate goblin;
 extern crate indexmap;
+#[cfg(feature = "jit")]
+extern crate libc;
 extern crate scroll;
 extern crate string_interner;
 #[macro_use]
@@ -13,17 +15,29 @@ use goblin::container;
 
 type Ctx = container::Ctx;
 
+mod archive;
+mod coff;
 mod elf;
+mod leb;
+mod linker;
 mod mach;
+mod read;
 mod target;
+mod wasm;
+
+#[cfg(all(feature = "jit", target_os = "linux"))]
+pub mod jit;
 
 pub mod artifact;
+pub mod dwarf;
+pub mod unwind;
 pub use crate::artifact::{
     decl::{
         DataDecl, DataImportDecl, DataType, Decl, FunctionDecl, FunctionImportDecl, Scope,
-        SectionDecl, SectionKind, Visibility,
+        SectionDecl, SectionKind, Signature, TlsImportDecl, UnwindTable, ValueType, Visibility,
     },
-    Artifact, ArtifactBuilder, ArtifactError, Data, ImportKind, Link, Reloc,
+    Artifact, ArtifactBuilder, ArtifactError, Data, ImportKind, Link, LinkRef, Reloc, SymbolId,
+    SymbolLink, SymbolRef,
 };
 
 pub use target_lexicon::{
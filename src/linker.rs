@@ -0,0 +1,1152 @@
+//! A small linker, combining artifacts into an x86-64 ELF executable which runs without a dynamic
+//! loader or a C runtime, or into a position-independent shared library.
+//!
+//! Definitions are merged by kind into `.text`, `.rodata`, `.data` and `.bss` sections, with the
+//! addresses of data loaded from code in a `.got`. Shared libraries additionally get a dynamic
+//! symbol table of their imports and exports, a `.plt` calling imported functions, and the dynamic
+//! relocations the loader applies. The sections are mapped by a read-only segment, which also holds
+//! the headers, an executable and a writable `PT_LOAD` segment. Debug sections, thread-local data,
+//! constructors and destructors need support from a runtime, and are left out.
+
+use crate::artifact::{
+    Artifact, ArtifactError, Data, Decl, DefinedDecl, Definition, ImportKind, LinkAndDecl, Reloc,
+    SectionKind,
+};
+use crate::elf::{portable_reloc, x86_64_auto_reloc};
+use crate::{target::make_ctx, Ctx, Scope, Visibility};
+
+use goblin::elf::dynamic::{
+    DT_HASH, DT_JMPREL, DT_NEEDED, DT_NULL, DT_PLTGOT, DT_PLTREL, DT_PLTRELSZ, DT_RELA, DT_RELAENT,
+    DT_RELASZ, DT_STRSZ, DT_STRTAB, DT_SYMENT, DT_SYMTAB,
+};
+use goblin::elf::header::{self, Header};
+use goblin::elf::program_header::{
+    ProgramHeader, PF_R, PF_W, PF_X, PT_DYNAMIC, PT_GNU_STACK, PT_LOAD,
+};
+use goblin::elf::reloc;
+use goblin::elf::section_header::{
+    SectionHeader, SHF_ALLOC, SHF_EXECINSTR, SHF_INFO_LINK, SHF_WRITE, SHT_DYNAMIC, SHT_DYNSYM,
+    SHT_HASH, SHT_NOBITS, SHT_PROGBITS, SHT_RELA, SHT_STRTAB, SHT_SYMTAB,
+};
+use goblin::elf::sym::{
+    Sym, STB_GLOBAL, STB_LOCAL, STB_WEAK, STT_FUNC, STT_OBJECT, STV_DEFAULT, STV_PROTECTED,
+};
+use indexmap::{IndexMap, IndexSet};
+use scroll::{IOwrite, Pwrite};
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::{Cursor, Seek, Write};
+use target_lexicon::{Architecture, BinaryFormat};
+
+/// The address executables are mapped at; shared libraries are mapped wherever the loader likes
+const EXECUTABLE_BASE_ADDRESS: u64 = 0x40_0000;
+/// The alignment of segments, in the file and in memory
+const PAGE_SIZE: u64 = 0x1000;
+/// The size of an address in the `.got` and `.got.plt`
+const GOT_ENTRY_SIZE: u64 = 8;
+/// The size of a `.plt` entry
+const PLT_ENTRY_SIZE: u64 = 16;
+/// The `.got.plt` entries reserved for the loader, before the ones of the imported functions
+const GOT_PLT_RESERVED: u64 = 3;
+/// The size of a `.rela.dyn` or `.rela.plt` entry
+const RELA_SIZE: u64 = 24;
+/// The size of a `.dynamic` entry
+const DYN_SIZE: u64 = 16;
+
+/// What the artifacts are linked into
+#[derive(Debug, Clone, Copy)]
+enum Output<'a> {
+    /// A static executable starting at the function `entry`
+    Executable { entry: &'a str },
+    /// A shared library depending on the `needed` libraries
+    SharedLibrary { needed: &'a [&'a str] },
+}
+
+/// The sections definitions are merged into, and the ones the linker creates, in the order they are
+/// laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum OutputKind {
+    Hash,
+    DynSym,
+    DynStr,
+    RelaDyn,
+    RelaPlt,
+    ReadOnly,
+    Plt,
+    Text,
+    Dynamic,
+    Got,
+    GotPlt,
+    Data,
+    Bss,
+}
+
+/// The output sections mapped by each segment, and its permissions
+const SEGMENTS: [(u32, &[OutputKind]); 3] = [
+    (
+        PF_R,
+        &[
+            OutputKind::Hash,
+            OutputKind::DynSym,
+            OutputKind::DynStr,
+            OutputKind::RelaDyn,
+            OutputKind::RelaPlt,
+            OutputKind::ReadOnly,
+        ],
+    ),
+    (PF_R | PF_X, &[OutputKind::Plt, OutputKind::Text]),
+    (
+        PF_R | PF_W,
+        &[
+            OutputKind::Dynamic,
+            OutputKind::Got,
+            OutputKind::GotPlt,
+            OutputKind::Data,
+            OutputKind::Bss,
+        ],
+    ),
+];
+
+impl OutputKind {
+    /// The output section of `def`, if it can be linked
+    fn of(def: &Definition) -> Option<OutputKind> {
+        Some(match def.decl {
+            DefinedDecl::Function(_) => OutputKind::Text,
+            DefinedDecl::Data(d) if d.is_tls() => return None,
+            DefinedDecl::Data(_) if def.data.is_zero_init() => OutputKind::Bss,
+            DefinedDecl::Data(d) if d.is_writable() => OutputKind::Data,
+            DefinedDecl::Data(_) => OutputKind::ReadOnly,
+            DefinedDecl::Section(s) => match s.kind() {
+                SectionKind::Text => OutputKind::Text,
+                SectionKind::Data => OutputKind::Data,
+                SectionKind::Debug => return None,
+            },
+        })
+    }
+    fn name(self) -> &'static str {
+        match self {
+            OutputKind::Hash => ".hash",
+            OutputKind::DynSym => ".dynsym",
+            OutputKind::DynStr => ".dynstr",
+            OutputKind::RelaDyn => ".rela.dyn",
+            OutputKind::RelaPlt => ".rela.plt",
+            OutputKind::ReadOnly => ".rodata",
+            OutputKind::Plt => ".plt",
+            OutputKind::Text => ".text",
+            OutputKind::Dynamic => ".dynamic",
+            OutputKind::Got => ".got",
+            OutputKind::GotPlt => ".got.plt",
+            OutputKind::Data => ".data",
+            OutputKind::Bss => ".bss",
+        }
+    }
+    fn section_type(self) -> u32 {
+        match self {
+            OutputKind::Hash => SHT_HASH,
+            OutputKind::DynSym => SHT_DYNSYM,
+            OutputKind::DynStr => SHT_STRTAB,
+            OutputKind::RelaDyn | OutputKind::RelaPlt => SHT_RELA,
+            OutputKind::Dynamic => SHT_DYNAMIC,
+            OutputKind::Bss => SHT_NOBITS,
+            _ => SHT_PROGBITS,
+        }
+    }
+    fn flags(self) -> u32 {
+        match self {
+            OutputKind::Plt | OutputKind::Text => SHF_ALLOC | SHF_EXECINSTR,
+            OutputKind::RelaPlt => SHF_ALLOC | SHF_INFO_LINK,
+            OutputKind::Dynamic
+            | OutputKind::Got
+            | OutputKind::GotPlt
+            | OutputKind::Data
+            | OutputKind::Bss => SHF_ALLOC | SHF_WRITE,
+            _ => SHF_ALLOC,
+        }
+    }
+    fn entsize(self) -> u64 {
+        match self {
+            OutputKind::Hash => 4,
+            OutputKind::DynSym => 24,
+            OutputKind::RelaDyn | OutputKind::RelaPlt => RELA_SIZE,
+            OutputKind::Plt => PLT_ENTRY_SIZE,
+            OutputKind::Dynamic => DYN_SIZE,
+            OutputKind::Got | OutputKind::GotPlt => GOT_ENTRY_SIZE,
+            _ => 0,
+        }
+    }
+    /// The alignment of definitions which don't specify their own, and of created sections
+    fn default_align(self) -> u64 {
+        match self {
+            OutputKind::Plt | OutputKind::Text => 0x10,
+            OutputKind::DynStr | OutputKind::ReadOnly => 1,
+            OutputKind::Hash => 4,
+            _ => 0x8,
+        }
+    }
+}
+
+/// A merged output section
+#[derive(Debug)]
+struct OutputSection {
+    kind: OutputKind,
+    /// The contents, empty for `.bss`
+    bytes: Vec<u8>,
+    size: u64,
+    align: u64,
+    addr: u64,
+    offset: u64,
+}
+
+impl OutputSection {
+    fn new(kind: OutputKind) -> Self {
+        OutputSection {
+            kind,
+            bytes: Vec::new(),
+            size: 0,
+            align: 1,
+            addr: 0,
+            offset: 0,
+        }
+    }
+    /// Append `data` aligned to `align`, returning its offset in the section
+    fn append(&mut self, data: &Data, align: u64) -> u64 {
+        let offset = align_up(self.size, align);
+        self.align = self.align.max(align);
+        if let Some(bytes) = data.bytes() {
+            self.bytes.resize(offset as usize, 0);
+            self.bytes.extend_from_slice(bytes);
+        }
+        self.size = offset + data.size() as u64;
+        offset
+    }
+    /// Reserve `size` bytes for contents which are only known once every address is
+    fn reserve(&mut self, size: u64) {
+        self.size = size;
+        self.align = self.kind.default_align();
+    }
+}
+
+fn align_up(offset: u64, align: u64) -> u64 {
+    (offset + align - 1) & !(align - 1)
+}
+
+/// A definition of one of the artifacts which is linked into the output
+#[derive(Debug)]
+struct Placed<'a> {
+    def: Definition<'a>,
+    kind: OutputKind,
+    offset: u64,
+    size: u64,
+}
+
+fn scope(decl: &DefinedDecl) -> Scope {
+    match decl {
+        DefinedDecl::Function(d) => d.get_scope(),
+        DefinedDecl::Data(d) => d.get_scope(),
+        DefinedDecl::Section(_) => Scope::Local,
+    }
+}
+
+fn visibility(decl: &DefinedDecl) -> Visibility {
+    match decl {
+        DefinedDecl::Function(d) => d.get_visibility(),
+        DefinedDecl::Data(d) => d.get_visibility(),
+        DefinedDecl::Section(_) => Visibility::Hidden,
+    }
+}
+
+/// What a link points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Target<'a> {
+    /// The placed definition with this index
+    Defined(usize),
+    /// A symbol imported from another shared library through the dynamic symbol table
+    Dynamic(&'a str),
+}
+
+/// A link to apply once every definition has an address
+#[derive(Debug)]
+struct Fixup<'a> {
+    link: LinkAndDecl<'a>,
+    from: usize,
+    typ: u32,
+    addend: i64,
+    target: Target<'a>,
+}
+
+/// The linker state: every definition placed in its output section, the symbols they define, and
+/// the links between them
+struct Linker<'a> {
+    ctx: Ctx,
+    shared: bool,
+    sections: IndexMap<OutputKind, OutputSection>,
+    placed: Vec<Placed<'a>>,
+    /// Definitions by artifact and name
+    definitions: HashMap<(usize, &'a str), usize>,
+    /// The definitions of global and weak symbols, across all artifacts
+    globals: HashMap<&'a str, usize>,
+    /// The offset of the `.got` entry of every target whose address is loaded from it
+    got: IndexMap<Target<'a>, u64>,
+    /// The imported functions called through the `.plt`
+    plt: IndexSet<&'a str>,
+    /// The imported symbols of a shared library, and whether each is a function
+    imports: IndexMap<&'a str, bool>,
+    /// The definitions a shared library exports
+    exports: Vec<usize>,
+    fixups: Vec<Fixup<'a>>,
+    /// The number of relocations in `.rela.dyn`
+    dynamic_relocations: u64,
+}
+
+impl<'a> Linker<'a> {
+    fn new(artifacts: &[&'a Artifact], shared: bool) -> Result<Self, ArtifactError> {
+        let mut linker = Linker {
+            ctx: make_ctx(&artifacts[0].target),
+            shared,
+            sections: SEGMENTS
+                .iter()
+                .flat_map(|(_, kinds)| kinds.iter())
+                .map(|&kind| (kind, OutputSection::new(kind)))
+                .collect(),
+            placed: Vec::new(),
+            definitions: HashMap::new(),
+            globals: HashMap::new(),
+            got: IndexMap::new(),
+            plt: IndexSet::new(),
+            imports: IndexMap::new(),
+            exports: Vec::new(),
+            fixups: Vec::new(),
+            dynamic_relocations: 0,
+        };
+        // the artifact defining each COMDAT group, whose definitions win over the other artifacts'
+        let mut groups = HashMap::new();
+        for (i, artifact) in artifacts.iter().enumerate() {
+            // the loader writes the addresses in linked data of a shared library, so it can't be
+            // mapped read-only
+            let relocated = match shared {
+                true => artifact.links().map(|link| link.from.name).collect(),
+                false => HashSet::new(),
+            };
+            for def in artifact.definitions() {
+                if let Some(group) = def.comdat {
+                    if *groups.entry(group).or_insert(i) != i {
+                        continue;
+                    }
+                }
+                let kind = match OutputKind::of(&def) {
+                    Some(OutputKind::ReadOnly) if relocated.contains(def.name) => OutputKind::Data,
+                    Some(kind) => kind,
+                    None => continue,
+                };
+                let align = def.decl.get_align().unwrap_or_else(|| kind.default_align());
+                let offset = linker.sections[&kind].append(def.data, align);
+                let size = linker.sections[&kind].size - offset;
+                let index = linker.placed.len();
+                linker.definitions.insert((i, def.name), index);
+                match (scope(def.decl), linker.globals.get(def.name)) {
+                    (Scope::Local, _) => (),
+                    (_, None) => {
+                        linker.globals.insert(def.name, index);
+                    }
+                    // a global definition replaces a weak one, but conflicts with another global
+                    (Scope::Global, Some(&other)) => match scope(linker.placed[other].def.decl) {
+                        Scope::Weak => {
+                            linker.globals.insert(def.name, index);
+                        }
+                        _ => return Err(ArtifactError::DuplicateDefinition(def.name.to_string())),
+                    },
+                    (Scope::Weak, Some(_)) => (),
+                }
+                linker.placed.push(Placed {
+                    def,
+                    kind,
+                    offset,
+                    size,
+                });
+            }
+        }
+        if shared {
+            // hidden definitions are only visible to the library itself
+            let mut exports = linker
+                .globals
+                .values()
+                .copied()
+                .filter(|&index| visibility(linker.placed[index].def.decl) != Visibility::Hidden)
+                .collect::<Vec<_>>();
+            exports.sort_unstable();
+            linker.exports = exports;
+        }
+        Ok(linker)
+    }
+
+    /// What `link` of the `artifact`th artifact points to
+    fn target(
+        &mut self,
+        artifact: usize,
+        link: &LinkAndDecl<'a>,
+    ) -> Result<Target<'a>, ArtifactError> {
+        let target = match link.to.decl {
+            Decl::Import(ImportKind::Tls) => None,
+            Decl::Import(kind) => match self.globals.get(link.to.name) {
+                Some(&index) => Some(Target::Defined(index)),
+                None if self.shared => {
+                    let function = *kind == ImportKind::Function;
+                    self.imports.entry(link.to.name).or_insert(function);
+                    Some(Target::Dynamic(link.to.name))
+                }
+                None => return Err(ArtifactError::UndefinedSymbols(vec![link.to.name.into()])),
+            },
+            Decl::Defined(d) if scope(d) == Scope::Local => self
+                .definitions
+                .get(&(artifact, link.to.name))
+                .copied()
+                .map(Target::Defined),
+            Decl::Defined(_) => self.globals.get(link.to.name).copied().map(Target::Defined),
+        };
+        target.ok_or_else(|| link.unsupported(Architecture::X86_64, BinaryFormat::Elf))
+    }
+
+    fn address(&self, index: usize) -> u64 {
+        let placed = &self.placed[index];
+        self.sections[&placed.kind].addr + placed.offset
+    }
+
+    /// The relocation type and addend of `link`, for the relocations the linker can apply
+    fn relocation(&self, link: &LinkAndDecl) -> Result<(u32, i64), ArtifactError> {
+        let unsupported = || link.unsupported(Architecture::X86_64, BinaryFormat::Elf);
+        let (typ, addend) = match link.reloc {
+            Reloc::Auto => x86_64_auto_reloc(link, self.ctx, Architecture::X86_64)?,
+            Reloc::Raw { reloc, addend } => (reloc, addend),
+            Reloc::Absolute { .. }
+            | Reloc::Call
+            | Reloc::PcRelative { .. }
+            | Reloc::GotPcRelative { .. }
+            | Reloc::SectionOffset { .. }
+            | Reloc::ImageOffset { .. } => portable_reloc(link, Architecture::X86_64)?,
+            Reloc::Debug { size: 4, addend } => (reloc::R_X86_64_32, addend),
+            Reloc::Debug { size: 8, addend } => (reloc::R_X86_64_64, addend),
+            Reloc::Debug { .. } => return Err(unsupported()),
+        };
+        match typ {
+            reloc::R_X86_64_64
+            | reloc::R_X86_64_PC64
+            | reloc::R_X86_64_32
+            | reloc::R_X86_64_32S
+            | reloc::R_X86_64_PC32
+            | reloc::R_X86_64_PLT32
+            | reloc::R_X86_64_GOTPCREL
+            | reloc::R_X86_64_GOTPCRELX
+            | reloc::R_X86_64_REX_GOTPCRELX => Ok((typ, addend)),
+            _ => Err(unsupported()),
+        }
+    }
+
+    /// Resolve the links of the `artifact`th artifact, allocating the `.got` and `.plt` entries and
+    /// counting the dynamic relocations they need
+    fn resolve(
+        &mut self,
+        artifact: usize,
+        links: Vec<LinkAndDecl<'a>>,
+    ) -> Result<(), ArtifactError> {
+        for link in links {
+            let from = match self.definitions.get(&(artifact, link.from.name)) {
+                Some(&from) => from,
+                // links from debug sections or discarded COMDAT groups aren't needed
+                None => continue,
+            };
+            let unsupported = || link.unsupported(Architecture::X86_64, BinaryFormat::Elf);
+            let (typ, addend) = self.relocation(&link)?;
+            let target = self.target(artifact, &link)?;
+            let size = match typ {
+                reloc::R_X86_64_64 | reloc::R_X86_64_PC64 => 8,
+                _ => 4,
+            };
+            let placed = &self.placed[from];
+            if placed.kind == OutputKind::Bss || link.at + size > placed.size {
+                return Err(unsupported());
+            }
+            match (typ, target) {
+                (typ, _) if is_got_relative(typ) && !self.got.contains_key(&target) => {
+                    self.got
+                        .insert(target, self.got.len() as u64 * GOT_ENTRY_SIZE);
+                    if self.shared {
+                        self.dynamic_relocations += 1;
+                    }
+                }
+                (typ, _) if is_got_relative(typ) => (),
+                // imported functions are called through the `.plt`
+                (reloc::R_X86_64_PLT32, Target::Dynamic(name)) if self.imports[name] => {
+                    self.plt.insert(name);
+                }
+                // pointers need to be relocated by the loader, wherever the library is mapped
+                (reloc::R_X86_64_64, _) if self.shared => self.dynamic_relocations += 1,
+                // the loader can't patch code, and nothing else fits a 32 bit address
+                (reloc::R_X86_64_32, _) | (reloc::R_X86_64_32S, _) if self.shared => {
+                    return Err(unsupported())
+                }
+                (_, Target::Dynamic(_)) => return Err(unsupported()),
+                _ => (),
+            }
+            self.fixups.push(Fixup {
+                link,
+                from,
+                typ,
+                addend,
+                target,
+            });
+        }
+        Ok(())
+    }
+
+    /// The index of every symbol in `.dynsym`: the imports, followed by the exports
+    fn dynamic_symbol(&self, target: Target) -> u32 {
+        match target {
+            Target::Dynamic(name) => 1 + self.imports.get_index_of(name).unwrap() as u32,
+            Target::Defined(index) => {
+                let export = self.exports.binary_search(&index).unwrap();
+                (1 + self.imports.len() + export) as u32
+            }
+        }
+    }
+
+    /// Reserve the sections of a shared library holding the dynamic symbols and relocations, and
+    /// fill in those known before the layout
+    fn reserve_dynamic_sections(&mut self, needed: &[&str]) {
+        let mut dynstr = StrTab::new();
+        let mut names = needed
+            .iter()
+            .map(|library| dynstr.add(library))
+            .collect::<Vec<_>>();
+        let symbols = self
+            .imports
+            .keys()
+            .copied()
+            .chain(
+                self.exports
+                    .iter()
+                    .map(|&index| self.placed[index].def.name),
+            )
+            .collect::<Vec<_>>();
+        names.extend(symbols.iter().map(|name| dynstr.add(name)));
+        let dynstr_size = dynstr.bytes.len() as u64;
+        self.sections[&OutputKind::DynStr].reserve(dynstr_size);
+        self.sections[&OutputKind::DynStr].bytes = dynstr.bytes;
+
+        let nsyms = 1 + symbols.len() as u64;
+        self.sections[&OutputKind::DynSym].reserve(nsyms * 24);
+        let hash = sysv_hash_table(&symbols);
+        self.sections[&OutputKind::Hash].reserve(hash.len() as u64);
+        self.sections[&OutputKind::Hash].bytes = hash;
+
+        let nplt = self.plt.len() as u64;
+        self.sections[&OutputKind::RelaDyn].reserve(self.dynamic_relocations * RELA_SIZE);
+        self.sections[&OutputKind::RelaPlt].reserve(nplt * RELA_SIZE);
+        if nplt != 0 {
+            self.sections[&OutputKind::Plt].reserve((1 + nplt) * PLT_ENTRY_SIZE);
+            self.sections[&OutputKind::GotPlt].reserve((GOT_PLT_RESERVED + nplt) * GOT_ENTRY_SIZE);
+        }
+        let ndynamic = self.dynamic_entries(needed).len() as u64;
+        self.sections[&OutputKind::Dynamic].reserve(ndynamic * DYN_SIZE);
+    }
+
+    /// The `.dynamic` entries of a shared library
+    fn dynamic_entries(&self, needed: &[&str]) -> Vec<(u64, u64)> {
+        let addr = |kind| self.sections[&kind].addr;
+        let size = |kind| self.sections[&kind].size;
+        // the names of the needed libraries are first in the `.dynstr`
+        let mut entries = Vec::new();
+        let mut name = 1;
+        for library in needed {
+            entries.push((DT_NEEDED, name));
+            name += library.len() as u64 + 1;
+        }
+        entries.extend_from_slice(&[
+            (DT_HASH, addr(OutputKind::Hash)),
+            (DT_STRTAB, addr(OutputKind::DynStr)),
+            (DT_SYMTAB, addr(OutputKind::DynSym)),
+            (DT_STRSZ, size(OutputKind::DynStr)),
+            (DT_SYMENT, OutputKind::DynSym.entsize()),
+        ]);
+        if self.dynamic_relocations != 0 {
+            entries.extend_from_slice(&[
+                (DT_RELA, addr(OutputKind::RelaDyn)),
+                (DT_RELASZ, size(OutputKind::RelaDyn)),
+                (DT_RELAENT, RELA_SIZE),
+            ]);
+        }
+        if !self.plt.is_empty() {
+            entries.extend_from_slice(&[
+                (DT_PLTGOT, addr(OutputKind::GotPlt)),
+                (DT_PLTRELSZ, size(OutputKind::RelaPlt)),
+                (DT_PLTREL, DT_RELA),
+                (DT_JMPREL, addr(OutputKind::RelaPlt)),
+            ]);
+        }
+        entries.push((DT_NULL, 0));
+        entries
+    }
+
+    /// Assign file offsets and addresses to the output sections, after `headers_size` bytes of
+    /// headers, and return the program headers of the segments mapping them
+    fn layout(&mut self, headers_size: u64, base: u64) -> Vec<ProgramHeader> {
+        let mut offset = headers_size;
+        let mut segments = Vec::new();
+        for (i, (flags, kinds)) in SEGMENTS.iter().enumerate() {
+            let empty = kinds.iter().all(|kind| self.sections[kind].size == 0);
+            // the headers are mapped along with the first segment
+            let start = match i {
+                0 => 0,
+                _ if empty => continue,
+                _ => align_up(offset, PAGE_SIZE),
+            };
+            let mut end = offset.max(start);
+            let mut file_end = end;
+            for kind in kinds.iter() {
+                let section = &mut self.sections[kind];
+                end = align_up(end, section.align);
+                section.addr = base + end;
+                section.offset = end;
+                end += section.size;
+                if *kind != OutputKind::Bss {
+                    file_end = end;
+                }
+            }
+            offset = file_end;
+            segments.push(ProgramHeader {
+                p_type: PT_LOAD,
+                p_flags: *flags,
+                p_offset: start,
+                p_vaddr: base + start,
+                p_paddr: base + start,
+                p_filesz: file_end - start,
+                p_memsz: end - start,
+                p_align: PAGE_SIZE,
+            });
+        }
+        if self.shared {
+            let dynamic = &self.sections[&OutputKind::Dynamic];
+            segments.push(ProgramHeader {
+                p_type: PT_DYNAMIC,
+                p_flags: PF_R | PF_W,
+                p_offset: dynamic.offset,
+                p_vaddr: dynamic.addr,
+                p_paddr: dynamic.addr,
+                p_filesz: dynamic.size,
+                p_memsz: dynamic.size,
+                p_align: dynamic.align,
+            });
+        }
+        // the stack doesn't need to be executable
+        segments.push(ProgramHeader {
+            p_type: PT_GNU_STACK,
+            p_flags: PF_R | PF_W,
+            p_align: 0x10,
+            ..Default::default()
+        });
+        segments
+    }
+
+    /// The number of program headers `layout` will create
+    fn segment_count(&self) -> usize {
+        let mapped = SEGMENTS[1..]
+            .iter()
+            .filter(|(_, kinds)| kinds.iter().any(|kind| self.sections[kind].size != 0))
+            .count();
+        1 + mapped + self.shared as usize + 1
+    }
+
+    /// Fill in the `.got`, the `.plt` and `.got.plt`, and apply every link, returning the
+    /// relocations the loader needs to apply
+    fn apply(&mut self) -> Result<Vec<reloc::Reloc>, ArtifactError> {
+        let mut relocations = Vec::new();
+        let relocation = |r_offset, r_sym, r_type, r_addend| reloc::Reloc {
+            r_offset,
+            r_addend: Some(r_addend),
+            r_sym,
+            r_type,
+        };
+
+        let got_addr = self.sections[&OutputKind::Got].addr;
+        let mut got = Vec::new();
+        for (&target, offset) in &self.got {
+            match target {
+                Target::Defined(index) => {
+                    let address = self.address(index);
+                    if self.shared {
+                        let reloc = relocation(
+                            got_addr + offset,
+                            0,
+                            reloc::R_X86_64_RELATIVE,
+                            address as i64,
+                        );
+                        relocations.push(reloc);
+                    }
+                    got.extend_from_slice(&address.to_le_bytes());
+                }
+                Target::Dynamic(_) => {
+                    let sym = self.dynamic_symbol(target) as usize;
+                    relocations.push(relocation(
+                        got_addr + offset,
+                        sym,
+                        reloc::R_X86_64_GLOB_DAT,
+                        0,
+                    ));
+                    got.extend_from_slice(&0u64.to_le_bytes());
+                }
+            }
+        }
+        self.sections[&OutputKind::Got].bytes = got;
+        self.sections[&OutputKind::Got].size = self.sections[&OutputKind::Got].bytes.len() as u64;
+
+        let mut plt_relocations = Vec::new();
+        if !self.plt.is_empty() {
+            let plt = self.sections[&OutputKind::Plt].addr as i64;
+            let got_plt = self.sections[&OutputKind::GotPlt].addr as i64;
+            let dynamic = self.sections[&OutputKind::Dynamic].addr;
+            // push the loader's data for the library, and jump to its lazy resolver
+            let mut code = vec![0xff, 0x35];
+            code.extend_from_slice(&((got_plt + 8 - (plt + 6)) as i32).to_le_bytes());
+            code.extend_from_slice(&[0xff, 0x25]);
+            code.extend_from_slice(&((got_plt + 16 - (plt + 12)) as i32).to_le_bytes());
+            code.extend_from_slice(&[0x0f, 0x1f, 0x40, 0x00]);
+            let mut slots = vec![dynamic, 0, 0];
+            for (i, &name) in self.plt.iter().enumerate() {
+                let entry = plt + (i as i64 + 1) * PLT_ENTRY_SIZE as i64;
+                let slot = got_plt + (GOT_PLT_RESERVED as i64 + i as i64) * GOT_ENTRY_SIZE as i64;
+                // jump through the slot, which initially points back at the push of the index of
+                // the relocation resolving it
+                code.extend_from_slice(&[0xff, 0x25]);
+                code.extend_from_slice(&((slot - (entry + 6)) as i32).to_le_bytes());
+                code.push(0x68);
+                code.extend_from_slice(&(i as u32).to_le_bytes());
+                code.push(0xe9);
+                code.extend_from_slice(&((plt - (entry + 16)) as i32).to_le_bytes());
+                slots.push(entry as u64 + 6);
+                let sym = self.dynamic_symbol(Target::Dynamic(name)) as usize;
+                plt_relocations.push(relocation(slot as u64, sym, reloc::R_X86_64_JUMP_SLOT, 0));
+            }
+            self.sections[&OutputKind::Plt].bytes = code;
+            self.sections[&OutputKind::GotPlt].bytes =
+                slots.iter().flat_map(|slot| slot.to_le_bytes()).collect();
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let unsupported = || {
+                fixup
+                    .link
+                    .unsupported(Architecture::X86_64, BinaryFormat::Elf)
+            };
+            let p = (self.address(fixup.from) + fixup.link.at) as i64;
+            let s = match fixup.target {
+                Target::Defined(index) => self.address(index) as i64,
+                Target::Dynamic(name) => match self.plt.get_index_of(name) {
+                    Some(i) => {
+                        (self.sections[&OutputKind::Plt].addr + (i as u64 + 1) * PLT_ENTRY_SIZE)
+                            as i64
+                    }
+                    None => 0,
+                },
+            };
+            let (typ, a) = (fixup.typ, fixup.addend);
+            let value = match typ {
+                reloc::R_X86_64_PC64 | reloc::R_X86_64_PC32 | reloc::R_X86_64_PLT32 => {
+                    s.wrapping_add(a).wrapping_sub(p)
+                }
+                typ if is_got_relative(typ) => {
+                    let entry = (got_addr + self.got[&fixup.target]) as i64;
+                    entry.wrapping_add(a).wrapping_sub(p)
+                }
+                _ => s.wrapping_add(a),
+            };
+            if typ == reloc::R_X86_64_64 && self.shared {
+                relocations.push(match fixup.target {
+                    Target::Defined(_) => relocation(p as u64, 0, reloc::R_X86_64_RELATIVE, value),
+                    Target::Dynamic(_) => {
+                        let sym = self.dynamic_symbol(fixup.target) as usize;
+                        relocation(p as u64, sym, reloc::R_X86_64_64, a)
+                    }
+                });
+            }
+            let bytes = match typ {
+                reloc::R_X86_64_64 | reloc::R_X86_64_PC64 => value.to_le_bytes().to_vec(),
+                reloc::R_X86_64_32 => u32::try_from(value)
+                    .map_err(|_| unsupported())?
+                    .to_le_bytes()
+                    .to_vec(),
+                _ => i32::try_from(value)
+                    .map_err(|_| unsupported())?
+                    .to_le_bytes()
+                    .to_vec(),
+            };
+            let placed = &self.placed[fixup.from];
+            if fixup.link.at + bytes.len() as u64 > placed.def.data.file_size() as u64 {
+                return Err(fixup.link.out_of_bounds());
+            }
+            let at = (placed.offset + fixup.link.at) as usize;
+            let section = &mut self.sections[&placed.kind];
+            section.bytes[at..at + bytes.len()].copy_from_slice(&bytes);
+        }
+        // the loader can process the relative relocations quickly, when they come first
+        relocations.sort_by_key(|reloc| reloc.r_type != reloc::R_X86_64_RELATIVE);
+        relocations.extend(plt_relocations);
+        Ok(relocations)
+    }
+
+    /// Fill in the dynamic symbols and relocations, and the `.dynamic` of a shared library
+    fn fill_dynamic_sections(
+        &mut self,
+        needed: &[&str],
+        relocations: Vec<reloc::Reloc>,
+        shndx: impl Fn(OutputKind) -> usize,
+    ) -> Result<(), ArtifactError> {
+        let ctx = self.ctx;
+        let mut dynsym = Cursor::new(Vec::new());
+        dynsym.iowrite_with(Sym::default(), ctx)?;
+        // the names of the symbols follow the names of the needed libraries
+        let mut name = 1 + needed
+            .iter()
+            .map(|library| library.len() + 1)
+            .sum::<usize>();
+        for (import, &function) in &self.imports {
+            let typ = if function { STT_FUNC } else { STT_OBJECT };
+            let sym = Sym {
+                st_name: name,
+                st_info: (STB_GLOBAL << 4) | typ,
+                ..Default::default()
+            };
+            dynsym.iowrite_with(sym, ctx)?;
+            name += import.len() + 1;
+        }
+        for &index in &self.exports {
+            let placed = &self.placed[index];
+            let sym = symbol(placed, name, self.address(index), shndx(placed.kind));
+            dynsym.iowrite_with(sym, ctx)?;
+            name += placed.def.name.len() + 1;
+        }
+        self.sections[&OutputKind::DynSym].bytes = dynsym.into_inner();
+
+        let mut rela = Cursor::new(Vec::new());
+        let nplt = self.plt.len();
+        let (dynamic, plt) = relocations.split_at(relocations.len() - nplt);
+        for &relocation in dynamic {
+            rela.iowrite_with(relocation, (true, ctx))?;
+        }
+        self.sections[&OutputKind::RelaDyn].bytes = rela.into_inner();
+        let mut rela = Cursor::new(Vec::new());
+        for &relocation in plt {
+            rela.iowrite_with(relocation, (true, ctx))?;
+        }
+        self.sections[&OutputKind::RelaPlt].bytes = rela.into_inner();
+
+        self.sections[&OutputKind::Dynamic].bytes = self
+            .dynamic_entries(needed)
+            .into_iter()
+            .flat_map(|(tag, val)| {
+                tag.to_le_bytes()
+                    .iter()
+                    .chain(val.to_le_bytes().iter())
+                    .copied()
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Ok(())
+    }
+}
+
+/// The symbol of a placed definition, with its name at `st_name` in the string table
+fn symbol(placed: &Placed, st_name: usize, st_value: u64, st_shndx: usize) -> Sym {
+    let binding = match scope(placed.def.decl) {
+        Scope::Local => STB_LOCAL,
+        Scope::Global => STB_GLOBAL,
+        Scope::Weak => STB_WEAK,
+    };
+    let typ = if placed.def.decl.is_function() {
+        STT_FUNC
+    } else {
+        STT_OBJECT
+    };
+    let st_other = match visibility(placed.def.decl) {
+        Visibility::Protected => STV_PROTECTED,
+        _ => STV_DEFAULT,
+    };
+    Sym {
+        st_name,
+        st_info: (binding << 4) | typ,
+        st_other,
+        st_shndx,
+        st_value,
+        st_size: placed.size,
+    }
+}
+
+fn is_got_relative(typ: u32) -> bool {
+    matches!(
+        typ,
+        reloc::R_X86_64_GOTPCREL | reloc::R_X86_64_GOTPCRELX | reloc::R_X86_64_REX_GOTPCRELX
+    )
+}
+
+/// The System V hash of a symbol name
+fn sysv_hash(name: &str) -> u32 {
+    let mut hash: u32 = 0;
+    for &byte in name.as_bytes() {
+        hash = (hash << 4).wrapping_add(u32::from(byte));
+        let high = hash & 0xf000_0000;
+        if high != 0 {
+            hash ^= high >> 24;
+        }
+        hash &= !high;
+    }
+    hash
+}
+
+/// The `.hash` section of the dynamic symbols with `names`, which follow the null symbol
+fn sysv_hash_table(names: &[&str]) -> Vec<u8> {
+    let nchain = names.len() + 1;
+    let nbucket = nchain;
+    let mut buckets = vec![0u32; nbucket];
+    let mut chains = vec![0u32; nchain];
+    for (i, name) in names.iter().enumerate() {
+        let index = i + 1;
+        let bucket = sysv_hash(name) as usize % nbucket;
+        chains[index] = buckets[bucket];
+        buckets[bucket] = index as u32;
+    }
+    [nbucket as u32, nchain as u32]
+        .iter()
+        .chain(buckets.iter())
+        .chain(chains.iter())
+        .flat_map(|word| word.to_le_bytes())
+        .collect()
+}
+
+/// A string table being built, with the offset of every string added
+#[derive(Debug, Default)]
+struct StrTab {
+    bytes: Vec<u8>,
+}
+
+impl StrTab {
+    fn new() -> Self {
+        StrTab { bytes: vec![0] }
+    }
+    fn add(&mut self, string: &str) -> u32 {
+        let offset = self.bytes.len() as u32;
+        self.bytes.extend_from_slice(string.as_bytes());
+        self.bytes.push(0);
+        offset
+    }
+}
+
+fn pad_to<W: Write + Seek>(file: &mut W, offset: u64) -> Result<(), ArtifactError> {
+    let position = file.stream_position()?;
+    debug_assert!(position <= offset);
+    file.write_all(&vec![0; (offset - position) as usize])?;
+    Ok(())
+}
+
+/// Link `artifacts` into a static executable starting at the global function `entry`
+pub fn executable(artifacts: &[&Artifact], entry: &str) -> Result<Vec<u8>, ArtifactError> {
+    link(artifacts, Output::Executable { entry })
+}
+
+/// Link `artifacts` into a shared library depending on the `needed` libraries
+pub fn shared_library(artifacts: &[&Artifact], needed: &[&str]) -> Result<Vec<u8>, ArtifactError> {
+    link(artifacts, Output::SharedLibrary { needed })
+}
+
+fn link(artifacts: &[&Artifact], output: Output) -> Result<Vec<u8>, ArtifactError> {
+    for artifact in artifacts {
+        if artifact.target.binary_format != BinaryFormat::Elf {
+            return Err(ArtifactError::UnsupportedBinaryFormat(
+                artifact.target.binary_format,
+            ));
+        }
+        if artifact.target.architecture != Architecture::X86_64 {
+            return Err(ArtifactError::UnsupportedArchitecture {
+                architecture: artifact.target.architecture,
+                format: BinaryFormat::Elf,
+            });
+        }
+        let undef = artifact.undefined_symbols();
+        if !undef.is_empty() {
+            return Err(ArtifactError::UndefinedSymbols(undef));
+        }
+        artifact.check_links()?;
+    }
+    let (shared, base) = match output {
+        Output::Executable { entry } if artifacts.is_empty() => {
+            return Err(ArtifactError::UndefinedSymbols(vec![entry.to_string()]))
+        }
+        Output::Executable { .. } => (false, EXECUTABLE_BASE_ADDRESS),
+        Output::SharedLibrary { .. } => (true, 0),
+    };
+
+    let mut linker = Linker::new(artifacts, shared)?;
+    let entry = match output {
+        Output::Executable { entry } => match linker.globals.get(entry) {
+            Some(&index) if linker.placed[index].def.decl.is_function() => Some(index),
+            _ => return Err(ArtifactError::UndefinedSymbols(vec![entry.to_string()])),
+        },
+        Output::SharedLibrary { .. } => None,
+    };
+    for (i, artifact) in artifacts.iter().enumerate() {
+        linker.resolve(i, artifact.links().collect())?;
+    }
+    let got_size = linker.got.len() as u64 * GOT_ENTRY_SIZE;
+    linker.sections[&OutputKind::Got].reserve(got_size);
+    if let Output::SharedLibrary { needed } = output {
+        linker.reserve_dynamic_sections(needed);
+    }
+    let ctx = linker.ctx;
+    let phdrs_size = (linker.segment_count() * ProgramHeader::size(ctx)) as u64;
+    let segments = linker.layout(Header::size(ctx) as u64 + phdrs_size, base);
+    let relocations = linker.apply()?;
+
+    // the sections, followed by the symbol table, the string tables and the section headers
+    let kinds = linker
+        .sections
+        .values()
+        .filter(|section| section.size != 0)
+        .map(|section| section.kind)
+        .collect::<Vec<_>>();
+    let shndx = |kind| {
+        1 + kinds
+            .iter()
+            .position(|&other| other == kind)
+            .expect("section of a definition is present")
+    };
+    if let Output::SharedLibrary { needed } = output {
+        linker.fill_dynamic_sections(needed, relocations, shndx)?;
+    }
+    let mut strtab = StrTab::new();
+    let mut symbols = vec![Sym::default()];
+    let mut globals = Vec::new();
+    for (index, placed) in linker.placed.iter().enumerate() {
+        let local = scope(placed.def.decl) == Scope::Local;
+        // the definitions of global symbols which lost to another aren't linked
+        if placed.def.decl.is_section() || (!local && linker.globals[placed.def.name] != index) {
+            continue;
+        }
+        let st_name = strtab.add(placed.def.name) as usize;
+        let symbol = symbol(placed, st_name, linker.address(index), shndx(placed.kind));
+        if local {
+            symbols.push(symbol);
+        } else {
+            globals.push(symbol);
+        }
+    }
+    let first_global = symbols.len();
+    symbols.extend(globals);
+
+    let mut shstrtab = StrTab::new();
+    let mut section_headers = vec![SectionHeader::default()];
+    for kind in &kinds {
+        let section = &linker.sections[kind];
+        let sh_link = match kind {
+            OutputKind::Hash | OutputKind::RelaDyn | OutputKind::RelaPlt => {
+                shndx(OutputKind::DynSym)
+            }
+            OutputKind::DynSym | OutputKind::Dynamic => shndx(OutputKind::DynStr),
+            _ => 0,
+        };
+        let sh_info = match kind {
+            // every dynamic symbol is global
+            OutputKind::DynSym => 1,
+            OutputKind::RelaPlt => shndx(OutputKind::GotPlt),
+            _ => 0,
+        };
+        section_headers.push(SectionHeader {
+            sh_name: shstrtab.add(kind.name()) as usize,
+            sh_type: kind.section_type(),
+            sh_flags: u64::from(kind.flags()),
+            sh_addr: section.addr,
+            sh_offset: section.offset,
+            sh_size: section.size,
+            sh_link: sh_link as u32,
+            sh_info: sh_info as u32,
+            sh_addralign: section.align,
+            sh_entsize: kind.entsize(),
+        });
+    }
+    let end_of_sections = segments
+        .iter()
+        .map(|segment| segment.p_offset + segment.p_filesz)
+        .max()
+        .unwrap_or(0);
+    let symtab_offset = align_up(end_of_sections, 8);
+    let symtab_size = (symbols.len() * Sym::size(ctx.container)) as u64;
+    let strtab_offset = symtab_offset + symtab_size;
+    let strtab_index = section_headers.len() as u32 + 1;
+    section_headers.push(SectionHeader {
+        sh_name: shstrtab.add(".symtab") as usize,
+        sh_type: SHT_SYMTAB,
+        sh_offset: symtab_offset,
+        sh_size: symtab_size,
+        sh_link: strtab_index,
+        sh_info: first_global as u32,
+        sh_addralign: 8,
+        sh_entsize: Sym::size(ctx.container) as u64,
+        ..Default::default()
+    });
+    section_headers.push(SectionHeader {
+        sh_name: shstrtab.add(".strtab") as usize,
+        sh_type: SHT_STRTAB,
+        sh_offset: strtab_offset,
+        sh_size: strtab.bytes.len() as u64,
+        sh_addralign: 1,
+        ..Default::default()
+    });
+    let shstrtab_offset = strtab_offset + strtab.bytes.len() as u64;
+    let shstrtab_name = shstrtab.add(".shstrtab") as usize;
+    section_headers.push(SectionHeader {
+        sh_name: shstrtab_name,
+        sh_type: SHT_STRTAB,
+        sh_offset: shstrtab_offset,
+        sh_size: shstrtab.bytes.len() as u64,
+        sh_addralign: 1,
+        ..Default::default()
+    });
+    let sh_offset = align_up(shstrtab_offset + shstrtab.bytes.len() as u64, 8);
+
+    let mut header = Header::new(ctx);
+    header.e_type = if shared {
+        header::ET_DYN
+    } else {
+        header::ET_EXEC
+    };
+    header.e_machine = header::EM_X86_64;
+    header.e_entry = entry.map_or(0, |entry| linker.address(entry));
+    header.e_phoff = Header::size(ctx) as u64;
+    header.e_phnum = segments.len() as u16;
+    header.e_shoff = sh_offset;
+    header.e_shnum = section_headers.len() as u16;
+    header.e_shstrndx = section_headers.len() as u16 - 1;
+
+    let mut file = Cursor::new(Vec::new());
+    file.iowrite_with(header, ctx)?;
+    for segment in segments {
+        let mut bytes = vec![0; ProgramHeader::size(ctx)];
+        bytes.pwrite_with(segment, 0, ctx)?;
+        file.write_all(&bytes)?;
+    }
+    for kind in &kinds {
+        let section = &linker.sections[kind];
+        if *kind != OutputKind::Bss {
+            pad_to(&mut file, section.offset)?;
+            file.write_all(&section.bytes)?;
+            pad_to(&mut file, section.offset + section.size)?;
+        }
+    }
+    pad_to(&mut file, symtab_offset)?;
+    for symbol in symbols {
+        file.iowrite_with(symbol, ctx)?;
+    }
+    file.write_all(&strtab.bytes)?;
+    file.write_all(&shstrtab.bytes)?;
+    pad_to(&mut file, sh_offset)?;
+    for section_header in section_headers {
+        file.iowrite_with(section_header, ctx)?;
+    }
+    Ok(file.into_inner())
+}
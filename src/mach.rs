@@ -1,23 +1,27 @@
 //! The Mach 32/64 bit backend for transforming an artifact to a valid, mach-o object file.
 
 use crate::artifact::{
-    Data, DataType, Decl, DefinedDecl, Definition, ImportKind, Reloc, SectionKind,
+    ArtifactError, Binding, Data, DataType, Decl, DefinedDecl, Definition, ImportKind, InitKind,
+    LinkAndDecl, Reloc, Scope, SectionKind, SymbolMap, UnwindTable,
 };
 use crate::target::make_ctx;
 use crate::{Artifact, Ctx};
 
 use indexmap::IndexMap;
 use scroll::ctx::SizeWith;
-use scroll::{IOwrite, Pwrite};
+use scroll::{Endian, IOwrite, Pwrite};
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::io::SeekFrom::*;
+use std::convert::TryFrom;
 use std::io::{BufWriter, Cursor, Seek, Write};
 use string_interner::StringInterner;
-use target_lexicon::Architecture;
+use target_lexicon::{Architecture, BinaryFormat};
 
 use goblin::mach::constants::{
-    S_ATTR_DEBUG, S_ATTR_PURE_INSTRUCTIONS, S_ATTR_SOME_INSTRUCTIONS, S_CSTRING_LITERALS,
-    S_REGULAR, S_ZEROFILL,
+    SECTION_TYPE, S_ATTR_DEBUG, S_ATTR_LIVE_SUPPORT, S_ATTR_NO_TOC, S_ATTR_PURE_INSTRUCTIONS,
+    S_ATTR_SOME_INSTRUCTIONS, S_ATTR_STRIP_STATIC_SYMS, S_COALESCED, S_CSTRING_LITERALS,
+    S_MOD_INIT_FUNC_POINTERS, S_MOD_TERM_FUNC_POINTERS, S_REGULAR, S_THREAD_LOCAL_REGULAR,
+    S_THREAD_LOCAL_VARIABLES, S_THREAD_LOCAL_ZEROFILL, S_ZEROFILL,
 };
 use goblin::mach::cputype;
 use goblin::mach::header::{Header, MH_OBJECT, MH_SUBSECTIONS_VIA_SYMBOLS};
@@ -26,22 +30,38 @@ use goblin::mach::relocation::{RelocType, RelocationInfo, SIZEOF_RELOCATION_INFO
 use goblin::mach::segment::{Section, Segment};
 use goblin::mach::symbols::Nlist;
 
-struct CpuType(cputype::CpuType);
+/// The cpu type and subtype of a mach-o file
+#[derive(Debug, Clone, Copy)]
+struct CpuType(cputype::CpuType, cputype::CpuSubType);
 
-impl From<Architecture> for CpuType {
-    fn from(architecture: Architecture) -> CpuType {
+impl TryFrom<Architecture> for CpuType {
+    type Error = ArtifactError;
+    fn try_from(architecture: Architecture) -> Result<CpuType, ArtifactError> {
         use goblin::mach::cputype::*;
-        use target_lexicon::Architecture::*;
-        CpuType(match architecture {
-            X86_64 => CPU_TYPE_X86_64,
-            X86_32(_) => CPU_TYPE_X86,
-            Aarch64(_) => CPU_TYPE_ARM64,
-            Arm(_) => CPU_TYPE_ARM,
-            Sparc => CPU_TYPE_SPARC,
-            Powerpc => CPU_TYPE_POWERPC,
-            Powerpc64 | Powerpc64le => CPU_TYPE_POWERPC64,
-            Unknown => 0,
-            arch => panic!("requested architecture {} does not exist in MachO", arch),
+        use target_lexicon::{Architecture::*, ArmArchitecture};
+        Ok(match architecture {
+            X86_64 => CpuType(CPU_TYPE_X86_64, CPU_SUBTYPE_X86_64_ALL),
+            X86_32(_) => CpuType(CPU_TYPE_X86, CPU_SUBTYPE_I386_ALL),
+            Aarch64(_) => CpuType(CPU_TYPE_ARM64, CPU_SUBTYPE_ARM64_ALL),
+            Arm(arm) => CpuType(
+                CPU_TYPE_ARM,
+                match arm {
+                    ArmArchitecture::Armv7 => CPU_SUBTYPE_ARM_V7,
+                    ArmArchitecture::Armv7k => CPU_SUBTYPE_ARM_V7K,
+                    ArmArchitecture::Armv7s => CPU_SUBTYPE_ARM_V7S,
+                    _ => CPU_SUBTYPE_ARM_ALL,
+                },
+            ),
+            Sparc => CpuType(CPU_TYPE_SPARC, CPU_SUBTYPE_SPARC_ALL),
+            Powerpc => CpuType(CPU_TYPE_POWERPC, CPU_SUBTYPE_POWERPC_ALL),
+            Powerpc64 | Powerpc64le => CpuType(CPU_TYPE_POWERPC64, CPU_SUBTYPE_POWERPC_ALL),
+            Unknown => CpuType(0, 0),
+            architecture => {
+                return Err(ArtifactError::UnsupportedArchitecture {
+                    architecture,
+                    format: BinaryFormat::Macho,
+                })
+            }
         })
     }
 }
@@ -59,11 +79,51 @@ fn align_to_align_exp(align: u64) -> u64 {
 type SectionIndex = usize;
 type StrtableOffset = u64;
 
-const CODE_SECTION_INDEX: SectionIndex = 0;
-const DATA_SECTION_INDEX: SectionIndex = 1;
-const CSTRING_SECTION_INDEX: SectionIndex = 2;
-const BSS_SECTION_INDEX: SectionIndex = 3;
-const NUM_DEFAULT_SECTIONS: SectionIndex = 4;
+/// The suffix of the symbol for the initial value of a thread-local variable; the variable's own
+/// symbol is its descriptor
+const TLV_INIT_SUFFIX: &str = "$tlv$init";
+/// The function which initializes thread-local variables on first access, from their descriptor
+const TLV_BOOTSTRAP: &str = "_tlv_bootstrap";
+/// The number of pointers in a thread-local variable descriptor
+const TLV_DESCRIPTOR_LEN: u64 = 3;
+
+/// Whether the linker should pick one of the copies of `def` in different objects, instead of
+/// reporting duplicates; Mach-o has no COMDAT groups, so their members are weak definitions instead
+fn is_weak_definition(def: &Definition) -> bool {
+    let scope = match def.decl {
+        DefinedDecl::Function(d) => d.get_scope(),
+        DefinedDecl::Data(d) => d.get_scope(),
+        DefinedDecl::Section(_) => return false,
+    };
+    match scope {
+        Scope::Local => false,
+        Scope::Weak => true,
+        Scope::Global => def.comdat.is_some(),
+    }
+}
+
+/// The sections of pointers to the constructors and destructors of `artifact`, with their flags and
+/// the functions they point to; Mach-o has no priorities, so they run in the order registered
+fn initializer_sections(artifact: &Artifact) -> Vec<(&'static str, u32, Vec<&str>)> {
+    let mut sections = vec![
+        ("__mod_init_func", S_MOD_INIT_FUNC_POINTERS, Vec::new()),
+        ("__mod_term_func", S_MOD_TERM_FUNC_POINTERS, Vec::new()),
+    ];
+    for init in artifact.initializers() {
+        let i = match init.kind {
+            InitKind::Constructor => 0,
+            InitKind::Destructor => 1,
+        };
+        sections[i].2.push(init.name);
+    }
+    sections.retain(|(_, _, functions)| !functions.is_empty());
+    sections
+}
+
+/// The symbol for the initial value of thread-local variable `name`
+fn tlv_init_name(name: &str) -> String {
+    format!("{}{}", name, TLV_INIT_SUFFIX)
+}
 
 /// A builder for creating a 32/64 bit Mach-o Nlist symbol
 #[derive(Debug)]
@@ -71,6 +131,7 @@ struct SymbolBuilder {
     name: StrtableOffset,
     section: Option<SectionIndex>,
     global: bool,
+    weak: bool,
     import: bool,
     offset: u64,
     segment_relative_offset: u64,
@@ -83,6 +144,7 @@ impl SymbolBuilder {
             name,
             section: None,
             global: false,
+            weak: false,
             import: false,
             offset: 0,
             segment_relative_offset: 0,
@@ -98,6 +160,11 @@ impl SymbolBuilder {
         self.global = global;
         self
     }
+    /// Is this symbol a weak definition, which the linker may replace with another of the same name?
+    pub fn weak(mut self, weak: bool) -> Self {
+        self.weak = weak;
+        self
+    }
     pub fn offset(mut self, offset: u64) -> Self {
         self.offset = offset;
         self
@@ -118,12 +185,12 @@ impl SymbolBuilder {
     }
     /// Finalize and create the symbol
     pub fn create(self) -> Nlist {
-        use goblin::mach::symbols::{NO_SECT, N_EXT, N_SECT, N_UNDF};
+        use goblin::mach::symbols::{NO_SECT, N_EXT, N_SECT, N_UNDF, N_WEAK_DEF};
         let n_strx = self.name;
         let mut n_sect = 0;
         let mut n_type = N_UNDF;
         let mut n_value = self.offset;
-        let n_desc = 0;
+        let n_desc = if self.weak { N_WEAK_DEF } else { 0 };
         if self.global {
             n_type |= N_EXT;
         } else {
@@ -162,6 +229,7 @@ struct RelocationBuilder {
     symbol: SymbolIndex,
     relocation_offset: u64,
     absolute: bool,
+    external: bool,
     size: u8,
     r_type: RelocType,
 }
@@ -173,10 +241,29 @@ impl RelocationBuilder {
             symbol,
             relocation_offset,
             absolute: false,
+            external: true,
             size: 0,
             r_type,
         }
     }
+    /// Create an `ARM64_RELOC_ADDEND`, which carries `addend` for the relocation following it
+    pub fn arm64_addend(addend: i32, relocation_offset: u64) -> Self {
+        use goblin::mach::relocation::ARM64_RELOC_ADDEND;
+        RelocationBuilder {
+            // the addend is stored in the 24 bit symbol number
+            symbol: (addend as u32 & 0x00ff_ffff) as SymbolIndex,
+            relocation_offset,
+            absolute: true,
+            external: false,
+            size: 4,
+            r_type: ARM64_RELOC_ADDEND,
+        }
+    }
+    /// This relocation is relative to the section whose ordinal is its symbol, not to a symbol
+    pub fn section_relative(mut self) -> Self {
+        self.external = false;
+        self
+    }
     /// This is an absolute relocation
     pub fn absolute(mut self) -> Self {
         self.absolute = true;
@@ -200,11 +287,13 @@ impl RelocationBuilder {
                     2
                 }
             }
+            1 => 0,
+            2 => 1,
             4 => 2,
             8 => 3,
             size => panic!("unsupported relocation size {}", size),
         } << 25;
-        let r_extern: u32 = 1 << 27;
+        let r_extern: u32 = if self.external { 1 } else { 0 } << 27;
         let r_type = (self.r_type as u32) << 28;
         // r_symbolnum, 24 bits, r_pcrel 1 bit, r_length 2 bits, r_extern 1 bit, r_type 4 bits
         let r_info = r_symbolnum | r_pcrel | r_length | r_extern | r_type;
@@ -262,6 +351,13 @@ impl SectionBuilder {
         self.flags = flags;
         self
     }
+    /// Whether this section takes up no space in the file
+    fn is_zerofill(&self) -> bool {
+        matches!(
+            self.flags & SECTION_TYPE,
+            S_ZEROFILL | S_THREAD_LOCAL_ZEROFILL
+        )
+    }
     /// Finalize and create the actual Mach-o section
     pub fn create(&self, section_offset: &mut u64, relocation_offset: &mut u64) -> Section {
         let mut sectname = [0u8; 16];
@@ -280,8 +376,12 @@ impl SectionBuilder {
             nreloc: 0,
             flags: self.flags,
         };
-        section.offset = *section_offset as u32;
-        *section_offset += section.size;
+        if self.is_zerofill() {
+            section.offset = 0;
+        } else {
+            section.offset = *section_offset as u32;
+            *section_offset += section.size;
+        }
         if !self.relocations.is_empty() {
             let nrelocs = self.relocations.len();
             section.nreloc = nrelocs as _;
@@ -329,6 +429,7 @@ enum SymbolType {
         absolute_offset: u64,
         segment_relative_offset: u64,
         global: bool,
+        weak: bool,
     },
     /// An undefined symbol (an import)
     Undefined,
@@ -360,7 +461,16 @@ impl SymbolTable {
         self.strtable
             .get(symbol_name)
             .and_then(|idx| self.symbols.get(&idx))
-            .and_then(|sym| Some(sym.get_segment_relative_offset()))
+            .map(|sym| sym.get_segment_relative_offset())
+    }
+    /// Lookup the index of the section this symbol is defined in, and its offset in the section
+    pub fn location(&self, symbol_name: &str) -> Option<(SectionIndex, u64)> {
+        let sym = self
+            .strtable
+            .get(symbol_name)
+            .and_then(|idx| self.symbols.get(&idx))?;
+        sym.section
+            .map(|section| (section, sym.segment_relative_offset))
     }
     /// Lookup this symbols ordinal index in the symbol table, if it has one
     pub fn index(&self, symbol_name: &str) -> Option<SymbolIndex> {
@@ -393,9 +503,11 @@ impl SymbolTable {
                     section,
                     absolute_offset,
                     global,
+                    weak,
                     segment_relative_offset,
                 } => SymbolBuilder::new(self.strtable_size)
                     .global(global)
+                    .weak(weak)
                     .offset(absolute_offset)
                     .relative_offset(segment_relative_offset)
                     .section(section),
@@ -410,6 +522,56 @@ impl SymbolTable {
     }
 }
 
+/// A value written into a definition, at an offset and with a size in bytes; Mach-o relocations
+/// have no explicit addend, so they add to whatever the relocated bytes hold
+#[derive(Debug, Clone)]
+struct ImplicitAddend {
+    /// The target of the link
+    to: String,
+    at: u64,
+    size: u8,
+    value: i64,
+}
+
+impl ImplicitAddend {
+    /// The error for an addend which doesn't fit in the contents of `from`
+    fn out_of_bounds(&self, from: &str) -> ArtifactError {
+        ArtifactError::LinkOutOfBounds {
+            from: from.to_string(),
+            to: self.to.clone(),
+            at: self.at,
+        }
+    }
+}
+
+/// The bytes of `def`, with the implicit addends of its relocations written in place
+fn contents<'b>(
+    def: &Definition<'b>,
+    implicit_addends: &HashMap<String, Vec<ImplicitAddend>>,
+    endian: Endian,
+) -> Result<Cow<'b, [u8]>, ArtifactError> {
+    let addends = match implicit_addends.get(def.name) {
+        Some(addends) => addends,
+        None => return Ok(Cow::Borrowed(def.data.bytes().unwrap_or(&[]))),
+    };
+    let mut bytes = match def.data.bytes() {
+        Some(bytes) => bytes.to_vec(),
+        // zero-init data has no bytes in the file to write an addend into
+        None => return Err(addends[0].out_of_bounds(def.name)),
+    };
+    for addend in addends {
+        let at = usize::try_from(addend.at).map_err(|_| addend.out_of_bounds(def.name))?;
+        match addend.size {
+            1 => bytes.pwrite_with(addend.value as i8, at, endian),
+            2 => bytes.pwrite_with(addend.value as i16, at, endian),
+            4 => bytes.pwrite_with(addend.value as i32, at, endian),
+            _ => bytes.pwrite_with(addend.value, at, endian),
+        }
+        .map_err(|_| addend.out_of_bounds(def.name))?;
+    }
+    Ok(Cow::Owned(bytes))
+}
+
 #[derive(Debug)]
 /// A Mach-o program segment
 struct SegmentBuilder {
@@ -419,23 +581,56 @@ struct SegmentBuilder {
     pub offset: u64,
     size: u64,
     align_pad_map: HashMap<String, u64>,
+    /// The size of `__thread_bss`, which isn't in the file
+    thread_bss_size: u64,
+    /// The values written in place of the relocated bytes of each definition
+    implicit_addends: HashMap<String, Vec<ImplicitAddend>>,
 }
 
 impl SegmentBuilder {
+    /// Write `value`, `size` bytes wide, in place of the bytes `link` relocates, for the relocation
+    /// to add to
+    fn add_implicit_addend(
+        &mut self,
+        link: &LinkAndDecl,
+        size: u8,
+        value: i64,
+    ) -> Result<(), ArtifactError> {
+        let fits = match size {
+            1 => i8::try_from(value).is_ok(),
+            2 => i16::try_from(value).is_ok(),
+            4 => i32::try_from(value).is_ok(),
+            _ => true,
+        };
+        if !fits {
+            return Err(link.addend_overflow(value));
+        }
+        self.implicit_addends
+            .entry(link.from.name.to_string())
+            .or_default()
+            .push(ImplicitAddend {
+                to: link.to.name.to_string(),
+                at: link.at,
+                size,
+                value,
+            });
+        Ok(())
+    }
     /// The size of this segment's _data_, in bytes
     pub fn size(&self) -> u64 {
         self.size
     }
     /// The size of this segment's _load command_, including its associated sections, in bytes
     pub fn load_command_size(&self, ctx: &Ctx) -> u64 {
-        Segment::size_with(&ctx) as u64
-            + (self.sections.len() as u64 * Section::size_with(&ctx) as u64)
+        Segment::size_with(ctx) as u64
+            + (self.sections.len() as u64 * Section::size_with(ctx) as u64)
     }
     fn _section_data_file_offset(&self, ctx: &Ctx) -> u64 {
         // section data
         Header::size_with(&ctx.container) as u64 + self.load_command_size(ctx)
     }
     // FIXME: this is in desperate need of refactoring, obviously
+    #[allow(clippy::too_many_arguments)]
     fn build_section(
         symtab: &mut SymbolTable,
         sectname: &'static str,
@@ -444,12 +639,14 @@ impl SegmentBuilder {
         offset: &mut u64,
         addr: &mut u64,
         symbol_offset: &mut u64,
-        section: SectionIndex,
         definitions: &[Definition],
         min_alignment_exponent: u64,
         flags: Option<u32>,
         align_pad_map: &mut HashMap<String, u64>,
+        thread_local: bool,
     ) {
+        // sections are numbered in the order they're added
+        let section = sections.len();
         let mut local_size = 0;
         let mut section_relative_offset = 0;
         let mut alignment_exponent = min_alignment_exponent;
@@ -459,13 +656,22 @@ impl SegmentBuilder {
                 unreachable!();
             }
 
+            // a thread-local variable's own symbol is its descriptor, so its initial value gets a
+            // local symbol of its own
+            let (name, global, weak) = if thread_local {
+                (tlv_init_name(def.name), false, false)
+            } else {
+                let weak = is_weak_definition(def);
+                (def.name.to_string(), def.decl.is_global() || weak, weak)
+            };
             symtab.insert(
-                def.name,
+                &name,
                 SymbolType::Defined {
                     section,
                     segment_relative_offset: section_relative_offset,
                     absolute_offset: *symbol_offset,
-                    global: def.decl.is_global(),
+                    global,
+                    weak,
                 },
             );
             *symbol_offset += def.data.file_size() as u64;
@@ -511,13 +717,13 @@ impl SegmentBuilder {
         offset: &mut u64,
         addr: &mut u64,
         symbol_offset: &mut u64,
-        section_idx: SectionIndex,
         def: &Definition,
     ) {
         let s = match def.decl {
             DefinedDecl::Section(s) => s,
             _ => unreachable!("in build_custom_section: def.decl != Section"),
         };
+        let section_idx = sections.len();
 
         let segment_name = match s.kind() {
             SectionKind::Data => "__DATA",
@@ -537,6 +743,19 @@ impl SegmentBuilder {
             flags |= S_ATTR_DEBUG;
         }
 
+        // the unwind info has sections of its own, which the linker turns into `__unwind_info`
+        let (segment_name, sectname, flags) = match s.get_unwind() {
+            Some(UnwindTable::EhFrame) => (
+                "__TEXT",
+                "__eh_frame".to_string(),
+                S_COALESCED | S_ATTR_NO_TOC | S_ATTR_STRIP_STATIC_SYMS | S_ATTR_LIVE_SUPPORT,
+            ),
+            Some(UnwindTable::CompactUnwind) => {
+                ("__LD", "__compact_unwind".to_string(), S_ATTR_DEBUG)
+            }
+            None => (segment_name, sectname, flags),
+        };
+
         for (symbol, symbol_dst_offset) in def.symbols {
             symtab.insert(
                 symbol,
@@ -545,6 +764,7 @@ impl SegmentBuilder {
                     segment_relative_offset: *symbol_dst_offset,
                     absolute_offset: *symbol_offset + *symbol_dst_offset,
                     global: true,
+                    weak: false,
                 },
             );
         }
@@ -562,6 +782,7 @@ impl SegmentBuilder {
     }
     /// Create a new program segment from an `artifact`, symbol table, and context
     // FIXME: this is pub(crate) for now because we can't leak pub(crate) Definition
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         artifact: &Artifact,
         code: &[Definition],
@@ -569,6 +790,8 @@ impl SegmentBuilder {
         zeroed_data: &[Definition],
         cstrings: &[Definition],
         custom_sections: &[Definition],
+        tls_data: &[Definition],
+        tls_zeroed: &[Definition],
         symtab: &mut SymbolTable,
         ctx: &Ctx,
     ) -> Self {
@@ -586,11 +809,11 @@ impl SegmentBuilder {
             &mut offset,
             &mut size,
             &mut symbol_offset,
-            CODE_SECTION_INDEX,
-            &code,
+            code,
             4,
             Some(S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS),
             &mut align_pad_map,
+            false,
         );
         Self::build_section(
             symtab,
@@ -600,12 +823,70 @@ impl SegmentBuilder {
             &mut offset,
             &mut size,
             &mut symbol_offset,
-            DATA_SECTION_INDEX,
-            &blob_data,
+            blob_data,
             3,
             None,
             &mut align_pad_map,
+            false,
         );
+        // __data ends pointer aligned too, and the pointers are filled in by their relocations
+        for (sectname, flags, functions) in initializer_sections(artifact) {
+            let local_size = functions.len() as u64 * ctx.size() as u64;
+            let section = SectionBuilder::new(sectname.to_string(), "__DATA", local_size)
+                .offset(offset)
+                .addr(size)
+                .align(align_to_align_exp(ctx.size() as u64))
+                .flags(flags);
+            sections.insert(sectname.to_string(), section);
+            offset += local_size;
+            size += local_size;
+            symbol_offset += local_size;
+        }
+        let has_tls = !tls_data.is_empty() || !tls_zeroed.is_empty();
+        if has_tls {
+            // __data ends pointer aligned, as the descriptors must be
+            let descriptor_size = TLV_DESCRIPTOR_LEN * ctx.size() as u64;
+            let section = sections.len();
+            let mut local_size = 0;
+            for def in tls_data.iter().chain(tls_zeroed) {
+                symtab.insert(
+                    def.name,
+                    SymbolType::Defined {
+                        section,
+                        segment_relative_offset: local_size,
+                        absolute_offset: symbol_offset + local_size,
+                        global: def.decl.is_global() || is_weak_definition(def),
+                        weak: is_weak_definition(def),
+                    },
+                );
+                local_size += descriptor_size;
+            }
+            let thread_vars =
+                SectionBuilder::new("__thread_vars".to_string(), "__DATA", local_size)
+                    .offset(offset)
+                    .addr(size)
+                    .align(align_to_align_exp(ctx.size() as u64))
+                    .flags(S_THREAD_LOCAL_VARIABLES);
+            sections.insert("__thread_vars".to_string(), thread_vars);
+            offset += local_size;
+            size += local_size;
+            symbol_offset += local_size;
+
+            Self::build_section(
+                symtab,
+                "__thread_data",
+                "__DATA",
+                &mut sections,
+                &mut offset,
+                &mut size,
+                &mut symbol_offset,
+                tls_data,
+                3,
+                Some(S_THREAD_LOCAL_REGULAR),
+                &mut align_pad_map,
+                true,
+            );
+        }
         Self::build_section(
             symtab,
             "__cstring",
@@ -614,11 +895,11 @@ impl SegmentBuilder {
             &mut offset,
             &mut size,
             &mut symbol_offset,
-            CSTRING_SECTION_INDEX,
-            &cstrings,
+            cstrings,
             0,
             Some(S_CSTRING_LITERALS),
             &mut align_pad_map,
+            false,
         );
         Self::build_section(
             symtab,
@@ -628,24 +909,57 @@ impl SegmentBuilder {
             &mut offset,
             &mut size,
             &mut symbol_offset,
-            BSS_SECTION_INDEX,
-            &zeroed_data,
+            zeroed_data,
             0,
             Some(S_ZEROFILL),
             &mut align_pad_map,
+            false,
         );
-        for (idx, def) in custom_sections.iter().enumerate() {
+        for def in custom_sections {
             Self::build_custom_section(
                 symtab,
                 &mut sections,
                 &mut offset,
                 &mut size,
                 &mut symbol_offset,
-                idx + NUM_DEFAULT_SECTIONS,
                 def,
             );
         }
-        for (ref import, _) in artifact.imports() {
+        // zero initialized thread-local variables come last, as they take up no space in the file
+        let mut thread_bss_size = 0;
+        if has_tls {
+            let mut addr = size;
+            let mut alignment_exponent = 0;
+            let section = sections.len();
+            let start = addr;
+            for def in tls_zeroed {
+                let align = def.decl.get_align().unwrap_or(1);
+                alignment_exponent = std::cmp::max(alignment_exponent, align_to_align_exp(align));
+                addr = addr.div_ceil(align) * align;
+                symtab.insert(
+                    &tlv_init_name(def.name),
+                    SymbolType::Defined {
+                        section,
+                        segment_relative_offset: addr - start,
+                        absolute_offset: addr,
+                        global: false,
+                        weak: false,
+                    },
+                );
+                if let Data::ZeroInit(size) = def.data {
+                    addr += *size as u64;
+                }
+            }
+            let thread_bss =
+                SectionBuilder::new("__thread_bss".to_string(), "__DATA", addr - start)
+                    .addr(start)
+                    .align(alignment_exponent)
+                    .flags(S_THREAD_LOCAL_ZEROFILL);
+            sections.insert("__thread_bss".to_string(), thread_bss);
+            thread_bss_size = addr - size;
+            symtab.insert(TLV_BOOTSTRAP, SymbolType::Undefined);
+        }
+        for (import, _) in artifact.imports() {
             symtab.insert(import, SymbolType::Undefined);
         }
         // FIXME re add assert
@@ -659,6 +973,8 @@ impl SegmentBuilder {
             sections,
             offset,
             align_pad_map,
+            thread_bss_size,
+            implicit_addends: HashMap::new(),
         }
     }
 }
@@ -667,7 +983,7 @@ impl SegmentBuilder {
 #[derive(Debug)]
 struct Mach<'a> {
     ctx: Ctx,
-    architecture: Architecture,
+    cputype: CpuType,
     symtab: SymbolTable,
     segment: SegmentBuilder,
     code: ArtifactCode<'a>,
@@ -675,12 +991,14 @@ struct Mach<'a> {
     bss_size: usize,
     cstrings: Vec<Definition<'a>>,
     sections: Vec<Definition<'a>>,
+    tls: Vec<Definition<'a>>,
     _p: ::std::marker::PhantomData<&'a ()>,
 }
 
 impl<'a> Mach<'a> {
-    pub fn new(artifact: &'a Artifact) -> Self {
+    pub fn new(artifact: &'a Artifact) -> Result<Self, ArtifactError> {
         let ctx = make_ctx(&artifact.target);
+        let cputype = CpuType::try_from(artifact.target.architecture)?;
         // FIXME: I believe we can avoid this partition by refactoring SegmentBuilder::new
         let (mut code, mut data, mut bss, mut cstrings, mut sections, mut bss_size) = (
             Vec::new(),
@@ -690,11 +1008,19 @@ impl<'a> Mach<'a> {
             Vec::new(),
             0,
         );
+        let (mut tls, mut tls_bss) = (Vec::new(), Vec::new());
         for def in artifact.definitions() {
             match def.decl {
                 DefinedDecl::Function { .. } => {
                     code.push(def);
                 }
+                DefinedDecl::Data(d) if d.is_tls() => {
+                    if let Data::ZeroInit(_) = def.data {
+                        tls_bss.push(def);
+                    } else {
+                        tls.push(def);
+                    }
+                }
                 DefinedDecl::Data(d) => {
                     if let Data::ZeroInit(size) = def.data {
                         bss.push(def);
@@ -713,42 +1039,46 @@ impl<'a> Mach<'a> {
 
         let mut symtab = SymbolTable::new();
         let mut segment = SegmentBuilder::new(
-            &artifact,
+            artifact,
             &code,
             &data,
             &bss,
             &cstrings,
             &sections,
+            &tls,
+            &tls_bss,
             &mut symtab,
             &ctx,
         );
-        build_relocations(&mut segment, &artifact, &symtab);
+        build_relocations(&mut segment, artifact, &symtab)?;
 
-        Mach {
+        Ok(Mach {
             ctx,
-            architecture: artifact.target.architecture,
+            cputype,
             symtab,
             segment,
-            _p: ::std::marker::PhantomData::default(),
+            _p: ::std::marker::PhantomData,
             code,
             data,
             bss_size,
             cstrings,
             sections,
-        }
+            tls,
+        })
     }
     fn header(&self, sizeofcmds: u64) -> Header {
         let mut header = Header::new(self.ctx);
         header.filetype = MH_OBJECT;
         // safe to divide up the sections into sub-sections via symbols for dead code stripping
         header.flags = MH_SUBSECTIONS_VIA_SYMBOLS;
-        header.cputype = CpuType::from(self.architecture).0;
-        header.cpusubtype = 3;
+        let CpuType(cputype, cpusubtype) = self.cputype;
+        header.cputype = cputype;
+        header.cpusubtype = cpusubtype;
         header.ncmds = 2;
         header.sizeofcmds = sizeofcmds as u32;
         header
     }
-    pub fn write<T: Write + Seek>(self, file: T) -> Result<(), std::io::Error> {
+    pub fn write<T: Write + Seek>(self, file: T) -> Result<(), ArtifactError> {
         let mut file = BufWriter::new(file);
         // FIXME: this is ugly af, need cmdsize to get symtable offset
         // construct symtab command
@@ -789,7 +1119,8 @@ impl<'a> Mach<'a> {
         segment_load_command.maxprot = 7;
         segment_load_command.filesize = self.segment.size();
         // segment size, with __bss data sizes added
-        segment_load_command.vmsize = segment_load_command.filesize + self.bss_size as u64;
+        segment_load_command.vmsize =
+            segment_load_command.filesize + self.bss_size as u64 + self.segment.thread_bss_size;
         segment_load_command.fileoff = first_section_offset;
         debug!("Segment: {:#?}", segment_load_command);
 
@@ -811,7 +1142,7 @@ impl<'a> Mach<'a> {
         // write header
         //////////////////////////////
         file.iowrite_with(header, self.ctx)?;
-        debug!("SEEK: after header: {}", file.seek(Current(0))?);
+        debug!("SEEK: after header: {}", file.stream_position()?);
 
         //////////////////////////////
         // write load commands
@@ -819,17 +1150,17 @@ impl<'a> Mach<'a> {
         file.iowrite_with(segment_load_command, self.ctx)?;
         file.write_all(&raw_sections)?;
         file.iowrite_with(symtab_load_command, self.ctx.le)?;
-        debug!("SEEK: after load commands: {}", file.seek(Current(0))?);
+        debug!("SEEK: after load commands: {}", file.stream_position()?);
 
         //////////////////////////////
         // write code
         //////////////////////////////
         for code in self.code {
-            if let Data::Blob(bytes) = code.data {
-                file.write_all(&bytes)?;
-            } else {
-                unreachable!()
-            }
+            file.write_all(&contents(
+                &code,
+                &self.segment.implicit_addends,
+                self.ctx.le,
+            )?)?;
 
             if let Some(&align_pad) = self.segment.align_pad_map.get(code.name) {
                 for _ in 0..align_pad {
@@ -839,15 +1170,17 @@ impl<'a> Mach<'a> {
                 }
             }
         }
-        debug!("SEEK: after code: {}", file.seek(Current(0))?);
+        debug!("SEEK: after code: {}", file.stream_position()?);
 
         //////////////////////////////
         // write data
         //////////////////////////////
         for data in self.data {
-            if let Data::Blob(bytes) = data.data {
-                file.write_all(bytes)?;
-            }
+            file.write_all(&contents(
+                &data,
+                &self.segment.implicit_addends,
+                self.ctx.le,
+            )?)?;
 
             if let Some(&align_pad) = self.segment.align_pad_map.get(data.name) {
                 for _ in 0..align_pad {
@@ -858,17 +1191,53 @@ impl<'a> Mach<'a> {
                 }
             }
         }
-        debug!("SEEK: after data: {}", file.seek(Current(0))?);
+        debug!("SEEK: after data: {}", file.stream_position()?);
+
+        //////////////////////////////
+        // write constructors and destructors
+        //////////////////////////////
+        for sectname in &["__mod_init_func", "__mod_term_func"] {
+            if let Some(section) = self.segment.sections.get(*sectname) {
+                // the pointers are filled in by their relocations
+                file.write_all(&vec![0; section.size as usize])?;
+            }
+        }
+        debug!(
+            "SEEK: after constructors and destructors: {}",
+            file.stream_position()?
+        );
+
+        //////////////////////////////
+        // write thread-local variables
+        //////////////////////////////
+        if let Some(thread_vars) = self.segment.sections.get("__thread_vars") {
+            // the descriptors are filled in by their relocations
+            file.write_all(&vec![0; thread_vars.size as usize])?;
+        }
+        for tls in self.tls {
+            file.write_all(&contents(
+                &tls,
+                &self.segment.implicit_addends,
+                self.ctx.le,
+            )?)?;
+
+            if let Some(&align_pad) = self.segment.align_pad_map.get(tls.name) {
+                for _ in 0..align_pad {
+                    // See comment above for explanation of 0xaa
+                    file.write_all(&[0xaa])?;
+                }
+            }
+        }
+        debug!(
+            "SEEK: after thread-local variables: {}",
+            file.stream_position()?
+        );
 
         //////////////////////////////
         // write cstrings
         //////////////////////////////
         for cstring in self.cstrings {
-            if let Data::Blob(bytes) = cstring.data {
-                file.write_all(bytes)?;
-            } else {
-                unreachable!();
-            }
+            file.write_all(cstring.data.bytes().unwrap())?;
 
             if let Some(&align_pad) = self.segment.align_pad_map.get(cstring.name) {
                 for _ in 0..align_pad {
@@ -877,17 +1246,17 @@ impl<'a> Mach<'a> {
                 }
             }
         }
-        debug!("SEEK: after cstrings: {}", file.seek(Current(0))?);
+        debug!("SEEK: after cstrings: {}", file.stream_position()?);
 
         //////////////////////////////
         // write custom sections
         //////////////////////////////
         for section in self.sections {
-            if let Data::Blob(bytes) = section.data {
-                file.write_all(bytes)?;
-            } else {
-                unreachable!()
-            }
+            file.write_all(&contents(
+                &section,
+                &self.segment.implicit_addends,
+                self.ctx.le,
+            )?)?;
 
             if let Some(&align_pad) = self.segment.align_pad_map.get(section.name) {
                 for _ in 0..align_pad {
@@ -896,7 +1265,7 @@ impl<'a> Mach<'a> {
                 }
             }
         }
-        debug!("SEEK: after custom sections: {}", file.seek(Current(0))?);
+        debug!("SEEK: after custom sections: {}", file.stream_position()?);
 
         //////////////////////////////
         // write symtable
@@ -906,7 +1275,7 @@ impl<'a> Mach<'a> {
             debug!("{}: {:?}", idx, symbol);
             file.iowrite_with(symbol, self.ctx)?;
         }
-        debug!("SEEK: after symtable: {}", file.seek(Current(0))?);
+        debug!("SEEK: after symtable: {}", file.stream_position()?);
 
         //////////////////////////////
         // write strtable
@@ -920,7 +1289,7 @@ impl<'a> Mach<'a> {
             file.write_all(string.as_bytes())?;
             file.iowrite(0u8)?;
         }
-        debug!("SEEK: after strtable: {}", file.seek(Current(0))?);
+        debug!("SEEK: after strtable: {}", file.stream_position()?);
 
         //////////////////////////////
         // write relocations
@@ -932,7 +1301,7 @@ impl<'a> Mach<'a> {
                 file.iowrite_with(reloc, self.ctx.le)?;
             }
         }
-        debug!("SEEK: after relocations: {}", file.seek(Current(0))?);
+        debug!("SEEK: after relocations: {}", file.stream_position()?);
 
         file.iowrite(0u8)?;
 
@@ -940,21 +1309,155 @@ impl<'a> Mach<'a> {
     }
 }
 
-// FIXME: this should actually return a runtime error if we encounter a from.decl to.decl pair which we don't explicitly match on
-fn build_relocations(segment: &mut SegmentBuilder, artifact: &Artifact, symtab: &SymbolTable) {
+/// Whether the arm64 relocation `r_type` is pc relative
+fn arm64_is_pcrel(r_type: RelocType) -> bool {
     use goblin::mach::relocation::{
-        R_ABS, X86_64_RELOC_BRANCH, X86_64_RELOC_GOT_LOAD, X86_64_RELOC_SIGNED,
-        X86_64_RELOC_UNSIGNED,
+        ARM64_RELOC_BRANCH26, ARM64_RELOC_GOT_LOAD_PAGE21, ARM64_RELOC_PAGE21,
+        ARM64_RELOC_TLVP_LOAD_PAGE21,
     };
-    let text_idx = segment.sections.get_full("__text").unwrap().0;
-    let data_idx = segment.sections.get_full("__data").unwrap().0;
+    matches!(
+        r_type,
+        ARM64_RELOC_BRANCH26
+            | ARM64_RELOC_PAGE21
+            | ARM64_RELOC_GOT_LOAD_PAGE21
+            | ARM64_RELOC_TLVP_LOAD_PAGE21
+    )
+}
+
+/// The index of the section holding the relocations of `from`, and the offset of `from` in it
+fn relocation_base(
+    segment: &SegmentBuilder,
+    symtab: &SymbolTable,
+    from: &Binding,
+) -> Option<(usize, u64)> {
+    match from.decl {
+        // custom sections have no symbol of their own
+        Decl::Defined(DefinedDecl::Section(_)) => segment
+            .sections
+            .get_full(from.name)
+            .map(|(idx, _, _)| (idx, 0)),
+        // the initial value of a thread-local variable is its own symbol
+        decl if decl.is_tls() => symtab.location(&tlv_init_name(from.name)),
+        _ => symtab.location(from.name),
+    }
+}
+
+/// Whether `decl` is a debug section
+fn is_debug(decl: &Decl) -> bool {
+    matches!(decl, Decl::Defined(DefinedDecl::Section(s)) if s.kind() == SectionKind::Debug)
+}
+
+/// Whether `decl` is a section of call frame information
+fn is_eh_frame(decl: &Decl) -> bool {
+    matches!(decl, Decl::Defined(DefinedDecl::Section(s)) if s.get_unwind() == Some(UnwindTable::EhFrame))
+}
+
+/// Add the relocation of `link` relative to the section of its target.
+///
+/// Custom sections have no symbol of their own, so the relocation is relative to the section
+/// instead (`r_extern = 0`), and the relocated bytes hold the address of the target in the object
+/// file, which the linker adjusts by however far the section moves. Addresses in debug info refer
+/// to definitions the same way, as debuggers reading it out of the object file expect.
+fn build_section_relocation(
+    segment: &mut SegmentBuilder,
+    symtab: &SymbolTable,
+    link: &LinkAndDecl,
+    r_type: RelocType,
+    size: u8,
+    addend: i64,
+) -> Result<(), ArtifactError> {
+    use goblin::mach::relocation::X86_64_RELOC_SIGNED;
+    let (from_idx, base_offset) =
+        relocation_base(segment, symtab, &link.from).ok_or_else(|| link.missing_symbol())?;
+    let (to_idx, to_offset) =
+        relocation_base(segment, symtab, &link.to).ok_or_else(|| link.missing_symbol())?;
+    let target = ((segment.sections[to_idx].addr + to_offset) as i64)
+        .checked_add(addend)
+        .ok_or_else(|| link.addend_overflow(addend))?;
+    let offset = base_offset + link.at;
+    let builder = RelocationBuilder::new(to_idx + 1, offset, r_type)
+        .section_relative()
+        .size(size);
+    let (builder, value) = if r_type == X86_64_RELOC_SIGNED {
+        // relative to the end of the 4 byte displacement
+        let from = &segment.sections[from_idx];
+        (
+            builder,
+            target.wrapping_sub((from.addr + offset + 4) as i64),
+        )
+    } else {
+        (builder.absolute(), target)
+    };
+    segment.sections[from_idx]
+        .relocations
+        .push(builder.create());
+    segment.add_implicit_addend(link, size, value)
+}
+
+fn build_relocations(
+    segment: &mut SegmentBuilder,
+    artifact: &Artifact,
+    symtab: &SymbolTable,
+) -> Result<(), ArtifactError> {
+    use goblin::mach::relocation::{
+        ARM64_RELOC_BRANCH26, ARM64_RELOC_GOT_LOAD_PAGE21, ARM64_RELOC_GOT_LOAD_PAGEOFF12,
+        ARM64_RELOC_PAGE21, ARM64_RELOC_PAGEOFF12, ARM64_RELOC_TLVP_LOAD_PAGE21,
+        ARM64_RELOC_TLVP_LOAD_PAGEOFF12, ARM64_RELOC_UNSIGNED, R_ABS, X86_64_RELOC_BRANCH,
+        X86_64_RELOC_GOT_LOAD, X86_64_RELOC_SIGNED, X86_64_RELOC_TLV, X86_64_RELOC_UNSIGNED,
+    };
+    let arm64 = matches!(artifact.target.architecture, Architecture::Aarch64(_));
+    let unsigned = if arm64 {
+        ARM64_RELOC_UNSIGNED
+    } else {
+        X86_64_RELOC_UNSIGNED
+    };
+    let pointer_size = make_ctx(&artifact.target).size() as u8;
+    let unsupported =
+        |link: &LinkAndDecl| link.unsupported(artifact.target.architecture, BinaryFormat::Macho);
+    // every thread-local variable descriptor points at the bootstrap function and its initial value
+    if let Some(thread_vars) = segment.sections.get_mut("__thread_vars") {
+        let bootstrap = symtab.index(TLV_BOOTSTRAP).unwrap();
+        let tls = artifact
+            .definitions()
+            .filter(|def| def.decl.is_tls())
+            .collect::<Vec<_>>();
+        let pointer_size = thread_vars.size / (tls.len() as u64 * TLV_DESCRIPTOR_LEN);
+        for def in tls {
+            let descriptor = symtab.offset(def.name).unwrap();
+            let init = symtab.index(&tlv_init_name(def.name)).unwrap();
+            for (to, offset) in [(bootstrap, 0), (init, 2 * pointer_size)] {
+                let builder = RelocationBuilder::new(to, descriptor + offset, unsigned)
+                    .absolute()
+                    .size(pointer_size as u8);
+                thread_vars.relocations.push(builder.create());
+            }
+        }
+    }
+    // every constructor and destructor pointer points at its function
+    for (sectname, _, functions) in initializer_sections(artifact) {
+        let section = &mut segment.sections[sectname];
+        let pointer_size = section.size / functions.len() as u64;
+        for (i, function) in functions.into_iter().enumerate() {
+            let to = symtab.index(function).unwrap();
+            let builder = RelocationBuilder::new(to, i as u64 * pointer_size, unsigned)
+                .absolute()
+                .size(pointer_size as u8);
+            section.relocations.push(builder.create());
+        }
+    }
     debug!("Generating relocations");
+    // the symbols links bind are looked up once each, however many links bind them
+    let mut symbol_indexes = SymbolMap::new();
+    let mut bases = SymbolMap::new();
     for link in artifact.links() {
         debug!(
             "Import links for: from {} to {} at {:#x} with {:?}",
             link.from.name, link.to.name, link.at, link.reloc
         );
-        let (absolute, reloc) = match link.reloc {
+        let (from_debug, to_debug) = (is_debug(link.from.decl), is_debug(link.to.decl));
+        // arm64 materializes addresses with an `adrp` at `link.at`, followed by an `add` or `ldr`
+        // which needs a page offset relocation of its own
+        let (reloc, pageoff, addend) = match link.reloc {
             Reloc::Auto => {
                 // NB: we currently deduce the meaning of our relocation from from decls -> to decl relocations
                 // e.g., global static data references, are constructed from Data -> Data links
@@ -963,34 +1466,94 @@ fn build_relocations(segment: &mut SegmentBuilder, artifact: &Artifact, symtab:
                     (Decl::Defined(DefinedDecl::Section(s)), _)
                         if s.kind() == SectionKind::Debug =>
                     {
-                        panic!("must use Reloc::Debug for debug section links")
+                        // must use Reloc::Debug for debug section links
+                        return Err(unsupported(&link));
                     }
                     // only debug sections should link to debug sections
                     (_, Decl::Defined(DefinedDecl::Section(s)))
                         if s.kind() == SectionKind::Debug =>
                     {
-                        panic!("invalid DebugSection link")
+                        return Err(unsupported(&link));
+                    }
+
+                    // to custom section
+                    (
+                        Decl::Defined(DefinedDecl::Function { .. }),
+                        Decl::Defined(DefinedDecl::Section(_)),
+                    ) => {
+                        // arm64 only has section relative relocations for pointers
+                        if arm64 {
+                            return Err(unsupported(&link));
+                        }
+                        build_section_relocation(
+                            segment,
+                            symtab,
+                            &link,
+                            X86_64_RELOC_SIGNED,
+                            4,
+                            link.addend,
+                        )?;
+                        continue;
+                    }
+                    (_, Decl::Defined(DefinedDecl::Section(_))) => {
+                        build_section_relocation(
+                            segment,
+                            symtab,
+                            &link,
+                            unsigned,
+                            pointer_size,
+                            link.addend,
+                        )?;
+                        continue;
                     }
 
-                    // from/to custom section
-                    (Decl::Defined(DefinedDecl::Section(_)), _)
-                    | (_, Decl::Defined(DefinedDecl::Section(_))) => {
-                        panic!("relocations are not yet supported for custom sections")
+                    // thread-local variables can only be accessed from code
+                    (Decl::Defined(DefinedDecl::Data { .. }), to)
+                    | (Decl::Defined(DefinedDecl::Section(_)), to)
+                        if to.is_tls() =>
+                    {
+                        return Err(unsupported(&link));
                     }
 
-                    // from data object
-                    (Decl::Defined(DefinedDecl::Data { .. }), _) => (true, X86_64_RELOC_UNSIGNED),
+                    // from data object or custom section
+                    (Decl::Defined(DefinedDecl::Data { .. }), _)
+                    | (Decl::Defined(DefinedDecl::Section(_)), _) => (unsigned, None, 0),
 
                     // from function
-                    (Decl::Defined(DefinedDecl::Function { .. }), to) => match to {
-                        Decl::Defined(DefinedDecl::Function { .. }) => (false, X86_64_RELOC_BRANCH),
-                        Decl::Import(ImportKind::Function) => (false, X86_64_RELOC_BRANCH),
+                    (Decl::Defined(DefinedDecl::Function { .. }), to) => match (to, arm64) {
+                        // thread-local variables are accessed through their descriptor
+                        (to, false) if to.is_tls() => (X86_64_RELOC_TLV, None, 0),
+                        (to, true) if to.is_tls() => (
+                            ARM64_RELOC_TLVP_LOAD_PAGE21,
+                            Some(ARM64_RELOC_TLVP_LOAD_PAGEOFF12),
+                            0,
+                        ),
+
+                        (Decl::Defined(DefinedDecl::Function { .. }), false)
+                        | (Decl::Import(ImportKind::Function), false) => {
+                            (X86_64_RELOC_BRANCH, None, 0)
+                        }
+                        (Decl::Defined(DefinedDecl::Function { .. }), true)
+                        | (Decl::Import(ImportKind::Function), true) => {
+                            (ARM64_RELOC_BRANCH26, None, 0)
+                        }
 
-                        Decl::Defined(DefinedDecl::Data { .. }) => (false, X86_64_RELOC_SIGNED),
-                        Decl::Import(ImportKind::Data) => (false, X86_64_RELOC_GOT_LOAD),
+                        (Decl::Defined(DefinedDecl::Data { .. }), false) => {
+                            (X86_64_RELOC_SIGNED, None, 0)
+                        }
+                        (Decl::Defined(DefinedDecl::Data { .. }), true) => {
+                            (ARM64_RELOC_PAGE21, Some(ARM64_RELOC_PAGEOFF12), 0)
+                        }
+                        (Decl::Import(ImportKind::Data), false) => (X86_64_RELOC_GOT_LOAD, None, 0),
+                        (Decl::Import(ImportKind::Data), true) => (
+                            ARM64_RELOC_GOT_LOAD_PAGE21,
+                            Some(ARM64_RELOC_GOT_LOAD_PAGEOFF12),
+                            0,
+                        ),
 
                         // handled above
-                        Decl::Defined(DefinedDecl::Section { .. }) => unreachable!(),
+                        (Decl::Defined(DefinedDecl::Section { .. }), _)
+                        | (Decl::Import(ImportKind::Tls), _) => unreachable!(),
                     },
 
                     (Decl::Import(_), _) => {
@@ -999,48 +1562,194 @@ fn build_relocations(segment: &mut SegmentBuilder, artifact: &Artifact, symtab:
                 }
             }
             Reloc::Raw { reloc, addend } => {
-                debug_assert!(reloc <= u8::max_value() as u32);
-                assert!(addend == 0);
-                match reloc as u8 {
-                    R_ABS => (true, R_ABS),
-                    reloc => (false, reloc),
+                debug_assert!(reloc <= u8::MAX as u32);
+                let reloc = reloc as u8;
+                // only arm64 instructions can have explicit addends, with an ARM64_RELOC_ADDEND,
+                // which ld64 only accepts for branches and page and page offset relocations; the
+                // addends of pointers, and of every x86 relocation, are written in place as wide
+                // as the `r_length` the relocation is emitted with
+                let explicit = matches!(
+                    reloc,
+                    ARM64_RELOC_BRANCH26 | ARM64_RELOC_PAGE21 | ARM64_RELOC_PAGEOFF12
+                );
+                if addend != 0 && arm64 && reloc != ARM64_RELOC_UNSIGNED && !explicit {
+                    return Err(unsupported(&link));
+                }
+                if addend != 0 && !(arm64 && explicit) {
+                    let size = if reloc == unsigned { pointer_size } else { 4 };
+                    segment.add_implicit_addend(&link, size, addend)?;
+                    (reloc, None, 0)
+                } else {
+                    (reloc, None, addend)
+                }
+            }
+            // the linker reads which function each frame description is for from the offset
+            // held in place, relative to the object file, so these are left without a relocation
+            Reloc::PcRelative { addend } if is_eh_frame(link.from.decl) => {
+                let address = |binding| {
+                    relocation_base(segment, symtab, binding)
+                        .map(|(idx, offset)| segment.sections[idx].addr + offset)
+                        .ok_or_else(|| link.missing_symbol())
+                };
+                if link.to.decl.is_import() || link.to.decl.is_tls() {
+                    return Err(unsupported(&link));
+                }
+                let field_end = address(&link.from)? + link.at + 4;
+                let value = (address(&link.to)?.wrapping_sub(field_end) as i64)
+                    .checked_add(addend)
+                    .ok_or_else(|| link.addend_overflow(addend))?;
+                segment.add_implicit_addend(&link, 4, value)?;
+                continue;
+            }
+            // offsets into debug sections are left in place, as for `Reloc::Debug`
+            Reloc::SectionOffset { size, addend } if matches!(size, 4 | 8) && to_debug => {
+                segment.add_implicit_addend(&link, size, addend)?;
+                continue;
+            }
+            Reloc::Absolute { size, addend } if size == pointer_size => {
+                // debuggers read the addresses in debug info out of the object file, so they're
+                // held in place relative to their section, as the Darwin toolchain does
+                let in_place = from_debug && !link.to.decl.is_import() && !link.to.decl.is_tls();
+                if link.to.decl.is_section() || in_place {
+                    build_section_relocation(segment, symtab, &link, unsigned, size, addend)?;
+                    continue;
                 }
+                segment.add_implicit_addend(&link, size, addend)?;
+                (unsigned, None, 0)
             }
-            Reloc::Debug { size, .. } => {
+            Reloc::Call if !link.to.decl.is_section() => match arm64 {
+                true => (ARM64_RELOC_BRANCH26, None, 0),
+                false => (X86_64_RELOC_BRANCH, None, 0),
+            },
+            // x86-64 PC-relative relocations are relative to the end of the field already
+            Reloc::PcRelative { addend } if !arm64 => {
                 if link.to.decl.is_section() {
-                    // TODO: not sure if these are needed for Mach
+                    build_section_relocation(
+                        segment,
+                        symtab,
+                        &link,
+                        X86_64_RELOC_SIGNED,
+                        4,
+                        addend,
+                    )?;
+                    continue;
+                }
+                segment.add_implicit_addend(&link, 4, addend)?;
+                (X86_64_RELOC_SIGNED, None, 0)
+            }
+            Reloc::GotPcRelative { addend: 0 } if !arm64 && !link.to.decl.is_section() => {
+                (X86_64_RELOC_GOT_LOAD, None, 0)
+            }
+            Reloc::Absolute { .. }
+            | Reloc::Call
+            | Reloc::PcRelative { .. }
+            | Reloc::GotPcRelative { .. }
+            | Reloc::SectionOffset { .. }
+            | Reloc::ImageOffset { .. } => return Err(unsupported(&link)),
+            Reloc::Debug { size, addend } => {
+                if !matches!(size, 1 | 2 | 4 | 8) {
+                    return Err(unsupported(&link));
+                }
+                if to_debug {
+                    // debug sections aren't linked, so offsets into them are left as they are,
+                    // without a relocation, as the Darwin toolchain does
+                    segment.add_implicit_addend(&link, size, addend)?;
+                } else if link.to.decl.is_section() {
+                    build_section_relocation(segment, symtab, &link, unsigned, size, addend)?;
                 } else {
-                    match symtab.index(link.to.name) {
-                        Some(to_symbol_index) => {
-                            let builder = RelocationBuilder::new(to_symbol_index, link.at, X86_64_RELOC_UNSIGNED).absolute().size(size);
-                            segment.sections[link.from.name].relocations.push(builder.create());
+                    // the bytes are left as they are without an addend, and used as one
+                    if addend != 0 {
+                        segment.add_implicit_addend(&link, size, addend)?;
+                    }
+                    match (
+                        bases.get_or_insert_with(&link.from, |_| {
+                            relocation_base(segment, symtab, &link.from)
+                        }),
+                        symbol_indexes.get_or_insert_with(&link.to, |name| symtab.index(name)),
+                    ) {
+                        (Some((section_idx, base_offset)), Some(to_symbol_index)) => {
+                            let offset = base_offset + link.at;
+                            let builder = RelocationBuilder::new(to_symbol_index, offset, unsigned)
+                                .absolute()
+                                .size(size);
+                            segment
+                                .sections
+                                .get_index_mut(section_idx)
+                                .unwrap()
+                                .1
+                                .relocations
+                                .push(builder.create());
                         }
-                        _ => error!("Import Relocation from {} to {} at {:#x} has a missing symbol. Dumping symtab {:?}", link.from.name, link.to.name, link.at, symtab)
+                        _ => return Err(link.missing_symbol()),
                     }
                 }
                 continue;
             }
         };
-        match (symtab.offset(link.from.name), symtab.index(link.to.name)) {
-            (Some(base_offset), Some(to_symbol_index)) => {
+        // only `Auto` links have an addend of their own, which is written in place, except for
+        // arm64 instructions, which need an ARM64_RELOC_ADDEND
+        let addend = match (link.addend, arm64, reloc) {
+            (0, _, _) => addend,
+            (_, true, ARM64_RELOC_BRANCH26) | (_, true, ARM64_RELOC_PAGE21) => link.addend,
+            (_, _, reloc) if reloc == unsigned => {
+                segment.add_implicit_addend(&link, pointer_size, link.addend)?;
+                0
+            }
+            (_, false, X86_64_RELOC_SIGNED) | (_, false, X86_64_RELOC_BRANCH) => {
+                segment.add_implicit_addend(&link, 4, link.addend)?;
+                0
+            }
+            // an addend would offset the GOT entry or thread-local variable descriptor instead
+            _ => return Err(unsupported(&link)),
+        };
+        // the addend is stored in 24 bits
+        if !(-(1 << 23)..1 << 23).contains(&addend) {
+            return Err(link.addend_overflow(addend));
+        }
+        match (
+            bases.get_or_insert_with(&link.from, |_| relocation_base(segment, symtab, &link.from)),
+            symbol_indexes.get_or_insert_with(&link.to, |name| symtab.index(name)),
+        ) {
+            (Some((section_idx, base_offset)), Some(to_symbol_index)) => {
                 debug!("{} offset: {}", link.to.name, base_offset + link.at);
-                let builder = RelocationBuilder::new(to_symbol_index, base_offset + link.at, reloc);
-                // NB: we currently associate absolute relocations with data relocations; this may prove
-                // too fragile for future additions; needs analysis
-                if absolute {
-                    segment.sections.get_index_mut(data_idx).unwrap().1.relocations.push(builder.absolute().create());
-                } else {
-                    segment.sections.get_index_mut(text_idx).unwrap().1.relocations.push(builder.create());
+                let relocations = &mut segment
+                    .sections
+                    .get_index_mut(section_idx)
+                    .unwrap()
+                    .1
+                    .relocations;
+                let relocs = std::iter::once((reloc, base_offset + link.at))
+                    .chain(pageoff.map(|pageoff| (pageoff, base_offset + link.at + 4)));
+                for (reloc, offset) in relocs {
+                    if addend != 0 {
+                        relocations
+                            .push(RelocationBuilder::arm64_addend(addend as i32, offset).create());
+                    }
+                    let builder = RelocationBuilder::new(to_symbol_index, offset, reloc);
+                    let builder = if arm64 {
+                        match reloc {
+                            ARM64_RELOC_UNSIGNED => builder.absolute().size(pointer_size),
+                            reloc if arm64_is_pcrel(reloc) => builder.size(4),
+                            // instructions are always 4 bytes
+                            _ => builder.absolute().size(4),
+                        }
+                    } else {
+                        match reloc {
+                            R_ABS => builder.absolute().size(pointer_size),
+                            _ => builder,
+                        }
+                    };
+                    relocations.push(builder.create());
                 }
-            },
-            _ => error!("Import Relocation from {} to {} at {:#x} has a missing symbol. Dumping symtab {:?}", link.from.name, link.to.name, link.at, symtab)
+            }
+            _ => return Err(link.missing_symbol()),
         }
     }
+    Ok(())
 }
 
-pub fn to_bytes(artifact: &Artifact) -> Result<Vec<u8>, std::io::Error> {
-    let mach = Mach::new(&artifact);
-    let mut buffer = Cursor::new(Vec::new());
-    mach.write(&mut buffer)?;
-    Ok(buffer.into_inner())
+pub fn write_to<W: Write + Seek>(artifact: &Artifact, sink: W) -> Result<(), ArtifactError> {
+    let mach = Mach::new(artifact)?;
+    mach.write(sink)?;
+    Ok(())
 }
@@ -0,0 +1,136 @@
+//! Readers for transforming existing object files back into an artifact.
+//!
+//! Object files are read with goblin, and their sections, symbols and relocations are mapped back
+//! onto faerie's declarations, definitions and links. Relocations are always read as
+//! `Reloc::Raw`, so they are emitted again exactly as they were found.
+
+use crate::artifact::{Artifact, ArtifactError, Data, Decl, ImportKind, InitKind, Link, Reloc};
+
+use goblin::Object;
+use std::collections::BTreeMap;
+use target_lexicon::{Architecture, BinaryFormat, Environment, OperatingSystem, Triple, Vendor};
+
+mod elf;
+mod mach;
+
+/// The name given to artifacts read from object files which don't record one
+const DEFAULT_NAME: &str = "faerie.o";
+
+/// A definition read from an object file
+struct Definition {
+    name: String,
+    decl: Decl,
+    data: Data,
+    symbols: BTreeMap<String, u64>,
+}
+
+/// A relocation read from an object file
+struct Relocation {
+    from: String,
+    to: String,
+    at: u64,
+    reloc: Reloc,
+}
+
+/// Everything read from an object file, in the order it is added to the artifact
+#[derive(Default)]
+struct Contents {
+    definitions: Vec<Definition>,
+    imports: Vec<(String, ImportKind)>,
+    relocations: Vec<Relocation>,
+    initializers: Vec<(String, InitKind, Option<u16>)>,
+    comdats: Vec<(String, String)>,
+}
+
+impl Contents {
+    fn define(&mut self, name: String, decl: Decl, data: Data, symbols: BTreeMap<String, u64>) {
+        self.definitions.push(Definition {
+            name,
+            decl,
+            data,
+            symbols,
+        });
+    }
+    fn import(&mut self, name: String, kind: ImportKind) {
+        self.imports.push((name, kind));
+    }
+    fn link(&mut self, from: String, to: String, at: u64, reloc: Reloc) {
+        self.relocations.push(Relocation {
+            from,
+            to,
+            at,
+            reloc,
+        });
+    }
+    fn comdat(&mut self, name: String, group: String) {
+        self.comdats.push((name, group));
+    }
+    fn initializer(&mut self, name: String, kind: InitKind, priority: Option<u16>) {
+        self.initializers.push((name, kind, priority));
+    }
+    /// Declare, define and link everything into a new artifact
+    fn finish(self, target: Triple, name: String) -> Result<Artifact, ArtifactError> {
+        let mut artifact = Artifact::new(target, name);
+        for def in self.definitions {
+            artifact.declare(&def.name, def.decl)?;
+            artifact.define_with_symbols(&def.name, def.data, def.symbols)?;
+        }
+        for (name, group) in self.comdats {
+            artifact.comdat(name, group)?;
+        }
+        for (name, kind) in self.imports {
+            artifact.import(name, kind)?;
+        }
+        for relocation in self.relocations {
+            artifact.link_with(
+                Link {
+                    from: &relocation.from,
+                    to: &relocation.to,
+                    at: relocation.at,
+                },
+                relocation.reloc,
+            )?;
+        }
+        for (name, kind, priority) in self.initializers {
+            match kind {
+                InitKind::Constructor => artifact.constructor(name, priority)?,
+                InitKind::Destructor => artifact.destructor(name, priority)?,
+            }
+        }
+        Ok(artifact)
+    }
+}
+
+/// Create the error for an object file we can't represent as an artifact
+fn unsupported<T: Into<String>>(reason: T) -> ArtifactError {
+    ArtifactError::UnsupportedObject(reason.into())
+}
+
+/// A target triple for an object file of `binary_format`, containing code for `architecture`
+fn triple(architecture: Architecture, binary_format: BinaryFormat) -> Triple {
+    let (vendor, operating_system) = match binary_format {
+        BinaryFormat::Macho => (Vendor::Apple, OperatingSystem::Darwin),
+        _ => (Vendor::Unknown, OperatingSystem::Unknown),
+    };
+    Triple {
+        architecture,
+        vendor,
+        operating_system,
+        environment: Environment::Unknown,
+        binary_format,
+    }
+}
+
+/// Read an ELF relocatable or Mach-o object file into a new artifact
+pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Artifact, ArtifactError> {
+    match Object::parse(bytes)? {
+        Object::Elf(elf) => elf::from_elf(&elf, bytes),
+        Object::Mach(goblin::mach::Mach::Binary(mach)) => mach::from_mach(&mach, bytes),
+        Object::Mach(goblin::mach::Mach::Fat(_)) => {
+            Err(unsupported("fat Mach-o files contain more than one object"))
+        }
+        Object::Archive(_) => Err(unsupported("archives contain more than one object")),
+        Object::PE(_) => Err(unsupported("PE images are not object files")),
+        Object::Unknown(magic) => Err(unsupported(format!("unknown magic {:#x}", magic))),
+    }
+}
@@ -0,0 +1,470 @@
+//! Reading ELF relocatable object files.
+//!
+//! Every allocated section becomes a single definition, named after the one symbol it defines (as
+//! with faerie's own output, or `-ffunction-sections -fdata-sections`), or after the section
+//! itself if it defines none. Other sections, such as DWARF, become section declarations.
+//! `.init_array` and `.fini_array` sections become the constructors and destructors they list.
+
+use super::{triple, unsupported, Contents, DEFAULT_NAME};
+use crate::artifact::{Artifact, ArtifactError, Data, DataType, Decl, ImportKind, InitKind, Reloc};
+use crate::elf::GRP_COMDAT;
+use crate::{Scope, SectionKind, UnwindTable, Visibility};
+
+use goblin::elf::header::{self, ET_REL};
+use goblin::elf::reloc;
+use goblin::elf::section_header::*;
+use goblin::elf::sym::*;
+use goblin::elf::Elf;
+use scroll::Pread;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use target_lexicon::{
+    Aarch64Architecture, Architecture, ArmArchitecture, BinaryFormat, X86_32Architecture,
+};
+
+/// The architecture of an ELF `machine`
+fn architecture(machine: u16) -> Result<Architecture, ArtifactError> {
+    Ok(match machine {
+        header::EM_X86_64 => Architecture::X86_64,
+        header::EM_386 => Architecture::X86_32(X86_32Architecture::I386),
+        header::EM_AARCH64 => Architecture::Aarch64(Aarch64Architecture::Aarch64),
+        header::EM_ARM => Architecture::Arm(ArmArchitecture::Arm),
+        machine => {
+            return Err(unsupported(format!(
+                "ELF machine {} is not supported",
+                header::machine_to_str(machine)
+            )))
+        }
+    })
+}
+
+/// The scope of a symbol
+fn scope(sym: &Sym) -> Scope {
+    match sym.st_bind() {
+        STB_GLOBAL => Scope::Global,
+        STB_WEAK => Scope::Weak,
+        _ => Scope::Local,
+    }
+}
+
+/// The visibility of a symbol
+fn visibility(sym: &Sym) -> Visibility {
+    match sym.st_visibility() {
+        STV_HIDDEN | STV_INTERNAL => Visibility::Hidden,
+        STV_PROTECTED => Visibility::Protected,
+        _ => Visibility::Default,
+    }
+}
+
+/// The alignment of a section, if it has one
+fn align(sh: &SectionHeader) -> Option<u64> {
+    if sh.sh_addralign.is_power_of_two() {
+        Some(sh.sh_addralign)
+    } else {
+        None
+    }
+}
+
+/// Whether `r_type` is the relocation the entries of `.init_array` and `.fini_array` are made with,
+/// which holds the address of its target
+fn is_pointer_reloc(machine: u16, is_64: bool, r_type: u32) -> bool {
+    match (machine, is_64) {
+        (header::EM_X86_64, true) => r_type == reloc::R_X86_64_64,
+        (header::EM_X86_64, false) => r_type == reloc::R_X86_64_32,
+        (header::EM_AARCH64, true) => r_type == reloc::R_AARCH64_ABS64,
+        (header::EM_AARCH64, false) => r_type == reloc::R_AARCH64_ABS32,
+        (header::EM_386, _) => r_type == reloc::R_386_32,
+        (header::EM_ARM, _) => r_type == reloc::R_ARM_ABS32,
+        _ => false,
+    }
+}
+
+/// Whether the functions of an `.init_array` or `.fini_array` section are constructors or
+/// destructors, and their priority, given by the suffix of the section's name
+fn initializers(sh: &SectionHeader, name: &str) -> Result<(InitKind, Option<u16>), ArtifactError> {
+    let (kind, prefix) = if sh.sh_type == SHT_INIT_ARRAY {
+        (InitKind::Constructor, ".init_array")
+    } else {
+        (InitKind::Destructor, ".fini_array")
+    };
+    match name.strip_prefix(prefix) {
+        Some("") => Ok((kind, None)),
+        Some(suffix) => suffix
+            .strip_prefix('.')
+            .and_then(|priority| priority.parse().ok())
+            .map(|priority| (kind, Some(priority)))
+            .ok_or_else(|| unsupported(format!("{} has an invalid priority", name))),
+        None => Err(unsupported(format!(
+            "{} must be named {} or {}.<priority>",
+            name, prefix, prefix
+        ))),
+    }
+}
+
+/// Whether relocations of `r_type` go through the GOT, which means their target is data
+fn is_got_reloc(machine: u16, r_type: u32) -> bool {
+    match machine {
+        header::EM_X86_64 => matches!(
+            r_type,
+            reloc::R_X86_64_GOTPCREL | reloc::R_X86_64_GOTPCRELX | reloc::R_X86_64_REX_GOTPCRELX
+        ),
+        header::EM_AARCH64 => matches!(
+            r_type,
+            reloc::R_AARCH64_ADR_GOT_PAGE | reloc::R_AARCH64_LD64_GOT_LO12_NC
+        ),
+        _ => false,
+    }
+}
+
+pub(crate) fn from_elf(elf: &Elf, bytes: &[u8]) -> Result<Artifact, ArtifactError> {
+    if elf.header.e_type != ET_REL {
+        return Err(unsupported(format!(
+            "ELF file type {} is not relocatable",
+            header::et_to_str(elf.header.e_type)
+        )));
+    }
+    let machine = elf.header.e_machine;
+    let architecture = architecture(machine)?;
+    let endian = if elf.little_endian {
+        scroll::LE
+    } else {
+        scroll::BE
+    };
+    let section_data = |sh: &SectionHeader| -> Result<&[u8], ArtifactError> {
+        let data = bytes.pread_with::<&[u8]>(sh.sh_offset as usize, sh.sh_size as usize);
+        Ok(data.map_err(goblin::error::Error::from)?)
+    };
+
+    // symbols in sections past SHN_LORESERVE store their section index in SHT_SYMTAB_SHNDX
+    let shndx_table = match elf
+        .section_headers
+        .iter()
+        .find(|sh| sh.sh_type == SHT_SYMTAB_SHNDX)
+    {
+        Some(sh) => section_data(sh)?,
+        None => &[],
+    };
+
+    let mut name = None;
+    let mut section_symbols: HashMap<usize, Vec<(usize, Sym, &str)>> = HashMap::new();
+    let mut undefined = Vec::new();
+    let mut common = Vec::new();
+    for (idx, sym) in elf.syms.iter().enumerate() {
+        let sym_name = elf.strtab.get_at(sym.st_name).unwrap_or("");
+        match sym.st_type() {
+            STT_FILE => {
+                name.get_or_insert(sym_name);
+                continue;
+            }
+            STT_SECTION => continue,
+            _ if sym_name.is_empty() => continue,
+            _ => {}
+        }
+        let shndx = if sym.st_shndx == SHN_XINDEX as usize {
+            let shndx: u32 = shndx_table
+                .pread_with(idx * 4, endian)
+                .map_err(goblin::error::Error::from)?;
+            shndx as usize
+        } else {
+            sym.st_shndx
+        };
+        match shndx as u32 {
+            SHN_UNDEF => undefined.push((idx, sym, sym_name)),
+            SHN_COMMON => common.push((idx, sym, sym_name)),
+            SHN_ABS => {
+                return Err(unsupported(format!(
+                    "absolute symbol {} is not supported",
+                    sym_name
+                )))
+            }
+            _ => section_symbols
+                .entry(shndx)
+                .or_default()
+                .push((idx, sym, sym_name)),
+        }
+    }
+
+    // the COMDAT group of each section in one, named after the group's signature symbol
+    let mut groups = HashMap::new();
+    for sh in elf
+        .section_headers
+        .iter()
+        .filter(|sh| sh.sh_type == SHT_GROUP)
+    {
+        let data = section_data(sh)?;
+        let words = (0..data.len() / 4)
+            .map(|i| data.pread_with::<u32>(i * 4, endian))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(goblin::error::Error::from)?;
+        match words.split_first() {
+            Some((&flags, members)) if flags & GRP_COMDAT != 0 => {
+                let group = elf
+                    .syms
+                    .get(sh.sh_info as usize)
+                    .and_then(|sym| elf.strtab.get_at(sym.st_name))
+                    .ok_or_else(|| unsupported("COMDAT groups must have a signature symbol"))?;
+                for &member in members {
+                    groups.insert(member as usize, group.to_string());
+                }
+            }
+            _ => return Err(unsupported("only COMDAT section groups are supported")),
+        }
+    }
+
+    let mut contents = Contents::default();
+    // the declaration each section was read into
+    let mut section_decls = HashMap::new();
+    // the declaration each symbol refers to, and its offset from the start of that declaration
+    let mut targets: HashMap<usize, (String, u64)> = HashMap::new();
+    // the name, kind and priority of each `.init_array` and `.fini_array` section
+    let mut arrays = BTreeMap::new();
+    for (shndx, sh) in elf.section_headers.iter().enumerate() {
+        match sh.sh_type {
+            SHT_PROGBITS | SHT_NOBITS => {}
+            SHT_INIT_ARRAY | SHT_FINI_ARRAY => {
+                let section_name = elf.shdr_strtab.get_at(sh.sh_name).unwrap_or("");
+                if section_symbols.contains_key(&shndx) {
+                    return Err(unsupported(format!(
+                        "{} must not define symbols",
+                        section_name
+                    )));
+                }
+                let (kind, priority) = initializers(sh, section_name)?;
+                arrays.insert(shndx, (section_name, kind, priority));
+                continue;
+            }
+            SHT_PREINIT_ARRAY => return Err(unsupported("preinit arrays are not supported")),
+            SHT_X86_64_UNWIND if machine == header::EM_X86_64 => {}
+            SHT_REL => return Err(unsupported("REL relocations are not supported")),
+            // relocations are read below, and everything else is rebuilt by the backend
+            typ => {
+                debug!("Skipping section {} of type {}", shndx, sht_to_str(typ));
+                continue;
+            }
+        }
+        let section_name = elf.shdr_strtab.get_at(sh.sh_name).unwrap_or("");
+        let symbols = section_symbols.remove(&shndx).unwrap_or_default();
+        // the backend always emits its own stack note, and compilers emit empty `.text`, `.data`
+        // and `.bss` sections even when every definition is in a section of its own
+        if section_name == ".note.GNU-stack" || (sh.sh_size == 0 && symbols.is_empty()) {
+            debug!("Skipping empty section {}", section_name);
+            continue;
+        }
+        let is_zero_init = sh.sh_type == SHT_NOBITS;
+        let is_string = sh.sh_flags & u64::from(SHF_STRINGS) != 0;
+
+        // the frame descriptions are loaded, but are a section of their own rather than a symbol's
+        if sh.sh_flags & u64::from(SHF_ALLOC) != 0 && section_name != ".eh_frame" {
+            let (name, scope, visibility) = match symbols.as_slice() {
+                [] => (section_name, Scope::Local, Visibility::Default),
+                [(idx, sym, sym_name)] if sym.st_value == 0 => {
+                    targets.insert(*idx, (sym_name.to_string(), 0));
+                    (*sym_name, scope(sym), visibility(sym))
+                }
+                _ => {
+                    return Err(unsupported(format!(
+                        "section {} must define exactly one symbol at its start; \
+                         try compiling with -ffunction-sections and -fdata-sections",
+                        section_name
+                    )))
+                }
+            };
+            let decl = if sh.sh_flags & u64::from(SHF_EXECINSTR) != 0 && !is_zero_init {
+                Decl::function()
+                    .with_scope(scope)
+                    .with_visibility(visibility)
+                    .with_align(align(sh))
+                    .into()
+            } else {
+                Decl::data()
+                    .with_scope(scope)
+                    .with_visibility(visibility)
+                    .with_writable(sh.sh_flags & u64::from(SHF_WRITE) != 0)
+                    .with_tls(sh.sh_flags & u64::from(SHF_TLS) != 0)
+                    .with_datatype(if is_string {
+                        DataType::String
+                    } else {
+                        DataType::Bytes
+                    })
+                    .with_align(align(sh))
+                    .into()
+            };
+            let data = if is_zero_init {
+                Data::ZeroInit(sh.sh_size as usize)
+            } else {
+                Data::Blob(section_data(sh)?.to_vec())
+            };
+            contents.define(name.to_string(), decl, data, BTreeMap::new());
+            if let Some(group) = groups.remove(&shndx) {
+                contents.comdat(name.to_string(), group);
+            }
+            section_decls.insert(shndx, name.to_string());
+        } else {
+            let kind = if section_name.starts_with(".debug") {
+                SectionKind::Debug
+            } else if section_name == ".eh_frame" {
+                SectionKind::Text
+            } else {
+                SectionKind::Data
+            };
+            let decl = Decl::section(kind)
+                .with_datatype(if is_string {
+                    DataType::String
+                } else {
+                    DataType::Bytes
+                })
+                .with_align(align(sh))
+                .with_unwind(if section_name == ".eh_frame" {
+                    Some(UnwindTable::EhFrame)
+                } else {
+                    None
+                });
+            // only data can be zero initialized, so the zeros of anything else are stored, up to the
+            // size of the object file itself
+            let data = if is_zero_init {
+                if sh.sh_size > bytes.len() as u64 {
+                    return Err(unsupported(format!(
+                        "zero-initialized section {} is larger than the object file",
+                        section_name
+                    )));
+                }
+                vec![0; sh.sh_size as usize]
+            } else {
+                section_data(sh)?.to_vec()
+            };
+            let mut custom_symbols = BTreeMap::new();
+            for (idx, sym, sym_name) in symbols {
+                custom_symbols.insert(sym_name.to_string(), sym.st_value);
+                targets.insert(idx, (section_name.to_string(), sym.st_value));
+            }
+            contents.define(
+                section_name.to_string(),
+                decl.into(),
+                Data::Blob(data),
+                custom_symbols,
+            );
+            if let Some(group) = groups.remove(&shndx) {
+                contents.comdat(section_name.to_string(), group);
+            }
+            section_decls.insert(shndx, section_name.to_string());
+        }
+    }
+
+    for (idx, sym, sym_name) in common {
+        // the value of a common symbol is its alignment
+        let align = if sym.st_value.is_power_of_two() {
+            Some(sym.st_value)
+        } else {
+            None
+        };
+        let decl = Decl::data()
+            .with_scope(scope(&sym))
+            .with_visibility(visibility(&sym))
+            .writable()
+            .with_align(align);
+        contents.define(
+            sym_name.to_string(),
+            decl.into(),
+            Data::ZeroInit(sym.st_size as usize),
+            BTreeMap::new(),
+        );
+        targets.insert(idx, (sym_name.to_string(), 0));
+    }
+
+    let mut got_targets = HashSet::new();
+    // the function each entry of the `.init_array` and `.fini_array` sections points to
+    let mut entries: HashMap<usize, BTreeMap<u64, String>> = HashMap::new();
+    for (rel_shndx, relocs) in &elf.shdr_relocs {
+        let target_shndx = elf.section_headers[*rel_shndx].sh_info as usize;
+        let array = arrays.get(&target_shndx);
+        let from = match (section_decls.get(&target_shndx), array) {
+            (Some(from), _) => from.as_str(),
+            (None, Some((name, _, _))) => name,
+            (None, None) => {
+                debug!("Skipping relocations for skipped section {}", target_shndx);
+                continue;
+            }
+        };
+        for reloc in relocs.iter() {
+            let sym = elf
+                .syms
+                .get(reloc.r_sym)
+                .filter(|_| reloc.r_sym != 0)
+                .ok_or_else(|| unsupported("relocations must have a symbol"))?;
+            let (to, offset) = if sym.st_type() == STT_SECTION {
+                let to = section_decls.get(&sym.st_shndx).ok_or_else(|| {
+                    unsupported(format!(
+                        "relocation in {} against skipped section {}",
+                        from, sym.st_shndx
+                    ))
+                })?;
+                (to.as_str(), 0)
+            } else if let Some((to, offset)) = targets.get(&reloc.r_sym) {
+                (to.as_str(), *offset)
+            } else {
+                // an import, which we declare once we know how it is used
+                if is_got_reloc(machine, reloc.r_type) {
+                    got_targets.insert(reloc.r_sym);
+                }
+                (elf.strtab.get_at(sym.st_name).unwrap_or(""), 0)
+            };
+            let addend = reloc.r_addend.unwrap_or(0) + offset as i64;
+            if array.is_some() {
+                if !is_pointer_reloc(machine, elf.is_64, reloc.r_type) || addend != 0 {
+                    return Err(unsupported(format!(
+                        "{} must only point to the start of functions",
+                        from
+                    )));
+                }
+                entries
+                    .entry(target_shndx)
+                    .or_default()
+                    .insert(reloc.r_offset, to.to_string());
+                continue;
+            }
+            contents.link(
+                from.to_string(),
+                to.to_string(),
+                reloc.r_offset,
+                Reloc::Raw {
+                    reloc: reloc.r_type,
+                    addend,
+                },
+            );
+        }
+    }
+
+    let pointer_size = if elf.is_64 { 8 } else { 4 };
+    for (shndx, (name, kind, priority)) in arrays {
+        let functions = entries.remove(&shndx).unwrap_or_default();
+        let size = elf.section_headers[shndx].sh_size;
+        // every entry must be relocated, as the address of a function isn't known until it's linked
+        let complete = functions.len() as u64 * pointer_size == size
+            && functions
+                .keys()
+                .enumerate()
+                .all(|(i, &offset)| offset == i as u64 * pointer_size);
+        if !complete {
+            return Err(unsupported(format!(
+                "every entry of {} must be relocated to a function",
+                name
+            )));
+        }
+        for (_, function) in functions {
+            contents.initializer(function, kind, priority);
+        }
+    }
+
+    for (idx, sym, sym_name) in undefined {
+        let kind = match sym.st_type() {
+            STT_OBJECT => ImportKind::Data,
+            STT_FUNC => ImportKind::Function,
+            STT_TLS => ImportKind::Tls,
+            _ if got_targets.contains(&idx) => ImportKind::Data,
+            _ => ImportKind::Function,
+        };
+        contents.import(sym_name.to_string(), kind);
+    }
+
+    let name = name.unwrap_or(DEFAULT_NAME).to_string();
+    contents.finish(triple(architecture, BinaryFormat::Elf), name)
+}
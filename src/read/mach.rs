@@ -0,0 +1,522 @@
+//! Reading Mach-o object files.
+//!
+//! The sections faerie places its functions and data into are split back into one definition per
+//! symbol, as laid out with `MH_SUBSECTIONS_VIA_SYMBOLS`. Every other section, such as DWARF,
+//! becomes a section declaration.
+
+use super::{triple, unsupported, Contents, DEFAULT_NAME};
+use crate::artifact::{Artifact, ArtifactError, Data, DataType, Decl, ImportKind, Reloc};
+use crate::{Scope, SectionKind, UnwindTable, Visibility};
+
+use goblin::container::{Container, Ctx};
+use goblin::mach::constants::cputype::{CPU_TYPE_ARM64, CPU_TYPE_X86_64};
+use goblin::mach::constants::{
+    SECTION_TYPE, S_THREAD_LOCAL_REGULAR, S_THREAD_LOCAL_VARIABLES, S_THREAD_LOCAL_ZEROFILL,
+    S_ZEROFILL,
+};
+use goblin::mach::header::MH_OBJECT;
+use goblin::mach::relocation::*;
+use goblin::mach::segment::Section;
+use goblin::mach::symbols::{Nlist, N_EXT, N_PEXT, N_SECT, N_STAB, N_TYPE, N_UNDF, N_WEAK_DEF};
+use goblin::mach::MachO;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use target_lexicon::{Aarch64Architecture, Architecture, BinaryFormat};
+
+/// How the contents of a section are read
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+    /// Split into functions, one per symbol
+    Code,
+    /// Split into data objects, one per symbol
+    Data { writable: bool, datatype: DataType },
+    /// Split into zero initialized data objects, one per symbol
+    ZeroInit,
+    /// Read whole, as a custom section
+    Section(SectionKind),
+}
+
+impl Layout {
+    fn new(segname: &str, sectname: &str, flags: u32) -> Self {
+        if flags & SECTION_TYPE == S_ZEROFILL {
+            return Layout::ZeroInit;
+        }
+        match (segname, sectname) {
+            ("__TEXT", "__text") => Layout::Code,
+            ("__TEXT", "__cstring") => Layout::Data {
+                writable: false,
+                datatype: DataType::String,
+            },
+            ("__TEXT", "__const") => Layout::Data {
+                writable: false,
+                datatype: DataType::Bytes,
+            },
+            ("__DATA", "__data") | ("__DATA", "__const") => Layout::Data {
+                writable: true,
+                datatype: DataType::Bytes,
+            },
+            ("__TEXT", _) => Layout::Section(SectionKind::Text),
+            ("__DWARF", _) => Layout::Section(SectionKind::Debug),
+            _ => Layout::Section(SectionKind::Data),
+        }
+    }
+    /// Whether faerie can emit relocations from definitions in this section; it places those from
+    /// functions in `__text` and those from other definitions in `__data`
+    fn is_relocatable(self) -> bool {
+        match self {
+            Layout::Code | Layout::Section(_) => true,
+            Layout::Data { writable, datatype } => writable && datatype == DataType::Bytes,
+            Layout::ZeroInit => false,
+        }
+    }
+}
+
+/// A section, and the definitions it was read into, by their offset in the section
+struct ReadSection {
+    name: String,
+    header: Section,
+    layout: Layout,
+    definitions: BTreeMap<u64, String>,
+}
+
+/// What a symbol refers to
+enum Target {
+    Definition(String),
+    Import(String),
+    Custom(String),
+}
+
+/// The name of a symbol, without the underscore prefixed to every Mach-o symbol
+fn symbol_name(name: &str) -> &str {
+    name.strip_prefix('_').unwrap_or(name)
+}
+
+/// The scope of a symbol
+fn scope(sym: &Nlist) -> Scope {
+    if sym.n_type & N_EXT == 0 {
+        Scope::Local
+    } else if sym.n_desc & N_WEAK_DEF != 0 {
+        Scope::Weak
+    } else {
+        Scope::Global
+    }
+}
+
+/// The visibility of a symbol
+fn visibility(sym: &Nlist) -> Visibility {
+    if sym.n_type & N_PEXT != 0 {
+        Visibility::Hidden
+    } else {
+        Visibility::Default
+    }
+}
+
+/// The `(r_pcrel, r_length)` faerie emits for a relocation of `r_type`
+fn relocation_format(arm64: bool, r_type: RelocType) -> (u8, u8) {
+    match (arm64, r_type) {
+        (true, ARM64_RELOC_UNSIGNED) => (0, 3),
+        (true, ARM64_RELOC_BRANCH26)
+        | (true, ARM64_RELOC_PAGE21)
+        | (true, ARM64_RELOC_GOT_LOAD_PAGE21)
+        | (true, ARM64_RELOC_TLVP_LOAD_PAGE21) => (1, 2),
+        // instructions are always 4 bytes
+        (true, _) => (0, 2),
+        (false, X86_64_RELOC_UNSIGNED) => (0, 3),
+        (false, _) => (1, 2),
+    }
+}
+
+/// Whether relocations of `r_type` go through the GOT, which means their target is data
+fn is_got_reloc(arm64: bool, r_type: RelocType) -> bool {
+    if arm64 {
+        matches!(
+            r_type,
+            ARM64_RELOC_GOT_LOAD_PAGE21
+                | ARM64_RELOC_GOT_LOAD_PAGEOFF12
+                | ARM64_RELOC_POINTER_TO_GOT
+        )
+    } else {
+        matches!(r_type, X86_64_RELOC_GOT_LOAD | X86_64_RELOC_GOT)
+    }
+}
+
+/// The definition a section relative relocation refers to, the addend from its start, given by
+/// the address of the target held in place, and whether the definition is a custom section
+fn section_relative_target(
+    sections: &[ReadSection],
+    bytes: &[u8],
+    little_endian: bool,
+    header: &Section,
+    reloc: &RelocationInfo,
+) -> Result<(String, i64, bool), ArtifactError> {
+    let size = 1 << reloc.r_length();
+    let at = header.offset as usize + reloc.r_address as usize;
+    let field = bytes
+        .get(at..at + size)
+        .ok_or_else(|| unsupported("relocation outside of its section"))?;
+    let value = if little_endian {
+        field
+            .iter()
+            .rev()
+            .fold(0, |value, &b| value << 8 | u64::from(b))
+    } else {
+        field.iter().fold(0, |value, &b| value << 8 | u64::from(b))
+    };
+    let target = reloc
+        .r_symbolnum()
+        .checked_sub(1)
+        .and_then(|idx| sections.get(idx))
+        .ok_or_else(|| {
+            unsupported(format!(
+                "relocation against unknown section {}",
+                reloc.r_symbolnum()
+            ))
+        })?;
+    let (&start, to) = value
+        .checked_sub(target.header.addr)
+        .and_then(|offset| target.definitions.range(..=offset).next_back())
+        .ok_or_else(|| unsupported(format!("relocation outside of {}", target.name)))?;
+    let custom = matches!(target.layout, Layout::Section(_));
+    Ok((
+        to.clone(),
+        (value - target.header.addr - start) as i64,
+        custom,
+    ))
+}
+
+pub(crate) fn from_mach(mach: &MachO, bytes: &[u8]) -> Result<Artifact, ArtifactError> {
+    if mach.header.filetype != MH_OBJECT {
+        return Err(unsupported("Mach-o file is not an object file"));
+    }
+    let (architecture, arm64) = match mach.header.cputype {
+        CPU_TYPE_X86_64 => (Architecture::X86_64, false),
+        CPU_TYPE_ARM64 => (Architecture::Aarch64(Aarch64Architecture::Aarch64), true),
+        cputype => {
+            return Err(unsupported(format!(
+                "Mach-o cputype {:#x} is not supported",
+                cputype
+            )))
+        }
+    };
+
+    let mut section_symbols: HashMap<usize, Vec<(usize, &str, Nlist)>> = HashMap::new();
+    let mut undefined = Vec::new();
+    let mut common = Vec::new();
+    for (idx, sym) in mach.symbols().enumerate() {
+        let (name, sym) = sym?;
+        if sym.n_type & N_STAB != 0 {
+            continue;
+        }
+        let name = symbol_name(name);
+        match sym.n_type & N_TYPE {
+            // undefined symbols with a value are common symbols of that size
+            N_UNDF if sym.n_value != 0 => common.push((idx, name, sym)),
+            N_UNDF => undefined.push((idx, name)),
+            N_SECT => section_symbols
+                .entry(sym.n_sect)
+                .or_default()
+                .push((idx, name, sym)),
+            _ => {
+                return Err(unsupported(format!(
+                    "symbol {} of type {:#x} is not supported",
+                    name, sym.n_type
+                )))
+            }
+        }
+    }
+
+    let mut contents = Contents::default();
+    let mut sections = Vec::new();
+    let mut targets = HashMap::new();
+    let mut ordinal = 0;
+    for segment in mach.segments.iter() {
+        for (section, data) in segment.sections()? {
+            // section ordinals start at 1, and count the sections of every segment
+            ordinal += 1;
+            let segname = section.segname()?;
+            let sectname = section.name()?;
+            if let S_THREAD_LOCAL_REGULAR | S_THREAD_LOCAL_VARIABLES | S_THREAD_LOCAL_ZEROFILL =
+                section.flags & SECTION_TYPE
+            {
+                return Err(unsupported(format!(
+                    "thread-local section {} is not supported",
+                    sectname
+                )));
+            }
+            let layout = Layout::new(segname, sectname, section.flags);
+            let mut symbols = section_symbols.remove(&ordinal).unwrap_or_default();
+            symbols.sort_by_key(|(_, _, sym)| sym.n_value);
+            // the offset of each symbol into the section, which must be within it
+            let mut offsets = Vec::with_capacity(symbols.len());
+            for (_, sym_name, sym) in &symbols {
+                match sym.n_value.checked_sub(section.addr) {
+                    Some(offset) if offset <= section.size => offsets.push(offset),
+                    _ => {
+                        return Err(unsupported(format!(
+                            "symbol {} is outside of its section {},{}",
+                            sym_name, segname, sectname
+                        )))
+                    }
+                }
+            }
+            let contents_of = |start: u64, end: u64| {
+                data.get(start as usize..end as usize)
+                    .map(|bytes| Data::Blob(bytes.to_vec()))
+                    .ok_or_else(|| {
+                        unsupported(format!(
+                            "contents of {},{} are outside of the file",
+                            segname, sectname
+                        ))
+                    })
+            };
+            let mut definitions = BTreeMap::new();
+
+            if let Layout::Section(kind) = layout {
+                let name = if kind == SectionKind::Debug && sectname.starts_with("__debug") {
+                    format!(".debug{}", &sectname["__debug".len()..])
+                } else if (segname, sectname) == ("__TEXT", "__eh_frame") {
+                    ".eh_frame".to_string()
+                } else {
+                    sectname.to_string()
+                };
+                let mut custom_symbols = BTreeMap::new();
+                for ((idx, sym_name, _), offset) in symbols.into_iter().zip(offsets) {
+                    custom_symbols.insert(sym_name.to_string(), offset);
+                    targets.insert(idx, Target::Custom(sym_name.to_string()));
+                }
+                let unwind = match (segname, sectname) {
+                    ("__TEXT", "__eh_frame") => Some(UnwindTable::EhFrame),
+                    ("__LD", "__compact_unwind") => Some(UnwindTable::CompactUnwind),
+                    _ => None,
+                };
+                let decl = Decl::section(kind)
+                    .with_align(Some(1 << section.align))
+                    .with_unwind(unwind);
+                contents.define(
+                    name.clone(),
+                    decl.into(),
+                    Data::Blob(data.to_vec()),
+                    custom_symbols,
+                );
+                definitions.insert(0, name);
+            } else {
+                match symbols.first() {
+                    None if section.size == 0 => {}
+                    Some((_, _, sym)) if sym.n_value == section.addr => {}
+                    _ => {
+                        return Err(unsupported(format!(
+                            "contents of {},{} must start with a symbol",
+                            segname, sectname
+                        )))
+                    }
+                }
+                for (i, (idx, sym_name, sym)) in symbols.iter().enumerate() {
+                    let start = offsets[i];
+                    let end = offsets.get(i + 1).copied().unwrap_or(section.size);
+                    if start == end && i + 1 < symbols.len() {
+                        return Err(unsupported(format!(
+                            "symbols {} and {} alias each other",
+                            sym_name,
+                            symbols[i + 1].1
+                        )));
+                    }
+                    let (decl, data) = match layout {
+                        Layout::Code => (
+                            Decl::function()
+                                .with_scope(scope(sym))
+                                .with_visibility(visibility(sym))
+                                .into(),
+                            contents_of(start, end)?,
+                        ),
+                        Layout::Data { writable, datatype } => (
+                            Decl::data()
+                                .with_scope(scope(sym))
+                                .with_visibility(visibility(sym))
+                                .with_writable(writable)
+                                .with_datatype(datatype)
+                                .into(),
+                            contents_of(start, end)?,
+                        ),
+                        Layout::ZeroInit => (
+                            Decl::data()
+                                .with_scope(scope(sym))
+                                .with_visibility(visibility(sym))
+                                .writable()
+                                .into(),
+                            Data::ZeroInit((end - start) as usize),
+                        ),
+                        Layout::Section(_) => unreachable!(),
+                    };
+                    contents.define(sym_name.to_string(), decl, data, BTreeMap::new());
+                    definitions.insert(start, sym_name.to_string());
+                    targets.insert(*idx, Target::Definition(sym_name.to_string()));
+                }
+            }
+            sections.push(ReadSection {
+                name: sectname.to_string(),
+                header: section,
+                layout,
+                definitions,
+            });
+        }
+    }
+
+    for (idx, name, sym) in common {
+        // the alignment of a common symbol is stored as a power of two in its description
+        let align = 1 << ((sym.n_desc >> 8) & 0xf);
+        let decl = Decl::data()
+            .with_scope(scope(&sym))
+            .with_visibility(visibility(&sym))
+            .writable()
+            .with_align(Some(align));
+        contents.define(
+            name.to_string(),
+            decl.into(),
+            Data::ZeroInit(sym.n_value as usize),
+            BTreeMap::new(),
+        );
+        targets.insert(idx, Target::Definition(name.to_string()));
+    }
+    for (idx, name) in &undefined {
+        targets.insert(*idx, Target::Import(name.to_string()));
+    }
+
+    // the relocation iterator only uses the byte order of the context
+    let ctx = Ctx::new(
+        if mach.is_64 {
+            Container::Big
+        } else {
+            Container::Little
+        },
+        if mach.little_endian {
+            scroll::LE
+        } else {
+            scroll::BE
+        },
+    );
+    let unsigned = if arm64 {
+        ARM64_RELOC_UNSIGNED
+    } else {
+        X86_64_RELOC_UNSIGNED
+    };
+    let mut got_targets = HashSet::new();
+    for ReadSection {
+        name,
+        header,
+        layout,
+        definitions,
+    } in &sections
+    {
+        if header.nreloc == 0 {
+            continue;
+        }
+        if !layout.is_relocatable() {
+            return Err(unsupported(format!(
+                "relocations in section {} are not supported",
+                name
+            )));
+        }
+        let mut addend = 0;
+        for reloc in header.iter_relocations(bytes, ctx) {
+            let reloc = reloc?;
+            if reloc.r_address < 0 {
+                return Err(unsupported("scattered relocations are not supported"));
+            }
+            if arm64 && reloc.r_type() == ARM64_RELOC_ADDEND {
+                // sign extend the 24 bit addend
+                addend = i64::from(((reloc.r_symbolnum() as i32) << 8) >> 8);
+                continue;
+            }
+            let section_relative = reloc.r_extern() == 0;
+            let (section_target, section_addend, to_custom) = if section_relative {
+                if !matches!(layout, Layout::Section(_)) {
+                    return Err(unsupported(format!(
+                        "section relative relocations in {} are not supported",
+                        name
+                    )));
+                }
+                section_relative_target(&sections, bytes, mach.little_endian, header, &reloc)?
+            } else {
+                (String::new(), 0, false)
+            };
+            let to = match targets.get(&reloc.r_symbolnum()) {
+                _ if section_relative => &section_target,
+                Some(Target::Definition(to)) => to,
+                Some(Target::Import(to)) => {
+                    if is_got_reloc(arm64, reloc.r_type()) {
+                        got_targets.insert(to.clone());
+                    }
+                    to
+                }
+                Some(Target::Custom(to)) => {
+                    return Err(unsupported(format!(
+                        "relocations against section symbol {} are not supported",
+                        to
+                    )))
+                }
+                None => {
+                    return Err(unsupported(format!(
+                        "relocation in {} against unknown symbol {}",
+                        name,
+                        reloc.r_symbolnum()
+                    )))
+                }
+            };
+            let address = reloc.r_address as u64;
+            let (&start, from) = definitions
+                .range(..=address)
+                .next_back()
+                .ok_or_else(|| unsupported(format!("relocation outside of {}", name)))?;
+            let format = (reloc.r_pcrel(), reloc.r_length());
+            let reloc = match layout {
+                Layout::Section(_) => match format {
+                    // addresses in debug info are held in place relative to their section
+                    (0, 3) if reloc.r_type() == unsigned && section_relative && !to_custom => {
+                        Reloc::Absolute {
+                            size: 8,
+                            addend: section_addend,
+                        }
+                    }
+                    (0, 2) | (0, 3) if reloc.r_type() == unsigned => Reloc::Debug {
+                        size: 1 << reloc.r_length(),
+                        addend: section_addend,
+                    },
+                    _ => {
+                        return Err(unsupported(format!(
+                            "relocation of type {} in section {} is not supported",
+                            reloc.r_type(),
+                            name
+                        )))
+                    }
+                },
+                _ if format == relocation_format(arm64, reloc.r_type()) => Reloc::Raw {
+                    reloc: u32::from(reloc.r_type()),
+                    addend,
+                },
+                _ => {
+                    return Err(unsupported(format!(
+                        "relocation of type {} with pcrel {} and length {} is not supported",
+                        reloc.r_type(),
+                        format.0,
+                        format.1
+                    )))
+                }
+            };
+            contents.link(from.clone(), to.clone(), address - start, reloc);
+            addend = 0;
+        }
+    }
+
+    for (_, name) in undefined {
+        let kind = if got_targets.contains(name) {
+            ImportKind::Data
+        } else {
+            ImportKind::Function
+        };
+        contents.import(name.to_string(), kind);
+    }
+
+    contents.finish(
+        triple(architecture, BinaryFormat::Macho),
+        DEFAULT_NAME.to_string(),
+    )
+}
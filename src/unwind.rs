@@ -0,0 +1,385 @@
+//! Generation of the call frame information describing how to unwind the stack out of each
+//! function, so that exceptions, panics and backtraces can unwind through the code of an artifact.
+//!
+//! The frame of every function is described by a frame description entry in `.eh_frame`, pointing
+//! at the function. Mach-o files also get an entry in `__LD,__compact_unwind` for each function,
+//! deferring to its frame description, from which the linker builds `__unwind_info`.
+//!
+//! ```
+//! # use faerie::{triple, Artifact, Decl};
+//! use faerie::unwind::{CallFrameInstruction, FrameRow, UnwindBuilder};
+//!
+//! let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "main.o".into());
+//! obj.declare("main", Decl::function().global()).unwrap();
+//! // push %rbp; mov %rsp, %rbp; xor %eax, %eax; pop %rbp; ret
+//! obj.define("main", vec![0x55, 0x48, 0x89, 0xe5, 0x31, 0xc0, 0x5d, 0xc3])
+//!     .unwrap();
+//! let row = |offset, instruction| FrameRow {
+//!     offset,
+//!     instruction,
+//! };
+//! UnwindBuilder::new()
+//!     .function(
+//!         "main",
+//!         vec![
+//!             // %rbp is saved below the return address
+//!             row(1, CallFrameInstruction::CfaOffset(16)),
+//!             row(1, CallFrameInstruction::Offset { register: 6, offset: -16 }),
+//!             // the frame is addressed through %rbp from then on
+//!             row(4, CallFrameInstruction::CfaRegister(6)),
+//!             row(7, CallFrameInstruction::Cfa { register: 7, offset: 8 }),
+//!         ],
+//!     )
+//!     .finish(&mut obj)
+//!     .unwrap();
+//! ```
+
+use crate::artifact::{ArtifactError, Reloc, SectionKind, UnwindTable};
+use crate::dwarf::{add_sections, function_size, Section};
+use crate::{Artifact, Decl};
+
+use target_lexicon::{Architecture, BinaryFormat, Endianness};
+
+// the subset of the call frame instructions of the DWARF 4 standard which is used
+const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+const DW_CFA_OFFSET: u8 = 0x80;
+const DW_CFA_RESTORE: u8 = 0xc0;
+const DW_CFA_NOP: u8 = 0x00;
+const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+const DW_CFA_OFFSET_EXTENDED: u8 = 0x05;
+const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+const DW_CFA_DEF_CFA: u8 = 0x0c;
+const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+const DW_CFA_DEF_CFA_SF: u8 = 0x12;
+const DW_CFA_DEF_CFA_OFFSET_SF: u8 = 0x13;
+
+/// The encoding of the pointers to functions in frame description entries, as the offset from
+/// the pointer itself, in 4 bytes (`DW_EH_PE_pcrel | DW_EH_PE_sdata4`)
+const POINTER_ENCODING: u8 = 0x1b;
+
+/// The version of `.eh_frame` common information entries
+const CIE_VERSION: u8 = 1;
+
+/// Every instruction is a multiple of a byte long
+const CODE_ALIGNMENT_FACTOR: u64 = 1;
+
+/// The compact unwind encodings which say the function is described by its frame description
+/// entry instead, as `UNWIND_X86_64_MODE_DWARF` and `UNWIND_ARM64_MODE_DWARF`
+const X86_64_MODE_DWARF: u32 = 0x0400_0000;
+const ARM64_MODE_DWARF: u32 = 0x0300_0000;
+
+/// A change to the rules for recovering the frame of the caller, which apply from the offset of
+/// its [FrameRow](struct.FrameRow.html) on.
+///
+/// Registers are numbered as the DWARF ABI of the architecture numbers them, e.g. 6 for `%rbp`
+/// and 7 for `%rsp` on x86-64, and 29 for the frame pointer `x29` and 31 for `sp` on aarch64. The
+/// canonical frame address (CFA) is the value of the stack pointer in the caller, at the call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CallFrameInstruction {
+    /// The CFA is `offset` bytes past the value of `register`
+    Cfa {
+        /// Register the CFA is computed from
+        register: u16,
+        /// Offset of the CFA from the register
+        offset: i64,
+    },
+    /// The CFA is `offset` bytes past the value of the register it's already computed from
+    CfaOffset(i64),
+    /// The CFA is computed from this register, at the offset it's already computed with
+    CfaRegister(u16),
+    /// The value `register` had in the caller is saved at `offset` bytes from the CFA, which must
+    /// be a multiple of the size of a stack slot
+    Offset {
+        /// Register which is saved
+        register: u16,
+        /// Offset of the saved value from the CFA
+        offset: i64,
+    },
+    /// The register is recovered as it is on entry to the function again
+    Restore(u16),
+    /// Push the rules in effect, to restore them with `RestoreState`
+    RememberState,
+    /// Pop the rules most recently pushed with `RememberState`, and put them in effect
+    RestoreState,
+}
+
+/// A change to the rules for unwinding out of a function, from `offset` into it on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameRow {
+    /// Offset of the code into the function
+    pub offset: u64,
+    /// The change to the rules
+    pub instruction: CallFrameInstruction,
+}
+
+/// Builder for the call frame information of the functions of an artifact.
+///
+/// On entry to a function, the CFA is the stack pointer plus the size of the return address
+/// pushed by the call, if any, and the return address is the only saved register; the rows of a
+/// function describe the changes its code makes to that.
+#[derive(Debug, Clone, Default)]
+pub struct UnwindBuilder {
+    functions: Vec<(String, Vec<FrameRow>)>,
+}
+
+/// How the frames of an architecture are laid out on entry to a function
+struct Abi {
+    /// The DWARF register the return address is in
+    return_address: u16,
+    /// The rules on entry to a function
+    initial: &'static [CallFrameInstruction],
+    /// Stack slots are this size, so saved registers are at a multiple of it from the CFA
+    data_alignment: i64,
+    /// The compact unwind encoding which defers to the frame description entry, on Mach-o
+    mode_dwarf: u32,
+}
+
+impl Abi {
+    fn new(architecture: Architecture) -> Option<Self> {
+        match architecture {
+            // the call pushes the return address
+            Architecture::X86_64 => Some(Abi {
+                return_address: 16,
+                initial: &[
+                    CallFrameInstruction::Cfa {
+                        register: 7,
+                        offset: 8,
+                    },
+                    CallFrameInstruction::Offset {
+                        register: 16,
+                        offset: -8,
+                    },
+                ],
+                data_alignment: -8,
+                mode_dwarf: X86_64_MODE_DWARF,
+            }),
+            // the return address is in the link register
+            Architecture::Aarch64(_) => Some(Abi {
+                return_address: 30,
+                initial: &[CallFrameInstruction::Cfa {
+                    register: 31,
+                    offset: 0,
+                }],
+                data_alignment: -8,
+                mode_dwarf: ARM64_MODE_DWARF,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl UnwindBuilder {
+    /// Start the call frame information of an artifact
+    pub fn new() -> Self {
+        UnwindBuilder::default()
+    }
+    /// Describe how to unwind out of the function `name`, which must be defined in the artifact
+    /// when the unwind info is added to it. The rows can be in any order, and rows at the same
+    /// offset apply in the order they're given.
+    pub fn function<T: Into<String>>(mut self, name: T, rows: Vec<FrameRow>) -> Self {
+        self.functions.push((name.into(), rows));
+        self
+    }
+    /// Generate the unwind sections and add them, with their links to the functions, to
+    /// `artifact`
+    pub fn finish(self, artifact: &mut Artifact) -> Result<(), ArtifactError> {
+        let format = artifact.target.binary_format;
+        if format != BinaryFormat::Elf && format != BinaryFormat::Macho {
+            return Err(ArtifactError::UnsupportedBinaryFormat(format));
+        }
+        let unsupported_architecture = || ArtifactError::UnsupportedArchitecture {
+            architecture: artifact.target.architecture,
+            format,
+        };
+        let abi = Abi::new(artifact.target.architecture).ok_or_else(unsupported_architecture)?;
+        let address_size = artifact
+            .target
+            .pointer_width()
+            .map_err(|()| unsupported_architecture())?
+            .bytes();
+        let big_endian = artifact.target.endianness() == Ok(Endianness::Big);
+        let mut functions = Vec::with_capacity(self.functions.len());
+        for (name, mut rows) in self.functions {
+            let size = function_size(artifact, &name, ArtifactError::NonFunctionUnwindInfo)?;
+            rows.sort_by_key(|row| row.offset);
+            functions.push((name, size, rows));
+        }
+
+        // one common information entry, at the start, has what every function shares
+        let mut eh_frame = Section::new(big_endian);
+        let length = eh_frame.begin_length();
+        // the CIE id
+        eh_frame.word(4, 0);
+        eh_frame.u8(CIE_VERSION);
+        // the augmentation data has the encoding of the pointers to functions
+        eh_frame.string("zR");
+        eh_frame.uleb(CODE_ALIGNMENT_FACTOR);
+        eh_frame.sleb(abi.data_alignment);
+        eh_frame.u8(abi.return_address as u8);
+        eh_frame.uleb(1);
+        eh_frame.u8(POINTER_ENCODING);
+        for instruction in abi.initial {
+            encode(&mut eh_frame, &abi, "", instruction)?;
+        }
+        end_entry(&mut eh_frame, length, address_size);
+
+        for (name, size, rows) in &functions {
+            let length = eh_frame.begin_length();
+            // the offset back to the CIE, from the field itself
+            let cie_pointer = eh_frame.bytes.len() as u64;
+            eh_frame.word(4, cie_pointer);
+            // the offset of the function from the field, which is where a PC-relative
+            // relocation is relative to the end of
+            eh_frame.relocate(4, name, Reloc::PcRelative { addend: 4 });
+            eh_frame.word(4, *size);
+            // no augmentation data
+            eh_frame.uleb(0);
+            let mut address = 0;
+            for row in rows {
+                advance(&mut eh_frame, row.offset - address);
+                address = row.offset;
+                encode(&mut eh_frame, &abi, name, &row.instruction)?;
+            }
+            end_entry(&mut eh_frame, length, address_size);
+        }
+
+        let mut sections = vec![(
+            ".eh_frame",
+            Decl::section(SectionKind::Text)
+                .with_align(Some(u64::from(address_size)))
+                .with_unwind(Some(UnwindTable::EhFrame)),
+            eh_frame,
+        )];
+        if format == BinaryFormat::Macho {
+            // the function, its size, encoding, personality function and language specific data
+            let mut compact_unwind = Section::new(big_endian);
+            for (name, size, _) in &functions {
+                compact_unwind.relocate(
+                    address_size,
+                    name,
+                    Reloc::Absolute {
+                        size: address_size,
+                        addend: 0,
+                    },
+                );
+                compact_unwind.word(4, *size);
+                compact_unwind.word(4, u64::from(abi.mode_dwarf));
+                compact_unwind.word(address_size, 0);
+                compact_unwind.word(address_size, 0);
+            }
+            sections.push((
+                "__compact_unwind",
+                Decl::section(SectionKind::Data)
+                    .with_align(Some(u64::from(address_size)))
+                    .with_unwind(Some(UnwindTable::CompactUnwind)),
+                compact_unwind,
+            ));
+        }
+        add_sections(artifact, sections)
+    }
+}
+
+/// Pad the entry starting with the length at `length` with `DW_CFA_nop` to a multiple of the
+/// address size, and patch its length
+fn end_entry(section: &mut Section, length: usize, address_size: u8) {
+    let address_size = address_size as usize;
+    let size = (section.bytes.len() - length).div_ceil(address_size) * address_size;
+    section.bytes.resize(length + size, DW_CFA_NOP);
+    section.end_length(length);
+}
+
+/// Move the location the rules apply from `delta` bytes further into the function
+fn advance(section: &mut Section, delta: u64) {
+    match delta / CODE_ALIGNMENT_FACTOR {
+        0 => {}
+        delta @ 0x01..=0x3f => section.u8(DW_CFA_ADVANCE_LOC | delta as u8),
+        delta @ 0x40..=0xff => {
+            section.u8(DW_CFA_ADVANCE_LOC1);
+            section.word(1, delta);
+        }
+        delta @ 0x100..=0xffff => {
+            section.u8(DW_CFA_ADVANCE_LOC2);
+            section.word(2, delta);
+        }
+        delta => {
+            section.u8(DW_CFA_ADVANCE_LOC4);
+            section.word(4, delta);
+        }
+    }
+}
+
+/// Write `instruction` of the unwind info of `function`, in its most compact form
+fn encode(
+    section: &mut Section,
+    abi: &Abi,
+    function: &str,
+    instruction: &CallFrameInstruction,
+) -> Result<(), ArtifactError> {
+    // offsets which can be negative are factored by the data alignment
+    let factored = |offset: i64| {
+        if offset % abi.data_alignment == 0 {
+            Ok(offset / abi.data_alignment)
+        } else {
+            Err(ArtifactError::MisalignedFrameOffset {
+                function: function.to_string(),
+                offset,
+            })
+        }
+    };
+    match *instruction {
+        CallFrameInstruction::Cfa { register, offset } if offset >= 0 => {
+            section.u8(DW_CFA_DEF_CFA);
+            section.uleb(u64::from(register));
+            section.uleb(offset as u64);
+        }
+        CallFrameInstruction::Cfa { register, offset } => {
+            section.u8(DW_CFA_DEF_CFA_SF);
+            section.uleb(u64::from(register));
+            section.sleb(factored(offset)?);
+        }
+        CallFrameInstruction::CfaOffset(offset) if offset >= 0 => {
+            section.u8(DW_CFA_DEF_CFA_OFFSET);
+            section.uleb(offset as u64);
+        }
+        CallFrameInstruction::CfaOffset(offset) => {
+            section.u8(DW_CFA_DEF_CFA_OFFSET_SF);
+            section.sleb(factored(offset)?);
+        }
+        CallFrameInstruction::CfaRegister(register) => {
+            section.u8(DW_CFA_DEF_CFA_REGISTER);
+            section.uleb(u64::from(register));
+        }
+        CallFrameInstruction::Offset { register, offset } => match factored(offset)? {
+            factored if factored >= 0 && register < 0x40 => {
+                section.u8(DW_CFA_OFFSET | register as u8);
+                section.uleb(factored as u64);
+            }
+            factored if factored >= 0 => {
+                section.u8(DW_CFA_OFFSET_EXTENDED);
+                section.uleb(u64::from(register));
+                section.uleb(factored as u64);
+            }
+            factored => {
+                section.u8(DW_CFA_OFFSET_EXTENDED_SF);
+                section.uleb(u64::from(register));
+                section.sleb(factored);
+            }
+        },
+        CallFrameInstruction::Restore(register) if register < 0x40 => {
+            section.u8(DW_CFA_RESTORE | register as u8);
+        }
+        CallFrameInstruction::Restore(register) => {
+            section.u8(DW_CFA_RESTORE_EXTENDED);
+            section.uleb(u64::from(register));
+        }
+        CallFrameInstruction::RememberState => section.u8(DW_CFA_REMEMBER_STATE),
+        CallFrameInstruction::RestoreState => section.u8(DW_CFA_RESTORE_STATE),
+    }
+    Ok(())
+}
@@ -0,0 +1,657 @@
+//! The WebAssembly backend for transforming an artifact to a relocatable wasm object file, as
+//! described by the [tool conventions](https://github.com/WebAssembly/tool-conventions/blob/main/Linking.md)
+//! `wasm-ld` follows.
+//!
+//! The definition of a function is its body in the code section: the declarations of its locals
+//! followed by its instructions, without the leading size. Every function and function import
+//! needs a `Signature`. Each data object gets a data segment of its own, and custom sections
+//! become custom sections of the same name. Destructors have no equivalent in WebAssembly, and are
+//! left out.
+
+use crate::{
+    artifact::{
+        Artifact, ArtifactError, Data, Decl, DefinedDecl, Definition, ImportKind, InitKind,
+        Initializer, LinkAndDecl, Reloc,
+    },
+    leb::{write_sleb, write_uleb},
+    DataType, Scope, Signature, ValueType, Visibility,
+};
+
+use indexmap::{IndexMap, IndexSet};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use target_lexicon::{Architecture, BinaryFormat};
+
+const MAGIC: &[u8] = b"\0asm";
+const VERSION: u32 = 1;
+/// The version of the `linking` section
+const LINKING_VERSION: u32 = 2;
+/// The module the memory, table and function imports come from
+const IMPORT_MODULE: &str = "env";
+const LINEAR_MEMORY: &str = "__linear_memory";
+const INDIRECT_FUNCTION_TABLE: &str = "__indirect_function_table";
+const PAGE_SIZE: u64 = 0x1_0000;
+/// The priority of constructors without one
+const DEFAULT_INIT_PRIORITY: u32 = 0xffff;
+
+const SECTION_CUSTOM: u8 = 0;
+const SECTION_TYPE: u8 = 1;
+const SECTION_IMPORT: u8 = 2;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_CODE: u8 = 10;
+const SECTION_DATA: u8 = 11;
+
+const EXTERNAL_FUNCTION: u8 = 0;
+const EXTERNAL_TABLE: u8 = 1;
+const EXTERNAL_MEMORY: u8 = 2;
+const TYPE_FUNC: u8 = 0x60;
+const TYPE_FUNCREF: u8 = 0x70;
+const OPCODE_I32_CONST: u8 = 0x41;
+const OPCODE_END: u8 = 0x0b;
+
+// subsections of the `linking` section
+const WASM_SEGMENT_INFO: u8 = 5;
+const WASM_INIT_FUNCS: u8 = 6;
+const WASM_COMDAT_INFO: u8 = 7;
+const WASM_SYMBOL_TABLE: u8 = 8;
+
+const SYMTAB_FUNCTION: u8 = 0;
+const SYMTAB_DATA: u8 = 1;
+const COMDAT_DATA: u8 = 0;
+const COMDAT_FUNCTION: u8 = 1;
+
+const WASM_SYM_BINDING_WEAK: u32 = 0x1;
+const WASM_SYM_BINDING_LOCAL: u32 = 0x2;
+const WASM_SYM_VISIBILITY_HIDDEN: u32 = 0x4;
+const WASM_SYM_UNDEFINED: u32 = 0x10;
+const WASM_SYM_TLS: u32 = 0x100;
+
+const WASM_SEG_FLAG_STRINGS: u32 = 0x1;
+const WASM_SEG_FLAG_TLS: u32 = 0x2;
+
+const R_WASM_FUNCTION_INDEX_LEB: u8 = 0;
+const R_WASM_TABLE_INDEX_SLEB: u8 = 1;
+const R_WASM_TABLE_INDEX_I32: u8 = 2;
+const R_WASM_MEMORY_ADDR_LEB: u8 = 3;
+const R_WASM_MEMORY_ADDR_SLEB: u8 = 4;
+const R_WASM_MEMORY_ADDR_I32: u8 = 5;
+const R_WASM_FUNCTION_OFFSET_I32: u8 = 8;
+const R_WASM_SECTION_OFFSET_I32: u8 = 9;
+const R_WASM_MEMORY_ADDR_REL_SLEB: u8 = 11;
+const R_WASM_TABLE_INDEX_REL_SLEB: u8 = 12;
+const R_WASM_MEMORY_ADDR_TLS_SLEB: u8 = 21;
+const R_WASM_MEMORY_ADDR_LOCREL_I32: u8 = 23;
+
+/// Whether relocations of type `typ` have an addend
+fn has_addend(typ: u8) -> bool {
+    matches!(
+        typ,
+        R_WASM_MEMORY_ADDR_LEB
+            | R_WASM_MEMORY_ADDR_SLEB
+            | R_WASM_MEMORY_ADDR_I32
+            | R_WASM_FUNCTION_OFFSET_I32
+            | R_WASM_SECTION_OFFSET_I32
+            | R_WASM_MEMORY_ADDR_REL_SLEB
+            | R_WASM_MEMORY_ADDR_TLS_SLEB
+            | R_WASM_MEMORY_ADDR_LOCREL_I32
+    )
+}
+
+/// Whether relocations of type `typ` need the address of a function in the table
+fn uses_table(typ: u8) -> bool {
+    matches!(
+        typ,
+        R_WASM_TABLE_INDEX_SLEB | R_WASM_TABLE_INDEX_I32 | R_WASM_TABLE_INDEX_REL_SLEB
+    )
+}
+
+fn write_name(buf: &mut Vec<u8>, name: &str) {
+    write_uleb(buf, name.len() as u64);
+    buf.extend_from_slice(name.as_bytes());
+}
+
+/// Write a section or subsection with `id`, prefixed by the size of its `payload`
+fn write_section(buf: &mut Vec<u8>, id: u8, payload: &[u8]) {
+    buf.push(id);
+    write_uleb(buf, payload.len() as u64);
+    buf.extend_from_slice(payload);
+}
+
+fn value_type(typ: ValueType) -> u8 {
+    match typ {
+        ValueType::I32 => 0x7f,
+        ValueType::I64 => 0x7e,
+        ValueType::F32 => 0x7d,
+        ValueType::F64 => 0x7c,
+    }
+}
+
+/// The symbol flags of a definition with `scope` and `visibility`
+fn symbol_flags(scope: Scope, visibility: Visibility) -> u32 {
+    let binding = match scope {
+        Scope::Global => 0,
+        Scope::Local => WASM_SYM_BINDING_LOCAL,
+        Scope::Weak => WASM_SYM_BINDING_WEAK,
+    };
+    match visibility {
+        Visibility::Hidden => binding | WASM_SYM_VISIBILITY_HIDDEN,
+        Visibility::Default | Visibility::Protected => binding,
+    }
+}
+
+/// What a symbol refers to
+#[derive(Debug)]
+enum SymbolKind {
+    /// The function with this index, counting the imported functions first
+    Function(u32),
+    /// Data at the start of a segment with its index and size, or undefined data
+    Data(Option<(u32, u64)>),
+}
+
+#[derive(Debug)]
+struct Symbol<'a> {
+    name: &'a str,
+    kind: SymbolKind,
+    flags: u32,
+}
+
+/// Where the bytes of a definition end up
+#[derive(Debug, Clone, Copy)]
+enum Location {
+    /// The body of the defined function with this index, not counting imports
+    Code(usize),
+    /// The data segment with this index
+    Data(usize),
+    /// The custom section with this index
+    Custom(usize),
+}
+
+#[derive(Debug)]
+struct Segment<'a> {
+    name: String,
+    data: &'a Data,
+    /// The alignment, as a power of two
+    align: u32,
+    flags: u32,
+    /// The address of the segment in the memory of this object, before linking
+    address: u64,
+}
+
+#[derive(Debug)]
+struct Relocation {
+    typ: u8,
+    /// The offset of the relocation in the definition it is in
+    at: u64,
+    symbol: u32,
+    addend: i32,
+}
+
+#[derive(Debug)]
+struct Wasm<'a> {
+    artifact: &'a Artifact,
+    types: IndexSet<&'a Signature>,
+    /// The imported functions and the indices of their types
+    function_imports: Vec<(&'a str, u32)>,
+    /// The body of every defined function, and the index of its type
+    functions: Vec<(&'a [u8], u32)>,
+    segments: Vec<Segment<'a>>,
+    /// The size of the memory of this object, before linking
+    memory_size: u64,
+    custom_sections: Vec<(&'a str, &'a [u8])>,
+    symbols: Vec<Symbol<'a>>,
+    symbol_indices: HashMap<&'a str, u32>,
+    locations: HashMap<&'a str, Location>,
+    relocations: Vec<(Location, Relocation)>,
+    /// The functions and segments of every COMDAT group
+    comdats: IndexMap<&'a str, Vec<(u8, u32)>>,
+    /// The priorities and symbol indices of the constructors
+    init_funcs: Vec<(u32, u32)>,
+}
+
+impl<'a> Wasm<'a> {
+    pub fn new(artifact: &'a Artifact) -> Result<Self, ArtifactError> {
+        match artifact.target.architecture {
+            Architecture::Wasm32 => (),
+            architecture => {
+                return Err(ArtifactError::UnsupportedArchitecture {
+                    architecture,
+                    format: BinaryFormat::Wasm,
+                })
+            }
+        }
+        Ok(Wasm {
+            artifact,
+            types: IndexSet::new(),
+            function_imports: Vec::new(),
+            functions: Vec::new(),
+            segments: Vec::new(),
+            memory_size: 0,
+            custom_sections: Vec::new(),
+            symbols: Vec::new(),
+            symbol_indices: HashMap::new(),
+            locations: HashMap::new(),
+            relocations: Vec::new(),
+            comdats: IndexMap::new(),
+            init_funcs: Vec::new(),
+        })
+    }
+    fn add_symbol(&mut self, name: &'a str, kind: SymbolKind, flags: u32) {
+        self.symbol_indices.insert(name, self.symbols.len() as u32);
+        self.symbols.push(Symbol { name, kind, flags });
+    }
+    /// The index of the type of the function `name`
+    fn type_index(&mut self, name: &str) -> Result<u32, ArtifactError> {
+        let signature = self
+            .artifact
+            .get_signature(name)
+            .ok_or_else(|| ArtifactError::MissingSignature(name.to_string()))?;
+        Ok(self.types.insert_full(signature).0 as u32)
+    }
+    /// Imports must be added before any definition, since imported functions come first in the
+    /// function index space
+    pub fn import(&mut self, name: &'a str, kind: ImportKind) -> Result<(), ArtifactError> {
+        match kind {
+            ImportKind::Function => {
+                let index = self.function_imports.len() as u32;
+                let typ = self.type_index(name)?;
+                self.function_imports.push((name, typ));
+                self.add_symbol(name, SymbolKind::Function(index), WASM_SYM_UNDEFINED);
+            }
+            ImportKind::Data => self.add_symbol(name, SymbolKind::Data(None), WASM_SYM_UNDEFINED),
+            ImportKind::Tls => self.add_symbol(
+                name,
+                SymbolKind::Data(None),
+                WASM_SYM_UNDEFINED | WASM_SYM_TLS,
+            ),
+        }
+        Ok(())
+    }
+    pub fn add_definition(&mut self, def: Definition<'a>) -> Result<(), ArtifactError> {
+        match def.decl {
+            DefinedDecl::Function(d) => {
+                let body = def
+                    .data
+                    .bytes()
+                    .expect("functions can't be zero-initialized");
+                let typ = self.type_index(def.name)?;
+                let index = self.functions.len();
+                self.functions.push((body, typ));
+                let function = (self.function_imports.len() + index) as u32;
+                if let Some(group) = def.comdat {
+                    let members = self.comdats.entry(group).or_default();
+                    members.push((COMDAT_FUNCTION, function));
+                }
+                self.locations.insert(def.name, Location::Code(index));
+                let flags = symbol_flags(d.get_scope(), d.get_visibility());
+                self.add_symbol(def.name, SymbolKind::Function(function), flags);
+            }
+            DefinedDecl::Data(d) => {
+                let prefix = match () {
+                    _ if d.is_tls() => ".tdata",
+                    _ if def.data.is_zero_init() => ".bss",
+                    _ if d.is_writable() => ".data",
+                    _ => ".rodata",
+                };
+                let mut segment_flags = 0;
+                let mut flags = symbol_flags(d.get_scope(), d.get_visibility());
+                if d.is_tls() {
+                    segment_flags |= WASM_SEG_FLAG_TLS;
+                    flags |= WASM_SYM_TLS;
+                }
+                if d.get_datatype() == DataType::String && !d.is_writable() {
+                    segment_flags |= WASM_SEG_FLAG_STRINGS;
+                }
+                let align = d.get_align().unwrap_or(1);
+                let address = (self.memory_size + align - 1) & !(align - 1);
+                let size = def.data.size() as u64;
+                self.memory_size = address + size;
+                let index = self.segments.len();
+                self.segments.push(Segment {
+                    name: format!("{}.{}", prefix, def.name),
+                    data: def.data,
+                    align: align.trailing_zeros(),
+                    flags: segment_flags,
+                    address,
+                });
+                if let Some(group) = def.comdat {
+                    let members = self.comdats.entry(group).or_default();
+                    members.push((COMDAT_DATA, index as u32));
+                }
+                self.locations.insert(def.name, Location::Data(index));
+                let kind = SymbolKind::Data(Some((index as u32, size)));
+                self.add_symbol(def.name, kind, flags);
+            }
+            DefinedDecl::Section(_) => {
+                let bytes = def
+                    .data
+                    .bytes()
+                    .expect("sections can't be zero-initialized");
+                let index = self.custom_sections.len();
+                self.custom_sections.push((def.name, bytes));
+                self.locations.insert(def.name, Location::Custom(index));
+            }
+        }
+        Ok(())
+    }
+    /// Register the constructors to run when the module is instantiated
+    pub fn add_initializers<I: Iterator<Item = Initializer<'a>>>(&mut self, initializers: I) {
+        for init in initializers {
+            if init.kind == InitKind::Constructor {
+                let priority = init.priority.map_or(DEFAULT_INIT_PRIORITY, u32::from);
+                self.init_funcs
+                    .push((priority, self.symbol_indices[init.name]));
+            }
+        }
+        // the linker expects the constructors in order of priority
+        self.init_funcs.sort_by_key(|&(priority, _)| priority);
+    }
+    pub fn link(&mut self, l: &LinkAndDecl) -> Result<(), ArtifactError> {
+        let unsupported = || l.unsupported(Architecture::Wasm32, BinaryFormat::Wasm);
+        let (typ, addend) = match (l.reloc, l.from.decl, l.to.decl) {
+            (Reloc::Raw { reloc, addend }, _, _) => (reloc as u8, addend),
+            // functions are referred to by their index, which can't be offset
+            (Reloc::Auto, _, Decl::Defined(DefinedDecl::Function(_)))
+            | (Reloc::Auto, _, Decl::Import(ImportKind::Function))
+                if l.addend != 0 =>
+            {
+                return Err(unsupported())
+            }
+            (Reloc::Auto, Decl::Defined(DefinedDecl::Function(_)), to) => match to {
+                // calls need the index of the function
+                Decl::Defined(DefinedDecl::Function(_)) | Decl::Import(ImportKind::Function) => {
+                    (R_WASM_FUNCTION_INDEX_LEB, 0)
+                }
+                // thread-local data is addressed relative to `__tls_base`
+                _ if to.is_tls() => (R_WASM_MEMORY_ADDR_TLS_SLEB, l.addend),
+                Decl::Defined(DefinedDecl::Data(_)) | Decl::Import(ImportKind::Data) => {
+                    (R_WASM_MEMORY_ADDR_SLEB, l.addend)
+                }
+                _ => return Err(unsupported()),
+            },
+            (Reloc::Auto, Decl::Defined(DefinedDecl::Data(_)), to) if !to.is_tls() => match to {
+                // pointers to functions are their index in the table
+                Decl::Defined(DefinedDecl::Function(_)) | Decl::Import(ImportKind::Function) => {
+                    (R_WASM_TABLE_INDEX_I32, 0)
+                }
+                Decl::Defined(DefinedDecl::Data(_)) | Decl::Import(ImportKind::Data) => {
+                    (R_WASM_MEMORY_ADDR_I32, l.addend)
+                }
+                _ => return Err(unsupported()),
+            },
+            // debug info refers to functions by the offset of their body in the code section
+            (Reloc::Debug { size: 4, addend }, _, Decl::Defined(DefinedDecl::Function(_)))
+            | (
+                Reloc::SectionOffset { size: 4, addend },
+                _,
+                Decl::Defined(DefinedDecl::Function(_)),
+            ) => (R_WASM_FUNCTION_OFFSET_I32, addend),
+            (Reloc::Call, _, Decl::Defined(DefinedDecl::Function(_)))
+            | (Reloc::Call, _, Decl::Import(ImportKind::Function)) => {
+                (R_WASM_FUNCTION_INDEX_LEB, 0)
+            }
+            (
+                Reloc::Absolute { size: 4, addend: 0 },
+                _,
+                Decl::Defined(DefinedDecl::Function(_)),
+            )
+            | (Reloc::Absolute { size: 4, addend: 0 }, _, Decl::Import(ImportKind::Function)) => {
+                (R_WASM_TABLE_INDEX_I32, 0)
+            }
+            (Reloc::Absolute { size: 4, addend }, _, to) if !to.is_tls() => match to {
+                Decl::Defined(DefinedDecl::Data(_)) | Decl::Import(ImportKind::Data) => {
+                    (R_WASM_MEMORY_ADDR_I32, addend)
+                }
+                _ => return Err(unsupported()),
+            },
+            (Reloc::Debug { size: 4, addend }, _, Decl::Defined(DefinedDecl::Data(_)))
+            | (Reloc::Debug { size: 4, addend }, _, Decl::Import(ImportKind::Data)) => {
+                (R_WASM_MEMORY_ADDR_I32, addend)
+            }
+            _ => return Err(unsupported()),
+        };
+        let location = *self.locations.get(l.from.name).ok_or_else(unsupported)?;
+        let symbol = *self
+            .symbol_indices
+            .get(l.to.name)
+            .ok_or_else(|| l.missing_symbol())?;
+        self.relocations.push((
+            location,
+            Relocation {
+                typ,
+                at: l.at,
+                symbol,
+                addend: i32::try_from(addend).map_err(|_| l.addend_overflow(addend))?,
+            },
+        ));
+        Ok(())
+    }
+    fn write(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.extend_from_slice(&VERSION.to_le_bytes());
+        // the index of the next section, which relocation sections refer to their target by
+        let mut sections = 0;
+        let mut section = |buf: &mut Vec<u8>, id, payload: &[u8]| {
+            write_section(buf, id, payload);
+            sections += 1;
+            sections - 1
+        };
+
+        let mut types = Vec::new();
+        write_uleb(&mut types, self.types.len() as u64);
+        for signature in &self.types {
+            types.push(TYPE_FUNC);
+            for values in &[&signature.params, &signature.results] {
+                write_uleb(&mut types, values.len() as u64);
+                types.extend(values.iter().map(|&typ| value_type(typ)));
+            }
+        }
+        if !self.types.is_empty() {
+            section(&mut buf, SECTION_TYPE, &types);
+        }
+
+        let table = self
+            .relocations
+            .iter()
+            .any(|(_, reloc)| uses_table(reloc.typ));
+        let mut imports = Vec::new();
+        let count = 1 + table as usize + self.function_imports.len();
+        write_uleb(&mut imports, count as u64);
+        write_name(&mut imports, IMPORT_MODULE);
+        write_name(&mut imports, LINEAR_MEMORY);
+        imports.extend_from_slice(&[EXTERNAL_MEMORY, 0]);
+        let pages = self.memory_size.div_ceil(PAGE_SIZE);
+        write_uleb(&mut imports, pages);
+        if table {
+            write_name(&mut imports, IMPORT_MODULE);
+            write_name(&mut imports, INDIRECT_FUNCTION_TABLE);
+            imports.extend_from_slice(&[EXTERNAL_TABLE, TYPE_FUNCREF, 0, 0]);
+        }
+        for (name, typ) in &self.function_imports {
+            write_name(&mut imports, IMPORT_MODULE);
+            write_name(&mut imports, name);
+            imports.push(EXTERNAL_FUNCTION);
+            write_uleb(&mut imports, u64::from(*typ));
+        }
+        section(&mut buf, SECTION_IMPORT, &imports);
+
+        // the offsets of the function bodies and data segments in their sections, which the
+        // relocations are relative to
+        let mut code_offsets = Vec::new();
+        let mut data_offsets = Vec::new();
+        let mut code_section = None;
+        let mut data_section = None;
+        if !self.functions.is_empty() {
+            let mut functions = Vec::new();
+            write_uleb(&mut functions, self.functions.len() as u64);
+            for (_, typ) in &self.functions {
+                write_uleb(&mut functions, u64::from(*typ));
+            }
+            section(&mut buf, SECTION_FUNCTION, &functions);
+
+            let mut code = Vec::new();
+            write_uleb(&mut code, self.functions.len() as u64);
+            for (body, _) in &self.functions {
+                write_uleb(&mut code, body.len() as u64);
+                code_offsets.push(code.len() as u64);
+                code.extend_from_slice(body);
+            }
+            code_section = Some(section(&mut buf, SECTION_CODE, &code));
+        }
+        if !self.segments.is_empty() {
+            let mut data = Vec::new();
+            write_uleb(&mut data, self.segments.len() as u64);
+            for segment in &self.segments {
+                // an active segment of the first memory
+                data.push(0);
+                data.push(OPCODE_I32_CONST);
+                write_sleb(&mut data, segment.address as i64);
+                data.push(OPCODE_END);
+                write_uleb(&mut data, segment.data.size() as u64);
+                data_offsets.push(data.len() as u64);
+                match segment.data.bytes() {
+                    Some(bytes) => data.extend_from_slice(bytes),
+                    None => data.resize(data.len() + segment.data.size(), 0),
+                }
+            }
+            data_section = Some(section(&mut buf, SECTION_DATA, &data));
+        }
+        let mut custom_sections = Vec::new();
+        for (name, bytes) in &self.custom_sections {
+            let mut custom = Vec::new();
+            write_name(&mut custom, name);
+            custom.extend_from_slice(bytes);
+            custom_sections.push(section(&mut buf, SECTION_CUSTOM, &custom));
+        }
+
+        let mut linking = Vec::new();
+        write_uleb(&mut linking, u64::from(LINKING_VERSION));
+        let mut symtab = Vec::new();
+        write_uleb(&mut symtab, self.symbols.len() as u64);
+        for symbol in &self.symbols {
+            let undefined = symbol.flags & WASM_SYM_UNDEFINED != 0;
+            match symbol.kind {
+                SymbolKind::Function(index) => {
+                    symtab.push(SYMTAB_FUNCTION);
+                    write_uleb(&mut symtab, u64::from(symbol.flags));
+                    write_uleb(&mut symtab, u64::from(index));
+                    // undefined functions are named by their import
+                    if !undefined {
+                        write_name(&mut symtab, symbol.name);
+                    }
+                }
+                SymbolKind::Data(segment) => {
+                    symtab.push(SYMTAB_DATA);
+                    write_uleb(&mut symtab, u64::from(symbol.flags));
+                    write_name(&mut symtab, symbol.name);
+                    if let Some((index, size)) = segment {
+                        write_uleb(&mut symtab, u64::from(index));
+                        write_uleb(&mut symtab, 0);
+                        write_uleb(&mut symtab, size);
+                    }
+                }
+            }
+        }
+        write_section(&mut linking, WASM_SYMBOL_TABLE, &symtab);
+        if !self.segments.is_empty() {
+            let mut info = Vec::new();
+            write_uleb(&mut info, self.segments.len() as u64);
+            for segment in &self.segments {
+                write_name(&mut info, &segment.name);
+                write_uleb(&mut info, u64::from(segment.align));
+                write_uleb(&mut info, u64::from(segment.flags));
+            }
+            write_section(&mut linking, WASM_SEGMENT_INFO, &info);
+        }
+        if !self.init_funcs.is_empty() {
+            let mut init = Vec::new();
+            write_uleb(&mut init, self.init_funcs.len() as u64);
+            for (priority, symbol) in &self.init_funcs {
+                write_uleb(&mut init, u64::from(*priority));
+                write_uleb(&mut init, u64::from(*symbol));
+            }
+            write_section(&mut linking, WASM_INIT_FUNCS, &init);
+        }
+        if !self.comdats.is_empty() {
+            let mut comdats = Vec::new();
+            write_uleb(&mut comdats, self.comdats.len() as u64);
+            for (name, members) in &self.comdats {
+                write_name(&mut comdats, name);
+                // no flags are defined yet
+                write_uleb(&mut comdats, 0);
+                write_uleb(&mut comdats, members.len() as u64);
+                for (kind, index) in members {
+                    comdats.push(*kind);
+                    write_uleb(&mut comdats, u64::from(*index));
+                }
+            }
+            write_section(&mut linking, WASM_COMDAT_INFO, &comdats);
+        }
+        let mut custom = Vec::new();
+        write_name(&mut custom, "linking");
+        custom.extend_from_slice(&linking);
+        section(&mut buf, SECTION_CUSTOM, &custom);
+
+        // the relocations of each section, relative to the start of its contents
+        let mut code_relocations = Vec::new();
+        let mut data_relocations = Vec::new();
+        let mut custom_relocations = self
+            .custom_sections
+            .iter()
+            .map(|_| Vec::new())
+            .collect::<Vec<_>>();
+        for (location, reloc) in &self.relocations {
+            match *location {
+                Location::Code(i) => code_relocations.push((code_offsets[i] + reloc.at, reloc)),
+                Location::Data(i) => data_relocations.push((data_offsets[i] + reloc.at, reloc)),
+                Location::Custom(i) => custom_relocations[i].push((reloc.at, reloc)),
+            }
+        }
+        let mut targets = vec![
+            ("reloc.CODE".to_string(), code_section, code_relocations),
+            ("reloc.DATA".to_string(), data_section, data_relocations),
+        ];
+        for (i, entries) in custom_relocations.into_iter().enumerate() {
+            let name = format!("reloc.{}", self.custom_sections[i].0);
+            targets.push((name, Some(custom_sections[i]), entries));
+        }
+        for (name, target, mut entries) in targets {
+            let target = match target {
+                Some(target) if !entries.is_empty() => target,
+                _ => continue,
+            };
+            // the linker expects relocations in order of their offsets
+            entries.sort_by_key(|&(offset, _)| offset);
+            let mut custom = Vec::new();
+            write_name(&mut custom, &name);
+            write_uleb(&mut custom, target as u64);
+            write_uleb(&mut custom, entries.len() as u64);
+            for (offset, reloc) in entries {
+                custom.push(reloc.typ);
+                write_uleb(&mut custom, offset);
+                write_uleb(&mut custom, u64::from(reloc.symbol));
+                if has_addend(reloc.typ) {
+                    write_sleb(&mut custom, i64::from(reloc.addend));
+                }
+            }
+            write_section(&mut buf, SECTION_CUSTOM, &custom);
+        }
+        buf
+    }
+}
+
+pub fn to_bytes(artifact: &Artifact) -> Result<Vec<u8>, ArtifactError> {
+    let mut wasm = Wasm::new(artifact)?;
+    for (import, kind) in artifact.imports() {
+        debug!("Import: {:?} -> {:?}", import, kind);
+        wasm.import(import, *kind)?;
+    }
+    for def in artifact.definitions() {
+        debug!("Def: {:?}", def);
+        wasm.add_definition(def)?;
+    }
+    wasm.add_initializers(artifact.initializers());
+    for link in artifact.links() {
+        wasm.link(&link)?;
+    }
+    Ok(wasm.write())
+}
@@ -0,0 +1,125 @@
+extern crate faerie;
+extern crate goblin;
+extern crate target_lexicon;
+
+use faerie::{triple, Artifact, ArtifactBuilder, BinaryFormat, Decl, Link};
+use goblin::archive::Archive;
+use goblin::Object;
+
+fn artifact(target: target_lexicon::Triple, name: &str, function: &str) -> Artifact {
+    let mut obj = ArtifactBuilder::new(target).name(name.to_string()).finish();
+    obj.declarations(
+        vec![
+            (function, Decl::function().global().into()),
+            ("helper", Decl::function().into()),
+            ("COUNTER", Decl::data().weak().writable().into()),
+            ("printf", Decl::function_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define(function, vec![0xe8, 0, 0, 0, 0, 0xc3])
+        .expect("can define function");
+    obj.define("helper", vec![0xc3]).expect("can define helper");
+    obj.define("COUNTER", vec![0; 8])
+        .expect("can define COUNTER");
+    obj.link(Link {
+        from: function,
+        to: "printf",
+        at: 1,
+    })
+    .expect("can link");
+    obj
+}
+
+#[test]
+fn gnu_archive() {
+    let a = artifact(triple!("x86_64-unknown-linux-gnu"), "a.o", "first");
+    let b = artifact(
+        triple!("x86_64-unknown-linux-gnu"),
+        "/tmp/a_rather_long_object_name.o",
+        "second",
+    );
+    let bytes = Artifact::emit_archive(&[&a, &b], BinaryFormat::Elf).expect("can emit archive");
+    let archive = Archive::parse(&bytes).expect("can parse archive");
+
+    assert_eq!(
+        archive.members(),
+        vec!["a.o", "a_rather_long_object_name.o"]
+    );
+    assert_eq!(archive.member_of_symbol("first"), Some("a.o"));
+    assert_eq!(
+        archive.member_of_symbol("second"),
+        Some("a_rather_long_object_name.o")
+    );
+    // weak definitions are indexed, local definitions and imports are not
+    assert!(archive.member_of_symbol("COUNTER").is_some());
+    assert_eq!(archive.member_of_symbol("helper"), None);
+    assert_eq!(archive.member_of_symbol("printf"), None);
+
+    let member = archive.extract("a.o", &bytes).expect("can extract member");
+    assert_eq!(member, &a.emit().expect("can emit object")[..]);
+    match Object::parse(member).expect("can parse member") {
+        Object::Elf(_) => {}
+        _ => panic!("member should be an ELF object"),
+    }
+}
+
+#[test]
+fn bsd_archive_from_library() {
+    let mut obj = artifact(triple!("x86_64-apple-darwin"), "lib.o", "first");
+    obj.is_library = true;
+    let bytes = obj.emit().expect("can emit archive");
+    let archive = Archive::parse(&bytes).expect("can parse archive");
+
+    assert_eq!(archive.members(), vec!["lib.o"]);
+    // mach-o symbols are prefixed with an underscore
+    assert_eq!(archive.member_of_symbol("_first"), Some("lib.o"));
+    assert_eq!(archive.member_of_symbol("_COUNTER"), Some("lib.o"));
+    assert_eq!(archive.member_of_symbol("_helper"), None);
+
+    let member = archive
+        .extract("lib.o", &bytes)
+        .expect("can extract member");
+    // ld64 requires archived objects to be 8 byte aligned
+    assert_eq!((member.as_ptr() as usize - bytes.as_ptr() as usize) % 8, 0);
+    match Object::parse(member).expect("can parse member") {
+        Object::Mach(_) => {}
+        _ => panic!("member should be a Mach-o object"),
+    }
+}
+
+#[test]
+fn member_headers() {
+    let names = ["a.o", "sixteen_chars.o", "/tmp/a_rather_long_object_name.o"];
+    for (target, format) in [
+        (triple!("x86_64-unknown-linux-gnu"), BinaryFormat::Elf),
+        (triple!("x86_64-apple-darwin"), BinaryFormat::Macho),
+    ] {
+        let artifacts = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| artifact(target.clone(), name, &format!("f{}", i)))
+            .collect::<Vec<_>>();
+        let artifacts = artifacts.iter().collect::<Vec<_>>();
+        let bytes = Artifact::emit_archive(&artifacts, format).expect("can emit archive");
+
+        // every header keeps its name and size inside their fields, so the next one starts where
+        // the size says it does
+        let mut offset = 8;
+        while offset < bytes.len() {
+            let header = std::str::from_utf8(&bytes[offset..offset + 60]).expect("header is text");
+            assert!(header.ends_with("`\n"), "malformed header {:?}", header);
+            assert_eq!(
+                &header[16..48],
+                format!("{:<12}{:<6}{:<6}{:<8}", 0, 0, 0, 644)
+            );
+            let size = header[48..58]
+                .trim_end()
+                .parse::<usize>()
+                .expect("size is a number");
+            offset += 60 + size + size % 2;
+        }
+        assert_eq!(offset, bytes.len());
+    }
+}
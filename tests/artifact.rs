@@ -1,11 +1,9 @@
 extern crate faerie;
-#[macro_use]
-extern crate target_lexicon;
 #[cfg(test)]
 extern crate goblin;
+extern crate target_lexicon;
 
 use faerie::*;
-use std::str::FromStr;
 
 #[test]
 fn duplicate_declarations_are_ok() {
@@ -157,7 +155,9 @@ fn vary_output_formats() {
     use goblin::Object;
     use target_lexicon::BinaryFormat;
 
-    let obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    let mut target = triple!("x86_64");
+    target.binary_format = BinaryFormat::Unknown;
+    let obj = Artifact::new(target, "t.o".into());
     assert!(obj.emit().is_err());
 
     let elf = obj.emit_as(BinaryFormat::Elf).unwrap();
@@ -172,13 +172,9 @@ fn vary_output_formats() {
         _ => panic!("emitted as MachO but didn't parse as MachO"),
     }
 
-    /* TODO: Enable when COFF is supported.
+    // goblin's `Object` only recognizes PE images, so parse the object file directly
     let coff = obj.emit_as(BinaryFormat::Coff).unwrap();
-    match Object::parse(&coff).unwrap() {
-         Object::PE(_) => {}
-         _ => panic!("emitted as COFF but didn't parse as COFF"),
-    }
-    */
+    assert!(goblin::pe::Coff::parse(&coff).is_ok());
 }
 
 #[test]
@@ -225,3 +221,378 @@ fn invalid_bss() {
         .unwrap();
     assert!(artifact.define_zero_init("my_section", 100).is_err());
 }
+
+#[test]
+fn invalid_alignment() {
+    let mut artifact = Artifact::new(triple!("x86_64-pc-windows-msvc"), "align".into());
+    match artifact.declare("f", Decl::function().with_align(Some(3))) {
+        Err(ArtifactError::InvalidAlignment { name, align }) => {
+            assert_eq!((name.as_str(), align), ("f", 3))
+        }
+        other => panic!("expected an invalid alignment, got {:?}", other),
+    }
+    assert!(artifact
+        .declare("DATA", Decl::data().with_align(Some(0)))
+        .is_err());
+    artifact
+        .declare("f", Decl::function().with_align(Some(16)))
+        .unwrap();
+    artifact.define("f", vec![0xc3]).unwrap();
+    artifact.emit().unwrap();
+}
+
+#[test]
+fn comdat_groups() {
+    let mut artifact = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "comdat".into());
+    artifact.declare("f", Decl::function()).unwrap();
+    artifact.declare("g", Decl::function_import()).unwrap();
+    artifact.comdat("f", "f").unwrap();
+    match artifact.comdat("g", "f") {
+        Err(ArtifactError::ImportComdat(name)) => assert_eq!(name, "g"),
+        other => panic!("expected an import in a group, got {:?}", other),
+    }
+    match artifact.comdat("h", "f") {
+        Err(ArtifactError::Undeclared(name)) => assert_eq!(name, "h"),
+        other => panic!("expected an undeclared symbol, got {:?}", other),
+    }
+    // the declarations themselves stay plain values
+    let decl = Decl::function().global();
+    artifact.declare("f2", decl).unwrap();
+    artifact.declare("f3", decl).unwrap();
+}
+
+#[test]
+fn write_to_sinks() {
+    use std::io::{Cursor, Seek, SeekFrom};
+    use target_lexicon::BinaryFormat;
+
+    let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    obj.declarations(
+        vec![
+            ("f", Decl::function().global().into()),
+            ("DATA", Decl::data().writable().into()),
+            ("g", Decl::function_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("f", vec![0xe8, 0, 0, 0, 0, 0xc3])
+        .expect("can define f");
+    obj.define("DATA", vec![1, 2, 3, 4])
+        .expect("can define DATA");
+    obj.link(Link {
+        from: "f",
+        to: "g",
+        at: 1,
+    })
+    .expect("can link");
+    obj.signature("f", Signature::default())
+        .expect("can sign f");
+    obj.signature("g", Signature::default())
+        .expect("can sign g");
+
+    for format in [
+        BinaryFormat::Elf,
+        BinaryFormat::Macho,
+        BinaryFormat::Coff,
+        BinaryFormat::Wasm,
+    ] {
+        let mut obj = obj.clone();
+        if format == BinaryFormat::Wasm {
+            obj.target = triple!("wasm32-unknown-unknown");
+        }
+        let bytes = obj.emit_as(format).expect("can emit");
+        // the object file is written from wherever the sink is
+        let mut sink = Cursor::new(b"prefix".to_vec());
+        sink.seek(SeekFrom::End(0)).unwrap();
+        obj.write_as(&mut sink, format).expect("can write");
+        let written = sink.into_inner();
+        assert_eq!(&written[..6], b"prefix");
+        assert_eq!(&written[6..], &bytes[..], "{} object differs", format);
+
+        obj.is_library = true;
+        let mut sink = Cursor::new(Vec::new());
+        obj.write_as(&mut sink, format).expect("can write library");
+        assert_eq!(sink.into_inner(), obj.emit_as(format).unwrap());
+    }
+
+    let mut sink = Cursor::new(Vec::new());
+    obj.write_to(&mut sink).expect("can write");
+    assert_eq!(sink.into_inner(), obj.emit().unwrap());
+    obj.declare("h", Decl::function()).expect("can declare h");
+    match obj.write_to(Cursor::new(Vec::new())) {
+        Err(ArtifactError::UndefinedSymbols(names)) => assert_eq!(names, vec!["h"]),
+        other => panic!("expected an undefined symbol, got {:?}", other),
+    }
+}
+
+#[test]
+fn shared_data() {
+    use std::sync::Arc;
+    use target_lexicon::BinaryFormat;
+
+    let code: Arc<[u8]> = Arc::from(vec![0xe8, 0, 0, 0, 0, 0xc3]);
+    let table: Arc<[u8]> = Arc::from(vec![1, 2, 3, 4]);
+    let object = |shared: bool| {
+        let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+        obj.declarations(
+            vec![
+                ("f", Decl::function().global().into()),
+                ("TABLE", Decl::data().into()),
+                ("g", Decl::function_import().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        if shared {
+            obj.define_shared("f", code.clone()).expect("can define f");
+            obj.define_shared("TABLE", table.clone())
+                .expect("can define TABLE");
+        } else {
+            obj.define("f", code.to_vec()).expect("can define f");
+            obj.define("TABLE", table.to_vec())
+                .expect("can define TABLE");
+        }
+        obj.link(Link {
+            from: "f",
+            to: "g",
+            at: 1,
+        })
+        .expect("can link");
+        obj
+    };
+
+    let shared = object(true);
+    // the artifact and its clone refer to the same bytes
+    let clone = shared.clone();
+    assert_eq!(Arc::strong_count(&code), 3);
+    assert_eq!(Arc::strong_count(&table), 3);
+    for format in [BinaryFormat::Elf, BinaryFormat::Macho, BinaryFormat::Coff] {
+        assert_eq!(
+            clone.emit_as(format).expect("can emit shared data"),
+            object(false).emit_as(format).expect("can emit blobs"),
+            "{} object differs",
+            format
+        );
+    }
+    drop((shared, clone));
+    assert_eq!(Arc::strong_count(&code), 1);
+
+    let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    match obj.define_shared("missing", code) {
+        Err(ArtifactError::Undeclared(name)) => assert_eq!(name, "missing"),
+        other => panic!("expected an undeclared symbol, got {:?}", other),
+    }
+}
+
+#[test]
+fn symbol_handles() {
+    use target_lexicon::BinaryFormat;
+
+    let by_name = {
+        let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+        obj.declare("f", Decl::function().global()).unwrap();
+        obj.declare("DATA", Decl::data()).unwrap();
+        obj.declare("g", Decl::function_import()).unwrap();
+        obj.define("f", vec![0xe8, 0, 0, 0, 0, 0xc3]).unwrap();
+        obj.define("DATA", vec![0; 8]).unwrap();
+        obj.link(Link {
+            from: "f",
+            to: "g",
+            at: 1,
+        })
+        .unwrap();
+        obj.link_with(
+            Link {
+                from: "DATA",
+                to: "f",
+                at: 0,
+            },
+            Reloc::Absolute { size: 8, addend: 0 },
+        )
+        .unwrap();
+        obj.constructor("f", Some(200)).unwrap();
+        obj.destructor("f", None).unwrap();
+        obj.signature("g", Signature::default()).unwrap();
+        obj
+    };
+
+    let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    let f = obj.declare("f", Decl::function().global()).unwrap();
+    let data = obj.declare("DATA", Decl::data()).unwrap();
+    let g = obj.import("g", ImportKind::Function).unwrap();
+    assert_ne!(f, data);
+    assert_eq!(obj.symbol("f"), Some(f));
+    assert_eq!(obj.symbol("missing"), None);
+    // declaring a symbol again gives back the same handle
+    assert_eq!(obj.declare("g", Decl::function_import()).unwrap(), g);
+    obj.define(f, vec![0xe8, 0, 0, 0, 0, 0xc3]).unwrap();
+    obj.define(data, vec![0; 8]).unwrap();
+    obj.link(SymbolLink {
+        from: f,
+        to: g,
+        at: 1,
+    })
+    .unwrap();
+    obj.link_with(
+        SymbolLink {
+            from: data,
+            to: f,
+            at: 0,
+        },
+        Reloc::Absolute { size: 8, addend: 0 },
+    )
+    .unwrap();
+    obj.constructor(f, Some(200)).unwrap();
+    obj.destructor(f, None).unwrap();
+    obj.signature(g, Signature::default()).unwrap();
+    for format in [BinaryFormat::Elf, BinaryFormat::Macho, BinaryFormat::Coff] {
+        assert_eq!(
+            obj.emit_as(format).unwrap(),
+            by_name.emit_as(format).unwrap(),
+            "{} object differs",
+            format
+        );
+    }
+
+    // errors name the symbol behind the handle
+    match obj.define(f, vec![0xc3]) {
+        Err(ArtifactError::DuplicateDefinition(name)) => assert_eq!(name, "f"),
+        other => panic!("expected a duplicate definition, got {:?}", other),
+    }
+    match obj.link(SymbolLink {
+        from: g,
+        to: f,
+        at: 0,
+    }) {
+        Err(ArtifactError::RelocateImport(name)) => assert_eq!(name, "g"),
+        other => panic!("expected a relocated import, got {:?}", other),
+    }
+    match obj.constructor(data, None) {
+        Err(ArtifactError::NonFunctionInitializer(name)) => assert_eq!(name, "DATA"),
+        other => panic!("expected a non function, got {:?}", other),
+    }
+    match obj.signature(data, Signature::default()) {
+        Err(ArtifactError::NonFunctionSignature(name)) => assert_eq!(name, "DATA"),
+        other => panic!("expected a non function, got {:?}", other),
+    }
+    // a handle from another artifact, even one naming the same symbol, or from a clone
+    let mut other = Artifact::new(triple!("x86_64"), "t.o".into());
+    let a = other.declare("a", Decl::function()).unwrap();
+    let b = other.declare("b", Decl::function()).unwrap();
+    let mut obj = Artifact::new(triple!("x86_64"), "t.o".into());
+    obj.declare("a", Decl::function()).unwrap();
+    obj.declare("b", Decl::function()).unwrap();
+    let mut clone = other.clone();
+    for result in [
+        obj.define(a, vec![0xc3]),
+        obj.define(b, vec![0xc3]),
+        clone.define(b, vec![0xc3]),
+        obj.constructor(a, None),
+        obj.destructor(b, None),
+        obj.signature(b, Signature::default()),
+        obj.comdat(a, "a"),
+    ] {
+        match result {
+            Err(ArtifactError::Undeclared(_)) => (),
+            other => panic!("expected an undeclared symbol, got {:?}", other),
+        }
+    }
+    other.define(b, vec![0xc3]).unwrap();
+}
+
+#[test]
+fn merge_artifacts() {
+    use std::thread;
+    use target_lexicon::BinaryFormat;
+
+    // `f` calls `g`, which reads `DATA`
+    fn declare_f(obj: &mut Artifact) {
+        obj.declare("f", Decl::function().global()).unwrap();
+        obj.declare("g", Decl::function_import()).unwrap();
+        obj.define("f", vec![0xe8, 0, 0, 0, 0, 0xc3]).unwrap();
+        obj.link(Link {
+            from: "f",
+            to: "g",
+            at: 1,
+        })
+        .unwrap();
+        obj.constructor("f", None).unwrap();
+        obj.comdat("f", "f").unwrap();
+    }
+    fn declare_g(obj: &mut Artifact) {
+        obj.declare("g", Decl::function()).unwrap();
+        obj.declare("DATA", Decl::data_import()).unwrap();
+        obj.declare("puts", Decl::function_import()).unwrap();
+        obj.define("g", vec![0x8b, 0x05, 0, 0, 0, 0, 0xc3]).unwrap();
+        obj.link(Link {
+            from: "g",
+            to: "DATA",
+            at: 2,
+        })
+        .unwrap();
+    }
+    fn declare_data(obj: &mut Artifact) {
+        obj.declare("DATA", Decl::data().global()).unwrap();
+        obj.define("DATA", vec![1, 2, 3, 4]).unwrap();
+    }
+    let new = || Artifact::new(triple!("x86_64"), "t.o".into());
+
+    let mut expected = new();
+    declare_data(&mut expected);
+    declare_f(&mut expected);
+    declare_g(&mut expected);
+
+    let mut merged = new();
+    declare_data(&mut merged);
+    let workers = [declare_f, declare_g]
+        .iter()
+        .map(|declare| {
+            let declare = *declare;
+            thread::spawn(move || {
+                let mut obj = new();
+                declare(&mut obj);
+                obj
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        merged.merge(worker.join().unwrap()).expect("can merge");
+    }
+    assert!(merged.undefined_symbols().is_empty());
+    for format in [BinaryFormat::Elf, BinaryFormat::Macho, BinaryFormat::Coff] {
+        assert_eq!(
+            merged.emit_as(format).unwrap(),
+            expected.emit_as(format).unwrap(),
+            "{} object differs",
+            format
+        );
+    }
+
+    // a failed merge leaves the artifact as it was
+    let before = merged.emit().unwrap();
+    let mut other = new();
+    other.declare("h", Decl::function()).unwrap();
+    other.define("h", vec![0xc3]).unwrap();
+    declare_data(&mut other);
+    match merged.merge(other) {
+        Err(ArtifactError::DuplicateDefinition(name)) => assert_eq!(name, "DATA"),
+        other => panic!("expected a duplicate definition, got {:?}", other),
+    }
+    let mut other = new();
+    other.declare("h", Decl::function()).unwrap();
+    other.declare("DATA", Decl::function_import()).unwrap();
+    match merged.merge(other) {
+        Err(ArtifactError::IncompatibleDeclaration { .. }) => (),
+        other => panic!("expected an incompatible declaration, got {:?}", other),
+    }
+    assert_eq!(merged.emit().unwrap(), before);
+    assert!(merged.symbol("h").is_none());
+
+    match merged.merge(Artifact::new(triple!("aarch64"), "t.o".into())) {
+        Err(ArtifactError::MismatchedTargets { expected, found }) => {
+            assert_eq!((expected, found), (triple!("x86_64"), triple!("aarch64")))
+        }
+        other => panic!("expected mismatched targets, got {:?}", other),
+    }
+}
@@ -0,0 +1,475 @@
+extern crate faerie;
+extern crate goblin;
+extern crate target_lexicon;
+
+use faerie::{triple, Artifact, Decl, Link, Reloc, SectionKind};
+use goblin::pe::relocation::*;
+use goblin::pe::section_table::*;
+use goblin::pe::symbol::*;
+use goblin::pe::Coff;
+
+fn symbol(coff: &Coff, name: &str) -> (usize, Symbol) {
+    coff.symbols
+        .iter()
+        .find(|(_, sym_name, sym)| sym_name.or_else(|| sym.name(&coff.strings).ok()) == Some(name))
+        .map(|(idx, _, sym)| (idx, sym))
+        .unwrap_or_else(|| panic!("symbol {} should exist", name))
+}
+
+fn section<'a>(coff: &'a Coff, name: &str) -> &'a SectionTable {
+    coff.sections
+        .iter()
+        .find(|section| section.name().unwrap() == name)
+        .unwrap_or_else(|| panic!("section {} should exist", name))
+}
+
+#[test]
+fn definitions_and_imports() {
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("helper", Decl::function().into()),
+            ("a_long_function_name", Decl::function().weak().into()),
+            ("str.1", Decl::cstring().into()),
+            ("STATIC", Decl::data().global().writable().into()),
+            ("ZEROS", Decl::data().global().writable().into()),
+            ("printf", Decl::function_import().into()),
+            ("DEADBEEF", Decl::data_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0xcc; 16]).expect("can define main");
+    obj.define("helper", vec![0xc3]).expect("can define helper");
+    obj.define("a_long_function_name", vec![0xc3])
+        .expect("can define a_long_function_name");
+    obj.define("str.1", b"hello\0".to_vec())
+        .expect("can define str.1");
+    obj.define("STATIC", vec![0; 8]).expect("can define STATIC");
+    obj.define_zero_init("ZEROS", 64).expect("can define ZEROS");
+    obj.link(Link {
+        from: "main",
+        to: "helper",
+        at: 1,
+    })
+    .expect("can link to function");
+    obj.link(Link {
+        from: "main",
+        to: "printf",
+        at: 5,
+    })
+    .expect("can link to function import");
+    obj.link(Link {
+        from: "main",
+        to: "DEADBEEF",
+        at: 9,
+    })
+    .expect("can link to data import");
+    obj.link(Link {
+        from: "STATIC",
+        to: "str.1",
+        at: 0,
+    })
+    .expect("can link from data");
+
+    let bytes = obj.emit().expect("can emit coff file");
+    let coff = Coff::parse(&bytes).expect("can parse coff file");
+    assert_eq!(coff.header.machine, goblin::pe::header::COFF_MACHINE_X86_64);
+
+    let text = section(&coff, ".text$main");
+    assert_ne!(text.characteristics & IMAGE_SCN_CNT_CODE, 0);
+    assert_ne!(text.characteristics & IMAGE_SCN_MEM_EXECUTE, 0);
+    assert_eq!(text.characteristics & IMAGE_SCN_LNK_COMDAT, 0);
+    assert_eq!(
+        text.characteristics & IMAGE_SCN_ALIGN_MASK,
+        IMAGE_SCN_ALIGN_16BYTES
+    );
+    let weak = section(&coff, ".text$a_long_function_name");
+    assert_ne!(weak.characteristics & IMAGE_SCN_LNK_COMDAT, 0);
+    let rdata = section(&coff, ".rdata$str.1");
+    assert_eq!(rdata.characteristics & IMAGE_SCN_MEM_WRITE, 0);
+    let data = section(&coff, ".data$STATIC");
+    assert_ne!(data.characteristics & IMAGE_SCN_MEM_WRITE, 0);
+    let bss = section(&coff, ".bss$ZEROS");
+    assert_ne!(bss.characteristics & IMAGE_SCN_CNT_UNINITIALIZED_DATA, 0);
+    assert_eq!(bss.size_of_raw_data, 64);
+    assert_eq!(bss.pointer_to_raw_data, 0);
+
+    let (_, main) = symbol(&coff, "main");
+    assert_eq!(main.storage_class, IMAGE_SYM_CLASS_EXTERNAL);
+    assert!(main.is_function_definition());
+    let (helper_idx, helper) = symbol(&coff, "helper");
+    assert_eq!(helper.storage_class, IMAGE_SYM_CLASS_STATIC);
+    let (_, weak) = symbol(&coff, "a_long_function_name");
+    assert_eq!(weak.storage_class, IMAGE_SYM_CLASS_EXTERNAL);
+    let (printf_idx, printf) = symbol(&coff, "printf");
+    assert_eq!(printf.section_number, IMAGE_SYM_UNDEFINED);
+    assert_eq!(printf.storage_class, IMAGE_SYM_CLASS_EXTERNAL);
+    let (deadbeef_idx, _) = symbol(&coff, "__imp_DEADBEEF");
+    let (str_idx, _) = symbol(&coff, "str.1");
+
+    let relocs = text
+        .relocations(&bytes)
+        .expect("can parse relocations")
+        .collect::<Vec<_>>();
+    assert_eq!(relocs.len(), 3);
+    assert!(relocs.iter().all(|r| r.typ == IMAGE_REL_AMD64_REL32));
+    assert_eq!(relocs[0].virtual_address, 1);
+    assert_eq!(relocs[0].symbol_table_index as usize, helper_idx);
+    assert_eq!(relocs[1].symbol_table_index as usize, printf_idx);
+    assert_eq!(relocs[2].symbol_table_index as usize, deadbeef_idx);
+
+    let relocs = data
+        .relocations(&bytes)
+        .expect("can parse relocations")
+        .collect::<Vec<_>>();
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].typ, IMAGE_REL_AMD64_ADDR64);
+    assert_eq!(relocs[0].symbol_table_index as usize, str_idx);
+}
+
+#[test]
+fn custom_sections_and_debug_addends() {
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+    obj.declare(".debug_str", Decl::section(SectionKind::Debug))
+        .expect("can declare .debug_str");
+    obj.declare(".debug_info", Decl::section(SectionKind::Debug))
+        .expect("can declare .debug_info");
+    obj.declare("f", Decl::function()).expect("can declare f");
+    obj.define(".debug_str", b"faerie\0f\0".to_vec())
+        .expect("can define .debug_str");
+    obj.define(".debug_info", vec![0; 12])
+        .expect("can define .debug_info");
+    obj.define("f", vec![0xc3]).expect("can define f");
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: ".debug_str",
+            at: 0,
+        },
+        Reloc::Debug { size: 4, addend: 7 },
+    )
+    .expect("can link to debug section");
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: "f",
+            at: 4,
+        },
+        Reloc::Debug { size: 8, addend: 0 },
+    )
+    .expect("can link to function");
+
+    let bytes = obj.emit().expect("can emit coff file");
+    let coff = Coff::parse(&bytes).expect("can parse coff file");
+    let debug_info = section(&coff, ".debug_info");
+    assert_ne!(debug_info.characteristics & IMAGE_SCN_MEM_DISCARDABLE, 0);
+    let relocs = debug_info
+        .relocations(&bytes)
+        .expect("can parse relocations")
+        .collect::<Vec<_>>();
+    assert_eq!(relocs.len(), 2);
+    assert_eq!(relocs[0].typ, IMAGE_REL_AMD64_SECREL);
+    assert_eq!(relocs[1].typ, IMAGE_REL_AMD64_ADDR64);
+    // COFF uses implicit addends, stored in the section data
+    let offset = debug_info.pointer_to_raw_data as usize;
+    assert_eq!(&bytes[offset..offset + 4], &[7, 0, 0, 0]);
+}
+
+#[test]
+fn thread_local_variables() {
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("COUNTER", Decl::data().global().tls().into()),
+            ("ZEROED", Decl::data().tls().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0xcc; 16]).expect("can define main");
+    obj.define("COUNTER", vec![42, 0, 0, 0])
+        .expect("can define COUNTER");
+    obj.define_zero_init("ZEROED", 8)
+        .expect("can define ZEROED");
+    obj.link(Link {
+        from: "main",
+        to: "COUNTER",
+        at: 3,
+    })
+    .expect("can link to thread-local variable");
+
+    let bytes = obj.emit().expect("can emit coff file");
+    let coff = Coff::parse(&bytes).expect("can parse coff file");
+    let tls = coff
+        .sections
+        .iter()
+        .filter(|section| section.name().unwrap() == ".tls$")
+        .collect::<Vec<_>>();
+    // zero initialized thread-local variables are written out, as the loader only copies the
+    // template
+    assert_eq!(tls.len(), 2);
+    assert!(tls.iter().all(|section| section.size_of_raw_data != 0
+        && section.characteristics & IMAGE_SCN_MEM_WRITE != 0));
+
+    let (counter_idx, _) = symbol(&coff, "COUNTER");
+    let relocs = section(&coff, ".text$main")
+        .relocations(&bytes)
+        .expect("can parse relocations")
+        .collect::<Vec<_>>();
+    assert_eq!(relocs.len(), 1);
+    assert_eq!(relocs[0].typ, IMAGE_REL_AMD64_SECREL);
+    assert_eq!(relocs[0].symbol_table_index as usize, counter_idx);
+}
+
+#[test]
+fn comdat_groups() {
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+    obj.declarations(
+        vec![
+            ("inline", Decl::function().global().into()),
+            ("inline.data", Decl::data().global().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.comdat("inline", "inline").expect("can add to group");
+    obj.comdat("inline.data", "inline")
+        .expect("can add to group");
+    // the leader is the definition named after the group, even if it isn't the first
+    obj.define("inline.data", vec![42, 0, 0, 0])
+        .expect("can define inline.data");
+    obj.define("inline", vec![0xc3]).expect("can define inline");
+
+    let bytes = obj.emit().expect("can emit coff file");
+    let coff = Coff::parse(&bytes).expect("can parse coff file");
+    let aux = |name: &str| {
+        let section = section(&coff, name);
+        assert_ne!(section.characteristics & IMAGE_SCN_LNK_COMDAT, 0);
+        let (idx, _) = symbol(&coff, name);
+        coff.symbols
+            .aux_section_definition(idx + 1)
+            .expect("section symbol has an aux record")
+    };
+    let text_number = coff
+        .sections
+        .iter()
+        .position(|section| section.name().unwrap() == ".text$inline")
+        .unwrap()
+        + 1;
+    assert_eq!(aux(".text$inline").selection, IMAGE_COMDAT_SELECT_ANY);
+    let data = aux(".rdata$inline.data");
+    assert_eq!(data.selection, IMAGE_COMDAT_SELECT_ASSOCIATIVE);
+    assert_eq!(data.number as usize, text_number);
+}
+
+#[test]
+fn constructors_and_destructors() {
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+    obj.declarations(
+        vec![
+            ("init", Decl::function().into()),
+            ("early", Decl::function().into()),
+            ("fini", Decl::function().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    for name in &["init", "early", "fini"] {
+        obj.define(name, vec![0xc3]).expect("can define function");
+    }
+    obj.constructor("init", None).expect("can add constructor");
+    obj.constructor("early", Some(101))
+        .expect("can add constructor");
+    obj.destructor("fini", None).expect("can add destructor");
+
+    let bytes = obj.emit().expect("can emit coff file");
+    let coff = Coff::parse(&bytes).expect("can parse coff file");
+    for (name, function) in &[
+        (".CRT$XCU", "init"),
+        (".CRT$XCA00101", "early"),
+        (".CRT$XTX", "fini"),
+    ] {
+        let section = section(&coff, name);
+        assert_eq!(section.size_of_raw_data, 8);
+        assert_eq!(section.characteristics & IMAGE_SCN_MEM_WRITE, 0);
+        let relocs = section
+            .relocations(&bytes)
+            .expect("can parse relocations")
+            .collect::<Vec<_>>();
+        assert_eq!(relocs.len(), 1);
+        assert_eq!(relocs[0].typ, IMAGE_REL_AMD64_ADDR64);
+        assert_eq!(relocs[0].virtual_address, 0);
+        assert_eq!(
+            relocs[0].symbol_table_index as usize,
+            symbol(&coff, function).0
+        );
+    }
+}
+
+#[test]
+fn unsupported_relocations() {
+    use faerie::{ArtifactError, BinaryFormat};
+
+    // only x86_64 relocations are supported so far
+    let mut obj = Artifact::new(triple!("aarch64-pc-windows-msvc"), "t.obj".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("puts", Decl::function_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0; 8]).expect("can define main");
+    obj.link(Link {
+        from: "main",
+        to: "puts",
+        at: 0,
+    })
+    .expect("can link");
+    match obj.emit() {
+        Err(ArtifactError::UnsupportedRelocation {
+            from,
+            to,
+            architecture,
+            format,
+            ..
+        }) => {
+            assert_eq!((from.as_str(), to.as_str()), ("main", "puts"));
+            assert_eq!(architecture, obj.target.architecture);
+            assert_eq!(format, BinaryFormat::Coff);
+        }
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
+
+#[test]
+fn portable_relocations() {
+    use faerie::ArtifactError;
+
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("TABLE", Decl::data().global().writable().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0; 16]).expect("can define main");
+    obj.define("TABLE", vec![0; 12]).expect("can define TABLE");
+    for (from, to, at, reloc) in [
+        ("main", "main", 1, Reloc::Call),
+        ("main", "TABLE", 6, Reloc::PcRelative { addend: 8 }),
+        ("TABLE", "main", 0, Reloc::Absolute { size: 8, addend: 1 }),
+        ("TABLE", "main", 8, Reloc::ImageOffset { addend: 2 }),
+    ] {
+        obj.link_with(Link { from, to, at }, reloc)
+            .expect("can link");
+    }
+    let bytes = obj.emit().expect("can emit coff file");
+    let coff = Coff::parse(&bytes).expect("can parse coff file");
+    let relocs = |name: &str| {
+        section(&coff, name)
+            .relocations(&bytes)
+            .expect("can parse relocations")
+            .map(|r| (r.virtual_address, r.typ))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        relocs(".text$main"),
+        vec![(1, IMAGE_REL_AMD64_REL32), (6, IMAGE_REL_AMD64_REL32)]
+    );
+    assert_eq!(
+        relocs(".data$TABLE"),
+        vec![(0, IMAGE_REL_AMD64_ADDR64), (8, IMAGE_REL_AMD64_ADDR32NB)]
+    );
+    // the addends are written in place
+    let data = section(&coff, ".data$TABLE");
+    let start = data.pointer_to_raw_data as usize;
+    assert_eq!(&bytes[start..start + 8], &1u64.to_le_bytes());
+    assert_eq!(&bytes[start + 8..start + 12], &2u32.to_le_bytes());
+
+    // COFF has no GOT
+    obj.link_with(
+        Link {
+            from: "main",
+            to: "TABLE",
+            at: 10,
+        },
+        Reloc::GotPcRelative { addend: 0 },
+    )
+    .expect("can link");
+    match obj.emit() {
+        Err(ArtifactError::UnsupportedRelocation { reloc, .. }) => {
+            assert_eq!(reloc, Reloc::GotPcRelative { addend: 0 })
+        }
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
+
+#[test]
+fn text_sections() {
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+    obj.declare(".trampolines", Decl::section(SectionKind::Text))
+        .expect("can declare .trampolines");
+    obj.define(".trampolines", vec![0xc3])
+        .expect("can define .trampolines");
+
+    let bytes = obj.emit().expect("can emit coff file");
+    let coff = Coff::parse(&bytes).expect("can parse coff file");
+    let trampolines = section(&coff, ".trampolines");
+    assert_ne!(trampolines.characteristics & IMAGE_SCN_CNT_CODE, 0);
+    assert_ne!(trampolines.characteristics & IMAGE_SCN_MEM_EXECUTE, 0);
+    assert_eq!(trampolines.characteristics & IMAGE_SCN_MEM_WRITE, 0);
+}
+
+#[test]
+fn addends_outside_definitions() {
+    use faerie::ArtifactError;
+
+    let object = || {
+        let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "t.obj".into());
+        obj.declarations(
+            vec![
+                ("f", Decl::function().into()),
+                ("BSS", Decl::data().writable().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        obj.define("f", vec![0x90, 0x90, 0x90, 0xc3])
+            .expect("can define f");
+        obj.define_zero_init("BSS", 16).expect("can define BSS");
+        obj
+    };
+    let link = |from, at| Link { from, to: "f", at };
+
+    // links are checked when they're added if `from` is defined, and when emitting otherwise
+    let mut obj = object();
+    match obj.link_with(link("f", 2), Reloc::PcRelative { addend: 5 }) {
+        Err(ArtifactError::LinkOutOfBounds { from, to, at }) => {
+            assert_eq!((from.as_str(), to.as_str(), at), ("f", "f", 2))
+        }
+        other => panic!("expected a link out of bounds, got {:?}", other),
+    }
+    let mut obj = object();
+    obj.declare("g", Decl::function()).expect("can declare g");
+    obj.link_with(link("g", 2), Reloc::PcRelative { addend: 5 })
+        .expect("can link before defining g");
+    obj.define("g", vec![0x90, 0x90, 0x90, 0xc3])
+        .expect("can define g");
+    match obj.emit() {
+        Err(ArtifactError::LinkOutOfBounds { from, .. }) => assert_eq!(from, "g"),
+        other => panic!("expected a link out of bounds, got {:?}", other),
+    }
+    // zero-initialized data has no contents to hold the addend
+    let mut obj = object();
+    match obj.link_with(link("BSS", 0), Reloc::Absolute { size: 8, addend: 5 }) {
+        Err(ArtifactError::LinkOutOfBounds { from, .. }) => assert_eq!(from, "BSS"),
+        other => panic!("expected a link out of bounds, got {:?}", other),
+    }
+}
@@ -0,0 +1,305 @@
+extern crate faerie;
+extern crate goblin;
+extern crate target_lexicon;
+
+use faerie::dwarf::{CompilationUnit, DwarfBuilder, LineRow};
+use faerie::{triple, Artifact, ArtifactError, Decl};
+use std::convert::TryInto;
+
+fn read_uleb(bytes: &[u8], offset: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*offset];
+        *offset += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+    }
+}
+
+fn read_sleb(bytes: &[u8], offset: &mut usize) -> i64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*offset];
+        *offset += 1;
+        value |= i64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            if shift < 64 && byte & 0x40 != 0 {
+                value |= -1 << shift;
+            }
+            return value;
+        }
+    }
+}
+
+fn read_string<'a>(bytes: &'a [u8], offset: &mut usize) -> &'a str {
+    let end = *offset + bytes[*offset..].iter().position(|&b| b == 0).unwrap();
+    let string = std::str::from_utf8(&bytes[*offset..end]).unwrap();
+    *offset = end + 1;
+    string
+}
+
+/// The rows of the little endian, 64 bit `.debug_line` section, as (address, file, line, column)
+/// with the addresses held in place, for the subset of the line number program faerie generates
+fn line_rows(bytes: &[u8]) -> Vec<(u64, String, u64, u64)> {
+    let length = u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize;
+    assert_eq!(length + 4, bytes.len());
+    assert_eq!(&bytes[4..6], &4u16.to_le_bytes());
+    let header_length = u32::from_le_bytes(bytes[6..10].try_into().unwrap()) as usize;
+    let opcode_base = bytes[15];
+    // no include directories
+    let mut offset = 10 + 6 + opcode_base as usize - 1;
+    assert_eq!(bytes[offset], 0);
+    offset += 1;
+    let mut files = Vec::new();
+    while bytes[offset] != 0 {
+        files.push(read_string(bytes, &mut offset).to_string());
+        for _ in 0..3 {
+            read_uleb(bytes, &mut offset);
+        }
+    }
+    assert_eq!(offset + 1, 10 + header_length);
+    offset += 1;
+
+    let mut rows = Vec::new();
+    let (mut address, mut file, mut line, mut column) = (0, 1, 1, 0);
+    while offset < bytes.len() {
+        let opcode = bytes[offset];
+        offset += 1;
+        match opcode {
+            0 => {
+                let length = read_uleb(bytes, &mut offset) as usize;
+                match bytes[offset] {
+                    // DW_LNE_end_sequence
+                    1 => {
+                        rows.push((address, String::new(), 0, 0));
+                        address = 0;
+                        file = 1;
+                        line = 1;
+                        column = 0;
+                    }
+                    // DW_LNE_set_address
+                    2 => {
+                        assert_eq!(length, 9);
+                        address =
+                            u64::from_le_bytes(bytes[offset + 1..offset + 9].try_into().unwrap());
+                    }
+                    op => panic!("unexpected extended opcode {}", op),
+                }
+                offset += length;
+            }
+            1 => rows.push((address, files[file - 1].clone(), line, column)),
+            2 => address += read_uleb(bytes, &mut offset),
+            3 => line = (line as i64 + read_sleb(bytes, &mut offset)) as u64,
+            4 => file = read_uleb(bytes, &mut offset) as usize,
+            5 => column = read_uleb(bytes, &mut offset),
+            op => panic!("unexpected opcode {}", op),
+        }
+    }
+    rows
+}
+
+fn row(offset: u64, file: &str, line: u64, column: u64) -> LineRow {
+    LineRow {
+        offset,
+        file: file.to_string(),
+        line,
+        column,
+    }
+}
+
+fn object(target: target_lexicon::Triple) -> Artifact {
+    let mut obj = Artifact::new(target, "a.o".into());
+    obj.declarations(
+        vec![
+            ("helper", Decl::function().into()),
+            ("main", Decl::function().global().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("helper", vec![0x90; 8])
+        .expect("can define helper");
+    obj.define("main", vec![0x31, 0xc0, 0x90, 0x90, 0xc3])
+        .expect("can define main");
+    DwarfBuilder::new(
+        CompilationUnit::new("main.c")
+            .comp_dir("/src")
+            .producer("faerie")
+            .language(0x0c),
+    )
+    .function(
+        "main",
+        vec![
+            row(2, "main.c", 2, 5),
+            row(0, "main.c", 1, 12),
+            row(4, "util.h", 7, 0),
+        ],
+    )
+    .function(
+        "helper",
+        vec![row(0, "main.c", 10, 1), row(4, "main.c", 9, 3)],
+    )
+    .finish(&mut obj)
+    .expect("can add debug info");
+    obj
+}
+
+/// The rows of `object(target)` once each function is at `address(name)`
+fn expected_rows(address: impl Fn(&str) -> u64) -> Vec<(u64, String, u64, u64)> {
+    let (main, helper) = (address("main"), address("helper"));
+    let row = |address, file: &str, line, column| (address, file.to_string(), line, column);
+    vec![
+        row(main, "main.c", 1, 12),
+        row(main + 2, "main.c", 2, 5),
+        row(main + 4, "util.h", 7, 0),
+        row(main + 5, "", 0, 0),
+        row(helper, "main.c", 10, 1),
+        row(helper + 4, "main.c", 9, 3),
+        row(helper + 8, "", 0, 0),
+    ]
+}
+
+#[test]
+fn elf_line_info() {
+    use goblin::elf::reloc::*;
+
+    let bytes = object(triple!("x86_64-unknown-linux-gnu"))
+        .emit()
+        .expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let section = |name: &str| {
+        elf.section_headers
+            .iter()
+            .position(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+    for name in &[".debug_abbrev", ".debug_info", ".debug_ranges"] {
+        section(name);
+    }
+    let line = &elf.section_headers[section(".debug_line")];
+    let line_bytes = &bytes[line.file_range().unwrap()];
+    // the addresses are left to the relocations, of the start of each function
+    assert_eq!(line_rows(line_bytes), expected_rows(|_| 0));
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|(shndx, _)| elf.section_headers[*shndx].sh_info as usize == section(".debug_line"))
+        .expect(".debug_line has relocations");
+    let relocs = relocs
+        .iter()
+        .map(|r| {
+            let sym = elf.syms.get(r.r_sym).unwrap();
+            let target = &elf.section_headers[sym.st_shndx];
+            let name = elf.shdr_strtab.get_at(target.sh_name).unwrap();
+            (r.r_type, name, r.r_addend)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relocs,
+        vec![
+            (R_X86_64_64, ".text.main", Some(0)),
+            (R_X86_64_64, ".text.helper", Some(0)),
+        ]
+    );
+}
+
+#[test]
+fn mach_line_info() {
+    use goblin::mach::MachO;
+
+    for target in [
+        triple!("x86_64-apple-darwin"),
+        triple!("aarch64-apple-darwin"),
+    ] {
+        let bytes = object(target).emit().expect("can emit mach file");
+        let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+        let sections = mach
+            .segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section"))
+            .collect::<Vec<_>>();
+        let section = |name: &str| {
+            sections
+                .iter()
+                .find(|(section, _)| section.name().unwrap() == name)
+                .map(|(section, data)| (section.segname().unwrap(), *data))
+                .unwrap_or_else(|| panic!("section {} should exist", name))
+        };
+        for name in &["__debug_abbrev", "__debug_info", "__debug_ranges"] {
+            assert_eq!(section(name).0, "__DWARF");
+        }
+        // the object file holds the address of each function in place
+        let symbols = mach
+            .symbols()
+            .map(|sym| sym.expect("can parse symbol"))
+            .collect::<Vec<_>>();
+        let address = |name: &str| {
+            let name = format!("_{}", name);
+            symbols
+                .iter()
+                .find(|(sym, _)| *sym == name)
+                .unwrap()
+                .1
+                .n_value
+        };
+        assert_eq!(line_rows(section("__debug_line").1), expected_rows(address));
+    }
+}
+
+#[test]
+fn line_info_errors() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    let unit = CompilationUnit::new("main.c");
+    let rows = vec![row(0, "main.c", 1, 1)];
+    let finish = |obj: &mut Artifact, name: &str| {
+        DwarfBuilder::new(unit.clone())
+            .function(name, rows.clone())
+            .finish(obj)
+    };
+
+    match finish(&mut obj, "missing") {
+        Err(ArtifactError::Undeclared(name)) => assert_eq!(name, "missing"),
+        other => panic!("expected an undeclared symbol, got {:?}", other),
+    }
+    match finish(&mut obj, "DATA") {
+        Err(ArtifactError::NonFunctionLineInfo(name)) => assert_eq!(name, "DATA"),
+        other => panic!("expected a non function, got {:?}", other),
+    }
+    match finish(&mut obj, "main") {
+        Err(ArtifactError::UndefinedSymbols(names)) => assert_eq!(names, vec!["main"]),
+        other => panic!("expected an undefined symbol, got {:?}", other),
+    }
+
+    // only ELF and Mach-o have debug sections addressing code this way
+    for target in [
+        triple!("wasm32-unknown-unknown"),
+        triple!("x86_64-pc-windows-msvc"),
+    ] {
+        let mut obj = Artifact::new(target.clone(), "a.o".into());
+        obj.declare("main", Decl::function())
+            .expect("can declare main");
+        obj.define("main", vec![0xc3]).expect("can define main");
+        match finish(&mut obj, "main") {
+            Err(ArtifactError::UnsupportedBinaryFormat(format)) => {
+                assert_eq!(format, target.binary_format)
+            }
+            other => panic!("expected an unsupported format, got {:?}", other),
+        }
+    }
+}
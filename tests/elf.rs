@@ -7,7 +7,6 @@ extern crate target_lexicon;
 use anyhow::{ensure, Error};
 use faerie::{Artifact, ArtifactBuilder, Decl, Link};
 use goblin::elf::*;
-use std::str::FromStr;
 
 #[test]
 // This test is for a known bug (issue #31).
@@ -23,7 +22,7 @@ fn file_name_is_same_as_symbol_name_issue_31() {
     println!("{:?}", bytes);
 
     // Presently, the following expect fails, `bytes` is not a valid Elf:
-    let elf = goblin::Object::parse(&bytes).expect("can parse elf file");
+    let elf = goblin::Object::parse(bytes).expect("can parse elf file");
     match elf {
         goblin::Object::Elf(elf) => {
             assert_eq!(elf.syms.len(), 4);
@@ -42,8 +41,7 @@ fn file_name_is_same_as_symbol_name_issue_31() {
             assert_eq!(&elf.strtab[sym.st_name], NAME);
         }
         _ => {
-            println!("Elf file not parsed as elf file");
-            assert!(false)
+            panic!("Elf file not parsed as elf file");
         }
     }
 }
@@ -241,7 +239,7 @@ fn decl_tests(tests: Vec<DeclTestCase>) {
     let bytes = bytes.as_slice();
     println!("{:?}", bytes);
 
-    let elf = goblin::Object::parse(&bytes).expect("can parse elf file");
+    let elf = goblin::Object::parse(bytes).expect("can parse elf file");
 
     match elf {
         goblin::Object::Elf(elf) => {
@@ -255,10 +253,12 @@ fn decl_tests(tests: Vec<DeclTestCase>) {
     }
 }
 
+type DeclPredicate = Box<dyn Fn(&Sym, &SectionHeader) -> Result<(), Error>>;
+
 struct DeclTestCase {
     name: String,
     decl: Decl,
-    pred: Box<dyn Fn(&Sym, &SectionHeader) -> Result<(), Error>>,
+    pred: DeclPredicate,
 }
 impl DeclTestCase {
     fn new<D, F>(name: &str, decl: D, pred: F) -> Self
@@ -274,21 +274,21 @@ impl DeclTestCase {
     }
     fn define(&self, art: &mut Artifact) {
         art.declare(&self.name, self.decl)
-            .expect(&format!("declare {}", self.name));
+            .unwrap_or_else(|_| panic!("declare {}", self.name));
         art.define(&self.name, vec![1, 2, 3, 4])
-            .expect(&format!("define {}", self.name));
+            .unwrap_or_else(|_| panic!("define {}", self.name));
     }
     fn check(&self, elf: &goblin::elf::Elf) {
         let sym = elf
             .syms
             .iter()
-            .find(|sym| &elf.strtab[sym.st_name] == self.name)
+            .find(|sym| elf.strtab[sym.st_name] == self.name)
             .expect("symbol should exist");
         let sectheader = elf
             .section_headers
             .get(sym.st_shndx)
             .expect("section header should exist");
-        (self.pred)(&sym, sectheader).expect(&format!("check {}", self.name))
+        (self.pred)(&sym, sectheader).unwrap_or_else(|_| panic!("check {}", self.name))
     }
 }
 
@@ -361,3 +361,675 @@ fn custom_section_symbols() {
         panic!("Elf file not parsed as elf file");
     }
 }
+
+#[test]
+fn aarch64_auto_relocations() {
+    use goblin::elf::reloc::*;
+
+    let mut obj = Artifact::new(triple!("aarch64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("helper", Decl::function().into()),
+            ("DATA", Decl::data().global().writable().into()),
+            ("EXTERNAL", Decl::data_import().into()),
+            ("printf", Decl::function_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    let main = [
+        0x94000000u32, // bl helper
+        0x90000000,    // adrp x0, DATA
+        0x91000000,    // add x0, x0, :lo12:DATA
+        0x90000001,    // adrp x1, :got:EXTERNAL
+        0xf9400021,    // ldr x1, [x1, :got_lo12:EXTERNAL]
+        0x94000000,    // bl printf
+        0xd65f03c0,    // ret
+    ];
+    obj.define(
+        "main",
+        main.iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>(),
+    )
+    .expect("can define main");
+    obj.define("helper", 0xd65f03c0u32.to_le_bytes().to_vec())
+        .expect("can define helper");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    for (to, at) in [("helper", 0), ("DATA", 4), ("EXTERNAL", 12), ("printf", 20)] {
+        obj.link(Link {
+            from: "main",
+            to,
+            at,
+        })
+        .expect("can link from main");
+    }
+    obj.link(Link {
+        from: "DATA",
+        to: "helper",
+        at: 0,
+    })
+    .expect("can link from DATA");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    assert_eq!(elf.header.e_machine, header::EM_AARCH64);
+    let relocs = |section: &str| {
+        let (idx, _) = elf
+            .section_headers
+            .iter()
+            .enumerate()
+            .find(|(_, sh)| &elf.shdr_strtab[sh.sh_name] == section)
+            .expect("section should exist");
+        elf.shdr_relocs
+            .iter()
+            .find(|(shndx, _)| elf.section_headers[*shndx].sh_info as usize == idx)
+            .expect("section should have relocations")
+            .1
+            .iter()
+            .map(|r| (r.r_type, r.r_offset, r.r_addend))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        relocs(".text.main"),
+        vec![
+            (R_AARCH64_CALL26, 0, Some(0)),
+            (R_AARCH64_ADR_PREL_PG_HI21, 4, Some(0)),
+            (R_AARCH64_ADD_ABS_LO12_NC, 8, Some(0)),
+            (R_AARCH64_ADR_GOT_PAGE, 12, Some(0)),
+            (R_AARCH64_LD64_GOT_LO12_NC, 16, Some(0)),
+            (R_AARCH64_CALL26, 20, Some(0)),
+        ]
+    );
+    assert_eq!(relocs(".data.DATA"), vec![(R_AARCH64_ABS64, 0, Some(0))]);
+}
+
+#[test]
+fn thread_local_variables() {
+    use goblin::elf::reloc::*;
+    use goblin::elf::section_header::{SHF_TLS, SHT_NOBITS};
+    use goblin::elf::sym::STT_TLS;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("COUNTER", Decl::data().global().tls().into()),
+            ("ZEROED", Decl::data().tls().into()),
+            ("EXTERNAL", Decl::tls_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define(
+        "main",
+        vec![
+            0x48, 0x8b, 0x05, 0, 0, 0, 0, // mov COUNTER@GOTTPOFF(%rip), %rax
+            0x48, 0x8b, 0x0d, 0, 0, 0, 0,    // mov EXTERNAL@GOTTPOFF(%rip), %rcx
+            0xc3, // ret
+        ],
+    )
+    .expect("can define main");
+    obj.define("COUNTER", vec![42, 0, 0, 0])
+        .expect("can define COUNTER");
+    obj.define_zero_init("ZEROED", 8)
+        .expect("can define ZEROED");
+    for (to, at) in [("COUNTER", 3), ("EXTERNAL", 10)] {
+        obj.link(Link {
+            from: "main",
+            to,
+            at,
+        })
+        .expect("can link from main");
+    }
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let section = |name: &str| {
+        elf.section_headers
+            .iter()
+            .find(|sh| &elf.shdr_strtab[sh.sh_name] == name)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+    assert_ne!(section(".tdata.COUNTER").sh_flags & u64::from(SHF_TLS), 0);
+    let tbss = section(".tbss.ZEROED");
+    assert_ne!(tbss.sh_flags & u64::from(SHF_TLS), 0);
+    assert_eq!(tbss.sh_type, SHT_NOBITS);
+    for name in &["COUNTER", "ZEROED", "EXTERNAL"] {
+        let sym = elf
+            .syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == *name)
+            .unwrap_or_else(|| panic!("symbol {} should exist", name));
+        assert_eq!(sym.st_type(), STT_TLS, "{} should be thread-local", name);
+    }
+    let relocs = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .map(|r| (r.r_type, r.r_offset, r.r_addend))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relocs,
+        vec![
+            (R_X86_64_GOTTPOFF, 3, Some(-4)),
+            (R_X86_64_GOTTPOFF, 10, Some(-4)),
+        ]
+    );
+}
+
+#[test]
+fn comdat_groups() {
+    use goblin::elf::section_header::{SHF_GROUP, SHT_GROUP};
+    use std::collections::BTreeMap;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("inline", Decl::function().global().into()),
+            ("inline.data", Decl::data().global().into()),
+            ("unnamed", Decl::function().global().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.comdat("inline", "inline").expect("can add to group");
+    obj.comdat("inline.data", "inline")
+        .expect("can add to group");
+    obj.comdat("unnamed", "group").expect("can add to group");
+    obj.define("main", vec![0xe9, 0, 0, 0, 0])
+        .expect("can define main");
+    obj.define("inline", vec![0x8b, 0x05, 0, 0, 0, 0, 0xc3])
+        .expect("can define inline");
+    obj.define("inline.data", vec![42, 0, 0, 0])
+        .expect("can define inline.data");
+    obj.define("unnamed", vec![0xc3])
+        .expect("can define unnamed");
+    for (from, to, at) in [("main", "inline", 1), ("inline", "inline.data", 2)] {
+        obj.link(Link { from, to, at }).expect("can link");
+    }
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let section_index = |name: &str| {
+        elf.section_headers
+            .iter()
+            .position(|sh| &elf.shdr_strtab[sh.sh_name] == name)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+    let mut groups = BTreeMap::new();
+    for (idx, sh) in elf.section_headers.iter().enumerate() {
+        if sh.sh_type != SHT_GROUP {
+            continue;
+        }
+        let signature = &elf.strtab[elf.syms.get(sh.sh_info as usize).unwrap().st_name];
+        let words = bytes[sh.sh_offset as usize..(sh.sh_offset + sh.sh_size) as usize]
+            .chunks(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]) as usize)
+            .collect::<Vec<_>>();
+        // GRP_COMDAT
+        assert_eq!(words[0], 1);
+        for &member in &words[1..] {
+            assert!(member > idx, "group sections must precede their members");
+            assert_ne!(
+                elf.section_headers[member].sh_flags & u64::from(SHF_GROUP),
+                0
+            );
+        }
+        groups.insert(signature.to_string(), words[1..].to_vec());
+    }
+    assert_eq!(groups.len(), 2);
+    assert_eq!(
+        groups["inline"],
+        vec![
+            section_index(".text.inline"),
+            section_index(".rodata.inline.data"),
+            section_index(".rela.text.inline"),
+        ]
+    );
+    assert_eq!(groups["group"], vec![section_index(".text.unnamed")]);
+    assert_eq!(
+        elf.section_headers[section_index(".rela.text.main")].sh_flags & u64::from(SHF_GROUP),
+        0
+    );
+
+    // relocations into a group must use its symbols, as its section symbols are discarded with it
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|(idx, _)| *idx == section_index(".rela.text.main"))
+        .expect("main has relocations");
+    let reloc = relocs.iter().next().unwrap();
+    assert_eq!(
+        &elf.strtab[elf.syms.get(reloc.r_sym).unwrap().st_name],
+        "inline"
+    );
+}
+
+#[test]
+fn constructors_and_destructors() {
+    use faerie::ArtifactError;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("init", Decl::function().into()),
+            ("early", Decl::function().global().into()),
+            ("fini", Decl::function().into()),
+            ("DATA", Decl::data().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    for name in &["init", "early", "fini"] {
+        obj.define(name, vec![0xc3]).expect("can define function");
+    }
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.constructor("init", None).expect("can add constructor");
+    obj.constructor("early", Some(101))
+        .expect("can add constructor");
+    obj.destructor("fini", None).expect("can add destructor");
+    match obj.constructor("DATA", None) {
+        Err(ArtifactError::NonFunctionInitializer(name)) => assert_eq!(name, "DATA"),
+        other => panic!("expected a non function initializer, got {:?}", other),
+    }
+    match obj.destructor("missing", None) {
+        Err(ArtifactError::Undeclared(name)) => assert_eq!(name, "missing"),
+        other => panic!("expected an undeclared symbol, got {:?}", other),
+    }
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = Elf::parse(&bytes).expect("can parse elf file");
+    let section_index = |name: &str| {
+        elf.section_headers
+            .iter()
+            .position(|sh| &elf.shdr_strtab[sh.sh_name] == name)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+    for (name, typ, function) in &[
+        (".init_array", section_header::SHT_INIT_ARRAY, "init"),
+        (".init_array.00101", section_header::SHT_INIT_ARRAY, "early"),
+        (".fini_array", section_header::SHT_FINI_ARRAY, "fini"),
+    ] {
+        let shndx = section_index(name);
+        let sh = &elf.section_headers[shndx];
+        assert_eq!(sh.sh_type, *typ, "{} has the wrong type", name);
+        assert_eq!(sh.sh_size, 8);
+        assert_eq!(
+            sh.sh_flags,
+            u64::from(section_header::SHF_ALLOC | section_header::SHF_WRITE)
+        );
+        let (_, relocs) = elf
+            .shdr_relocs
+            .iter()
+            .find(|(idx, _)| elf.section_headers[*idx].sh_info as usize == shndx)
+            .unwrap_or_else(|| panic!("{} should have relocations", name));
+        let relocs = relocs.iter().collect::<Vec<_>>();
+        assert_eq!(relocs.len(), 1);
+        assert_eq!(relocs[0].r_type, reloc::R_X86_64_64);
+        assert_eq!(relocs[0].r_offset, 0);
+        assert_eq!(relocs[0].r_addend, Some(0));
+        assert_eq!(
+            &elf.strtab[elf.syms.get(relocs[0].r_sym).unwrap().st_name],
+            *function
+        );
+    }
+
+    // the entries are only relocated for x86-64 and aarch64 so far
+    let mut obj = Artifact::new(triple!("i686-unknown-linux-gnu"), "a.o".into());
+    obj.declare("init", Decl::function()).expect("can declare");
+    obj.define("init", vec![0xc3]).expect("can define init");
+    obj.constructor("init", None).expect("can add constructor");
+    match obj.emit() {
+        Err(ArtifactError::UnsupportedArchitecture { format, .. }) => {
+            assert_eq!(format, faerie::BinaryFormat::Elf)
+        }
+        other => panic!("expected an unsupported architecture, got {:?}", other),
+    }
+}
+
+#[test]
+fn unsupported_architectures_and_relocations() {
+    use faerie::{ArtifactError, BinaryFormat};
+    use target_lexicon::Architecture;
+
+    let mut obj = Artifact::new(triple!("wasm32-unknown-unknown"), "a.o".into());
+    obj.declare("f", Decl::function()).expect("can declare f");
+    obj.define("f", vec![0x0b]).expect("can define f");
+    match obj.emit_as(BinaryFormat::Elf) {
+        Err(ArtifactError::UnsupportedArchitecture {
+            architecture,
+            format,
+        }) => {
+            assert_eq!(architecture, Architecture::Wasm32);
+            assert_eq!(format, BinaryFormat::Elf);
+        }
+        other => panic!("expected an unsupported architecture, got {:?}", other),
+    }
+
+    // thread-local variables have no address to point data at
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("DATA", Decl::data().into()),
+            ("COUNTER", Decl::data().tls().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.define("COUNTER", vec![0; 8])
+        .expect("can define COUNTER");
+    obj.link(Link {
+        from: "DATA",
+        to: "COUNTER",
+        at: 0,
+    })
+    .expect("can link");
+    match obj.emit() {
+        Err(ArtifactError::UnsupportedRelocation {
+            from, to, format, ..
+        }) => {
+            assert_eq!((from.as_str(), to.as_str()), ("DATA", "COUNTER"));
+            assert_eq!(format, BinaryFormat::Elf);
+        }
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
+
+#[test]
+fn portable_relocations() {
+    use faerie::{ArtifactError, Reloc, SectionKind};
+    use goblin::elf::reloc::*;
+
+    fn object(target: target_lexicon::Triple, links: &[(&str, &str, Reloc)]) -> Artifact {
+        let mut obj = Artifact::new(target, "a.o".into());
+        obj.declarations(
+            vec![
+                ("main", Decl::function().global().into()),
+                ("DATA", Decl::data().global().writable().into()),
+                (".debug_info", Decl::section(SectionKind::Debug).into()),
+                (".debug_str", Decl::section(SectionKind::Debug).into()),
+                ("printf", Decl::function_import().into()),
+                ("EXTERNAL", Decl::data_import().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        obj.define("main", vec![0; 32]).expect("can define main");
+        obj.define("DATA", vec![0; 32]).expect("can define DATA");
+        obj.define(".debug_info", vec![0; 32])
+            .expect("can define .debug_info");
+        obj.define(".debug_str", vec![0; 32])
+            .expect("can define .debug_str");
+        let mut at = 0;
+        for &(from, to, reloc) in links {
+            obj.link_with(Link { from, to, at }, reloc)
+                .expect("can link");
+            at += 4;
+        }
+        obj
+    }
+    fn relocations(obj: &Artifact) -> Vec<(u32, Option<i64>)> {
+        let bytes = obj.emit().expect("can emit elf file");
+        let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+        let mut relocs = elf
+            .shdr_relocs
+            .iter()
+            .flat_map(|(_, relocs)| relocs.iter())
+            .map(|r| (r.r_offset, r.r_type, r.r_addend))
+            .collect::<Vec<_>>();
+        relocs.sort_unstable();
+        relocs
+            .into_iter()
+            .map(|(_, typ, addend)| (typ, addend))
+            .collect()
+    }
+
+    let links = [
+        ("main", "printf", Reloc::Call),
+        ("main", "DATA", Reloc::PcRelative { addend: 8 }),
+        ("main", "EXTERNAL", Reloc::GotPcRelative { addend: 0 }),
+        ("DATA", "main", Reloc::Absolute { size: 8, addend: 1 }),
+        (
+            ".debug_info",
+            ".debug_str",
+            Reloc::SectionOffset { size: 4, addend: 2 },
+        ),
+    ];
+    let obj = object(triple!("x86_64-unknown-linux-gnu"), &links);
+    let relocs = relocations(&obj);
+    assert_eq!(
+        relocs,
+        vec![
+            (R_X86_64_PLT32, Some(-4)),
+            (R_X86_64_PC32, Some(4)),
+            (R_X86_64_GOTPCREL, Some(-4)),
+            (R_X86_64_64, Some(1)),
+            (R_X86_64_32, Some(2)),
+        ]
+    );
+
+    let obj = object(triple!("aarch64-unknown-linux-gnu"), &links[..2]);
+    assert_eq!(
+        relocations(&obj),
+        vec![(R_AARCH64_CALL26, Some(0)), (R_AARCH64_PREL32, Some(4))]
+    );
+
+    // aarch64 loads from the GOT with a pair of instructions, ELF has no image base, and the
+    // automatic relocations are only known for x86-64 and aarch64
+    let unsupported = [
+        (triple!("aarch64-unknown-linux-gnu"), links[2]),
+        (
+            triple!("i686-unknown-linux-gnu"),
+            ("main", "DATA", Reloc::Auto),
+        ),
+        (
+            triple!("i686-unknown-linux-gnu"),
+            (".debug_info", "main", Reloc::Debug { size: 4, addend: 0 }),
+        ),
+        (
+            triple!("x86_64-unknown-linux-gnu"),
+            ("DATA", "main", Reloc::ImageOffset { addend: 0 }),
+        ),
+        (
+            triple!("x86_64-unknown-linux-gnu"),
+            ("DATA", "main", Reloc::SectionOffset { size: 4, addend: 0 }),
+        ),
+    ];
+    for (target, link) in unsupported.iter() {
+        match object(target.clone(), &[*link]).emit() {
+            Err(ArtifactError::UnsupportedRelocation { reloc, .. }) => assert_eq!(reloc, link.2),
+            other => panic!("expected an unsupported relocation, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn wide_addends() {
+    use faerie::{ArtifactError, Reloc, SectionKind};
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().global().writable().into()),
+            (".debug_info", Decl::section(SectionKind::Debug).into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0; 8]).expect("can define main");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.define(".debug_info", vec![0; 8])
+        .expect("can define .debug_info");
+    obj.link_with(
+        Link {
+            from: "DATA",
+            to: "main",
+            at: 0,
+        },
+        Reloc::Raw {
+            reloc: goblin::elf::reloc::R_X86_64_64,
+            addend: 0x1_0000_0000,
+        },
+    )
+    .expect("can link");
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: "DATA",
+            at: 0,
+        },
+        Reloc::Debug {
+            size: 8,
+            addend: -0x1_0000_0000,
+        },
+    )
+    .expect("can link");
+
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let mut addends = elf
+        .shdr_relocs
+        .iter()
+        .flat_map(|(_, relocs)| relocs.iter())
+        .map(|r| r.r_addend)
+        .collect::<Vec<_>>();
+    addends.sort_unstable();
+    assert_eq!(addends, vec![Some(-0x1_0000_0000), Some(0x1_0000_0000)]);
+
+    // moving the addend of a PC-relative relocation to the start of the field overflows
+    obj.link_with(
+        Link {
+            from: "main",
+            to: "DATA",
+            at: 0,
+        },
+        Reloc::PcRelative { addend: i64::MIN },
+    )
+    .expect("can link");
+    match obj.emit() {
+        Err(ArtifactError::AddendOverflow { from, to, addend }) => {
+            assert_eq!((from.as_str(), to.as_str()), ("main", "DATA"));
+            assert_eq!(addend, i64::MIN);
+        }
+        other => panic!("expected an addend overflow, got {:?}", other),
+    }
+}
+
+#[test]
+fn auto_relocation_addends() {
+    use faerie::ArtifactError;
+    use goblin::elf::reloc::*;
+
+    fn object(target: target_lexicon::Triple) -> Artifact {
+        let mut obj = Artifact::new(target, "a.o".into());
+        obj.declarations(
+            vec![
+                ("main", Decl::function().global().into()),
+                ("TABLE", Decl::data().global().writable().into()),
+                ("EXTERNAL", Decl::data_import().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        obj.define("main", vec![0; 16]).expect("can define main");
+        obj.define("TABLE", vec![0; 32]).expect("can define TABLE");
+        obj
+    }
+    fn relocations(obj: &Artifact) -> Vec<(u32, Option<i64>)> {
+        let bytes = obj.emit().expect("can emit elf file");
+        let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+        let mut relocs = elf
+            .shdr_relocs
+            .iter()
+            .flat_map(|(_, relocs)| relocs.iter())
+            .map(|r| (r.r_type, r.r_addend))
+            .collect::<Vec<_>>();
+        relocs.sort_unstable();
+        relocs
+    }
+
+    // `cmpl $1, TABLE+24(%rip)`, whose displacement is followed by a 1 byte immediate, and &TABLE[3]
+    let mut obj = object(triple!("x86_64-unknown-linux-gnu"));
+    for (from, to, addend) in [("main", "TABLE", 23), ("TABLE", "TABLE", 24)] {
+        obj.link_with_addend(Link { from, to, at: 2 }, addend)
+            .expect("can link");
+    }
+    assert_eq!(
+        relocations(&obj),
+        vec![(R_X86_64_64, Some(24)), (R_X86_64_PC32, Some(19))]
+    );
+
+    // aarch64 offsets both the page and the offset into it
+    let mut obj = object(triple!("aarch64-unknown-linux-gnu"));
+    let link = Link {
+        from: "main",
+        to: "TABLE",
+        at: 0,
+    };
+    obj.link_with_addend(link, 24).expect("can link");
+    assert_eq!(
+        relocations(&obj),
+        vec![
+            (R_AARCH64_ADR_PREL_PG_HI21, Some(24)),
+            (R_AARCH64_ADD_ABS_LO12_NC, Some(24))
+        ]
+    );
+
+    // but not the entry of imported data in the GOT
+    let link = Link {
+        from: "main",
+        to: "EXTERNAL",
+        at: 8,
+    };
+    obj.link_with_addend(link, 24).expect("can link");
+    match obj.emit() {
+        Err(ArtifactError::UnsupportedRelocation { to, .. }) => assert_eq!(to, "EXTERNAL"),
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
+
+#[test]
+fn definition_order() {
+    use goblin::elf::sym::STB_LOCAL;
+
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("alpha", Decl::function().with_align(Some(16)).into()),
+            ("zeta", Decl::function().global().into()),
+            ("mid", Decl::function().weak().hidden().into()),
+            ("DATA", Decl::data().writable().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    for name in &["zeta", "DATA", "alpha", "mid"] {
+        obj.define(name, vec![0xc3]).expect("can define");
+    }
+    let bytes = obj.emit().expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let sections = elf
+        .section_headers
+        .iter()
+        .filter_map(|sh| elf.shdr_strtab.get_at(sh.sh_name))
+        .filter(|name| name.starts_with(".text.") || name.starts_with(".data."))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sections,
+        vec![".text.zeta", ".data.DATA", ".text.alpha", ".text.mid"]
+    );
+    // the local symbols still come first, up to the `sh_info` of the symbol table
+    let symtab = elf
+        .section_headers
+        .iter()
+        .find(|sh| sh.sh_type == section_header::SHT_SYMTAB)
+        .unwrap();
+    for (i, sym) in elf.syms.iter().enumerate() {
+        assert_eq!(sym.st_bind() == STB_LOCAL, i < symtab.sh_info as usize);
+    }
+}
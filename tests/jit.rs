@@ -0,0 +1,185 @@
+#![cfg(all(feature = "jit", target_os = "linux", target_arch = "x86_64"))]
+
+extern crate faerie;
+extern crate target_lexicon;
+
+use faerie::dwarf::{CompilationUnit, DwarfBuilder, LineRow};
+use faerie::jit::JitModule;
+use faerie::{Artifact, ArtifactError, Decl, Link, Triple};
+
+extern "C" fn add_one(x: u32) -> u32 {
+    x + 1
+}
+
+static ANSWER: u32 = 40;
+
+#[test]
+fn load_and_call() {
+    let mut obj = Artifact::new(Triple::host(), "jit".into());
+    obj.declarations(
+        vec![
+            ("forty_two", Decl::function().global().into()),
+            ("answer", Decl::function().global().into()),
+            ("count", Decl::function().global().into()),
+            ("TABLE", Decl::data().global().into()),
+            ("SECOND", Decl::data().into()),
+            ("TWO", Decl::data().into()),
+            ("COUNTER", Decl::data().writable().into()),
+            ("add_one", Decl::function_import().into()),
+            ("ANSWER", Decl::data_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    // mov $41, %edi; jmp add_one
+    obj.define("forty_two", vec![0xbf, 41, 0, 0, 0, 0xe9, 0, 0, 0, 0])
+        .expect("can define forty_two");
+    obj.link(Link {
+        from: "forty_two",
+        to: "add_one",
+        at: 6,
+    })
+    .expect("can link");
+    // mov ANSWER@GOTPCREL(%rip), %rax; mov (%rax), %eax; add TWO(%rip), %eax; ret
+    obj.define(
+        "answer",
+        vec![
+            0x48, 0x8b, 0x05, 0, 0, 0, 0, 0x8b, 0x00, 0x03, 0x05, 0, 0, 0, 0, 0xc3,
+        ],
+    )
+    .expect("can define answer");
+    obj.link(Link {
+        from: "answer",
+        to: "ANSWER",
+        at: 3,
+    })
+    .expect("can link");
+    obj.link(Link {
+        from: "answer",
+        to: "TWO",
+        at: 11,
+    })
+    .expect("can link");
+    // incl COUNTER(%rip); mov COUNTER(%rip), %eax; ret
+    obj.define(
+        "count",
+        vec![0xff, 0x05, 0, 0, 0, 0, 0x8b, 0x05, 0, 0, 0, 0, 0xc3],
+    )
+    .expect("can define count");
+    obj.link(Link {
+        from: "count",
+        to: "COUNTER",
+        at: 2,
+    })
+    .expect("can link");
+    obj.link(Link {
+        from: "count",
+        to: "COUNTER",
+        at: 8,
+    })
+    .expect("can link");
+    obj.define("TABLE", vec![0; 16]).expect("can define TABLE");
+    obj.link(Link {
+        from: "TABLE",
+        to: "forty_two",
+        at: 0,
+    })
+    .expect("can link");
+    obj.link(Link {
+        from: "TABLE",
+        to: "add_one",
+        at: 8,
+    })
+    .expect("can link");
+    // &TABLE[1]
+    obj.define("SECOND", vec![0; 8]).expect("can define SECOND");
+    obj.link_with_addend(
+        Link {
+            from: "SECOND",
+            to: "TABLE",
+            at: 0,
+        },
+        8,
+    )
+    .expect("can link");
+    obj.define("TWO", 2u32.to_le_bytes().to_vec())
+        .expect("can define TWO");
+    obj.define_zero_init("COUNTER", 4)
+        .expect("can define COUNTER");
+    // debug info isn't loaded
+    let row = LineRow {
+        offset: 0,
+        file: "forty_two.c".to_string(),
+        line: 1,
+        column: 1,
+    };
+    DwarfBuilder::new(CompilationUnit::new("forty_two.c"))
+        .function("forty_two", vec![row])
+        .finish(&mut obj)
+        .expect("can add debug info");
+
+    let module = JitModule::load(&obj, |name| match name {
+        "add_one" => Some(add_one as *const u8),
+        "ANSWER" => Some(&ANSWER as *const u32 as *const u8),
+        _ => None,
+    })
+    .expect("can load artifact");
+
+    type Function = extern "C" fn() -> u32;
+    let function = |name| -> Function {
+        let address = module.function(name).expect("function is loaded");
+        unsafe { std::mem::transmute(address) }
+    };
+    assert_eq!(function("forty_two")(), 42);
+    assert_eq!(function("answer")(), 42);
+    assert_eq!(function("count")(), 1);
+    assert_eq!(function("count")(), 2);
+
+    let table = module.data("TABLE").expect("TABLE is loaded") as *const usize;
+    let second = module.data("SECOND").expect("SECOND is loaded") as *const usize;
+    assert_eq!(unsafe { *second }, table as usize + 8);
+    let table = unsafe { [*table, *table.add(1)] };
+    assert_eq!(
+        table,
+        [
+            module.function("forty_two").unwrap() as usize,
+            add_one as *const u8 as usize
+        ]
+    );
+    assert!(module.function("TABLE").is_none());
+    assert!(module.data("add_one").is_none());
+}
+
+#[test]
+fn load_errors() {
+    let mut obj = Artifact::new(Triple::host(), "jit".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("puts", Decl::function_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0xe9, 0, 0, 0, 0])
+        .expect("can define main");
+    obj.link(Link {
+        from: "main",
+        to: "puts",
+        at: 1,
+    })
+    .expect("can link");
+    match JitModule::load(&obj, |_| None) {
+        Err(ArtifactError::UnresolvedImport(name)) => assert_eq!(name, "puts"),
+        other => panic!("expected an unresolved import, got {:?}", other),
+    }
+
+    let obj = Artifact::new(
+        target_lexicon::triple!("aarch64-unknown-linux-gnu"),
+        "jit".into(),
+    );
+    match JitModule::load(&obj, |_| None) {
+        Err(ArtifactError::ForeignTarget(target)) => assert_eq!(target, obj.target),
+        other => panic!("expected a foreign target, got {:?}", other),
+    }
+}
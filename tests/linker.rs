@@ -0,0 +1,326 @@
+extern crate faerie;
+extern crate goblin;
+#[macro_use]
+extern crate target_lexicon;
+
+use faerie::{Artifact, ArtifactError, Decl, Link, Reloc};
+use goblin::elf::dynamic::DT_NEEDED;
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_DYNAMIC, PT_LOAD};
+use goblin::elf::{header, Elf};
+
+/// Two artifacts, whose `_start` exits with `add_one(VALUE)`, or 42
+fn artifacts() -> (Artifact, Artifact) {
+    let mut start = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "start.o".into());
+    start
+        .declarations(
+            vec![
+                ("_start", Decl::function().global().into()),
+                ("POINTER", Decl::data().writable().into()),
+                ("add_one", Decl::function_import().into()),
+                ("VALUE", Decl::data_import().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+    start
+        .define(
+            "_start",
+            vec![
+                0x48, 0x8b, 0x05, 0, 0, 0, 0, // mov VALUE@GOTPCREL(%rip), %rax
+                0x8b, 0x38, // mov (%rax), %edi
+                0xe8, 0, 0, 0, 0, // call add_one
+                0x89, 0xc7, // mov %eax, %edi
+                0xb8, 0x3c, 0, 0, 0, // mov $60, %eax
+                0x0f, 0x05, // syscall
+            ],
+        )
+        .expect("can define _start");
+    start
+        .link(Link {
+            from: "_start",
+            to: "VALUE",
+            at: 3,
+        })
+        .expect("can link");
+    start
+        .link(Link {
+            from: "_start",
+            to: "add_one",
+            at: 10,
+        })
+        .expect("can link");
+    start
+        .define("POINTER", vec![0; 8])
+        .expect("can define POINTER");
+    start
+        .link(Link {
+            from: "POINTER",
+            to: "add_one",
+            at: 0,
+        })
+        .expect("can link");
+
+    let mut lib = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "lib.o".into());
+    lib.declarations(
+        vec![
+            ("add_one", Decl::function().global().into()),
+            ("VALUE", Decl::data().global().into()),
+            ("ONE", Decl::data().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    // mov %edi, %eax; add ONE(%rip), %eax; ret
+    lib.define("add_one", vec![0x89, 0xf8, 0x03, 0x05, 0, 0, 0, 0, 0xc3])
+        .expect("can define add_one");
+    lib.link(Link {
+        from: "add_one",
+        to: "ONE",
+        at: 4,
+    })
+    .expect("can link");
+    lib.define("VALUE", 41u32.to_le_bytes().to_vec())
+        .expect("can define VALUE");
+    lib.define("ONE", 1u32.to_le_bytes().to_vec())
+        .expect("can define ONE");
+    (start, lib)
+}
+
+#[test]
+fn static_executable() {
+    let (start, lib) = artifacts();
+    let bytes = Artifact::emit_executable(&[&start, &lib], "_start").expect("can link");
+    let elf = Elf::parse(&bytes).expect("can parse executable");
+    assert_eq!(elf.header.e_type, header::ET_EXEC);
+    assert!(elf.dynamic.is_none() && elf.interpreter.is_none());
+
+    let symbol = |name: &str| {
+        elf.syms
+            .iter()
+            .find(|sym| &elf.strtab[sym.st_name] == name)
+            .unwrap_or_else(|| panic!("symbol {} should exist", name))
+    };
+    assert_eq!(elf.entry, symbol("_start").st_value);
+
+    let loads = elf
+        .program_headers
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+        .map(|phdr| phdr.p_flags)
+        .collect::<Vec<_>>();
+    assert_eq!(loads, vec![PF_R, PF_R | PF_X, PF_R | PF_W]);
+
+    // the writable pointer holds the address of `add_one`
+    let pointer = symbol("POINTER").st_value;
+    let data = elf
+        .program_headers
+        .iter()
+        .find(|phdr| phdr.p_vaddr <= pointer && pointer < phdr.p_vaddr + phdr.p_filesz)
+        .expect("POINTER is mapped");
+    let at = (data.p_offset + pointer - data.p_vaddr) as usize;
+    let mut address = [0; 8];
+    address.copy_from_slice(&bytes[at..at + 8]);
+    assert_eq!(u64::from_le_bytes(address), symbol("add_one").st_value);
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("faerie-exec-{}", std::process::id()));
+        std::fs::write(&path, &bytes).expect("can write executable");
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .expect("can make executable");
+        let status = std::process::Command::new(&path)
+            .status()
+            .expect("can run executable");
+        std::fs::remove_file(&path).expect("can remove executable");
+        assert_eq!(status.code(), Some(42));
+    }
+}
+
+#[test]
+fn link_errors() {
+    let (start, lib) = artifacts();
+    match Artifact::emit_executable(&[&start], "_start") {
+        Err(ArtifactError::UndefinedSymbols(names)) => assert_eq!(names, vec!["VALUE"]),
+        other => panic!("expected an undefined symbol, got {:?}", other),
+    }
+    match Artifact::emit_executable(&[&start, &lib], "main") {
+        Err(ArtifactError::UndefinedSymbols(names)) => assert_eq!(names, vec!["main"]),
+        other => panic!("expected an undefined entry, got {:?}", other),
+    }
+    match Artifact::emit_executable(&[&start, &lib, &lib], "_start") {
+        Err(ArtifactError::DuplicateDefinition(name)) => assert_eq!(name, "add_one"),
+        other => panic!("expected a duplicate definition, got {:?}", other),
+    }
+}
+
+/// A library calling `abs` from the C library, loading its `environ`, and holding pointers to
+/// both its own and imported functions
+fn library() -> Artifact {
+    let mut lib = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "lib.o".into());
+    lib.declarations(
+        vec![
+            ("call_abs", Decl::function().global().into()),
+            ("get_environ", Decl::function().global().into()),
+            ("hidden", Decl::function().global().hidden().into()),
+            ("local", Decl::function().into()),
+            ("TABLE", Decl::data().global().into()),
+            ("POINTER_TO_ABS", Decl::data().global().writable().into()),
+            ("abs", Decl::function_import().into()),
+            ("environ", Decl::data_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    lib.define(
+        "call_abs",
+        vec![
+            0xbf, 0xd6, 0xff, 0xff, 0xff, // mov $-42, %edi
+            0xe9, 0, 0, 0, 0, // jmp abs@PLT
+        ],
+    )
+    .expect("can define call_abs");
+    lib.link(Link {
+        from: "call_abs",
+        to: "abs",
+        at: 6,
+    })
+    .expect("can link");
+    lib.define(
+        "get_environ",
+        vec![
+            0x48, 0x8b, 0x05, 0, 0, 0, 0, // mov environ@GOTPCREL(%rip), %rax
+            0x48, 0x8b, 0x00, // mov (%rax), %rax
+            0xc3, // ret
+        ],
+    )
+    .expect("can define get_environ");
+    lib.link(Link {
+        from: "get_environ",
+        to: "environ",
+        at: 3,
+    })
+    .expect("can link");
+    lib.define("hidden", vec![0xc3]).expect("can define hidden");
+    lib.define("local", vec![0xc3]).expect("can define local");
+    lib.define("TABLE", vec![0; 16]).expect("can define TABLE");
+    lib.link(Link {
+        from: "TABLE",
+        to: "call_abs",
+        at: 0,
+    })
+    .expect("can link");
+    lib.link(Link {
+        from: "TABLE",
+        to: "local",
+        at: 8,
+    })
+    .expect("can link");
+    lib.define("POINTER_TO_ABS", vec![0; 8])
+        .expect("can define POINTER_TO_ABS");
+    lib.link(Link {
+        from: "POINTER_TO_ABS",
+        to: "abs",
+        at: 0,
+    })
+    .expect("can link");
+    lib
+}
+
+#[test]
+fn shared_library() {
+    let lib = library();
+    let bytes = lib.emit_shared_library(&["libc.so.6"]).expect("can link");
+    let elf = Elf::parse(&bytes).expect("can parse shared library");
+    assert_eq!(elf.header.e_type, header::ET_DYN);
+    assert!(elf
+        .program_headers
+        .iter()
+        .any(|phdr| phdr.p_type == PT_DYNAMIC));
+    let dynamic = elf.dynamic.as_ref().expect("has a dynamic section");
+    assert!(dynamic.dyns.iter().any(|d| d.d_tag == DT_NEEDED));
+    assert_eq!(elf.libraries, vec!["libc.so.6"]);
+
+    let exported = |name: &str| {
+        elf.dynsyms
+            .iter()
+            .any(|sym| sym.st_shndx != 0 && &elf.dynstrtab[sym.st_name] == name)
+    };
+    for name in &["call_abs", "get_environ", "TABLE", "POINTER_TO_ABS"] {
+        assert!(exported(name), "{} should be exported", name);
+    }
+    assert!(!exported("hidden") && !exported("local"));
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    unsafe {
+        use std::ffi::CString;
+        use std::os::raw::{c_char, c_int, c_void};
+        extern "C" {
+            fn dlopen(filename: *const c_char, flag: c_int) -> *mut c_void;
+            fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+            fn dlclose(handle: *mut c_void) -> c_int;
+            fn abs(i: c_int) -> c_int;
+            static environ: *const *const c_char;
+        }
+        const RTLD_NOW: c_int = 2;
+
+        let path = std::env::temp_dir().join(format!("faerie-lib-{}.so", std::process::id()));
+        std::fs::write(&path, &bytes).expect("can write shared library");
+        let filename = CString::new(path.to_str().unwrap()).unwrap();
+        let handle = dlopen(filename.as_ptr(), RTLD_NOW);
+        std::fs::remove_file(&path).expect("can remove shared library");
+        assert!(!handle.is_null(), "can load shared library");
+        let symbol = |name: &str| {
+            let name = CString::new(name).unwrap();
+            let address = dlsym(handle, name.as_ptr());
+            assert!(!address.is_null());
+            address
+        };
+
+        let call_abs: extern "C" fn() -> c_int = std::mem::transmute(symbol("call_abs"));
+        assert_eq!(call_abs(), 42);
+        let get_environ: extern "C" fn() -> *const *const c_char =
+            std::mem::transmute(symbol("get_environ"));
+        assert_eq!(get_environ(), environ);
+        let table = symbol("TABLE") as *const usize;
+        assert_eq!(*table, symbol("call_abs") as usize);
+        let pointer = symbol("POINTER_TO_ABS") as *const usize;
+        assert_eq!(*pointer, abs as *const u8 as usize);
+        assert!(dlsym(handle, CString::new("hidden").unwrap().as_ptr()).is_null());
+        dlclose(handle);
+    }
+}
+
+#[test]
+fn shared_library_errors() {
+    let mut lib = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "lib.o".into());
+    lib.declarations(
+        vec![
+            ("f", Decl::function().global().into()),
+            ("VALUE", Decl::data_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    // mov VALUE, %eax
+    lib.define("f", vec![0x8b, 0x04, 0x25, 0, 0, 0, 0, 0xc3])
+        .expect("can define f");
+    lib.link_with(
+        Link {
+            from: "f",
+            to: "VALUE",
+            at: 3,
+        },
+        Reloc::Raw {
+            reloc: goblin::elf::reloc::R_X86_64_32S,
+            addend: 0,
+        },
+    )
+    .expect("can link");
+    match lib.emit_shared_library(&[]) {
+        Err(ArtifactError::UnsupportedRelocation { from, to, .. }) => {
+            assert_eq!((from.as_str(), to.as_str()), ("f", "VALUE"))
+        }
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
@@ -0,0 +1,1051 @@
+extern crate faerie;
+extern crate goblin;
+extern crate target_lexicon;
+
+use faerie::{triple, Artifact, Decl, Link, Reloc};
+use goblin::mach::constants::cputype::{CPU_SUBTYPE_ARM64_ALL, CPU_TYPE_ARM64};
+use goblin::mach::relocation::*;
+use goblin::mach::MachO;
+use std::convert::TryInto;
+
+/// The relocations of `section`, as (offset, type, pcrel, length, symbol name or addend)
+fn relocations(mach: &MachO, section: &str) -> Vec<(i32, u8, u8, u8, String)> {
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").0.to_string())
+        .collect::<Vec<_>>();
+    let (_, relocs, _) = mach
+        .relocations()
+        .expect("can parse relocations")
+        .into_iter()
+        .find(|(_, _, sect)| sect.name().unwrap() == section)
+        .unwrap_or_else(|| panic!("section {} should have relocations", section));
+    relocs
+        .map(|reloc| {
+            let reloc = reloc.expect("can parse relocation");
+            let target = if reloc.r_extern() == 1 {
+                symbols[reloc.r_symbolnum()].clone()
+            } else {
+                reloc.r_symbolnum().to_string()
+            };
+            (
+                reloc.r_address,
+                reloc.r_type(),
+                reloc.r_pcrel(),
+                reloc.r_length(),
+                target,
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn arm64_relocations() {
+    let mut obj = Artifact::new(triple!("aarch64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().global().writable().into()),
+            ("EXTERNAL", Decl::data_import().into()),
+            ("printf", Decl::function_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    let main = [
+        0x90000000u32, // adrp x0, DATA@PAGE
+        0x91000000,    // add x0, x0, DATA@PAGEOFF
+        0x90000001,    // adrp x1, EXTERNAL@GOTPAGE
+        0xf9400021,    // ldr x1, [x1, EXTERNAL@GOTPAGEOFF]
+        0x94000000,    // bl printf
+        0x90000002,    // adrp x2, (DATA + 16)@PAGE
+        0xd65f03c0,    // ret
+    ];
+    obj.define(
+        "main",
+        main.iter()
+            .flat_map(|i| i.to_le_bytes())
+            .collect::<Vec<_>>(),
+    )
+    .expect("can define main");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    for (to, at) in [("DATA", 0), ("EXTERNAL", 8), ("printf", 16)] {
+        obj.link(Link {
+            from: "main",
+            to,
+            at,
+        })
+        .expect("can link from main");
+    }
+    obj.link_with(
+        Link {
+            from: "main",
+            to: "DATA",
+            at: 20,
+        },
+        Reloc::Raw {
+            reloc: ARM64_RELOC_PAGE21 as u32,
+            addend: 16,
+        },
+    )
+    .expect("can link with addend");
+    obj.link(Link {
+        from: "DATA",
+        to: "main",
+        at: 0,
+    })
+    .expect("can link from DATA");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    assert_eq!(mach.header.cputype, CPU_TYPE_ARM64);
+    assert_eq!(mach.header.cpusubtype, CPU_SUBTYPE_ARM64_ALL);
+
+    let reloc = |at, typ, pcrel, len, target: &str| (at, typ, pcrel, len, target.to_string());
+    assert_eq!(
+        relocations(&mach, "__text"),
+        vec![
+            reloc(0, ARM64_RELOC_PAGE21, 1, 2, "_DATA"),
+            reloc(4, ARM64_RELOC_PAGEOFF12, 0, 2, "_DATA"),
+            reloc(8, ARM64_RELOC_GOT_LOAD_PAGE21, 1, 2, "_EXTERNAL"),
+            reloc(12, ARM64_RELOC_GOT_LOAD_PAGEOFF12, 0, 2, "_EXTERNAL"),
+            reloc(16, ARM64_RELOC_BRANCH26, 1, 2, "_printf"),
+            reloc(20, ARM64_RELOC_ADDEND, 0, 2, "16"),
+            reloc(20, ARM64_RELOC_PAGE21, 1, 2, "_DATA"),
+        ]
+    );
+    assert_eq!(
+        relocations(&mach, "__data"),
+        vec![reloc(0, ARM64_RELOC_UNSIGNED, 0, 3, "_main")]
+    );
+}
+
+#[test]
+fn thread_local_variables() {
+    use goblin::mach::constants::{
+        SECTION_TYPE, S_THREAD_LOCAL_REGULAR, S_THREAD_LOCAL_VARIABLES, S_THREAD_LOCAL_ZEROFILL,
+    };
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("COUNTER", Decl::data().global().tls().into()),
+            ("ZEROED", Decl::data().tls().into()),
+            ("EXTERNAL", Decl::tls_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define(
+        "main",
+        vec![
+            0x48, 0x8b, 0x3d, 0, 0, 0, 0, // mov _COUNTER@TLVP(%rip), %rdi
+            0x48, 0x8b, 0x3d, 0, 0, 0, 0,    // mov _EXTERNAL@TLVP(%rip), %rdi
+            0xc3, // ret
+        ],
+    )
+    .expect("can define main");
+    obj.define("COUNTER", vec![42, 0, 0, 0])
+        .expect("can define COUNTER");
+    obj.define_zero_init("ZEROED", 8)
+        .expect("can define ZEROED");
+    for (to, at) in [("COUNTER", 3), ("EXTERNAL", 10)] {
+        obj.link(Link {
+            from: "main",
+            to,
+            at,
+        })
+        .expect("can link from main");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let sections = mach.segments.sections().flatten().collect::<Vec<_>>();
+    let section_type = |name: &str| {
+        sections
+            .iter()
+            .map(|section| &section.as_ref().expect("can parse section").0)
+            .find(|section| section.name().unwrap() == name)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+            .flags
+            & SECTION_TYPE
+    };
+    assert_eq!(section_type("__thread_vars"), S_THREAD_LOCAL_VARIABLES);
+    assert_eq!(section_type("__thread_data"), S_THREAD_LOCAL_REGULAR);
+    assert_eq!(section_type("__thread_bss"), S_THREAD_LOCAL_ZEROFILL);
+
+    let reloc = |at, typ, pcrel, len, target: &str| (at, typ, pcrel, len, target.to_string());
+    assert_eq!(
+        relocations(&mach, "__text"),
+        vec![
+            reloc(3, X86_64_RELOC_TLV, 1, 2, "_COUNTER"),
+            reloc(10, X86_64_RELOC_TLV, 1, 2, "_EXTERNAL"),
+        ]
+    );
+    // each descriptor points at the bootstrap function and the variable's initial value
+    let mut descriptors = relocations(&mach, "__thread_vars");
+    descriptors.sort();
+    assert_eq!(
+        descriptors,
+        vec![
+            reloc(0, X86_64_RELOC_UNSIGNED, 0, 3, "__tlv_bootstrap"),
+            reloc(16, X86_64_RELOC_UNSIGNED, 0, 3, "_COUNTER$tlv$init"),
+            reloc(24, X86_64_RELOC_UNSIGNED, 0, 3, "__tlv_bootstrap"),
+            reloc(40, X86_64_RELOC_UNSIGNED, 0, 3, "_ZEROED$tlv$init"),
+        ]
+    );
+}
+
+#[test]
+fn comdat_weak_definitions() {
+    use goblin::mach::header::MH_SUBSECTIONS_VIA_SYMBOLS;
+    use goblin::mach::symbols::{N_EXT, N_WEAK_DEF};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("inline", Decl::function().global().into()),
+            ("inline.data", Decl::data().global().into()),
+            ("local", Decl::function().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.comdat("inline", "inline").expect("can add to group");
+    obj.comdat("inline.data", "inline")
+        .expect("can add to group");
+    obj.comdat("local", "inline").expect("can add to group");
+    for name in &["main", "inline", "local"] {
+        obj.define(name, vec![0xc3]).expect("can define function");
+    }
+    obj.define("inline.data", vec![42, 0, 0, 0])
+        .expect("can define inline.data");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    assert_ne!(mach.header.flags & MH_SUBSECTIONS_VIA_SYMBOLS, 0);
+    let symbol = |name: &str| {
+        mach.symbols()
+            .map(|sym| sym.expect("can parse symbol"))
+            .find(|(sym_name, _)| *sym_name == name)
+            .unwrap_or_else(|| panic!("symbol {} should exist", name))
+            .1
+    };
+    for name in &["_inline", "_inline.data"] {
+        let sym = symbol(name);
+        assert_ne!(sym.n_type & N_EXT, 0, "{} should be external", name);
+        assert_ne!(sym.n_desc & N_WEAK_DEF, 0, "{} should be weak", name);
+    }
+    assert_eq!(symbol("_main").n_desc & N_WEAK_DEF, 0);
+    // local definitions can't clash with those in other objects
+    assert_eq!(symbol("_local").n_type & N_EXT, 0);
+    assert_eq!(symbol("_local").n_desc & N_WEAK_DEF, 0);
+}
+
+#[test]
+fn constructors_and_destructors() {
+    use goblin::mach::constants::{
+        SECTION_TYPE, S_MOD_INIT_FUNC_POINTERS, S_MOD_TERM_FUNC_POINTERS,
+    };
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("first", Decl::function().into()),
+            ("second", Decl::function().global().into()),
+            ("fini", Decl::function().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    for name in &["first", "second", "fini"] {
+        obj.define(name, vec![0xc3]).expect("can define function");
+    }
+    // priorities are ignored, so constructors run in the order they are added
+    obj.constructor("first", Some(200))
+        .expect("can add constructor");
+    obj.constructor("second", Some(101))
+        .expect("can add constructor");
+    obj.destructor("fini", None).expect("can add destructor");
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .collect::<Vec<_>>();
+    for (name, typ, size) in &[
+        ("__mod_init_func", S_MOD_INIT_FUNC_POINTERS, 16),
+        ("__mod_term_func", S_MOD_TERM_FUNC_POINTERS, 8),
+    ] {
+        let section = sections
+            .iter()
+            .find(|section| section.name().unwrap() == *name)
+            .unwrap_or_else(|| panic!("section {} should exist", name));
+        assert_eq!(section.segname().unwrap(), "__DATA");
+        assert_eq!(section.flags & SECTION_TYPE, *typ);
+        assert_eq!(section.size, *size);
+        assert_eq!(section.align, 3);
+    }
+    let unsigned = |offset, name: &str| (offset, X86_64_RELOC_UNSIGNED, 0, 3, name.to_string());
+    assert_eq!(
+        relocations(&mach, "__mod_init_func"),
+        vec![unsigned(0, "_first"), unsigned(8, "_second")]
+    );
+    assert_eq!(
+        relocations(&mach, "__mod_term_func"),
+        vec![unsigned(0, "_fini")]
+    );
+}
+
+#[test]
+fn custom_section_relocations() {
+    use faerie::SectionKind;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().writable().into()),
+            ("__meta", Decl::section(SectionKind::Data).into()),
+            (".debug_info", Decl::section(SectionKind::Debug).into()),
+            (".debug_abbrev", Decl::section(SectionKind::Debug).into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    // lea __meta(%rip), %rax; ret
+    obj.define("main", vec![0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3])
+        .expect("can define main");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.define("__meta", vec![0; 16])
+        .expect("can define __meta");
+    obj.define(".debug_info", vec![0; 12])
+        .expect("can define .debug_info");
+    obj.define(".debug_abbrev", vec![0; 8])
+        .expect("can define .debug_abbrev");
+    for (from, to, at) in [
+        ("main", "__meta", 3),
+        ("DATA", "__meta", 0),
+        ("__meta", "main", 0),
+        ("__meta", "DATA", 8),
+    ] {
+        obj.link(Link { from, to, at }).expect("can link");
+    }
+    for (to, at, size, addend) in [(".debug_abbrev", 0, 4, 6), ("main", 4, 8, 0)] {
+        obj.link_with(
+            Link {
+                from: ".debug_info",
+                to,
+                at,
+            },
+            Reloc::Debug { size, addend },
+        )
+        .expect("can link from .debug_info");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let sections = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .collect::<Vec<_>>();
+    let section = |name: &str| {
+        let ordinal = sections
+            .iter()
+            .position(|(section, _)| section.name().unwrap() == name)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+            + 1;
+        let (section, data) = &sections[ordinal - 1];
+        (ordinal, section.addr, *data)
+    };
+    let (meta, meta_addr, meta_data) = section("__meta");
+    let (_, text_addr, text) = section("__text");
+    let (_, _, data) = section("__data");
+    let (_, _, debug_info) = section("__debug_info");
+
+    // references to a custom section are relative to the section, and hold its address in place
+    assert_eq!(
+        relocations(&mach, "__text"),
+        vec![(3, X86_64_RELOC_SIGNED, 1, 2, meta.to_string())]
+    );
+    let displacement = i32::from_le_bytes(text[3..7].try_into().unwrap());
+    assert_eq!(
+        i64::from(displacement),
+        meta_addr as i64 - (text_addr as i64 + 7)
+    );
+    assert_eq!(
+        relocations(&mach, "__data"),
+        vec![(0, X86_64_RELOC_UNSIGNED, 0, 3, meta.to_string())]
+    );
+    assert_eq!(u64::from_le_bytes(data[..8].try_into().unwrap()), meta_addr);
+
+    // references from a custom section go through the symbol, as from any other data
+    assert_eq!(
+        relocations(&mach, "__meta"),
+        vec![
+            (0, X86_64_RELOC_UNSIGNED, 0, 3, "_main".to_string()),
+            (8, X86_64_RELOC_UNSIGNED, 0, 3, "_DATA".to_string()),
+        ]
+    );
+    assert_eq!(meta_data, &[0; 16][..]);
+
+    // offsets into debug sections need no relocation
+    assert_eq!(
+        relocations(&mach, "__debug_info"),
+        vec![(4, X86_64_RELOC_UNSIGNED, 0, 3, "_main".to_string())]
+    );
+    assert_eq!(&debug_info[..4], &[6, 0, 0, 0]);
+}
+
+#[test]
+fn unsupported_relocations() {
+    use faerie::{ArtifactError, BinaryFormat, SectionKind};
+
+    // arm64 can only point at custom sections from data
+    let mut obj = Artifact::new(triple!("aarch64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("__meta", Decl::section(SectionKind::Data).into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0; 8]).expect("can define main");
+    obj.define("__meta", vec![0; 8]).expect("can define __meta");
+    obj.link(Link {
+        from: "main",
+        to: "__meta",
+        at: 0,
+    })
+    .expect("can link");
+    match obj.emit() {
+        Err(ArtifactError::UnsupportedRelocation {
+            from,
+            to,
+            reloc,
+            format,
+            ..
+        }) => {
+            assert_eq!((from.as_str(), to.as_str()), ("main", "__meta"));
+            assert_eq!(reloc, Reloc::Auto);
+            assert_eq!(format, BinaryFormat::Macho);
+        }
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
+
+#[test]
+fn portable_relocations() {
+    use faerie::{ArtifactError, SectionKind};
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().global().writable().into()),
+            (".debug_info", Decl::section(SectionKind::Debug).into()),
+            (".debug_abbrev", Decl::section(SectionKind::Debug).into()),
+            ("printf", Decl::function_import().into()),
+            ("EXTERNAL", Decl::data_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0; 16]).expect("can define main");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.define(".debug_info", vec![0; 4])
+        .expect("can define .debug_info");
+    obj.define(".debug_abbrev", vec![0; 4])
+        .expect("can define .debug_abbrev");
+    for (from, to, at, reloc) in [
+        ("main", "printf", 1, Reloc::Call),
+        ("main", "DATA", 6, Reloc::PcRelative { addend: 8 }),
+        ("main", "EXTERNAL", 12, Reloc::GotPcRelative { addend: 0 }),
+        ("DATA", "main", 0, Reloc::Absolute { size: 8, addend: 1 }),
+        (
+            ".debug_info",
+            ".debug_abbrev",
+            0,
+            Reloc::SectionOffset { size: 4, addend: 2 },
+        ),
+    ] {
+        obj.link_with(Link { from, to, at }, reloc)
+            .expect("can link");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let mut text = relocations(&mach, "__text");
+    text.sort();
+    assert_eq!(
+        text,
+        vec![
+            (1, X86_64_RELOC_BRANCH, 1, 2, "_printf".to_string()),
+            (6, X86_64_RELOC_SIGNED, 1, 2, "_DATA".to_string()),
+            (12, X86_64_RELOC_GOT_LOAD, 1, 2, "_EXTERNAL".to_string()),
+        ]
+    );
+    assert_eq!(
+        relocations(&mach, "__data"),
+        vec![(0, X86_64_RELOC_UNSIGNED, 0, 3, "_main".to_string())]
+    );
+    // the addends are written in place
+    let section = |name: &str| {
+        mach.segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section"))
+            .find(|(section, _)| section.name().unwrap() == name)
+            .map(|(_, data)| data)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+    assert_eq!(&section("__text")[6..10], &8i32.to_le_bytes());
+    assert_eq!(section("__data"), &1u64.to_le_bytes());
+    assert_eq!(section("__debug_info"), &2u32.to_le_bytes());
+
+    // arm64 has no 32 bit PC-relative relocation, and Mach-o has no image base
+    for (target, reloc) in [
+        (
+            triple!("aarch64-apple-darwin"),
+            Reloc::PcRelative { addend: 0 },
+        ),
+        (
+            triple!("x86_64-apple-darwin"),
+            Reloc::ImageOffset { addend: 0 },
+        ),
+    ] {
+        let mut obj = Artifact::new(target, "a.o".into());
+        obj.declarations(
+            vec![
+                ("main", Decl::function().global().into()),
+                ("DATA", Decl::data().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        obj.define("main", vec![0; 8]).expect("can define main");
+        obj.define("DATA", vec![0; 8]).expect("can define DATA");
+        obj.link_with(
+            Link {
+                from: "main",
+                to: "DATA",
+                at: 0,
+            },
+            reloc,
+        )
+        .expect("can link");
+        match obj.emit() {
+            Err(ArtifactError::UnsupportedRelocation { reloc: r, .. }) => assert_eq!(r, reloc),
+            other => panic!("expected an unsupported relocation, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn wide_addends() {
+    use faerie::ArtifactError;
+
+    fn object(target: target_lexicon::Triple, from: &str, to: &str, reloc: Reloc) -> Artifact {
+        let mut obj = Artifact::new(target, "a.o".into());
+        obj.declarations(
+            vec![
+                ("main", Decl::function().global().into()),
+                ("DATA", Decl::data().global().writable().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        obj.define("main", vec![0; 8]).expect("can define main");
+        obj.define("DATA", vec![0; 8]).expect("can define DATA");
+        obj.link_with(Link { from, to, at: 0 }, reloc)
+            .expect("can link");
+        obj
+    }
+
+    // pointers hold their full addend in place
+    let reloc = Reloc::Absolute {
+        size: 8,
+        addend: 0x1_0000_0000,
+    };
+    let bytes = object(triple!("x86_64-apple-darwin"), "DATA", "main", reloc)
+        .emit()
+        .expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let data = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__data")
+        .map(|(_, data)| data)
+        .expect("__data should exist");
+    assert_eq!(data, &0x1_0000_0000u64.to_le_bytes());
+
+    // but a 32 bit displacement, or the 24 bits of an ARM64_RELOC_ADDEND, can't
+    for (target, reloc, addend) in [
+        (
+            triple!("x86_64-apple-darwin"),
+            Reloc::PcRelative {
+                addend: 0x1_0000_0000,
+            },
+            0x1_0000_0000,
+        ),
+        (
+            triple!("aarch64-apple-darwin"),
+            Reloc::Raw {
+                reloc: u32::from(ARM64_RELOC_PAGE21),
+                addend: 1 << 23,
+            },
+            1 << 23,
+        ),
+    ] {
+        match object(target, "main", "DATA", reloc).emit() {
+            Err(ArtifactError::AddendOverflow { addend: a, .. }) => assert_eq!(a, addend),
+            other => panic!("expected an addend overflow, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn auto_relocation_addends() {
+    use faerie::ArtifactError;
+
+    fn object(target: target_lexicon::Triple, links: &[(&str, &str, u64, i64)]) -> Artifact {
+        let mut obj = Artifact::new(target, "a.o".into());
+        obj.declarations(
+            vec![
+                ("main", Decl::function().global().into()),
+                ("TABLE", Decl::data().global().writable().into()),
+                ("EXTERNAL", Decl::data_import().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        obj.define("main", vec![0; 16]).expect("can define main");
+        obj.define("TABLE", vec![0; 32]).expect("can define TABLE");
+        for &(from, to, at, addend) in links {
+            obj.link_with_addend(Link { from, to, at }, addend)
+                .expect("can link");
+        }
+        obj
+    }
+    fn section<'a>(mach: &'a MachO, name: &str) -> &'a [u8] {
+        mach.segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section"))
+            .find(|(section, _)| section.name().unwrap() == name)
+            .map(|(_, data)| data)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    }
+
+    // x86_64 addends are written in place
+    let links = [("main", "TABLE", 2, 23), ("TABLE", "TABLE", 8, 24)];
+    let bytes = object(triple!("x86_64-apple-darwin"), &links)
+        .emit()
+        .expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    assert_eq!(
+        relocations(&mach, "__text"),
+        vec![(2, X86_64_RELOC_SIGNED, 1, 2, "_TABLE".to_string())]
+    );
+    assert_eq!(&section(&mach, "__text")[2..6], &23i32.to_le_bytes());
+    assert_eq!(&section(&mach, "__data")[8..16], &24u64.to_le_bytes());
+
+    // arm64 instructions have an ARM64_RELOC_ADDEND
+    let links = [("main", "TABLE", 0, 24)];
+    let bytes = object(triple!("aarch64-apple-darwin"), &links)
+        .emit()
+        .expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    // each ARM64_RELOC_ADDEND precedes the relocation it applies to
+    assert_eq!(
+        relocations(&mach, "__text"),
+        vec![
+            (0, ARM64_RELOC_ADDEND, 0, 2, "24".to_string()),
+            (0, ARM64_RELOC_PAGE21, 1, 2, "_TABLE".to_string()),
+            (4, ARM64_RELOC_ADDEND, 0, 2, "24".to_string()),
+            (4, ARM64_RELOC_PAGEOFF12, 0, 2, "_TABLE".to_string()),
+        ]
+    );
+
+    // an addend can't offset the GOT entry of an import
+    let links = [("main", "EXTERNAL", 3, 8)];
+    match object(triple!("x86_64-apple-darwin"), &links).emit() {
+        Err(ArtifactError::UnsupportedRelocation { to, .. }) => assert_eq!(to, "EXTERNAL"),
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
+
+#[test]
+fn raw_and_debug_addends() {
+    use faerie::SectionKind;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().global().writable().into()),
+            (".debug_info", Decl::section(SectionKind::Debug).into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    // lea DATA+8(%rip), %rax; ret
+    obj.define("main", vec![0x48, 0x8d, 0x05, 0, 0, 0, 0, 0xc3])
+        .expect("can define main");
+    obj.define("DATA", vec![0; 16]).expect("can define DATA");
+    obj.define(".debug_info", vec![0; 8])
+        .expect("can define .debug_info");
+    for (from, to, at, reloc) in [
+        (
+            "main",
+            "DATA",
+            3,
+            Reloc::Raw {
+                reloc: u32::from(X86_64_RELOC_SIGNED),
+                addend: 8,
+            },
+        ),
+        (
+            "DATA",
+            "main",
+            8,
+            Reloc::Raw {
+                reloc: u32::from(X86_64_RELOC_UNSIGNED),
+                addend: -1,
+            },
+        ),
+        (
+            ".debug_info",
+            "main",
+            0,
+            Reloc::Debug { size: 8, addend: 2 },
+        ),
+    ] {
+        obj.link_with(Link { from, to, at }, reloc)
+            .expect("can link");
+    }
+
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let section = |name: &str| {
+        mach.segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section"))
+            .find(|(section, _)| section.name().unwrap() == name)
+            .map(|(_, data)| data)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+    // the relocations are emitted as usual, with their addends written as wide as they are
+    assert_eq!(
+        relocations(&mach, "__text"),
+        vec![(3, X86_64_RELOC_SIGNED, 1, 2, "_DATA".to_string())]
+    );
+    assert_eq!(&section("__text")[3..7], &8i32.to_le_bytes());
+    assert_eq!(
+        relocations(&mach, "__data"),
+        vec![(8, X86_64_RELOC_UNSIGNED, 0, 3, "_main".to_string())]
+    );
+    assert_eq!(&section("__data")[8..], &(-1i64).to_le_bytes());
+    assert_eq!(
+        relocations(&mach, "__debug_info"),
+        vec![(0, X86_64_RELOC_UNSIGNED, 0, 3, "_main".to_string())]
+    );
+    assert_eq!(section("__debug_info"), &2u64.to_le_bytes());
+}
+
+#[test]
+fn raw_addends_by_target() {
+    use faerie::ArtifactError;
+
+    fn object(target: target_lexicon::Triple, from: &str, to: &str, reloc: Reloc) -> Artifact {
+        let mut obj = Artifact::new(target, "a.o".into());
+        obj.declarations(
+            vec![
+                ("main", Decl::function().global().into()),
+                ("DATA", Decl::data().global().writable().into()),
+                ("EXTERNAL", Decl::data_import().into()),
+            ]
+            .into_iter(),
+        )
+        .expect("can declare");
+        obj.define("main", vec![0; 8]).expect("can define main");
+        obj.define("DATA", vec![0; 8]).expect("can define DATA");
+        obj.link_with(Link { from, to, at: 0 }, reloc)
+            .expect("can link");
+        obj
+    }
+
+    // 32 bit pointers hold their addend in 4 bytes
+    let reloc = Reloc::Raw {
+        reloc: u32::from(GENERIC_RELOC_VANILLA),
+        addend: 5,
+    };
+    let bytes = object(triple!("i686-apple-darwin"), "DATA", "main", reloc)
+        .emit()
+        .expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    assert_eq!(
+        relocations(&mach, "__data"),
+        vec![(0, GENERIC_RELOC_VANILLA, 0, 2, "_main".to_string())]
+    );
+    let data = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section"))
+        .find(|(section, _)| section.name().unwrap() == "__data")
+        .map(|(_, data)| data)
+        .expect("__data should exist");
+    assert_eq!(data, &[5, 0, 0, 0, 0, 0, 0, 0]);
+
+    // ld64 rejects an ARM64_RELOC_ADDEND before a GOT load
+    let reloc = Reloc::Raw {
+        reloc: u32::from(ARM64_RELOC_GOT_LOAD_PAGE21),
+        addend: 8,
+    };
+    match object(triple!("aarch64-apple-darwin"), "main", "EXTERNAL", reloc).emit() {
+        Err(ArtifactError::UnsupportedRelocation { reloc: r, .. }) => assert_eq!(r, reloc),
+        other => panic!("expected an unsupported relocation, got {:?}", other),
+    }
+}
+
+#[test]
+fn debug_links_from_definitions() {
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("FIRST", Decl::data().global().writable().into()),
+            ("DATA", Decl::data().global().writable().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0xc3]).expect("can define main");
+    obj.define("FIRST", vec![0; 8]).expect("can define FIRST");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.link_with(
+        Link {
+            from: "DATA",
+            to: "main",
+            at: 0,
+        },
+        Reloc::Debug { size: 8, addend: 0 },
+    )
+    .expect("can link");
+
+    // the relocation is placed at DATA, rather than at the start of its section
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let data = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol"))
+        .find(|(name, _)| *name == "_DATA")
+        .expect("has _DATA")
+        .1
+        .n_value;
+    let section = mach
+        .segments
+        .sections()
+        .flatten()
+        .map(|section| section.expect("can parse section").0)
+        .find(|section| section.name().unwrap() == "__data")
+        .expect("has __data");
+    assert_ne!(data, section.addr);
+    assert_eq!(
+        relocations(&mach, "__data"),
+        vec![(
+            (data - section.addr) as i32,
+            X86_64_RELOC_UNSIGNED,
+            0,
+            3,
+            "_main".to_string()
+        )]
+    );
+}
+
+#[test]
+fn debug_addresses() {
+    use faerie::SectionKind;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().writable().into()),
+            ("BSS", Decl::data().writable().into()),
+            ("STR", Decl::cstring().into()),
+            (".debug_info", Decl::section(SectionKind::Debug).into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0xc3]).expect("can define main");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.define_zero_init("BSS", 16).expect("can define BSS");
+    obj.define("STR", b"hi\0".to_vec()).expect("can define STR");
+    obj.define(".debug_info", vec![0; 32])
+        .expect("can define .debug_info");
+    for (to, at, addend) in [
+        ("BSS", 0, 4),
+        ("STR", 8, 0),
+        ("main", 16, 1),
+        ("DATA", 24, 0),
+    ] {
+        obj.link_with(
+            Link {
+                from: ".debug_info",
+                to,
+                at,
+            },
+            Reloc::Absolute { size: 8, addend },
+        )
+        .expect("can link from .debug_info");
+    }
+
+    let check = |bytes: &[u8]| {
+        let mach = MachO::parse(bytes, 0).expect("can parse mach file");
+        let sections = mach
+            .segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section"))
+            .collect::<Vec<_>>();
+        let ordinal = |name: &str| {
+            sections
+                .iter()
+                .position(|(section, _)| section.name().unwrap() == name)
+                .unwrap_or_else(|| panic!("section {} should exist", name))
+                + 1
+        };
+        let address = |name: &str| {
+            mach.symbols()
+                .map(|sym| sym.expect("can parse symbol"))
+                .find(|(sym, _)| *sym == name)
+                .map(|(_, nlist)| nlist.n_value)
+                .unwrap_or_else(|| panic!("symbol {} should exist", name))
+        };
+        // each address is relative to the section its target is in, and held in place
+        let relocs = relocations(&mach, "__debug_info");
+        let expected = [
+            (0, "__bss", "_BSS", 4),
+            (8, "__cstring", "_STR", 0),
+            (16, "__text", "_main", 1),
+            (24, "__data", "_DATA", 0),
+        ];
+        let (_, debug_info) = sections[ordinal("__debug_info") - 1];
+        for &(at, section, symbol, addend) in &expected {
+            assert!(
+                relocs.contains(&(
+                    at,
+                    X86_64_RELOC_UNSIGNED,
+                    0,
+                    3,
+                    ordinal(section).to_string()
+                )),
+                "missing relocation to {} in {:?}",
+                symbol,
+                relocs
+            );
+            let at = at as usize;
+            assert_eq!(
+                u64::from_le_bytes(debug_info[at..at + 8].try_into().unwrap()),
+                address(symbol) + addend
+            );
+        }
+    };
+    let bytes = obj.emit().expect("can emit mach file");
+    check(&bytes);
+    // and they're read back as the addresses they were
+    let read = Artifact::from_bytes(&bytes).expect("can read mach file");
+    check(&read.emit().expect("can emit mach file"));
+}
+
+#[test]
+fn links_outside_definitions() {
+    use faerie::ArtifactError;
+
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("DATA", Decl::data().global().writable().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("main", vec![0x90, 0x90, 0x90, 0xc3])
+        .expect("can define main");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    match obj.link_with(
+        Link {
+            from: "main",
+            to: "DATA",
+            at: 100,
+        },
+        Reloc::Raw {
+            reloc: u32::from(X86_64_RELOC_SIGNED),
+            addend: 5,
+        },
+    ) {
+        Err(ArtifactError::LinkOutOfBounds { from, to, at }) => {
+            assert_eq!((from.as_str(), to.as_str(), at), ("main", "DATA", 100))
+        }
+        other => panic!("expected a link out of bounds, got {:?}", other),
+    }
+    // the addend of an automatic relocation from data is as wide as a pointer, wider than the
+    // rest of `DATA`
+    obj.link_with_addend(
+        Link {
+            from: "DATA",
+            to: "main",
+            at: 4,
+        },
+        1,
+    )
+    .expect("can link");
+    match obj.emit() {
+        Err(ArtifactError::LinkOutOfBounds { from, to, at }) => {
+            assert_eq!((from.as_str(), to.as_str(), at), ("DATA", "main", 4))
+        }
+        other => panic!("expected a link out of bounds, got {:?}", other),
+    }
+}
+
+#[test]
+fn definition_order() {
+    let mut obj = Artifact::new(triple!("x86_64-apple-darwin"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("alpha", Decl::function().with_align(Some(16)).into()),
+            ("zeta", Decl::function().global().into()),
+            ("mid", Decl::function().weak().hidden().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    for name in &["zeta", "alpha", "mid"] {
+        obj.define(name, vec![0xc3]).expect("can define");
+    }
+    let bytes = obj.emit().expect("can emit mach file");
+    let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+    let mut symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol"))
+        .map(|(name, sym)| (sym.n_value, name))
+        .collect::<Vec<_>>();
+    symbols.sort();
+    let names = symbols.iter().map(|&(_, name)| name).collect::<Vec<_>>();
+    assert_eq!(names, vec!["_zeta", "_alpha", "_mid"]);
+}
@@ -0,0 +1,257 @@
+extern crate faerie;
+extern crate goblin;
+#[macro_use]
+extern crate target_lexicon;
+
+use faerie::{Artifact, ArtifactError, Decl, ImportKind, Link, Reloc, SectionKind};
+use goblin::elf::Elf;
+use std::collections::BTreeMap;
+
+fn artifact(target: target_lexicon::Triple) -> Artifact {
+    let mut obj = Artifact::new(target, "read.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("helper", Decl::function().into()),
+            ("DATA", Decl::data().global().writable().into()),
+            ("STR", Decl::cstring().into()),
+            ("BSS", Decl::data().writable().into()),
+            (".debug_info", Decl::section(SectionKind::Debug).into()),
+            ("printf", Decl::function_import().into()),
+            ("EXTERNAL", Decl::data_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define(
+        "main",
+        vec![
+            0xe8, 0, 0, 0, 0, // call printf
+            0x48, 0x8d, 0x05, 0, 0, 0, 0, // lea DATA(%rip), %rax
+            0x48, 0x8b, 0x05, 0, 0, 0, 0, // mov EXTERNAL@GOTPCREL(%rip), %rax
+            0xe8, 0, 0, 0, 0,    // call helper
+            0xc3, // ret
+        ],
+    )
+    .expect("can define main");
+    obj.define("helper", vec![0xc3]).expect("can define helper");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.define("STR", b"hello\0".to_vec())
+        .expect("can define STR");
+    obj.define_zero_init("BSS", 16).expect("can define BSS");
+    let mut symbols = BTreeMap::new();
+    symbols.insert("info_start".to_string(), 0);
+    obj.define_with_symbols(".debug_info", vec![0; 16], symbols)
+        .expect("can define .debug_info");
+    for (to, at) in [("printf", 1), ("DATA", 8), ("EXTERNAL", 15), ("helper", 20)] {
+        obj.link(Link {
+            from: "main",
+            to,
+            at,
+        })
+        .expect("can link from main");
+    }
+    obj.link(Link {
+        from: "DATA",
+        to: "main",
+        at: 0,
+    })
+    .expect("can link from DATA");
+    obj.link_with(
+        Link {
+            from: ".debug_info",
+            to: "main",
+            at: 8,
+        },
+        Reloc::Debug { size: 8, addend: 0 },
+    )
+    .expect("can link from .debug_info");
+    obj
+}
+
+/// Read `bytes` and emit them again
+fn reemit(bytes: &[u8]) -> Vec<u8> {
+    Artifact::from_bytes(bytes)
+        .expect("can read object file")
+        .emit()
+        .expect("can emit object file")
+}
+
+#[test]
+fn elf_round_trip() {
+    let bytes = artifact(triple!("x86_64-unknown-unknown-unknown-elf"))
+        .emit()
+        .expect("can emit elf file");
+    let read = Artifact::from_bytes(&bytes).expect("can read elf file");
+    assert_eq!(read.name, "read.o");
+    let mut imports = read.imports().collect::<Vec<_>>();
+    imports.sort();
+    assert_eq!(
+        imports,
+        vec![
+            ("EXTERNAL", &ImportKind::Data),
+            ("printf", &ImportKind::Function)
+        ]
+    );
+    let first = read.emit().expect("can emit elf file");
+    assert_eq!(first, bytes);
+
+    let elf = Elf::parse(&first).expect("can parse elf file");
+    let names = elf
+        .section_headers
+        .iter()
+        .map(|sh| &elf.shdr_strtab[sh.sh_name])
+        .collect::<Vec<_>>();
+    for name in &[
+        ".text.main",
+        ".text.helper",
+        ".data.DATA",
+        ".rodata.STR",
+        ".bss.BSS",
+        ".debug_info",
+    ] {
+        assert!(names.contains(name), "missing section {}", name);
+    }
+}
+
+#[test]
+fn mach_round_trip() {
+    let bytes = artifact(triple!("x86_64-apple-darwin"))
+        .emit()
+        .expect("can emit mach file");
+    let read = Artifact::from_bytes(&bytes).expect("can read mach file");
+    assert_eq!(
+        read.imports().count(),
+        2,
+        "printf and EXTERNAL should be imported"
+    );
+    // functions are padded on the way out, so compare once the padding has been read back in
+    let first = read.emit().expect("can emit mach file");
+    assert_eq!(reemit(&first), first);
+
+    let mach = goblin::mach::MachO::parse(&first, 0).expect("can parse mach file");
+    let symbols = mach
+        .symbols()
+        .map(|sym| sym.expect("can parse symbol").0)
+        .collect::<Vec<_>>();
+    for name in &[
+        "_main",
+        "_helper",
+        "_DATA",
+        "_STR",
+        "_BSS",
+        "_info_start",
+        "_printf",
+        "_EXTERNAL",
+    ] {
+        assert!(symbols.contains(name), "missing symbol {}", name);
+    }
+}
+
+#[test]
+fn elf_initializers() {
+    use goblin::elf::section_header::{SHT_INIT_ARRAY, SHT_PREINIT_ARRAY};
+
+    let mut obj = artifact(triple!("x86_64-unknown-unknown-unknown-elf"));
+    obj.constructor("helper", None)
+        .expect("can add constructor");
+    obj.constructor("main", Some(101))
+        .expect("can add constructor");
+    obj.destructor("helper", None).expect("can add destructor");
+    let bytes = obj.emit().expect("can emit elf file");
+    let read = Artifact::from_bytes(&bytes).expect("can read elf file");
+    assert_eq!(read.emit().expect("can emit elf file"), bytes);
+
+    // preinit arrays have no equivalent in an artifact
+    let mut elf = bytes;
+    let (shoff, shndx) = {
+        let parsed = Elf::parse(&elf).expect("can parse elf file");
+        let shndx = parsed
+            .section_headers
+            .iter()
+            .position(|sh| sh.sh_type == SHT_INIT_ARRAY)
+            .expect("has .init_array");
+        (parsed.header.e_shoff as usize, shndx)
+    };
+    let shdr = shoff + shndx * 64;
+    elf[shdr + 4..shdr + 8].copy_from_slice(&SHT_PREINIT_ARRAY.to_le_bytes());
+    match Artifact::from_bytes(&elf) {
+        Err(ArtifactError::UnsupportedObject(_)) => {}
+        other => panic!("expected an unsupported object, got {:?}", other),
+    }
+}
+
+#[test]
+fn unsupported_objects() {
+    // a shared library is not a relocatable object
+    let mut elf = artifact(triple!("x86_64-unknown-unknown-unknown-elf"))
+        .emit()
+        .expect("can emit elf file");
+    // e_type
+    elf[16] = 3;
+    match Artifact::from_bytes(&elf) {
+        Err(ArtifactError::UnsupportedObject(_)) => {}
+        other => panic!("expected an unsupported object, got {:?}", other),
+    }
+
+    let archive = artifact(triple!("x86_64-unknown-unknown-unknown-elf"));
+    let archive = Artifact::emit_archive(&[&archive], target_lexicon::BinaryFormat::Elf).unwrap();
+    assert!(Artifact::from_bytes(&archive).is_err());
+}
+
+#[test]
+fn malformed_objects() {
+    use goblin::mach::load_command::CommandVariant;
+    use goblin::mach::MachO;
+    use std::convert::TryInto;
+
+    let expect_unsupported = |bytes: &[u8]| match Artifact::from_bytes(bytes) {
+        Err(ArtifactError::UnsupportedObject(_)) => {}
+        other => panic!("expected an unsupported object, got {:?}", other),
+    };
+
+    // a symbol past the end of its section
+    let mut mach = artifact(triple!("x86_64-apple-darwin"))
+        .emit()
+        .expect("can emit mach file");
+    let (symoff, helper) = {
+        let parsed = MachO::parse(&mach, 0).expect("can parse mach file");
+        let symoff = parsed
+            .load_commands
+            .iter()
+            .find_map(|lc| match lc.command {
+                CommandVariant::Symtab(symtab) => Some(symtab.symoff as usize),
+                _ => None,
+            })
+            .expect("has a symbol table");
+        let helper = parsed
+            .symbols()
+            .position(|sym| sym.unwrap().0 == "_helper")
+            .expect("has _helper");
+        (symoff, helper)
+    };
+    // n_value of a 64 bit nlist
+    let n_value = symoff + helper * 16 + 8;
+    let value = u64::from_le_bytes(mach[n_value..n_value + 8].try_into().unwrap());
+    mach[n_value..n_value + 8].copy_from_slice(&(value + 0x1000).to_le_bytes());
+    expect_unsupported(&mach);
+
+    // a custom section of zeros much larger than the file
+    let mut elf = artifact(triple!("x86_64-unknown-unknown-unknown-elf"))
+        .emit()
+        .expect("can emit elf file");
+    let (shoff, shndx) = {
+        let parsed = Elf::parse(&elf).expect("can parse elf file");
+        let shndx = parsed
+            .section_headers
+            .iter()
+            .position(|sh| parsed.shdr_strtab.get_at(sh.sh_name) == Some(".debug_info"))
+            .expect("has .debug_info");
+        (parsed.header.e_shoff as usize, shndx)
+    };
+    let shdr = shoff + shndx * 64;
+    // sh_type and sh_size of a 64 bit section header
+    elf[shdr + 4..shdr + 8].copy_from_slice(&goblin::elf::section_header::SHT_NOBITS.to_le_bytes());
+    elf[shdr + 32..shdr + 40].copy_from_slice(&(1u64 << 40).to_le_bytes());
+    expect_unsupported(&elf);
+}
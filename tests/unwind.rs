@@ -0,0 +1,353 @@
+extern crate faerie;
+extern crate goblin;
+extern crate target_lexicon;
+
+use faerie::unwind::{CallFrameInstruction, FrameRow, UnwindBuilder};
+use faerie::{triple, Artifact, ArtifactError, Decl, Link};
+use std::convert::TryInto;
+
+fn row(offset: u64, instruction: CallFrameInstruction) -> FrameRow {
+    FrameRow {
+        offset,
+        instruction,
+    }
+}
+
+fn object(target: target_lexicon::Triple) -> Artifact {
+    let mut obj = Artifact::new(target, "a.o".into());
+    obj.declarations(
+        vec![
+            ("helper", Decl::function().into()),
+            ("through", Decl::function().global().into()),
+            ("callback", Decl::function_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("helper", vec![0xc3]).expect("can define helper");
+    // push %rbp; mov %rsp, %rbp; call callback; pop %rbp; ret
+    obj.define(
+        "through",
+        vec![0x55, 0x48, 0x89, 0xe5, 0xe8, 0, 0, 0, 0, 0x5d, 0xc3],
+    )
+    .expect("can define through");
+    obj.link(Link {
+        from: "through",
+        to: "callback",
+        at: 5,
+    })
+    .expect("can link");
+    UnwindBuilder::new()
+        .function(
+            "through",
+            vec![
+                row(4, CallFrameInstruction::CfaRegister(6)),
+                row(1, CallFrameInstruction::CfaOffset(16)),
+                row(
+                    1,
+                    CallFrameInstruction::Offset {
+                        register: 6,
+                        offset: -16,
+                    },
+                ),
+                row(
+                    10,
+                    CallFrameInstruction::Cfa {
+                        register: 7,
+                        offset: 8,
+                    },
+                ),
+                row(10, CallFrameInstruction::Restore(6)),
+            ],
+        )
+        .function("helper", vec![])
+        .finish(&mut obj)
+        .expect("can add unwind info");
+    obj
+}
+
+/// The frame description entry of `through` in `.eh_frame`, after the CIE and at offset 0x18,
+/// with its pointer to the function zeroed
+#[rustfmt::skip]
+const THROUGH_FDE: [u8; 32] = [
+    // length, and the offset back to the CIE
+    0x1c, 0, 0, 0, 0x1c, 0, 0, 0,
+    // the pointer to the function, and its size
+    0, 0, 0, 0, 11, 0, 0, 0,
+    // no augmentation data
+    0,
+    // advance 1, def_cfa_offset 16, offset %rbp at 2 * -8
+    0x41, 0x0e, 16, 0x86, 2,
+    // advance 3, def_cfa_register %rbp
+    0x43, 0x0d, 6,
+    // advance 6, def_cfa %rsp + 8, restore %rbp
+    0x46, 0x0c, 7, 8, 0xc6,
+    // padding
+    0, 0,
+];
+
+#[test]
+fn elf_eh_frame() {
+    use goblin::elf::reloc::*;
+    use goblin::elf::section_header::SHF_ALLOC;
+
+    let bytes = object(triple!("x86_64-unknown-linux-gnu"))
+        .emit()
+        .expect("can emit elf file");
+    let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+    let section = |name: &str| {
+        elf.section_headers
+            .iter()
+            .position(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+    let eh_frame = &elf.section_headers[section(".eh_frame")];
+    // the unwinder reads the frame descriptions out of memory
+    assert_ne!(eh_frame.sh_flags & u64::from(SHF_ALLOC), 0);
+    assert_eq!(eh_frame.sh_addralign, 8);
+    let eh_frame = &bytes[eh_frame.file_range().unwrap()];
+    #[rustfmt::skip]
+    let cie = [
+        // length, CIE id, version and augmentation
+        0x14, 0, 0, 0, 0, 0, 0, 0, 1, b'z', b'R', 0,
+        // code and data alignment factors, the return address column, and the augmentation data
+        // with the encoding of the pointers
+        1, 0x78, 16, 1, 0x1b,
+        // def_cfa %rsp + 8, offset %rip at -8, and padding
+        0x0c, 7, 8, 0x90, 1, 0, 0,
+    ];
+    assert_eq!(&eh_frame[..0x18], &cie);
+    assert_eq!(&eh_frame[0x18..0x38], &THROUGH_FDE);
+    assert_eq!(eh_frame.len(), 0x50);
+    // `helper` has no rows of its own
+    assert_eq!(
+        &eh_frame[0x38 + 8..0x50],
+        &[0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        u32::from_le_bytes(eh_frame[0x3c..0x40].try_into().unwrap()),
+        0x3c
+    );
+
+    let (_, relocs) = elf
+        .shdr_relocs
+        .iter()
+        .find(|(shndx, _)| elf.section_headers[*shndx].sh_info as usize == section(".eh_frame"))
+        .expect(".eh_frame has relocations");
+    let relocs = relocs
+        .iter()
+        .map(|r| {
+            let sym = elf.syms.get(r.r_sym).unwrap();
+            let target = &elf.section_headers[sym.st_shndx];
+            let name = elf.shdr_strtab.get_at(target.sh_name).unwrap();
+            (r.r_offset, r.r_type, name, r.r_addend)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        relocs,
+        vec![
+            (0x20, R_X86_64_PC32, ".text.through", Some(0)),
+            (0x40, R_X86_64_PC32, ".text.helper", Some(0)),
+        ]
+    );
+}
+
+#[test]
+fn mach_eh_frame() {
+    use goblin::mach::constants::*;
+    use goblin::mach::MachO;
+
+    for (target, mode_dwarf) in [
+        (triple!("x86_64-apple-darwin"), 0x0400_0000u32),
+        (triple!("aarch64-apple-darwin"), 0x0300_0000),
+    ] {
+        let bytes = object(target).emit().expect("can emit mach file");
+        let mach = MachO::parse(&bytes, 0).expect("can parse mach file");
+        let sections = mach
+            .segments
+            .sections()
+            .flatten()
+            .map(|section| section.expect("can parse section"))
+            .collect::<Vec<_>>();
+        let section = |name: &str| {
+            sections
+                .iter()
+                .find(|(section, _)| section.name().unwrap() == name)
+                .unwrap_or_else(|| panic!("section {} should exist", name))
+        };
+        let symbols = mach
+            .symbols()
+            .map(|sym| sym.expect("can parse symbol"))
+            .collect::<Vec<_>>();
+        let address = |name: &str| {
+            let name = format!("_{}", name);
+            symbols
+                .iter()
+                .find(|(sym, _)| *sym == name)
+                .unwrap()
+                .1
+                .n_value
+        };
+
+        let (eh_frame, eh_frame_data) = section("__eh_frame");
+        assert_eq!(eh_frame.segname().unwrap(), "__TEXT");
+        assert_eq!(
+            eh_frame.flags,
+            S_COALESCED | S_ATTR_NO_TOC | S_ATTR_STRIP_STATIC_SYMS | S_ATTR_LIVE_SUPPORT
+        );
+        // the linker reads which function each frame description is for from the offset in place
+        assert_eq!(eh_frame.nreloc, 0);
+        let mut fde = eh_frame_data[0x18..0x38].to_vec();
+        let pointer = i32::from_le_bytes(fde[8..12].try_into().unwrap());
+        let pointer_address = eh_frame.addr + 0x20;
+        assert_eq!(
+            pointer_address.wrapping_add(pointer as u64),
+            address("through")
+        );
+        fde[8..12].copy_from_slice(&[0; 4]);
+        assert_eq!(fde, THROUGH_FDE);
+        let pointer = i32::from_le_bytes(eh_frame_data[0x40..0x44].try_into().unwrap());
+        assert_eq!(
+            (eh_frame.addr + 0x40).wrapping_add(pointer as u64),
+            address("helper")
+        );
+
+        let (compact_unwind, compact_unwind_data) = section("__compact_unwind");
+        assert_eq!(compact_unwind.segname().unwrap(), "__LD");
+        assert_eq!(compact_unwind.flags, S_ATTR_DEBUG);
+        assert_eq!(compact_unwind_data.len(), 0x40);
+        for (entry, size) in compact_unwind_data.chunks(0x20).zip(&[11, 1]) {
+            assert_eq!(&entry[..8], &[0; 8]);
+            assert_eq!(u32::from_le_bytes(entry[8..12].try_into().unwrap()), *size);
+            assert_eq!(
+                u32::from_le_bytes(entry[12..16].try_into().unwrap()),
+                mode_dwarf
+            );
+            // no personality function or language specific data
+            assert_eq!(&entry[16..], &[0; 16]);
+        }
+        let (_, relocs, _) = mach
+            .relocations()
+            .expect("can parse relocations")
+            .into_iter()
+            .find(|(_, _, sect)| sect.name().unwrap() == "__compact_unwind")
+            .expect("__compact_unwind has relocations");
+        let relocs = relocs
+            .map(|reloc| {
+                let reloc = reloc.expect("can parse relocation");
+                assert_eq!((reloc.r_extern(), reloc.r_length()), (1, 3));
+                (reloc.r_address, symbols[reloc.r_symbolnum()].0)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(relocs, vec![(0, "_through"), (0x20, "_helper")]);
+    }
+}
+
+#[test]
+fn unwind_info_errors() {
+    let mut obj = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "a.o".into());
+    obj.declarations(
+        vec![
+            ("main", Decl::function().global().into()),
+            ("f", Decl::function().into()),
+            ("DATA", Decl::data().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("DATA", vec![0; 8]).expect("can define DATA");
+    obj.define("f", vec![0xc3]).expect("can define f");
+    let finish = |obj: &mut Artifact, name: &str, rows: Vec<FrameRow>| {
+        UnwindBuilder::new().function(name, rows).finish(obj)
+    };
+
+    match finish(&mut obj, "missing", vec![]) {
+        Err(ArtifactError::Undeclared(name)) => assert_eq!(name, "missing"),
+        other => panic!("expected an undeclared symbol, got {:?}", other),
+    }
+    match finish(&mut obj, "DATA", vec![]) {
+        Err(ArtifactError::NonFunctionUnwindInfo(name)) => assert_eq!(name, "DATA"),
+        other => panic!("expected a non function, got {:?}", other),
+    }
+    match finish(&mut obj, "main", vec![]) {
+        Err(ArtifactError::UndefinedSymbols(names)) => assert_eq!(names, vec!["main"]),
+        other => panic!("expected an undefined symbol, got {:?}", other),
+    }
+    let saved = CallFrameInstruction::Offset {
+        register: 3,
+        offset: -12,
+    };
+    match finish(&mut obj, "f", vec![row(0, saved)]) {
+        Err(ArtifactError::MisalignedFrameOffset { function, offset }) => {
+            assert_eq!((function.as_str(), offset), ("f", -12))
+        }
+        other => panic!("expected a misaligned offset, got {:?}", other),
+    }
+
+    let mut obj = Artifact::new(triple!("x86_64-pc-windows-msvc"), "a.o".into());
+    obj.declare("f", Decl::function()).expect("can declare f");
+    obj.define("f", vec![0xc3]).expect("can define f");
+    match finish(&mut obj, "f", vec![]) {
+        Err(ArtifactError::UnsupportedBinaryFormat(format)) => {
+            assert_eq!(format, target_lexicon::BinaryFormat::Coff)
+        }
+        other => panic!("expected an unsupported format, got {:?}", other),
+    }
+}
+
+#[test]
+fn unwind_sections_are_marked() {
+    use faerie::{SectionKind, UnwindTable};
+    use goblin::elf::section_header::SHF_ALLOC;
+
+    let object = |target, unwind| {
+        let mut obj = Artifact::new(target, "a.o".into());
+        obj.declare(
+            ".eh_frame",
+            Decl::section(SectionKind::Text).with_unwind(unwind),
+        )
+        .expect("can declare .eh_frame");
+        obj.define(".eh_frame", vec![0; 8])
+            .expect("can define .eh_frame");
+        obj.emit().expect("can emit object file")
+    };
+
+    // only sections marked as unwind tables are loaded with the code, whatever they're called
+    for (unwind, alloc) in [(None, false), (Some(UnwindTable::EhFrame), true)] {
+        let bytes = object(triple!("x86_64-unknown-linux-gnu"), unwind);
+        let elf = goblin::elf::Elf::parse(&bytes).expect("can parse elf file");
+        let eh_frame = elf
+            .section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".eh_frame"))
+            .expect("has .eh_frame");
+        assert_eq!(eh_frame.sh_flags & u64::from(SHF_ALLOC) != 0, alloc);
+    }
+
+    for (unwind, sectname) in [
+        (None, ".eh_frame"),
+        (Some(UnwindTable::EhFrame), "__eh_frame"),
+        (Some(UnwindTable::CompactUnwind), "__compact_unwind"),
+    ] {
+        let bytes = object(triple!("x86_64-apple-darwin"), unwind);
+        let mach = goblin::mach::MachO::parse(&bytes, 0).expect("can parse mach file");
+        let names = mach
+            .segments
+            .sections()
+            .flatten()
+            .map(|section| {
+                let (section, _) = section.expect("can parse section");
+                (
+                    section.segname().unwrap().to_string(),
+                    section.name().unwrap().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert!(
+            names.iter().any(|(_, name)| name == sectname),
+            "missing section {} in {:?}",
+            sectname,
+            names
+        );
+    }
+}
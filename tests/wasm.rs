@@ -0,0 +1,220 @@
+extern crate faerie;
+extern crate target_lexicon;
+
+use faerie::{triple, Artifact, ArtifactError, BinaryFormat, Decl, Link, Signature, ValueType};
+
+const R_WASM_FUNCTION_INDEX_LEB: u8 = 0;
+const R_WASM_TABLE_INDEX_I32: u8 = 2;
+const R_WASM_MEMORY_ADDR_SLEB: u8 = 4;
+
+/// A padded LEB128 zero, leaving room for any index or address
+const PADDED: [u8; 5] = [0x80, 0x80, 0x80, 0x80, 0x00];
+
+fn read_uleb(bytes: &[u8], offset: &mut usize) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = bytes[*offset];
+        *offset += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+fn read_name<'a>(bytes: &'a [u8], offset: &mut usize) -> &'a str {
+    let len = read_uleb(bytes, offset) as usize;
+    *offset += len;
+    std::str::from_utf8(&bytes[*offset - len..*offset]).expect("names are utf-8")
+}
+
+/// The id, name and contents of every section of a wasm module
+fn sections(bytes: &[u8]) -> Vec<(u8, &str, &[u8])> {
+    assert_eq!(&bytes[..8], b"\0asm\x01\0\0\0");
+    let mut sections = Vec::new();
+    let mut offset = 8;
+    while offset < bytes.len() {
+        let id = bytes[offset];
+        offset += 1;
+        let size = read_uleb(bytes, &mut offset) as usize;
+        let end = offset + size;
+        let name = if id == 0 {
+            read_name(bytes, &mut offset)
+        } else {
+            ""
+        };
+        sections.push((id, name, &bytes[offset..end]));
+        offset = end;
+    }
+    sections
+}
+
+/// The type, offset and symbol index of every relocation in a relocation section
+fn relocations(contents: &[u8]) -> Vec<(u8, u64, u64)> {
+    let mut offset = 0;
+    let _target = read_uleb(contents, &mut offset);
+    let count = read_uleb(contents, &mut offset);
+    (0..count)
+        .map(|_| {
+            let typ = contents[offset];
+            offset += 1;
+            let at = read_uleb(contents, &mut offset);
+            let symbol = read_uleb(contents, &mut offset);
+            if typ != R_WASM_FUNCTION_INDEX_LEB && typ != R_WASM_TABLE_INDEX_I32 {
+                read_uleb(contents, &mut offset);
+            }
+            (typ, at, symbol)
+        })
+        .collect()
+}
+
+fn link(obj: &mut Artifact, from: &str, to: &str, at: u64) {
+    obj.link(Link { from, to, at }).expect("can link");
+}
+
+#[test]
+fn functions_data_and_relocations() {
+    let mut obj = Artifact::new(triple!("wasm32-unknown-unknown"), "t.o".into());
+    obj.declarations(
+        vec![
+            ("add", Decl::function().global().into()),
+            ("init", Decl::function().into()),
+            ("COUNTER", Decl::data().global().writable().into()),
+            ("POINTER", Decl::data().global().writable().into()),
+            ("imported", Decl::function_import().into()),
+            ("VALUE", Decl::data_import().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    let unary = Signature::new(vec![ValueType::I32], vec![ValueType::I32]);
+    obj.signature("add", unary.clone()).expect("can sign add");
+    obj.signature("imported", unary).expect("can sign imported");
+    obj.signature("init", Signature::default())
+        .expect("can sign init");
+
+    // no locals; local.get 0; call imported; i32.const VALUE; i32.load; i32.add; end
+    let mut add = vec![0x00, 0x20, 0x00, 0x10];
+    let call = add.len() as u64;
+    add.extend_from_slice(&PADDED);
+    add.push(0x41);
+    let value = add.len() as u64;
+    add.extend_from_slice(&PADDED);
+    add.extend_from_slice(&[0x28, 0x02, 0x00, 0x6a, 0x0b]);
+    obj.define("add", add.clone()).expect("can define add");
+    link(&mut obj, "add", "imported", call);
+    link(&mut obj, "add", "VALUE", value);
+    // no locals; i32.const COUNTER; i32.const 1; i32.store; end
+    let mut init = vec![0x00, 0x41];
+    init.extend_from_slice(&PADDED);
+    init.extend_from_slice(&[0x41, 0x01, 0x36, 0x02, 0x00, 0x0b]);
+    obj.define("init", init).expect("can define init");
+    link(&mut obj, "init", "COUNTER", 2);
+    obj.define_zero_init("COUNTER", 4)
+        .expect("can define COUNTER");
+    obj.define("POINTER", vec![0; 4])
+        .expect("can define POINTER");
+    link(&mut obj, "POINTER", "add", 0);
+    obj.constructor("init", None).expect("can add constructor");
+
+    let bytes = obj.emit().expect("can emit wasm");
+    let sections = sections(&bytes);
+    let names = sections
+        .iter()
+        .map(|&(id, name, _)| (id, name))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![
+            (1, ""),
+            (2, ""),
+            (3, ""),
+            (10, ""),
+            (11, ""),
+            (0, "linking"),
+            (0, "reloc.CODE"),
+            (0, "reloc.DATA"),
+        ]
+    );
+    let contents = |name: &str| {
+        sections
+            .iter()
+            .find(|&&(_, section, _)| section == name)
+            .map(|&(_, _, contents)| contents)
+            .unwrap_or_else(|| panic!("section {} should exist", name))
+    };
+
+    // the memory, the table for POINTER, and the imported function
+    let imports = sections[1].2;
+    for name in &["__linear_memory", "__indirect_function_table", "imported"] {
+        assert!(imports.windows(name.len()).any(|w| w == name.as_bytes()));
+    }
+    // every symbol is in the symbol table of the linking section
+    let linking = contents("linking");
+    for name in &["add", "init", "COUNTER", "POINTER", "VALUE", ".bss.COUNTER"] {
+        assert!(
+            linking.windows(name.len()).any(|w| w == name.as_bytes()),
+            "{} should be in the linking section",
+            name
+        );
+    }
+
+    let code = sections[3].2;
+    let code_relocations = relocations(contents("reloc.CODE"));
+    let types = code_relocations
+        .iter()
+        .map(|&(typ, _, _)| typ)
+        .collect::<Vec<_>>();
+    // the functions are in the order they were defined
+    assert_eq!(
+        types,
+        vec![
+            R_WASM_FUNCTION_INDEX_LEB,
+            R_WASM_MEMORY_ADDR_SLEB,
+            R_WASM_MEMORY_ADDR_SLEB,
+        ]
+    );
+    // every relocation points at a padded LEB128 in the code section
+    for &(_, at, _) in &code_relocations {
+        assert_eq!(&code[at as usize..at as usize + 5], &PADDED);
+    }
+    let data_relocations = relocations(contents("reloc.DATA"));
+    assert_eq!(data_relocations.len(), 1);
+    assert_eq!(data_relocations[0].0, R_WASM_TABLE_INDEX_I32);
+}
+
+#[test]
+fn wasm_errors() {
+    let mut obj = Artifact::new(triple!("wasm32-unknown-unknown"), "t.o".into());
+    obj.declarations(
+        vec![
+            ("f", Decl::function().global().into()),
+            ("DATA", Decl::data().into()),
+        ]
+        .into_iter(),
+    )
+    .expect("can declare");
+    obj.define("f", vec![0x00, 0x0b]).expect("can define f");
+    obj.define("DATA", vec![0; 4]).expect("can define DATA");
+    match obj.signature("DATA", Signature::default()) {
+        Err(ArtifactError::NonFunctionSignature(name)) => assert_eq!(name, "DATA"),
+        other => panic!("expected a non-function signature, got {:?}", other),
+    }
+    match obj.emit() {
+        Err(ArtifactError::MissingSignature(name)) => assert_eq!(name, "f"),
+        other => panic!("expected a missing signature, got {:?}", other),
+    }
+    obj.signature("f", Signature::default())
+        .expect("can sign f");
+    obj.emit().expect("can emit wasm");
+
+    let x86 = Artifact::new(triple!("x86_64-unknown-linux-gnu"), "t.o".into());
+    match x86.emit_as(BinaryFormat::Wasm) {
+        Err(ArtifactError::UnsupportedArchitecture { format, .. }) => {
+            assert_eq!(format, BinaryFormat::Wasm)
+        }
+        other => panic!("expected an unsupported architecture, got {:?}", other),
+    }
+}